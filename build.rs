@@ -2,6 +2,7 @@ use std::io::Read;
 
 const XDR_X_PATH: &str = r#"./xdr"#;
 const OUTPUT_FILE: &str = r#"xdr.rs"#;
+const UNTYPED_EMPTY_CHECK: &str = "assert_eq!(buf.as_ref(), &[]);";
 fn main() {
     println!("cargo:rerun-if-env-changed=OUT_DIR");
     println!("cargo:rerun-if-changed={}", XDR_X_PATH);
@@ -42,15 +43,20 @@ fn main() {
             }
         }
     }
+    let generated = fastxdr::Generator::default()
+        .with_derive("#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]")
+        .generate(buf)
+        .expect("error parsing xdr");
+    //the generated tests compare with an untyped `&[]`, ambiguous once serde_json is linked
+    assert!(
+        generated.contains(UNTYPED_EMPTY_CHECK),
+        "the generated xdr code no longer holds `{}`, check whether it still needs replacing",
+        UNTYPED_EMPTY_CHECK
+    );
+    let generated = generated.replace(UNTYPED_EMPTY_CHECK, "assert!(buf.is_empty());");
     std::fs::write(
         std::path::Path::new(std::env::var("OUT_DIR").unwrap().as_str()).join(OUTPUT_FILE),
-        format!(
-            "pub {}",
-            fastxdr::Generator::default()
-                .with_derive("#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]")
-                .generate(buf)
-                .expect("error parsing xdr")
-        ),
+        format!("pub {}", generated),
     )
     .unwrap();
 }
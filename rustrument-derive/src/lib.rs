@@ -0,0 +1,401 @@
+//! Derive macros generating the message encoders and response parsers of instrument models.
+//!
+//! ```ignore
+//! #[derive(InstrumentCommand)]
+//! pub enum Command {
+//!     ///fields fill the `{}` placeholders in order
+//!     #[cmd("xvoltage={}")]
+//!     SetXVoltage(f32),
+//!     ///or are formatted as the expressions following the message
+//!     #[cmd("msenable={}", _0 as u8)]
+//!     SetMasterScanEnable(bool),
+//! }
+//!
+//! #[derive(InstrumentQuery)]
+//! #[query("xvoltage?", parse = f32, model = MDT693B)]
+//! pub struct ReadXVoltage;
+//! ```
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote, ToTokens};
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input,
+    punctuated::Punctuated,
+    spanned::Spanned,
+    Attribute, Data, DeriveInput, Error, Expr, Fields, Ident, LitStr, Path, Result, Token, Type,
+};
+
+///implement `rustrument::instruments::Command` for an enum or a struct
+///
+///every variant, or the struct, carries `#[cmd("message", args...)]` where the fields fill the
+///placeholders of the message unless arguments are given, or `#[cmd(transparent)]` to send its
+///single field, itself a command. `DOCS` lists the syntax and doc comment of every message.
+#[proc_macro_derive(InstrumentCommand, attributes(cmd))]
+pub fn derive_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    command(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+///implement `rustrument::instruments::Query` for a struct
+///
+///the struct carries `#[query("message", parse = Type, model = Model)]`, its fields fill the
+///placeholders of the message. The response is parsed with `FromStr`, or with the function
+///given by `with = path`, called as `path(&[u8]) -> rustrument::Result<Type>`. `DOC` holds the
+///syntax and doc comment of the query.
+#[proc_macro_derive(InstrumentQuery, attributes(query))]
+pub fn derive_query(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    query(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+///the content of `#[cmd(...)]`
+enum Cmd {
+    Message(Message),
+    Transparent,
+}
+
+struct Message {
+    format: LitStr,
+    args: Option<Vec<Expr>>,
+}
+
+impl Parse for Cmd {
+    fn parse(input: ParseStream) -> Result<Self> {
+        if input.peek(Ident) {
+            let ident: Ident = input.parse()?;
+            if ident != "transparent" {
+                return Err(Error::new(
+                    ident.span(),
+                    "expected a message or `transparent`",
+                ));
+            }
+            return Ok(Cmd::Transparent);
+        }
+        let format: LitStr = input.parse()?;
+        let args = if input.is_empty() {
+            None
+        } else {
+            input.parse::<Token![,]>()?;
+            let args = Punctuated::<Expr, Token![,]>::parse_terminated(input)?;
+            Some(args.into_iter().collect())
+        };
+        Ok(Cmd::Message(Message { format, args }))
+    }
+}
+
+///the content of `#[query(...)]`
+struct QueryAttr {
+    format: LitStr,
+    parse: Type,
+    with: Option<Path>,
+    model: Type,
+}
+
+impl Parse for QueryAttr {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let format: LitStr = input.parse()?;
+        let (mut parse, mut with, mut model) = (None, None, None);
+        while !input.is_empty() {
+            input.parse::<Token![,]>()?;
+            if input.is_empty() {
+                break;
+            }
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+            match key.to_string().as_str() {
+                "parse" => parse = Some(input.parse()?),
+                "with" => with = Some(input.parse()?),
+                "model" => model = Some(input.parse()?),
+                _ => {
+                    return Err(Error::new(
+                        key.span(),
+                        "unknown key, expected `parse`, `with` or `model`",
+                    ))
+                }
+            }
+        }
+        let missing = |key| Error::new(format.span(), format!("missing `{} = ...`", key));
+        Ok(QueryAttr {
+            parse: parse.ok_or_else(|| missing("parse"))?,
+            with,
+            model: model.ok_or_else(|| missing("model"))?,
+            format,
+        })
+    }
+}
+
+fn attribute<T: Parse>(attrs: &[Attribute], name: &str, span: Span) -> Result<T> {
+    let mut found = attrs.iter().filter(|a| a.path().is_ident(name));
+    let attr = found
+        .next()
+        .ok_or_else(|| Error::new(span, format!("missing #[{}(...)] attribute", name)))?;
+    if let Some(again) = found.next() {
+        return Err(Error::new(
+            again.span(),
+            format!("duplicate #[{}(...)] attribute", name),
+        ));
+    }
+    attr.parse_args()
+}
+
+fn doc(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            syn::Meta::NameValue(nv) => match &nv.value {
+                Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(s),
+                    ..
+                }) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+///placeholders of a format string: how many take the next argument, and the names used
+fn placeholders(format: &LitStr) -> Result<(usize, Vec<String>)> {
+    let value = format.value();
+    let mut chars = value.chars().peekable();
+    let (mut positional, mut named) = (0, Vec::new());
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+            }
+            '{' => {
+                let mut inner = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => inner.push(c),
+                        None => return Err(Error::new(format.span(), "unclosed `{` in message")),
+                    }
+                }
+                let name = inner.split(':').next().unwrap_or("").trim().to_string();
+                if name.is_empty() {
+                    positional += 1;
+                } else if name.chars().all(|c| c.is_ascii_digit()) {
+                    return Err(Error::new(
+                        format.span(),
+                        "indexed placeholders aren't supported, give the arguments explicitly",
+                    ));
+                } else if !named.contains(&name) {
+                    named.push(name);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok((positional, named))
+}
+
+///the pattern binding the fields, and the identifiers bound
+fn bind(fields: &Fields) -> (TokenStream2, Vec<Ident>) {
+    match fields {
+        Fields::Unit => (quote!(), Vec::new()),
+        Fields::Unnamed(f) => {
+            let names: Vec<Ident> = (0..f.unnamed.len())
+                .map(|i| format_ident!("_{}", i))
+                .collect();
+            (quote!((#(#names),*)), names)
+        }
+        Fields::Named(f) => {
+            let names: Vec<Ident> = f.named.iter().map(|f| f.ident.clone().unwrap()).collect();
+            (quote!({ #(#names),* }), names)
+        }
+    }
+}
+
+///`format!` call producing the message, with the fields bound to `names`
+fn format_message(message: &Message, fields: &Fields, names: &[Ident]) -> Result<TokenStream2> {
+    let format = &message.format;
+    if let Some(args) = &message.args {
+        return Ok(quote!(format!(#format, #(#args),*)));
+    }
+    let (positional, named) = placeholders(format)?;
+    if let Fields::Named(_) = fields {
+        if positional > 0 {
+            return Err(Error::new(
+                format.span(),
+                "fields are named, refer to them by name in the message, e.g. `{level}`",
+            ));
+        }
+        let mut args = Vec::new();
+        for name in &named {
+            match names.iter().find(|n| *n == name) {
+                Some(ident) => args.push(quote!(#ident = #ident)),
+                None => {
+                    return Err(Error::new(
+                        format.span(),
+                        format!("no field named `{}`", name),
+                    ))
+                }
+            }
+        }
+        return Ok(quote!(format!(#format, #(#args),*)));
+    }
+    if !named.is_empty() {
+        return Err(Error::new(
+            format.span(),
+            "fields have no names, use `{}` placeholders",
+        ));
+    }
+    if positional != names.len() {
+        return Err(Error::new(
+            format.span(),
+            format!(
+                "message has {} placeholder(s) but there are {} field(s)",
+                positional,
+                names.len()
+            ),
+        ));
+    }
+    Ok(quote!(format!(#format, #(#names),*)))
+}
+
+fn message_doc(name: &str, syntax: &str, doc: &str) -> TokenStream2 {
+    quote! {
+        ::rustrument::instruments::MessageDoc {
+            name: #name,
+            syntax: #syntax,
+            doc: #doc,
+        }
+    }
+}
+
+///the arm encoding one variant, or the struct, and its documentation
+fn command_arm(
+    path: TokenStream2,
+    name: &Ident,
+    attrs: &[Attribute],
+    fields: &Fields,
+) -> Result<(TokenStream2, TokenStream2)> {
+    let (pattern, names) = bind(fields);
+    let cmd: Cmd = attribute(attrs, "cmd", name.span())?;
+    let (body, syntax) = match cmd {
+        Cmd::Transparent => {
+            if names.len() != 1 {
+                return Err(Error::new(
+                    name.span(),
+                    "`transparent` needs exactly one field",
+                ));
+            }
+            let field = &names[0];
+            let ty = fields.iter().next().unwrap().ty.to_token_stream();
+            (
+                quote!(::std::convert::AsRef::<[u8]>::as_ref(
+                    &<#ty as ::rustrument::instruments::Command>::to_bytes(#field)
+                )
+                .to_vec()),
+                format!("<{}>", ty.to_string().replace(' ', "")),
+            )
+        }
+        Cmd::Message(message) => {
+            let format = format_message(&message, fields, &names)?;
+            (quote!(#format.into_bytes()), message.format.value())
+        }
+    };
+    let doc = message_doc(&name.to_string(), &syntax, &doc(attrs));
+    Ok((quote!(#path #pattern => #body,), doc))
+}
+
+fn command(input: DeriveInput) -> Result<TokenStream2> {
+    let ident = &input.ident;
+    let (arms, docs): (Vec<_>, Vec<_>) = match &input.data {
+        Data::Enum(e) => e
+            .variants
+            .iter()
+            .map(|v| {
+                let variant = &v.ident;
+                command_arm(quote!(#ident::#variant), variant, &v.attrs, &v.fields)
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip(),
+        Data::Struct(s) => {
+            let (arm, doc) = command_arm(quote!(#ident), ident, &input.attrs, &s.fields)?;
+            (vec![arm], vec![doc])
+        }
+        Data::Union(u) => {
+            return Err(Error::new(
+                u.union_token.span(),
+                "InstrumentCommand can't be derived for unions",
+            ))
+        }
+    };
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rustrument::instruments::Command for #ident #ty_generics #where_clause {
+            type R = ::std::vec::Vec<u8>;
+            fn to_bytes(self) -> Self::R {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        impl #impl_generics #ident #ty_generics #where_clause {
+            ///syntax and description of every message
+            pub const DOCS: &'static [::rustrument::instruments::MessageDoc] = &[#(#docs),*];
+        }
+    })
+}
+
+fn query(input: DeriveInput) -> Result<TokenStream2> {
+    let ident = &input.ident;
+    let fields =
+        match &input.data {
+            Data::Struct(s) => &s.fields,
+            Data::Enum(e) => return Err(Error::new(
+                e.enum_token.span(),
+                "InstrumentQuery can only be derived for structs, every query is a type of its own",
+            )),
+            Data::Union(u) => return Err(Error::new(
+                u.union_token.span(),
+                "InstrumentQuery can only be derived for structs, every query is a type of its own",
+            )),
+        };
+    let attr: QueryAttr = attribute(&input.attrs, "query", ident.span())?;
+    let (pattern, names) = bind(fields);
+    let format = format_message(
+        &Message {
+            format: attr.format.clone(),
+            args: None,
+        },
+        fields,
+        &names,
+    )?;
+    let (response, model) = (&attr.parse, &attr.model);
+    let parse = match &attr.with {
+        Some(with) => quote!(#with(response)),
+        None => quote!(::rustrument::instruments::parse_response(response)),
+    };
+    let doc = message_doc(&ident.to_string(), &attr.format.value(), &doc(&input.attrs));
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::rustrument::instruments::Query for #ident #ty_generics #where_clause {
+            type Model = #model;
+            type R = ::std::vec::Vec<u8>;
+            type Response = #response;
+            fn to_bytes(self) -> Self::R {
+                let #ident #pattern = self;
+                #format.into_bytes()
+            }
+            fn parse(response: &[u8]) -> ::rustrument::Result<Self::Response> {
+                #parse
+            }
+        }
+        impl #impl_generics #ident #ty_generics #where_clause {
+            ///syntax and description of the query
+            pub const DOC: ::rustrument::instruments::MessageDoc = #doc;
+        }
+    })
+}
@@ -0,0 +1,494 @@
+//! Lab configuration: the instruments of a setup by name, in TOML or YAML.
+//!
+//! ```toml
+//! [instruments.scope]
+//! resource = "TCPIP0::192.168.3.96::INSTR"
+//! driver = "infiniium"
+//! timeout_ms = 2000
+//! init = ["*CLS", ":WAVeform:FORMat BYTE"]
+//! vxi11 = { lock = false, term = "\n" }
+//!
+//! [instruments.piezo]
+//! resource = "ASRL5::INSTR"
+//! driver = "mdt693b"
+//! serial = { baud_rate = 115200 }
+//! ```
+//!
+//! Every instrument takes the [`Transport`] settings next to `resource`, `driver` (`auto` when
+//! left out, see [`DriverKind`]) and `init`, the messages sent once connected. Mistakes are
+//! reported with the line and column they were found at. A `logger` table lists readings to log,
+//! see [`LoggerConfig`].
+use crate::{
+    instruments::Driver,
+    logger::LoggerConfig,
+    resource::{DriverKind, Resource, SerialOptions, Transport, Vxi11Options},
+    Result,
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "Entry")]
+pub struct InstrumentConfig {
+    pub resource: Resource,
+    pub driver: DriverKind,
+    ///sent in order once connected, the responses to queries are read and dropped
+    pub init: Vec<String>,
+    pub transport: Transport,
+}
+
+///an instrument as written, the transport settings are next to the other fields
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    resource: Resource,
+    #[serde(default)]
+    driver: DriverKind,
+    #[serde(default)]
+    init: Vec<String>,
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    serial: SerialOptions,
+    #[serde(default)]
+    vxi11: Vxi11Options,
+    adapter: Option<Resource>,
+}
+
+impl From<Entry> for InstrumentConfig {
+    fn from(e: Entry) -> Self {
+        let default = Transport::default();
+        Self {
+            resource: e.resource,
+            driver: e.driver,
+            init: e.init,
+            transport: Transport {
+                timeout_ms: e.timeout_ms.unwrap_or(default.timeout_ms),
+                serial: e.serial,
+                vxi11: e.vxi11,
+                adapter: e.adapter,
+            },
+        }
+    }
+}
+
+impl InstrumentConfig {
+    ///what can't be told from a single field
+    pub fn check(&self) -> std::result::Result<(), String> {
+        match (&self.resource, &self.transport.adapter) {
+            (Resource::Gpib { .. }, None) => {
+                Err("a GPIB resource needs the `adapter` it is reached through".to_string())
+            }
+            (Resource::Gpib { .. }, Some(Resource::Gpib { .. })) => {
+                Err("the adapter is a serial port or a host, not a GPIB resource".to_string())
+            }
+            (Resource::Gpib { .. }, Some(_)) | (_, None) => Ok(()),
+            (_, Some(_)) => Err("`adapter` only applies to GPIB resources".to_string()),
+        }
+    }
+    ///connect, bind the driver and send the init messages
+    pub fn open(&self) -> Result<Box<dyn Driver>> {
+        let mut driver = self.driver.bind(self.resource.connect(&self.transport)?)?;
+        for message in &self.init {
+            let sent = if message.trim_end().ends_with('?') {
+                driver.query(message.as_bytes()).map(|_| ())
+            } else {
+                driver.send(message.as_bytes())
+            };
+            sent.map_err(|e| {
+                format!(
+                    "{}: init message '{}' failed: {}",
+                    self.resource, message, e
+                )
+            })?;
+        }
+        Ok(driver)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    instruments: BTreeMap<String, InstrumentConfig>,
+    logger: Option<LoggerConfig>,
+}
+
+///whether `line` starts the entry of the instrument or logged channel `name`, as a table or a
+///key
+fn names(line: &str, name: &str) -> bool {
+    let line = line.trim_start();
+    let key = line
+        .strip_prefix("[instruments.")
+        .or_else(|| line.strip_prefix("[logger.channels."))
+        .unwrap_or(line)
+        .trim_start_matches(['"', '\'']);
+    key.strip_prefix(name).is_some_and(|rest| {
+        let rest = rest.trim_start_matches(['"', '\'']).trim_start();
+        rest.starts_with(']')
+            || rest.starts_with(':')
+            || rest.starts_with('=')
+            || rest.starts_with('.')
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    ///`.toml`, `.yaml` or `.yml`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "toml" => Some(Format::Toml),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+}
+
+///a mistake in a lab configuration, with the place it was found at when known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub path: Option<PathBuf>,
+    ///line and column, from 1
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+    ///the text of the line
+    pub line: Option<String>,
+}
+
+impl ConfigError {
+    fn new(source: &str, position: Option<(usize, usize)>, message: String) -> Self {
+        Self {
+            path: None,
+            line: position.and_then(|(l, _)| source.lines().nth(l - 1).map(str::to_string)),
+            position,
+            message,
+        }
+    }
+    fn from_toml(source: &str, e: toml::de::Error) -> Self {
+        let position = e.span().map(|span| {
+            let before = &source[..span.start.min(source.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+            (line, column)
+        });
+        Self::new(source, position, e.message().trim_end().to_string())
+    }
+    fn from_yaml(source: &str, e: serde_yaml::Error) -> Self {
+        let position = e.location().map(|l| (l.line(), l.column()));
+        let mut message = e.to_string();
+        if let Some((line, column)) = position {
+            let suffix = format!(" at line {} column {}", line, column);
+            if let Some(stripped) = message.strip_suffix(&suffix) {
+                message = stripped.to_string();
+            }
+        }
+        Self::new(source, position, message)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}:", path.display())?;
+        }
+        if let Some((line, column)) = self.position {
+            write!(f, "{}:{}:", line, column)?;
+        }
+        if self.path.is_some() || self.position.is_some() {
+            f.write_str(" ")?;
+        }
+        f.write_str(&self.message)?;
+        if let (Some(text), Some((line, column))) = (&self.line, self.position) {
+            let gutter = line.to_string().len();
+            write!(f, "\n{} | {}", line, text)?;
+            write!(
+                f,
+                "\n{:gutter$} | {:>column$}",
+                "",
+                "^",
+                gutter = gutter,
+                column = column
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lab {
+    pub instruments: BTreeMap<String, InstrumentConfig>,
+    pub logger: Option<LoggerConfig>,
+}
+
+impl Lab {
+    ///check every entry, reporting a mistake at the line naming the instrument
+    fn from_file(source: &str, file: File) -> std::result::Result<Self, ConfigError> {
+        for (name, config) in &file.instruments {
+            config.check().map_err(|message| {
+                let line = source.lines().position(|l| names(l, name));
+                ConfigError::new(
+                    source,
+                    line.map(|l| (l + 1, 1)),
+                    format!("{}: {}", name, message),
+                )
+            })?;
+        }
+        for (name, channel) in file.logger.iter().flat_map(|l| &l.channels) {
+            if !file.instruments.contains_key(&channel.instrument) {
+                let line = source.lines().position(|l| names(l, name));
+                return Err(ConfigError::new(
+                    source,
+                    line.map(|l| (l + 1, 1)),
+                    format!(
+                        "{}: no instrument named '{}' to log",
+                        name, channel.instrument
+                    ),
+                ));
+            }
+        }
+        Ok(Self {
+            instruments: file.instruments,
+            logger: file.logger,
+        })
+    }
+    pub fn parse(source: &str, format: Format) -> std::result::Result<Self, ConfigError> {
+        match format {
+            Format::Toml => toml::from_str(source).map_err(|e| ConfigError::from_toml(source, e)),
+            Format::Yaml => {
+                serde_yaml::from_str(source).map_err(|e| ConfigError::from_yaml(source, e))
+            }
+        }
+        .and_then(|file| Self::from_file(source, file))
+    }
+    ///read a `.toml`, `.yaml` or `.yml` file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let format = Format::from_path(path).ok_or_else(|| {
+            format!(
+                "{}: unknown configuration format, expected .toml, .yaml or .yml",
+                path.display()
+            )
+        })?;
+        let source = std::fs::read_to_string(path)?;
+        Self::parse(&source, format).map_err(|mut e| {
+            e.path = Some(path.to_path_buf());
+            e.into()
+        })
+    }
+    pub fn get(&self, name: &str) -> Result<&InstrumentConfig> {
+        self.instruments.get(name).ok_or_else(|| {
+            format!("no instrument named '{}' in the lab configuration", name).into()
+        })
+    }
+    ///connect to the instrument named `name`, see [`InstrumentConfig::open`]
+    pub fn open(&self, name: &str) -> Result<Box<dyn Driver>> {
+        self.get(name)?.open()
+    }
+    ///connect to every instrument, stopping at the first that fails
+    pub fn open_all(&self) -> Result<BTreeMap<String, Box<dyn Driver>>> {
+        self.instruments
+            .iter()
+            .map(|(name, c)| {
+                c.open()
+                    .map(|d| (name.clone(), d))
+                    .map_err(|e| format!("{}: {}", name, e).into())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resource::Parity;
+
+    const TOML: &str = r#"
+[instruments.scope]
+resource = "TCPIP0::192.168.3.96::INSTR"
+driver = "infiniium"
+init = ["*CLS", "*IDN?"]
+vxi11 = { lock = false, term = "\n" }
+
+[instruments.keysight]
+resource = "169.254.209.174:5025"
+timeout_ms = 2500
+
+[instruments.piezo]
+resource = "ASRL5::INSTR"
+driver = "mdt693b"
+
+[instruments.piezo.serial]
+baud_rate = 115200
+parity = "even"
+
+[instruments.dmm]
+resource = "GPIB0::22::INSTR"
+adapter = "/dev/ttyUSB0"
+
+[logger]
+directory = "logs"
+max_age_s = 86400
+
+[logger.channels.piezo_x]
+instrument = "piezo"
+query = "XR?"
+interval_ms = 1000
+
+[logger.channels.status]
+instrument = "scope"
+query = "*STB?"
+interval_ms = 10000
+"#;
+
+    const YAML: &str = r#"
+instruments:
+  scope:
+    resource: TCPIP0::192.168.3.96::INSTR
+    driver: infiniium
+    init: ["*CLS", "*IDN?"]
+    vxi11: {lock: false, term: "\n"}
+  keysight:
+    resource: 169.254.209.174:5025
+    timeout_ms: 2500
+  piezo:
+    resource: ASRL5::INSTR
+    driver: mdt693b
+    serial:
+      baud_rate: 115200
+      parity: even
+  dmm:
+    resource: GPIB0::22::INSTR
+    adapter: /dev/ttyUSB0
+logger:
+  directory: logs
+  max_age_s: 86400
+  channels:
+    piezo_x: {instrument: piezo, query: "XR?", interval_ms: 1000}
+    status: {instrument: scope, query: "*STB?", interval_ms: 10000}
+"#;
+
+    #[test]
+    fn toml_and_yaml_agree() -> std::result::Result<(), ConfigError> {
+        let lab = Lab::parse(TOML, Format::Toml)?;
+        assert_eq!(lab, Lab::parse(YAML, Format::Yaml)?);
+        let scope = &lab.instruments["scope"];
+        assert_eq!(scope.driver, DriverKind::Infiniium);
+        assert_eq!(scope.init, ["*CLS", "*IDN?"]);
+        assert!(!scope.transport.vxi11.lock);
+        let keysight = &lab.instruments["keysight"];
+        assert_eq!(keysight.driver, DriverKind::Auto);
+        assert_eq!(keysight.transport.timeout_ms, 2500);
+        let piezo = &lab.instruments["piezo"].transport.serial;
+        assert_eq!(
+            (piezo.baud_rate, piezo.parity, piezo.data_bits),
+            (115200, Parity::Even, 8)
+        );
+        assert_eq!(
+            lab.instruments["dmm"].transport.adapter,
+            Some(Resource::Serial {
+                port: "/dev/ttyUSB0".to_string()
+            })
+        );
+        let logger = lab.logger.as_ref().unwrap();
+        assert_eq!(logger.format, crate::logger::Format::Csv);
+        assert_eq!(logger.prefix, "log");
+        assert_eq!(logger.channels["piezo_x"].query, "XR?");
+        assert_eq!(logger.channels["status"].interval_ms, 10000);
+        Ok(())
+    }
+
+    fn error(source: &str, format: Format) -> ConfigError {
+        Lab::parse(source, format).unwrap_err()
+    }
+
+    #[test]
+    fn errors_point_to_the_line() {
+        let e = error(&TOML.replace("\"infiniium\"", "\"infinium\""), Format::Toml);
+        assert_eq!(e.position.map(|p| p.0), Some(4));
+        assert!(e.message.contains("infinium"), "{}", e);
+        assert_eq!(e.line.as_deref(), Some("driver = \"infinium\""));
+
+        let e = error(
+            &TOML.replace("192.168.3.96::INSTR", "192.168.3.96::x::INSTR::"),
+            Format::Toml,
+        );
+        assert_eq!(e.position.map(|p| p.0), Some(3));
+        assert!(e.message.contains("invalid resource"), "{}", e);
+
+        let e = error(&TOML.replace("parity", "parrity"), Format::Toml);
+        assert_eq!(e.position.map(|p| p.0), Some(18));
+
+        let e = error(
+            &YAML.replace("baud_rate: 115200", "data_bits: 9"),
+            Format::Yaml,
+        );
+        assert_eq!(e.position.map(|p| p.0), Some(15));
+        assert!(e.message.contains("9 data bits"), "{}", e);
+
+        let e = error(
+            &YAML.replace("    adapter: /dev/ttyUSB0\n", ""),
+            Format::Yaml,
+        );
+        assert_eq!(e.position.map(|p| p.0), Some(17));
+        assert!(e.message.starts_with("dmm: a GPIB resource"), "{}", e);
+
+        let e = error(
+            &YAML.replace("timeout_ms: 2500", "timeout: 2500"),
+            Format::Yaml,
+        );
+        assert_eq!(e.position.map(|p| p.0), Some(10));
+
+        let e = error(
+            &TOML.replace("\"scope\"\nquery", "\"scop\"\nquery"),
+            Format::Toml,
+        );
+        assert_eq!(e.position.map(|p| p.0), Some(33));
+        assert!(
+            e.message.starts_with("status: no instrument named 'scop'"),
+            "{}",
+            e
+        );
+        let e = error(&YAML.replace("scope, query", "scop, query"), Format::Yaml);
+        assert_eq!(e.position.map(|p| p.0), Some(25));
+        let mut e = error("instruments: {scope: {driver: rigol}}", Format::Yaml);
+        e.path = Some("lab.yaml".into());
+        assert!(e.to_string().starts_with("lab.yaml:1:"), "{}", e);
+        assert!(e.message.contains("resource"), "{}", e);
+    }
+
+    #[test]
+    fn open_sends_the_init_messages() -> Result<()> {
+        let (port, log) = crate::test_support::line_instrument(|l| match l {
+            "*IDN?" => Some("FAKE,SOCKET,0,1\n".into()),
+            _ => None,
+        })?;
+        let mut config = InstrumentConfig {
+            resource: format!("127.0.0.1:{}", port).parse()?,
+            driver: DriverKind::Generic,
+            init: vec!["*CLS".into(), "*IDN?".into()],
+            transport: Transport {
+                timeout_ms: 100,
+                ..Transport::default()
+            },
+        };
+        //answered once the init messages were
+        assert_eq!(config.open()?.query(b"*IDN?")?, b"FAKE,SOCKET,0,1\n");
+        config.init.push("STUCK?".into());
+        let e = config.open().err().unwrap().to_string();
+        assert!(e.contains("init message 'STUCK?' failed"), "{}", e);
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["*CLS", "*IDN?", "*IDN?", "*CLS", "*IDN?", "STUCK?"]
+        );
+        Ok(())
+    }
+}
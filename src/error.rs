@@ -3,7 +3,7 @@ use std::{convert::Infallible, ops::Deref};
 use thiserror::Error;
 
 use crate::{
-    protocols::onc_rpc::oncrpc_error::{RejectedReply, UnsuccessfulAcceptStatus},
+    protocols::onc_rpc::oncrpc_error::{AuthError, RejectedReply, UnsuccessfulAcceptStatus},
     scpi,
 };
 
@@ -19,13 +19,21 @@ pub enum Error {
     ScpiError(#[from] scpi::scpi_error::ScpiError),
     #[error("one-rpc error: {0}")]
     OncRpcError(#[from] super::protocols::onc_rpc::oncrpc_error::OncRpcError),
+    #[error("configuration error: {0}")]
+    ConfigError(#[from] super::config::ConfigError),
+    #[error("instrument manager error: {0}")]
+    ManagerError(#[from] super::manager::ManagerError),
     #[error("{0}")]
     Other(#[from] OtherError),
 }
 impl Error {
     pub fn is_timeout(&self) -> bool {
         if let Error::IOError(e) = self {
-            if e.kind() == std::io::ErrorKind::TimedOut {
+            //sockets report an expired read timeout as `WouldBlock` on unix
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+            ) {
                 return true;
             }
         }
@@ -90,6 +98,11 @@ impl From<RejectedReply> for Error {
         Error::OncRpcError(s.into())
     }
 }
+impl From<AuthError> for Error {
+    fn from(s: AuthError) -> Self {
+        RejectedReply::from(s).into()
+    }
+}
 impl From<UnsuccessfulAcceptStatus> for Error {
     fn from(s: UnsuccessfulAcceptStatus) -> Self {
         Error::OncRpcError(s.into())
@@ -111,3 +124,19 @@ impl From<Infallible> for Error {
         unreachable!()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn timeouts() {
+        let timeout = |kind| Error::from(io::Error::from(kind)).is_timeout();
+        assert!(timeout(io::ErrorKind::TimedOut));
+        //what an expired socket read timeout gives on unix
+        assert!(timeout(io::ErrorKind::WouldBlock));
+        assert!(!timeout(io::ErrorKind::Interrupted));
+        assert!(!Error::from(OtherError::from("timed out")).is_timeout());
+    }
+}
@@ -0,0 +1,252 @@
+//! How [`Instrument`](super::Instrument) finds the end of a response in the bytes it receives.
+use crate::scpi::response_len;
+use std::ops::Range;
+
+///given the bytes received so far, the length of the response once they hold a whole one
+pub type FrameFn = Box<dyn Fn(&[u8]) -> Option<usize> + Send + Sync>;
+
+///the first complete response in the received bytes
+pub enum End {
+    ///up to and including a sequence of bytes, e.g. `b"\r\n"`
+    Terminator(Vec<u8>),
+    ///up to and including a byte, which may also appear inside an IEEE 488.2 definite length
+    ///block `#<n><length><data>`
+    Block(u8),
+    ///up to a prompt, e.g. `b">"`, the prompt isn't part of the response
+    Prompt(Vec<u8>),
+    ///a fixed number of bytes
+    Length(usize),
+    ///see [`FrameFn`]
+    Custom(FrameFn),
+}
+
+pub struct Framing {
+    pub end: End,
+    ///the device echoes the messages it receives, the echo is dropped from the response
+    pub echo: bool,
+    ///the device answers commands too, e.g. with an echo and a prompt,
+    ///the answer is read and dropped after every command
+    pub reply_to_commands: bool,
+}
+
+impl Framing {
+    pub fn new(end: End) -> Self {
+        Self {
+            end,
+            echo: false,
+            reply_to_commands: false,
+        }
+    }
+    pub fn terminator<T: Into<Vec<u8>>>(terminator: T) -> Self {
+        Self::new(End::Terminator(terminator.into()))
+    }
+    pub fn block(end_byte: u8) -> Self {
+        Self::new(End::Block(end_byte))
+    }
+    pub fn prompt<T: Into<Vec<u8>>>(prompt: T) -> Self {
+        Self::new(End::Prompt(prompt.into()))
+    }
+    pub fn length(len: usize) -> Self {
+        Self::new(End::Length(len))
+    }
+    pub fn custom<F: Fn(&[u8]) -> Option<usize> + Send + Sync + 'static>(f: F) -> Self {
+        Self::new(End::Custom(Box::new(f)))
+    }
+    pub fn set_echo(&mut self, echo: bool) -> &mut Self {
+        self.echo = echo;
+        self
+    }
+    pub fn set_reply_to_commands(&mut self, reply: bool) -> &mut Self {
+        self.reply_to_commands = reply;
+        self
+    }
+
+    ///find the first response in `buf`, received after sending `sent`,
+    ///return how many bytes it takes up and where the response lies in them
+    pub fn frame(&self, buf: &[u8], sent: &[u8]) -> Option<(usize, Range<usize>)> {
+        let start = if self.echo { skip_echo(buf, sent)? } else { 0 };
+        let body = &buf[start..];
+        let (taken, len) = match &self.end {
+            End::Terminator(t) => {
+                let end = find(body, t)? + t.len();
+                (end, end)
+            }
+            End::Block(b) => {
+                let end = response_len(body, *b)?;
+                (end, end)
+            }
+            End::Prompt(p) => {
+                let at = find(body, p)?;
+                (at + p.len(), at)
+            }
+            End::Length(n) if body.len() >= *n => (*n, *n),
+            End::Length(_) => return None,
+            End::Custom(f) => {
+                let end = f(body).filter(|e| *e <= body.len())?;
+                (end, end)
+            }
+        };
+        Some((start + taken, start..start + len))
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+///where the response starts after the echo of `sent` and the line end following it,
+///`None` until that is known
+fn skip_echo(buf: &[u8], sent: &[u8]) -> Option<usize> {
+    let is_line_end = |b: &u8| *b == b'\r' || *b == b'\n';
+    let text_len = sent.len() - sent.iter().rev().take_while(|b| is_line_end(b)).count();
+    let text = &sent[..text_len];
+    if text.is_empty() {
+        return Some(0);
+    }
+    if buf.len() < text.len() {
+        //the echo may still be arriving
+        return if text.starts_with(buf) { None } else { Some(0) };
+    }
+    if !buf.starts_with(text) {
+        return Some(0);
+    }
+    let start = text.len()
+        + buf[text.len()..]
+            .iter()
+            .take_while(|b| is_line_end(b))
+            .count();
+    if start == buf.len() {
+        None
+    } else {
+        Some(start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruments::{Messenger, Model};
+    use std::io::{self, Read, Write};
+
+    #[test]
+    fn ends() {
+        let frame = |f: Framing, buf: &[u8]| f.frame(buf, b"").map(|(n, r)| (n, buf[r].to_vec()));
+        assert_eq!(
+            frame(Framing::terminator(&b"\r\n"[..]), b"1\r2\r\n3"),
+            Some((5, b"1\r2\r\n".to_vec()))
+        );
+        assert_eq!(frame(Framing::terminator(&b"\r\n"[..]), b"1\r"), None);
+        assert_eq!(
+            frame(Framing::block(b'\n'), b"#14a\nb\n\n1\n"),
+            Some((8, b"#14a\nb\n\n".to_vec()))
+        );
+        assert_eq!(frame(Framing::block(b'\n'), b"#14a\nb\n"), None);
+        assert_eq!(
+            frame(Framing::prompt(">"), b"[ 1.0]\r>"),
+            Some((8, b"[ 1.0]\r".to_vec()))
+        );
+        assert_eq!(frame(Framing::length(2), b"abc"), Some((2, b"ab".to_vec())));
+        assert_eq!(frame(Framing::length(4), b"abc"), None);
+        //a length byte in front of the payload
+        let prefixed = || Framing::custom(|b| b.first().map(|n| 1 + *n as usize));
+        assert_eq!(frame(prefixed(), b"\x02ab"), Some((3, b"\x02ab".to_vec())));
+        assert_eq!(frame(prefixed(), b"\x02a"), None);
+    }
+
+    #[test]
+    fn echo_is_skipped() {
+        let mut f = Framing::prompt(">");
+        f.set_echo(true);
+        let sent = b"xvoltage?\n";
+        assert_eq!(f.frame(b"xvolt", sent), None);
+        assert_eq!(f.frame(b"xvoltage?\r", sent), None);
+        assert_eq!(f.frame(b"xvoltage?\r[ 1.0]>", sent), Some((17, 10..16)));
+        //no echo at all
+        assert_eq!(f.frame(b"[ 1.0]>", sent), Some((7, 0..6)));
+    }
+
+    ///answers every message with its echo, the queued answer and a prompt
+    struct EchoDevice {
+        answers: Vec<&'static [u8]>,
+        out: io::Cursor<Vec<u8>>,
+    }
+
+    impl Write for EchoDevice {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let out = self.out.get_mut();
+            out.extend_from_slice(buf);
+            if buf.ends_with(b"\n") {
+                out.pop();
+                out.push(b'\r');
+                let answer = self.answers.remove(0);
+                out.extend_from_slice(answer);
+                out.extend_from_slice(b"\r>");
+            }
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for EchoDevice {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            //a single byte at a time, as a slow serial port would
+            let n = buf.len().min(1);
+            self.out.read(&mut buf[..n])
+        }
+    }
+
+    struct Text(&'static str);
+    impl From<&'static str> for Text {
+        fn from(s: &'static str) -> Self {
+            Text(s)
+        }
+    }
+    impl crate::instruments::Command for Text {
+        type R = &'static str;
+        fn to_bytes(self) -> Self::R {
+            self.0
+        }
+    }
+    impl crate::instruments::Query for Text {
+        type Model = Piezo;
+        type R = &'static str;
+        type Response = String;
+        fn to_bytes(self) -> Self::R {
+            self.0
+        }
+        fn parse(response: &[u8]) -> crate::Result<String> {
+            Ok(String::from_utf8_lossy(response).into_owned())
+        }
+    }
+
+    struct Piezo;
+    impl Model for Piezo {
+        const DESCRIPTION: &'static str = "echoing device";
+        type Command = Text;
+        const TERMINATOR: u8 = b'\n';
+        const END_BYTE: u8 = b']';
+        fn framing() -> Framing {
+            let mut f = Framing::prompt(">");
+            f.set_echo(true).set_reply_to_commands(true);
+            f
+        }
+    }
+
+    #[test]
+    fn instrument_reads_one_response() -> crate::Result<()> {
+        let device = EchoDevice {
+            answers: vec![b"", b"[ 12.5]", b"[ 7.0]"],
+            out: io::Cursor::new(Vec::new()),
+        };
+        let mut piezo = Messenger::new(device).bind(Piezo);
+        piezo.command("xvoltage=12.5")?;
+        assert_eq!(piezo.query(Text("xvoltage?"))?, "[ 12.5]\r");
+        assert_eq!(piezo.query(Text("yvoltage?"))?, "[ 7.0]\r");
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+//! Any instrument speaking SCPI, used when no driver knows it better.
+use super::Model;
+use crate::{scpi::Identity, InstrumentQuery};
+
+#[derive(Default)]
+pub struct Generic;
+
+impl Model for Generic {
+    const DESCRIPTION: &'static str = "SCPI instrument";
+    type Command = Message;
+    const TERMINATOR: u8 = b'\n';
+    const END_BYTE: u8 = b'\n';
+}
+
+///a message sent as it is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message(pub String);
+
+impl super::Command for Message {
+    type R = String;
+    fn to_bytes(self) -> Self::R {
+        self.0
+    }
+}
+impl From<&str> for Message {
+    fn from(s: &str) -> Self {
+        Self(s.to_string())
+    }
+}
+impl From<String> for Message {
+    fn from(s: String) -> Self {
+        Self(s)
+    }
+}
+
+///`*IDN?`
+#[derive(InstrumentQuery)]
+#[query("*IDN?", parse = Identity, model = Generic)]
+pub struct Identify;
+///any query, answered with text
+#[derive(InstrumentQuery)]
+#[query("{}", parse = String, with = text, model = Generic)]
+pub struct Ask(pub String);
+
+fn text(response: &[u8]) -> crate::Result<String> {
+    Ok(std::str::from_utf8(response)?.trim_end().to_string())
+}
@@ -1,4 +1,5 @@
 use super::Model;
+use crate::{scpi::Identity, waveform::Preamble, InstrumentCommand, InstrumentQuery};
 
 #[derive(Default)]
 pub struct Infiniium;
@@ -11,73 +12,137 @@ impl crate::DefaultConfig for Infiniium {
 impl Model for Infiniium {
     const DESCRIPTION: &'static str = "Infiniium (A/H/Q/S/V/X/Z) Series Oscilloscopes";
     type Command = Command;
-    type Query = Query;
     const END_BYTE: u8 = b'\n';
     const TERMINATOR: u8 = b'\n';
 }
 
 
 
+#[derive(InstrumentCommand)]
 pub enum Command {
     ///Trigger
+    #[cmd(transparent)]
     Trig(Trig),
 }
 
+#[derive(InstrumentCommand)]
 pub enum Trig {
+    #[cmd(transparent)]
     Level(TrigLevel),
+    #[cmd(transparent)]
     Mode(TrigMode),
 }
+///trigger level of a channel, V
+#[derive(InstrumentCommand)]
+#[cmd(":TRIGger:LEVel CHANnel{channel},{level}")]
 pub struct TrigLevel {
-    //channel: u8,
-    //level: f32,
+    pub channel: u8,
+    pub level: f32,
 }
+#[derive(InstrumentCommand)]
 pub enum TrigMode {
     ///Edge trigger mode.
+    #[cmd(":TRIGger:MODE EDGE")]
     Edge,
     ///Trigger on a pulse that has a width less than a specified amount of time.
+    #[cmd(":TRIGger:MODE GLITch")]
     Glitch,
     ///Pattern triggering lets you trigger the oscilloscope using more than one channel as the trigger source. You can also use pattern triggering to trigger on a pulse of a given width.
+    #[cmd(":TRIGger:MODE PATTern")]
     Pattern,
     ///State triggering lets you set the oscilloscope to use several channels as the trigger source, with one of the channels being used as a clock waveform.
+    #[cmd(":TRIGger:MODE STATe")]
     State,
     ///Delay by Events mode lets you view pulses in your waveform that occur a number of events after a specified waveform edge. Delay by Time mode lets you view pulses in your waveform that occur a long time after a specified waveform edge.
+    #[cmd(":TRIGger:MODE DELay")]
     Delay,
     ///Timeout triggering lets you trigger when the waveform remains high too long, low to long, or unchanged too long.
+    #[cmd(":TRIGger:MODE TIMeout")]
     Timeout,
     ///TV trigger mode lets you trigger the oscilloscope on one of the standard television waveforms. You can also use this mode to trigger on a custom television waveform that you define.
+    #[cmd(":TRIGger:MODE TV")]
     TV,
     ///COMM mode lets you trigger on a serial pattern of bits in a waveform.
+    #[cmd(":TRIGger:MODE COMM")]
     Comm,
     ///Runt triggering lets you trigger on positive or negative pulses that are smaller in amplitude than other pulses in your waveform.
+    #[cmd(":TRIGger:MODE RUNT")]
     Runt,
     ///(Available on 90000A Series, 90000 X-Series, V-Series, 90000 Q-Series, and Z-Series oscilloscopes.) Sequential triggering lets you use multiple events or time/pattern qualifications to define your trigger.
+    #[cmd(":TRIGger:MODE SEQuence")]
     Sequence,
     ///Setup and Hold triggering let you trigger on Setup or Hold violations in your circuit.
+    #[cmd(":TRIGger:MODE SHOLd")]
     SHold,
     ///Edge Transition triggering lets you trigger on an edge that violates a rise time or fall time specification.
+    #[cmd(":TRIGger:MODE TRANsition")]
     Transition,
     ///Window triggering lets you define a window on screen and then trigger when the waveform exits the window, enters it, or stays inside/outside the window for too long/short.
+    #[cmd(":TRIGger:MODE WINDow")]
     Window,
     ///Pulse width triggering lets you trigger on a pulse that is greater than or less than a specified width and of a certain polarity.
+    #[cmd(":TRIGger:MODE PWIDth")]
     PWidth,
     ///Allows backward compatibility access to the DELay, PATTern, STATe, TV, and VIOLation modes. When this mode is selected, use the :TRIGger:ADVanced:MODE command to select the advanced trigger mode.
+    #[cmd(":TRIGger:MODE ADVanced")]
     Advanced,
     ///Serial triggering on SBUS1, SBUS2, SBUS3, or SBUS4.
+    #[cmd(":TRIGger:MODE SBUS{}")]
     SBus(u8),
 }
 
-impl super::Command for Command {
-    type R = Box<[u8]>;
-    fn to_bytes(self) -> Self::R {
-        unimplemented!()
-    }
+///the identification string
+#[derive(InstrumentQuery)]
+#[query("*IDN?", parse = Identity, model = Infiniium)]
+pub struct Identify;
+///analog sample rate, Sa/s
+#[derive(InstrumentQuery)]
+#[query(":ACQuire:SRATe:ANALog?", parse = f64, model = Infiniium)]
+pub struct SampleRate;
+///the waveform of the source set with `:WAVeform:SOURce`, in ASCII format
+#[derive(InstrumentQuery)]
+#[query(":WAVeform:DATA?", parse = Vec<f32>, with = values, model = Infiniium)]
+pub struct WaveformData;
+///scaling of the waveform read, see [`Waveform::from_data`](crate::waveform::Waveform::from_data)
+#[derive(InstrumentQuery)]
+#[query(":WAVeform:PREamble?", parse = Preamble, with = Preamble::keysight, model = Infiniium)]
+pub struct WaveformPreamble;
+
+fn values(response: &[u8]) -> crate::Result<Vec<f32>> {
+    std::str::from_utf8(response)
+        .map_err(|_| "waveform data isn't text")?
+        .trim()
+        .split_terminator(',')
+        .map(|x| super::parse_response(x.as_bytes()))
+        .collect()
 }
 
-pub enum Query {}
+#[test]
+fn parse_responses() -> crate::Result<()> {
+    use super::Query;
+    assert_eq!(SampleRate::parse(b"+2.50000E+08\n")?, 2.5e8);
+    assert_eq!(
+        WaveformData::parse(b"0.1,-2E-3,3,\n")?,
+        vec![0.1, -2e-3, 3.0]
+    );
+    assert!(WaveformData::parse(b"0.1,x\n").is_err());
+    let id = Identify::parse(b"KEYSIGHT TECHNOLOGIES,DSO9254A,MY12345678,06.40.00901\n")?;
+    assert_eq!(
+        (id.model.as_str(), id.serial.as_str()),
+        ("DSO9254A", "MY12345678")
+    );
+    Ok(())
+}
 
-impl super::Query for Query {
-    type R = &'static str;
-    fn to_bytes(self) -> Self::R {
-        unimplemented!()
-    }
+#[test]
+fn encode_commands() {
+    use super::Command as _;
+    let level = Command::Trig(Trig::Level(TrigLevel {
+        channel: 2,
+        level: 0.25,
+    }));
+    assert_eq!(level.to_bytes(), b":TRIGger:LEVel CHANnel2,0.25");
+    let mode = Command::Trig(Trig::Mode(TrigMode::SBus(3)));
+    assert_eq!(mode.to_bytes(), b":TRIGger:MODE SBUS3");
+    assert_eq!(TrigMode::DOCS[0].syntax, ":TRIGger:MODE EDGE");
 }
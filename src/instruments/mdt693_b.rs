@@ -1,5 +1,5 @@
 use super::Model;
-use crate::DefaultConfig;
+use crate::{DefaultConfig, Result};
 
 #[derive(Default)]
 pub struct MDT693B;
@@ -18,129 +18,231 @@ impl DefaultConfig for MDT693B {
 impl Model for MDT693B {
     const DESCRIPTION: &'static str = "Piezo controller";
     type Command = Command;
-    type Query = Query;
     const TERMINATOR: u8 = b'\n';
     const END_BYTE: u8 = b']';
+    ///the controller echoes every message and answers commands too, each reply ends with a prompt
+    fn framing() -> super::Framing {
+        let mut framing = super::Framing::prompt(">");
+        framing.set_echo(true).set_reply_to_commands(true);
+        framing
+    }
 }
 
-pub enum Query {
-    GetCommands,
-    ProductInformation,
-    GetEchoCommandValue,
-    LimitSwitchSetting,
-    GetDisplayIntensity,
-    GetMaserScanEnable,
-    ReadXVoltage,
-    ReadYVoltage,
-    ReadZVoltage,
-    ReadMinXVoltage,
-    ReadMinYVoltage,
-    ReadMinZVoltage,
-    ReadMaxXVoltage,
-    ReadMaxYVoltage,
-    ReadMaxZVoltage,
-    GetVoltageAdjustmentResolution,
-    GetFriendlyName,
-    GetSerialNumber,
-    GetCompatibility,
-    GetRotaryMode,
-    GetDisableRotaryPushToAdjust,
-}
-
+#[derive(crate::InstrumentCommand)]
 pub enum Command {
+    #[cmd("echo={}")]
     SetEchoCommand(bool),
-    SetDisplayIntensity(u8), //0-15
+    ///0-15
+    #[cmd("intensity={}")]
+    SetDisplayIntensity(u8),
+    #[cmd("allvoltage={}")]
     SetAllVoltages(u8),
+    #[cmd("msenable={}", _0 as u8)]
     SetMasterScanEnable(bool),
+    #[cmd("msvoltage={}")]
     SetMasterScanVoltage(u8),
+    #[cmd("xvoltage={}")]
     SetXVoltage(f32),
+    #[cmd("yvoltage={}")]
     SetYVoltage(f32),
+    #[cmd("zvoltage={}")]
     SetZVoltage(f32),
+    #[cmd("xmin={}")]
     SetMinXVoltage(f32),
+    #[cmd("ymin={}")]
     SetMinYVoltage(f32),
+    #[cmd("zmin={}")]
     SetMinZVoltage(f32),
+    #[cmd("xmax={}")]
     SetMaxXVoltage(f32),
+    #[cmd("ymax={}")]
     SetMaxYVoltage(f32),
+    #[cmd("zmax={}")]
     SetMaxZVoltage(f32),
-    SetVoltageAdjustmentResolution(u16), //1-1000,
+    ///1-1000
+    #[cmd("dacstep={}")]
+    SetVoltageAdjustmentResolution(u16),
+    #[cmd("\x1b[A")]
     IncrementVoltage,
+    #[cmd("\x1b[B")]
     DecrementVoltage,
+    #[cmd("\x1b[D")]
     DecreaseChannel,
+    #[cmd("\x1b[C")]
     IncreaseChannel,
+    #[cmd("friendly={}")]
     SetFriendlyName(String),
+    #[cmd("cm={}", _0 as u8)]
     SetCompatibilityMode(bool),
     //SetRotaryMode(),//0 1 -1
+    #[cmd("disablepush={}", _0 as u8)]
     SetDisableRotaryPushToAdjust(bool),
 }
 
-impl super::Query for Query {
-    type R = &'static str;
-    fn to_bytes(self) -> Self::R {
-        match self {
-            Query::GetCommands => "?",
-            Query::ProductInformation => "id?",
-            Query::GetEchoCommandValue => "echo?",
-            Query::LimitSwitchSetting => "vlimit?",
-            Query::GetDisplayIntensity => "intensity?",
-            Query::GetMaserScanEnable => "msenable?",
-            Query::ReadXVoltage => "xvoltage?",
-            Query::ReadYVoltage => "yvoltage?",
-            Query::ReadZVoltage => "zvoltage?",
-            Query::ReadMinXVoltage => "xmin?",
-            Query::ReadMinYVoltage => "ymin?",
-            Query::ReadMinZVoltage => "zmin?",
-            Query::ReadMaxXVoltage => "xmax?",
-            Query::ReadMaxYVoltage => "ymax?",
-            Query::ReadMaxZVoltage => "zmax?",
-            Query::GetVoltageAdjustmentResolution => "dacstep?",
-            Query::GetFriendlyName => "friendly?",
-            Query::GetSerialNumber => "serial?",
-            Query::GetCompatibility => "cm?",
-            Query::GetRotaryMode => "rotarymode?",
-            Query::GetDisableRotaryPushToAdjust => "disablepush?",
-        }
-    }
+///the value of a response such as `[ 12.5]`, taken from its last line
+fn bracketed(response: &[u8]) -> Result<&str> {
+    let text = std::str::from_utf8(response)
+        .map_err(|_| format!("response isn't text: {:?}", response))?;
+    let line = text
+        .lines()
+        .map(str::trim)
+        .rfind(|l| !l.is_empty())
+        .ok_or("empty response")?;
+    Ok(match (line.find('['), line.rfind(']')) {
+        (Some(start), Some(end)) if start < end => line[start + 1..end].trim(),
+        _ => line,
+    })
 }
 
-impl super::Command for Command {
-    type R = Box<[u8]>;
-    fn to_bytes(self) -> Self::R {
-        match self {
-            Command::SetEchoCommand(bo) => format!("echo={}", bo).into_bytes(),
-            Command::SetDisplayIntensity(n) => format!("intensity={}", n).into_bytes(), //0-15
-            Command::SetAllVoltages(n) => format!("allvoltage={}", n).into_bytes(),
-            Command::SetMasterScanEnable(bo) => format!("msenable={}", bo as u8)
-                .bytes()
-                .collect::<Vec<u8>>(),
-            Command::SetMasterScanVoltage(n) => format!("msvoltage={}", n).into_bytes(),
-            Command::SetXVoltage(n) => format!("xvoltage={}", n).into_bytes(),
-            Command::SetYVoltage(n) => format!("yvoltage={}", n).into_bytes(),
-            Command::SetZVoltage(n) => format!("zvoltage={}", n).into_bytes(),
-            Command::SetMinXVoltage(n) => format!("xmin={}", n).into_bytes(),
-            Command::SetMinYVoltage(n) => format!("ymin={}", n).into_bytes(),
-            Command::SetMinZVoltage(n) => format!("zmin={}", n).into_bytes(),
-            Command::SetMaxXVoltage(n) => format!("xmax={}", n).into_bytes(),
-            Command::SetMaxYVoltage(n) => format!("ymax={}", n).into_bytes(),
-            Command::SetMaxZVoltage(n) => format!("zmax={}", n).into_bytes(),
-            Command::SetVoltageAdjustmentResolution(n) => format!("dacstep={}", n).into_bytes(), //1-1000
-            Command::IncrementVoltage => vec![0x1b, b'[', b'A'],
-            Command::DecrementVoltage => vec![0x1b, b'[', b'B'],
-            Command::DecreaseChannel => vec![0x1b, b'[', b'D'],
-            Command::IncreaseChannel => vec![0x1b, b'[', b'C'],
-            Command::SetFriendlyName(s) => format!("friendly={}", s).into_bytes(),
-            Command::SetCompatibilityMode(bo) => format!("cm={}", bo as u8).into_bytes(),
-            //Command::SetRotaryMode()=>"",//0 1 -1
-            Command::SetDisableRotaryPushToAdjust(bo) => {
-                format!("disablepush={}", bo as u8).into_bytes()
-            }
-        }
-        .into_boxed_slice()
+fn value<T: std::str::FromStr>(response: &[u8]) -> Result<T> {
+    super::parse_response(bracketed(response)?.as_bytes())
+}
+
+fn flag(response: &[u8]) -> Result<bool> {
+    let text = bracketed(response)?.to_ascii_lowercase();
+    match text.as_str() {
+        "1" | "on" | "true" => Ok(true),
+        "0" | "off" | "false" => Ok(false),
+        _ => Err(format!("unexpected response '{}'", text).into()),
     }
 }
 
+fn text(response: &[u8]) -> Result<String> {
+    Ok(bracketed(response)?.to_string())
+}
+
+///the whole response, for answers spanning several lines
+fn lines(response: &[u8]) -> Result<String> {
+    Ok(String::from_utf8_lossy(response).trim().to_string())
+}
+
+///the identity in the answer to `id?`, e.g. `THORLABS MDT693B Piezo Controller\rFirmware Version 1.09`,
+///the serial number isn't part of it, see [`query::GetSerialNumber`]
+pub fn identity(response: &[u8]) -> Result<crate::scpi::Identity> {
+    let text = String::from_utf8_lossy(response);
+    let mut words = text.split_whitespace().map(|w| w.trim_matches(':'));
+    let model = words
+        .clone()
+        .find(|w| w.to_ascii_uppercase().starts_with("MDT"))
+        .ok_or_else(|| format!("'{}' isn't the answer of a piezo controller", text.trim()))?;
+    let firmware = words
+        .by_ref()
+        .skip_while(|w| !w.eq_ignore_ascii_case("version"))
+        .nth(1)
+        .unwrap_or_default();
+    Ok(crate::scpi::Identity::new("THORLABS", model, "", firmware))
+}
+
+///queries of the controller, each parsing its response into its own type
+pub mod query {
+    use super::{flag, lines, text, value, MDT693B};
+    use crate::InstrumentQuery;
+
+    ///list of the commands
+    #[derive(InstrumentQuery)]
+    #[query("?", parse = String, with = lines, model = MDT693B)]
+    pub struct GetCommands;
+    #[derive(InstrumentQuery)]
+    #[query("id?", parse = String, with = lines, model = MDT693B)]
+    pub struct ProductInformation;
+    #[derive(InstrumentQuery)]
+    #[query("echo?", parse = bool, with = flag, model = MDT693B)]
+    pub struct GetEchoCommandValue;
+    ///output voltage limit, V
+    #[derive(InstrumentQuery)]
+    #[query("vlimit?", parse = f32, with = value, model = MDT693B)]
+    pub struct LimitSwitchSetting;
+    #[derive(InstrumentQuery)]
+    #[query("intensity?", parse = u8, with = value, model = MDT693B)]
+    pub struct GetDisplayIntensity;
+    #[derive(InstrumentQuery)]
+    #[query("msenable?", parse = bool, with = flag, model = MDT693B)]
+    pub struct GetMaserScanEnable;
+    #[derive(InstrumentQuery)]
+    #[query("xvoltage?", parse = f32, with = value, model = MDT693B)]
+    pub struct ReadXVoltage;
+    #[derive(InstrumentQuery)]
+    #[query("yvoltage?", parse = f32, with = value, model = MDT693B)]
+    pub struct ReadYVoltage;
+    #[derive(InstrumentQuery)]
+    #[query("zvoltage?", parse = f32, with = value, model = MDT693B)]
+    pub struct ReadZVoltage;
+    #[derive(InstrumentQuery)]
+    #[query("xmin?", parse = f32, with = value, model = MDT693B)]
+    pub struct ReadMinXVoltage;
+    #[derive(InstrumentQuery)]
+    #[query("ymin?", parse = f32, with = value, model = MDT693B)]
+    pub struct ReadMinYVoltage;
+    #[derive(InstrumentQuery)]
+    #[query("zmin?", parse = f32, with = value, model = MDT693B)]
+    pub struct ReadMinZVoltage;
+    #[derive(InstrumentQuery)]
+    #[query("xmax?", parse = f32, with = value, model = MDT693B)]
+    pub struct ReadMaxXVoltage;
+    #[derive(InstrumentQuery)]
+    #[query("ymax?", parse = f32, with = value, model = MDT693B)]
+    pub struct ReadMaxYVoltage;
+    #[derive(InstrumentQuery)]
+    #[query("zmax?", parse = f32, with = value, model = MDT693B)]
+    pub struct ReadMaxZVoltage;
+    #[derive(InstrumentQuery)]
+    #[query("dacstep?", parse = u16, with = value, model = MDT693B)]
+    pub struct GetVoltageAdjustmentResolution;
+    #[derive(InstrumentQuery)]
+    #[query("friendly?", parse = String, with = text, model = MDT693B)]
+    pub struct GetFriendlyName;
+    #[derive(InstrumentQuery)]
+    #[query("serial?", parse = String, with = text, model = MDT693B)]
+    pub struct GetSerialNumber;
+    #[derive(InstrumentQuery)]
+    #[query("cm?", parse = bool, with = flag, model = MDT693B)]
+    pub struct GetCompatibility;
+    ///0, 1 or -1
+    #[derive(InstrumentQuery)]
+    #[query("rotarymode?", parse = i8, with = value, model = MDT693B)]
+    pub struct GetRotaryMode;
+    #[derive(InstrumentQuery)]
+    #[query("disablepush?", parse = bool, with = flag, model = MDT693B)]
+    pub struct GetDisableRotaryPushToAdjust;
+}
+
 #[test]
-fn stress_serial() -> Result<(), Box<dyn std::error::Error>> {
+fn stress_serial() -> std::result::Result<(), Box<dyn std::error::Error>> {
     let mut handler = MDT693B::default_connect(5)?;
-    handler.query(Query::GetCommands)?;
+    handler.query(query::GetCommands)?;
     Ok(())
 }
+
+#[test]
+fn parse_responses() -> Result<()> {
+    use super::Query;
+    assert_eq!(query::ReadXVoltage::parse(b"[ 12.5]\r")?, 12.5);
+    assert_eq!(query::ReadXVoltage::parse(b"\r\n[150]")?, 150.0);
+    assert_eq!(query::GetSerialNumber::parse(b"[ 12345]\r")?, "12345");
+    assert_eq!(query::GetRotaryMode::parse(b"[-1]")?, -1);
+    assert!(query::GetEchoCommandValue::parse(b"[1]")?);
+    assert!(query::ReadYVoltage::parse(b"[abc]").is_err());
+    Ok(())
+}
+
+#[test]
+fn parse_identity() -> Result<()> {
+    let id = identity(b"THORLABS MDT693B Piezo Controller\rFirmware Version: 1.09\r")?;
+    assert_eq!(
+        (id.model.as_str(), id.firmware.as_str()),
+        ("MDT693B", "1.09")
+    );
+    assert!(identity(b"CMD_NOT_DEFINED").is_err());
+    Ok(())
+}
+
+#[test]
+fn encode_commands() {
+    use super::Command as _;
+    assert_eq!(Command::SetXVoltage(12.5).to_bytes(), b"xvoltage=12.5");
+    assert_eq!(Command::SetMasterScanEnable(true).to_bytes(), b"msenable=1");
+    assert_eq!(Command::SetEchoCommand(false).to_bytes(), b"echo=false");
+    assert_eq!(Command::IncrementVoltage.to_bytes(), b"\x1b[A");
+    assert_eq!(Command::DOCS[1].syntax, "intensity={}");
+    assert_eq!(Command::DOCS[1].doc, "0-15");
+}
@@ -1,8 +1,14 @@
+pub mod framing;
+pub mod generic;
 pub mod infiniium;
 pub mod mdt693_b;
+pub mod registry;
+pub mod rigol;
 
-use crate::protocols::Protocol;
+use crate::{protocols::Protocol, trace};
 use core::str;
+pub use framing::Framing;
+pub use registry::{Driver, Registry};
 use std::{
     io::{BufRead, BufReader, Error, Read, Write},
     marker::PhantomData,
@@ -15,18 +21,43 @@ pub type Bound<P, ID> =
 pub trait Model {
     const DESCRIPTION: &'static str;
     type Command: Command;
-    type Query: Query;
     const TERMINATOR: u8;
     const END_BYTE: u8;
+    ///how responses end, up to `END_BYTE` by default, skipping over block data holding it
+    fn framing() -> Framing {
+        Framing::block(Self::END_BYTE)
+    }
 }
 pub trait Command {
     type R: AsRef<[u8]>;
     fn to_bytes(self) -> Self::R;
 }
 
+///a query of `Model`, answered with a `Response`
 pub trait Query {
+    type Model: Model;
     type R: AsRef<[u8]>;
+    type Response;
     fn to_bytes(self) -> Self::R;
+    ///parse one response, as delimited by the framing of the model
+    fn parse(response: &[u8]) -> crate::Result<Self::Response>;
+}
+
+///syntax and description of a message, listed by the derive macros
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageDoc {
+    pub name: &'static str,
+    pub syntax: &'static str,
+    pub doc: &'static str,
+}
+
+///parse a response holding a single value, surrounding whitespace is ignored
+pub fn parse_response<T: str::FromStr>(response: &[u8]) -> crate::Result<T> {
+    let text = str::from_utf8(response)
+        .map_err(|_| format!("response isn't text: {:?}", response))?
+        .trim();
+    text.parse()
+        .map_err(|_| format!("unexpected response '{}'", text).into())
 }
 
 pub struct Messenger<IO: Write + Read> {
@@ -35,7 +66,9 @@ pub struct Messenger<IO: Write + Read> {
 
 impl<IO: Write + Read> Write for Messenger<IO> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.io.write(buf)
+        let n = self.io.write(buf)?;
+        tracing::trace!(len = n, data = %trace::payload(&buf[..n]), "write");
+        Ok(n)
     }
     fn flush(&mut self) -> std::io::Result<()> {
         self.io.flush()
@@ -44,7 +77,9 @@ impl<IO: Write + Read> Write for Messenger<IO> {
 
 impl<IO: Write + Read> Read for Messenger<IO> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.io.read(buf)
+        let n = self.io.read(buf)?;
+        tracing::trace!(len = n, data = %trace::payload(&buf[..n]), "read");
+        Ok(n)
     }
 }
 
@@ -52,11 +87,20 @@ impl<IO: Write + Read> Messenger<IO> {
     pub fn new(io: IO) -> Self {
         Self { io }
     }
+    pub fn get_ref(&self) -> &IO {
+        &self.io
+    }
+    pub fn get_mut(&mut self) -> &mut IO {
+        &mut self.io
+    }
     pub fn bind<M: Model>(self, _model: M) -> Instrument<Self, M> {
         Instrument {
             messenger: BufReader::new(self),
             model: PhantomData,
             buf: Vec::new(),
+            framing: M::framing(),
+            sent: Vec::new(),
+            sent_complete: false,
         }
     }
 }
@@ -65,13 +109,26 @@ pub struct Instrument<IO: Write + Read, M: Model> {
     messenger: BufReader<IO>,
     model: PhantomData<M>,
     buf: Vec<u8>,
+    framing: Framing,
+    ///last message sent, to recognise its echo
+    sent: Vec<u8>,
+    sent_complete: bool,
 }
 
 impl<IO: Write + Read, M: Model> Write for Instrument<IO, M> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.messenger.get_mut().write(buf)
+        let n = self.messenger.get_mut().write(buf)?;
+        if self.framing.echo {
+            if self.sent_complete {
+                self.sent.clear();
+                self.sent_complete = false;
+            }
+            self.sent.extend_from_slice(&buf[..n]);
+        }
+        Ok(n)
     }
     fn flush(&mut self) -> std::io::Result<()> {
+        self.sent_complete = true;
         self.messenger.get_mut().flush()
     }
 }
@@ -93,27 +150,106 @@ impl<IO: Write + Read, M: Model> BufRead for Instrument<IO, M> {
 
 impl<IO: Write + Read, M: Model> Instrument<IO, M> {
     fn terminate_send(&mut self) -> Result<(), Error> {
-        self.write(&[M::TERMINATOR])?;
+        self.write_all(&[M::TERMINATOR])?;
         self.flush()?;
         Ok(())
     }
+    ///the same connection driven as another model, bytes already received are kept
+    pub fn rebind<N: Model>(self, _model: N) -> Instrument<IO, N> {
+        Instrument {
+            messenger: self.messenger,
+            model: PhantomData,
+            buf: self.buf,
+            framing: N::framing(),
+            sent: self.sent,
+            sent_complete: self.sent_complete,
+        }
+    }
+    pub fn get_ref(&self) -> &IO {
+        self.messenger.get_ref()
+    }
+    ///the underlying transport, bytes already buffered by the instrument aren't seen there
+    pub fn get_mut(&mut self) -> &mut IO {
+        self.messenger.get_mut()
+    }
+    ///drop the bytes received and not read yet, e.g. after a device clear
+    pub fn discard_buffered(&mut self) {
+        let buffered = self.messenger.buffer().len();
+        self.messenger.consume(buffered);
+        self.buf.clear();
+    }
+    pub fn framing(&self) -> &Framing {
+        &self.framing
+    }
+    ///replace the framing given by the model
+    pub fn set_framing(&mut self, framing: Framing) -> &mut Self {
+        self.framing = framing;
+        self
+    }
     pub fn command<C: Into<M::Command>>(&mut self, command: C) -> Result<(), Error> {
         let message = Command::to_bytes(command.into());
-        self.write(message.as_ref())?;
+        let _span = tracing::debug_span!(
+            "command",
+            model = M::DESCRIPTION,
+            message = %trace::text(message.as_ref())
+        )
+        .entered();
+        self.write_all(message.as_ref())?;
         self.terminate_send()?;
+        if self.framing.reply_to_commands {
+            self.read_response()?;
+        }
         Ok(())
     }
-    pub fn query<Q: Into<M::Query>>(&mut self, query: Q) -> Result<&[u8], Error> {
-        let message = Query::to_bytes(query.into());
-        self.write(&message.as_ref())?;
+    ///send the query and parse exactly one response, as delimited by the framing
+    pub fn query<Q: Query<Model = M>>(&mut self, query: Q) -> crate::Result<Q::Response> {
+        let message = query.to_bytes();
+        let _span = tracing::debug_span!(
+            "query",
+            model = M::DESCRIPTION,
+            message = %trace::text(message.as_ref())
+        )
+        .entered();
+        let start = std::time::Instant::now();
+        self.write_all(message.as_ref())?;
         self.terminate_send()?;
+        let response = self.read_response()?;
+        tracing::debug!(elapsed = ?start.elapsed(), "answered");
+        Q::parse(response)
+    }
+    ///read one response, without its echo and whatever the framing strips
+    pub fn read_response(&mut self) -> Result<&[u8], Error> {
         self.buf.clear();
-        self.messenger.read_until(M::END_BYTE, &mut self.buf)?;
-        Ok(&self.buf)
+        let sent = if self.framing.echo {
+            &self.sent[..]
+        } else {
+            &[]
+        };
+        let range = loop {
+            let chunk = self.messenger.fill_buf()?;
+            if chunk.is_empty() {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let (received, available) = (self.buf.len(), chunk.len());
+            self.buf.extend_from_slice(chunk);
+            match self.framing.frame(&self.buf, sent) {
+                Some((taken, range)) => {
+                    //what follows belongs to the next response
+                    self.messenger.consume(taken.saturating_sub(received));
+                    self.buf.truncate(taken);
+                    break range;
+                }
+                None => self.messenger.consume(available),
+            }
+        };
+        let response = &self.buf[range];
+        tracing::debug!(len = response.len(), response = %trace::payload(response));
+        Ok(response)
     }
-    
+
     pub fn send_raw<S: AsRef<[u8]>>(&mut self, raw: S) -> Result<(), Error> {
-        self.write(raw.as_ref())?;
+        tracing::debug!(model = M::DESCRIPTION, message = %trace::payload(raw.as_ref()), "send");
+        self.write_all(raw.as_ref())?;
         self.terminate_send()?;
         Ok(())
     }
@@ -122,4 +258,16 @@ impl<IO: Write + Read, M: Model> Instrument<IO, M> {
         self.messenger.read_until(byte, &mut self.buf)?;
         Ok(&self.buf)
     }
-}
\ No newline at end of file
+}
+impl<IO: Write + Read, M: Model> crate::scpi::Scpi for Instrument<Messenger<IO>, M> {
+    const TERM: u8 = M::TERMINATOR;
+    fn write_bin<C: AsRef<[u8]>>(&mut self, content: C) -> crate::Result<()> {
+        self.write_all(content.as_ref())?;
+        self.flush()?;
+        Ok(())
+    }
+    ///one response, as delimited by the framing
+    fn read_bin(&mut self) -> crate::Result<bytes::Bytes> {
+        Ok(bytes::Bytes::copy_from_slice(self.read_response()?))
+    }
+}
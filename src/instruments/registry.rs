@@ -0,0 +1,326 @@
+//! Choose the driver of an instrument from the way it identifies itself.
+//!
+//! Each [`Probe`] is tried in turn until one gets an [`Identity`], e.g. `*IDN?` for SCPI
+//! instruments and `id?` for the Thorlabs piezo controllers. The first driver whose pattern
+//! matches the identity is bound to the connection, [`Generic`] when none does.
+use super::{
+    generic::Generic, infiniium::Infiniium, mdt693_b::MDT693B, rigol::Rigol, Framing, Instrument,
+    Messenger, Model,
+};
+use crate::{
+    protocols::Protocol,
+    scpi::{identity::Vendor, Identity},
+};
+use std::{
+    any::Any,
+    io::{Read, Write},
+    time::Duration,
+};
+
+///an instrument of any model, as returned by a [`Registry`]
+pub trait Driver: Send {
+    fn description(&self) -> &'static str;
+    ///send a message, the terminator of the model is appended
+    fn send(&mut self, message: &[u8]) -> crate::Result<()>;
+    ///read one response, as delimited by the framing of the model
+    fn read(&mut self) -> crate::Result<Vec<u8>>;
+    fn query(&mut self, message: &[u8]) -> crate::Result<Vec<u8>> {
+        self.send(message)?;
+        self.read()
+    }
+    ///the driver itself, an `Instrument<Messenger<IO>, M>`
+    fn as_any(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<IO: Read + Write + Send + 'static, M: Model + Send + 'static> Driver
+    for Instrument<Messenger<IO>, M>
+{
+    fn description(&self) -> &'static str {
+        M::DESCRIPTION
+    }
+    fn send(&mut self, message: &[u8]) -> crate::Result<()> {
+        Ok(self.send_raw(message)?)
+    }
+    fn read(&mut self) -> crate::Result<Vec<u8>> {
+        Ok(self.read_response()?.to_vec())
+    }
+    fn as_any(&mut self) -> &mut dyn Any {
+        self
+    }
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl dyn Driver {
+    ///the driver as `T`, e.g. `Instrument<Messenger<TcpStream>, Infiniium>`
+    pub fn downcast_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.as_any().downcast_mut()
+    }
+}
+
+///a message asking an instrument who it is
+#[derive(Clone, Copy)]
+pub struct Probe {
+    pub message: &'static str,
+    ///how the answer ends
+    pub framing: fn() -> Framing,
+    pub parse: fn(&[u8]) -> crate::Result<Identity>,
+}
+
+impl Probe {
+    ///`*IDN?`, answered by every SCPI instrument
+    pub const IDN: Probe = Probe {
+        message: "*IDN?",
+        framing: Generic::framing,
+        parse: super::parse_response,
+    };
+    ///`id?` of the Thorlabs piezo controllers
+    pub const THORLABS: Probe = Probe {
+        message: "id?",
+        framing: MDT693B::framing,
+        parse: super::mdt693_b::identity,
+    };
+}
+
+///whether a driver fits an identity
+pub type MatchFn = Box<dyn Fn(&Identity) -> bool + Send + Sync>;
+
+struct Entry<IO: Read + Write> {
+    matches: MatchFn,
+    description: &'static str,
+    bind: fn(Instrument<Messenger<IO>, Generic>) -> Box<dyn Driver>,
+}
+
+fn bind<IO: Read + Write + Send + 'static, M: Model + Default + Send + 'static>(
+    instrument: Instrument<Messenger<IO>, Generic>,
+) -> Box<dyn Driver> {
+    Box::new(instrument.rebind(M::default()))
+}
+
+///an identified instrument
+pub struct Detected {
+    pub identity: Identity,
+    pub driver: Box<dyn Driver>,
+}
+
+pub struct Registry<IO: Read + Write> {
+    probes: Vec<Probe>,
+    entries: Vec<Entry<IO>>,
+}
+
+impl<IO: Read + Write + Send + 'static> Registry<IO> {
+    ///no driver and `*IDN?` as the only probe, every instrument is opened as [`Generic`]
+    pub fn new() -> Self {
+        Self {
+            probes: vec![Probe::IDN],
+            entries: Vec::new(),
+        }
+    }
+    ///try `probe` after the ones added before
+    pub fn add_probe(&mut self, probe: Probe) -> &mut Self {
+        self.probes.push(probe);
+        self
+    }
+    ///open the instruments `matches` accepts as `M`, drivers registered earlier take precedence
+    pub fn register<M, F>(&mut self, matches: F) -> &mut Self
+    where
+        M: Model + Default + Send + 'static,
+        F: Fn(&Identity) -> bool + Send + Sync + 'static,
+    {
+        self.entries.push(Entry {
+            matches: Box::new(matches),
+            description: M::DESCRIPTION,
+            bind: bind::<IO, M>,
+        });
+        self
+    }
+    ///the description of the driver an identity would be opened with
+    pub fn description(&self, identity: &Identity) -> &'static str {
+        self.entries
+            .iter()
+            .find(|e| (e.matches)(identity))
+            .map_or(Generic::DESCRIPTION, |e| e.description)
+    }
+    ///ask the instrument on `io` who it is and bind the matching driver, `io` should time out
+    ///reads, a probe left unanswered gives way to the next one
+    pub fn identify(&self, io: IO) -> crate::Result<Detected> {
+        let mut instrument = Messenger::new(io).bind(Generic);
+        let mut failures = Vec::new();
+        for probe in &self.probes {
+            instrument.set_framing((probe.framing)());
+            let answer = instrument
+                .send_raw(probe.message)
+                .and_then(|_| instrument.read_response());
+            let identity = match answer {
+                Ok(answer) => (probe.parse)(answer),
+                Err(e) => Err(e.into()),
+            };
+            match identity {
+                Ok(identity) => {
+                    let driver = match self.entries.iter().find(|e| (e.matches)(&identity)) {
+                        Some(entry) => (entry.bind)(instrument),
+                        None => {
+                            instrument.set_framing(Generic::framing());
+                            Box::new(instrument)
+                        }
+                    };
+                    return Ok(Detected { identity, driver });
+                }
+                Err(e) => failures.push(format!("{}: {}", probe.message, e)),
+            }
+        }
+        Err(format!(
+            "instrument didn't identify itself ({})",
+            failures.join(", ")
+        )
+        .into())
+    }
+    ///connect to `address` and [`identify`](Self::identify) the instrument there
+    pub fn connect<P>(
+        &self,
+        protocol: P,
+        address: P::Address,
+        time_out: Duration,
+    ) -> crate::Result<Detected>
+    where
+        P: Protocol<IO = IO>,
+        crate::error::Error: From<P::Error>,
+    {
+        self.identify(protocol.connect(address, time_out)?)
+    }
+}
+
+impl<IO: Read + Write + Send + 'static> Default for Registry<IO> {
+    ///the drivers of this crate, probing with `*IDN?` then `id?`
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry
+            .add_probe(Probe::THORLABS)
+            .register::<Infiniium, _>(|id| {
+                id.vendor() == Vendor::Keysight
+                    && id.model_starts_with(&[
+                        "DSO9", "DSA9", "MSO9", "DSOX9", "DSAX9", "DSOV", "DSAV", "MSOV", "DSOS",
+                        "MSOS", "DSOZ", "DSAZ", "MSOX9", "UXR",
+                    ])
+            })
+            .register::<MDT693B, _>(|id| {
+                id.vendor() == Vendor::Thorlabs && id.model_starts_with(&["MDT693"])
+            })
+            .register::<Rigol, _>(|id| {
+                id.vendor() == Vendor::Rigol && id.model_starts_with(&["DS", "MSO"])
+            });
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+
+    ///answers each line it receives with `answer(line)`
+    struct Device {
+        answer: fn(&str) -> String,
+        line: Vec<u8>,
+        out: Cursor<Vec<u8>>,
+    }
+
+    impl Device {
+        fn new(answer: fn(&str) -> String) -> Self {
+            Self {
+                answer,
+                line: Vec::new(),
+                out: Cursor::new(Vec::new()),
+            }
+        }
+    }
+
+    impl Write for Device {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for b in buf {
+                if *b == b'\n' {
+                    let answer = (self.answer)(&String::from_utf8_lossy(&self.line));
+                    let position = self.out.position();
+                    self.out.get_mut().drain(..position as usize);
+                    self.out.get_mut().extend_from_slice(answer.as_bytes());
+                    self.out.set_position(0);
+                    self.line.clear();
+                } else {
+                    self.line.push(*b);
+                }
+            }
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Device {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            //an unanswered read times out instead of ending the stream
+            match self.out.read(buf)? {
+                0 => Err(io::ErrorKind::TimedOut.into()),
+                n => Ok(n),
+            }
+        }
+    }
+
+    fn scope(line: &str) -> String {
+        match line {
+            "*IDN?" => "KEYSIGHT TECHNOLOGIES,DSOS254A,MY55160101,06.40.01101\n".into(),
+            ":ACQuire:SRATe:ANALog?" => "+2.50000E+08\n".into(),
+            other => panic!("unexpected message {}", other),
+        }
+    }
+
+    fn piezo(line: &str) -> String {
+        match line {
+            "id?" => format!(
+                "{}\rTHORLABS MDT693B Piezo Controller\rFirmware Version: 1.09\r>",
+                line
+            ),
+            "xvoltage?" => format!("{}\r[ 12.5]\r>", line),
+            other => format!("{}\rCMD_NOT_DEFINED\r>", other),
+        }
+    }
+
+    fn unknown(line: &str) -> String {
+        match line {
+            "*IDN?" => "ACME,X1,42,1.0\n".into(),
+            _ => "1\n".into(),
+        }
+    }
+
+    #[test]
+    fn drivers_are_chosen_by_identity() -> crate::Result<()> {
+        let registry = Registry::default();
+        let mut detected = registry.identify(Device::new(scope))?;
+        assert_eq!(detected.identity.model, "DSOS254A");
+        assert_eq!(detected.driver.description(), Infiniium::DESCRIPTION);
+        let scope = detected
+            .driver
+            .downcast_mut::<Instrument<Messenger<Device>, Infiniium>>()
+            .unwrap();
+        assert_eq!(scope.query(super::super::infiniium::SampleRate)?, 2.5e8);
+
+        //`*IDN?` is left unanswered, `id?` is not
+        let mut detected = registry.identify(Device::new(piezo))?;
+        assert_eq!(detected.identity.manufacturer, "THORLABS");
+        assert_eq!(detected.driver.description(), MDT693B::DESCRIPTION);
+        assert_eq!(detected.driver.query(b"xvoltage?")?, b"[ 12.5]\r");
+
+        let mut detected = registry.identify(Device::new(unknown))?;
+        assert_eq!(detected.driver.description(), Generic::DESCRIPTION);
+        assert_eq!(detected.driver.query(b"MEAS?")?, b"1\n");
+        assert_eq!(
+            registry.description(&detected.identity),
+            Generic::DESCRIPTION
+        );
+
+        assert!(Registry::new().identify(Device::new(piezo)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,80 @@
+//! Rigol oscilloscopes (DS1000Z, DS2000, MSO5000 ...), reachable on [`RIGOL_PORT`](crate::protocols::socket::RIGOL_PORT).
+use super::Model;
+use crate::{scpi::Identity, waveform::Preamble, InstrumentCommand, InstrumentQuery};
+
+#[derive(Default)]
+pub struct Rigol;
+
+impl crate::DefaultConfig for Rigol {
+    type DefaultProtocol = crate::protocols::Tcp;
+    const DEFAULT_PROTOCOL: crate::protocols::Tcp = crate::protocols::Tcp;
+}
+
+impl Model for Rigol {
+    const DESCRIPTION: &'static str = "Rigol oscilloscopes";
+    type Command = Command;
+    const END_BYTE: u8 = b'\n';
+    const TERMINATOR: u8 = b'\n';
+}
+
+#[derive(InstrumentCommand)]
+pub enum Command {
+    #[cmd(":RUN")]
+    Run,
+    #[cmd(":STOP")]
+    Stop,
+    #[cmd(":SINGle")]
+    Single,
+    #[cmd(":AUToscale")]
+    AutoScale,
+    ///clear the waveforms on screen
+    #[cmd(":CLEar")]
+    Clear,
+    ///source of the waveform read, 1-4
+    #[cmd(":WAVeform:SOURce CHANnel{}")]
+    WaveformSource(u8),
+    ///waveform read in ASCII, as comma separated voltages
+    #[cmd(":WAVeform:FORMat ASCii")]
+    WaveformAscii,
+}
+
+#[derive(InstrumentQuery)]
+#[query("*IDN?", parse = Identity, model = Rigol)]
+pub struct Identify;
+///sample rate, Sa/s
+#[derive(InstrumentQuery)]
+#[query(":ACQuire:SRATe?", parse = f64, model = Rigol)]
+pub struct SampleRate;
+///the waveform of the source, in ASCII format, preceded by a definite length block header
+#[derive(InstrumentQuery)]
+#[query(":WAVeform:DATA?", parse = Vec<f32>, with = values, model = Rigol)]
+pub struct WaveformData;
+///scaling of the waveform read, see [`Waveform::from_data`](crate::waveform::Waveform::from_data)
+#[derive(InstrumentQuery)]
+#[query(":WAVeform:PREamble?", parse = Preamble, with = Preamble::rigol, model = Rigol)]
+pub struct WaveformPreamble;
+
+fn values(response: &[u8]) -> crate::Result<Vec<f32>> {
+    let text = std::str::from_utf8(response)
+        .map_err(|_| "waveform data isn't text")?
+        .trim();
+    //`#9000001399` in front of the values
+    let text = match text.as_bytes() {
+        [b'#', n, ..] if n.is_ascii_digit() => &text[2 + (*n - b'0') as usize..],
+        _ => text,
+    };
+    text.split_terminator(',')
+        .map(|x| super::parse_response(x.as_bytes()))
+        .collect()
+}
+
+#[test]
+fn parse_responses() -> crate::Result<()> {
+    use super::Query;
+    assert_eq!(
+        WaveformData::parse(b"#9000000019-1.200e-02,4.0e-02,\n")?,
+        vec![-0.012, 0.04]
+    );
+    assert_eq!(SampleRate::parse(b"1.000000e+09\n")?, 1e9);
+    Ok(())
+}
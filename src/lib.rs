@@ -1,14 +1,28 @@
-use error::other_error;
 use instruments::{mdt693_b::MDT693B, Instrument, Messenger, Model};
 use protocols::{Protocol, Serial};
 use serial::SerialPort;
-use std::{fmt::Display, time::Duration};
+use std::{
+    fmt::Display,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+pub mod config;
 pub mod error;
 pub mod instruments;
+pub mod logger;
+pub mod manager;
 pub mod protocols;
+pub mod resource;
 pub mod scpi;
+#[cfg(any(test, feature = "test-support"))]
+#[doc(hidden)]
+pub mod test_support;
+pub mod trace;
+pub mod waveform;
 #[macro_use]
 extern crate serde;
+//lets the derive macros refer to `::rustrument` inside this crate too
+extern crate self as rustrument;
+pub use rustrument_derive::{InstrumentCommand, InstrumentQuery};
 pub type Result<T> = std::result::Result<T, error::Error>;
 pub trait DefaultConfig: Model + Sized + Default {
     type DefaultProtocol: Protocol;
@@ -33,6 +47,19 @@ fn config_serial<T: SerialPort>(port: &mut T, config: Serial) -> serial::Result<
     })
 }
 
+///seconds since the Unix epoch with an optional fraction, e.g. `1700000000.25`, digits only and
+///anything past the nanoseconds dropped
+fn parse_unix_time(text: &str) -> Option<SystemTime> {
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    let (seconds, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if seconds.is_empty() || !digits(seconds) || !digits(fraction) {
+        return None;
+    }
+    let nanos = format!("{:0<9}", fraction);
+    let since = Duration::new(seconds.parse().ok()?, nanos.get(..9)?.parse().ok()?);
+    UNIX_EPOCH.checked_add(since)
+}
+
 pub struct PiezoController {
     x_voltage: f32,
     y_voltage: f32,
@@ -43,33 +70,8 @@ pub struct PiezoController {
 }
 
 impl PiezoController {
-    fn extract_num(message: &[u8]) -> Result<f32> {
-        Ok(std::str::from_utf8({
-            let temp = message
-                .split(|x| *x == b'\n' || *x == b'\r')
-                .last()
-                .ok_or(other_error("no line received"))?;
-            let mut iter = temp
-                .iter()
-                .enumerate()
-                .skip_while(|x| !(*(*x).1 as char).is_numeric())
-                .take_while(|x| *(*x).1 != b']');
-            if let Some((start, _)) = iter.next() {
-                if let Some((end, _)) = iter.last() {
-                    &temp[start..end + 1]
-                } else {
-                    &temp[start..start + 1]
-                }
-            } else {
-                return Err("no number found".into());
-            }
-        })?
-        .parse()
-        .unwrap())
-    }
-
     pub fn update(&mut self) -> Result<()> {
-        use instruments::mdt693_b::Query;
+        use instruments::mdt693_b::query;
         if self.flag == (true, true, true) {
             return Ok(());
         } else {
@@ -80,21 +82,21 @@ impl PiezoController {
                 && std::time::Instant::now() - self.time_set
                     <= std::time::Duration::new(0, 1000_000_000)
             {
-                self.x_voltage = Self::extract_num(self.messenger.query(Query::ReadXVoltage)?)?;
-                self.y_voltage = Self::extract_num(self.messenger.query(Query::ReadYVoltage)?)?;
-                self.z_voltage = Self::extract_num(self.messenger.query(Query::ReadZVoltage)?)?;
+                self.x_voltage = self.messenger.query(query::ReadXVoltage)?;
+                self.y_voltage = self.messenger.query(query::ReadYVoltage)?;
+                self.z_voltage = self.messenger.query(query::ReadZVoltage)?;
             }
             self.flag = (true, true, true);
             Ok(())
         }
     }
     pub fn new(address: <Serial as Protocol>::Address) -> Result<Self> {
-        use instruments::mdt693_b::Query;
+        use instruments::mdt693_b::query;
 
-        let mut messenger = instruments::mdt693_b::MDT693B::default_connect(address).unwrap();
-        let x_voltage = Self::extract_num(messenger.query(Query::ReadXVoltage)?)?;
-        let y_voltage = Self::extract_num(messenger.query(Query::ReadYVoltage)?)?;
-        let z_voltage = Self::extract_num(messenger.query(Query::ReadZVoltage)?)?;
+        let mut messenger = instruments::mdt693_b::MDT693B::default_connect(address)?;
+        let x_voltage = messenger.query(query::ReadXVoltage)?;
+        let y_voltage = messenger.query(query::ReadYVoltage)?;
+        let z_voltage = messenger.query(query::ReadZVoltage)?;
         Ok(Self {
             x_voltage,
             y_voltage,
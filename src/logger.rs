@@ -0,0 +1,1139 @@
+//! Periodic logging of instrument readings, e.g. piezo voltages, powers and temperatures over
+//! days.
+//!
+//! A [`Logger`] polls its [`Channel`]s, each a reading of an instrument shared through a
+//! [`Handle`], every interval of the channel:
+//!
+//! ```no_run
+//! # use rustrument::{logger::*, manager::Handle, PiezoController, Result};
+//! # use std::time::Duration;
+//! # fn main() -> Result<()> {
+//! let piezo = Handle::connect_later("piezo", || PiezoController::new(5));
+//! let mut logger = Logger::new(Files::new("logs", "lab", Format::Csv));
+//! logger.add(Channel::new("x", &piezo, Duration::from_secs(1), PiezoController::rt_x));
+//! let running = logger.start()?;
+//! # running.stop()
+//! # }
+//! ```
+//!
+//! Every instrument is polled by a thread of its own so a slow one doesn't hold the others back.
+//! A failed or panicking reading is recorded with its error and tried again at the next interval,
+//! the handle connecting again when the connection was lost. Intervals passed without a reading,
+//! while an instrument was too slow or the logger wasn't running, are recorded as missed.
+//!
+//! Samples go to rotating [`Files`], CSV or a compact binary format starting with [`MAGIC`].
+//! The binary format is a row log, one tagged record per sample in the order they were taken:
+//! it is neither columnar nor self-describing and no Parquet reader opens it, convert it with
+//! [`read`] when columns are needed. Started again, the logger appends to the latest file and
+//! records what was missed since.
+use crate::{
+    config::Lab,
+    instruments::{parse_response, Driver},
+    manager::Handle,
+    scpi::Scpi,
+    Result,
+};
+use std::{
+    any::Any,
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Condvar, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+///start of a binary log, followed by the version
+pub const MAGIC: &[u8; 4] = b"RSTL";
+const VERSION: u8 = 1;
+const BINARY_HEADER: [u8; 5] = [MAGIC[0], MAGIC[1], MAGIC[2], MAGIC[3], VERSION];
+const CSV_HEADER: &str = "time,channel,value,missed,error\n";
+///the shortest interval between two readings of a channel
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reading {
+    Value(f64),
+    ///the reading failed
+    Error(String),
+    ///that many intervals passed without a reading, from the time of the sample
+    Missed(u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub time: SystemTime,
+    pub channel: String,
+    pub reading: Reading,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    ///`time,channel,value,missed,error` rows, the time in seconds since the Unix epoch
+    #[default]
+    Csv,
+    ///a row of typed records after [`MAGIC`], about 20 bytes a value; not Parquet, nor columnar
+    Binary,
+}
+
+impl Format {
+    ///what a file of this format starts with
+    fn header(&self) -> &'static [u8] {
+        match self {
+            Format::Csv => CSV_HEADER.as_bytes(),
+            Format::Binary => &BINARY_HEADER,
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Binary => "bin",
+        }
+    }
+}
+
+type Read = Box<dyn FnMut() -> Result<f64> + Send>;
+
+///a reading of an instrument, taken every `interval`
+pub struct Channel {
+    name: String,
+    instrument: String,
+    interval: Duration,
+    read: Read,
+}
+
+impl Channel {
+    ///`read` the instrument of `handle`, e.g. `PiezoController::rt_x` or
+    ///`|scope| scope.query(SampleRate)`
+    pub fn new<S, T, R, F>(name: S, handle: &Handle<T>, interval: Duration, mut read: F) -> Self
+    where
+        S: Into<String>,
+        T: Send + 'static,
+        R: Into<f64>,
+        F: FnMut(&mut T) -> Result<R> + Send + 'static,
+    {
+        let handle = handle.clone();
+        Self {
+            name: name.into(),
+            instrument: handle.name().to_string(),
+            interval: interval.max(MIN_INTERVAL),
+            read: Box::new(move || handle.transaction(|t| read(t)).map(Into::into)),
+        }
+    }
+    ///send `query`, e.g. `*STB?`, and read a number back
+    pub fn scpi<S, T, Q>(name: S, handle: &Handle<T>, interval: Duration, query: Q) -> Self
+    where
+        S: Into<String>,
+        T: Scpi + Send + 'static,
+        Q: Into<String>,
+    {
+        let query = query.into();
+        Self::new(name, handle, interval, move |t: &mut T| {
+            parse_response::<f64>(t.scpi_query(&query)?.as_bytes())
+        })
+    }
+    ///same as [`scpi`](Self::scpi) through a driver, e.g. opened from a lab configuration
+    pub fn driver<S, Q>(
+        name: S,
+        handle: &Handle<Box<dyn Driver>>,
+        interval: Duration,
+        query: Q,
+    ) -> Self
+    where
+        S: Into<String>,
+        Q: Into<String>,
+    {
+        let query = query.into();
+        Self::new(name, handle, interval, move |d: &mut Box<dyn Driver>| {
+            parse_response::<f64>(&d.query(query.as_bytes())?)
+        })
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+fn unix(time: SystemTime) -> Duration {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+fn invalid<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+///the fields of a row, quotes removed
+fn csv_fields(line: &str) -> Vec<String> {
+    let (mut fields, mut field, mut quoted) = (Vec::new(), String::new(), false);
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn parse_time(text: &str) -> io::Result<SystemTime> {
+    crate::parse_unix_time(text).ok_or_else(|| invalid(format!("invalid time '{}'", text)))
+}
+
+fn encode_csv(sample: &Sample) -> String {
+    let since = unix(sample.time);
+    let (value, missed, error) = match &sample.reading {
+        Reading::Value(v) => (v.to_string(), String::new(), String::new()),
+        Reading::Missed(n) => (String::new(), n.to_string(), String::new()),
+        //a row per line
+        Reading::Error(e) => (String::new(), String::new(), e.replace(['\n', '\r'], " ")),
+    };
+    format!(
+        "{}.{:09},{},{},{},{}\n",
+        since.as_secs(),
+        since.subsec_nanos(),
+        csv_field(&sample.channel),
+        value,
+        missed,
+        csv_field(&error)
+    )
+}
+
+fn decode_csv(line: &str) -> io::Result<Sample> {
+    let fields = csv_fields(line);
+    if fields.len() != 5 {
+        return Err(invalid(format!("expected 5 fields in '{}'", line)));
+    }
+    let reading = match (&*fields[2], &*fields[3]) {
+        ("", "") => Reading::Error(fields[4].clone()),
+        ("", missed) => Reading::Missed(missed.parse().map_err(invalid)?),
+        (value, _) => Reading::Value(value.parse().map_err(invalid)?),
+    };
+    Ok(Sample {
+        time: parse_time(&fields[0])?,
+        channel: fields[1].clone(),
+        reading,
+    })
+}
+
+///the samples of a CSV log and the length of its complete lines, a line cut short by a crash
+///is left out
+fn read_csv(data: &[u8]) -> io::Result<(Vec<Sample>, usize)> {
+    let complete = data.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    if complete == 0 && !CSV_HEADER.as_bytes().starts_with(data) {
+        return Err(invalid("not a log, the header doesn't match"));
+    }
+    let text = std::str::from_utf8(&data[..complete]).map_err(invalid)?;
+    let mut lines = text.lines();
+    if lines
+        .next()
+        .is_some_and(|header| header.trim_end() != CSV_HEADER.trim_end())
+    {
+        return Err(invalid("not a log, the header doesn't match"));
+    }
+    Ok((lines.map(decode_csv).collect::<io::Result<_>>()?, complete))
+}
+
+///binary records, each a tag byte and the channel id
+mod tag {
+    ///the id of a channel name, before its first sample
+    pub const CHANNEL: u8 = 0;
+    pub const VALUE: u8 = 1;
+    pub const MISSED: u8 = 2;
+    pub const ERROR: u8 = 3;
+}
+
+fn encode_binary(sample: &Sample, id: u16) -> Vec<u8> {
+    let mut record = Vec::with_capacity(19);
+    let tag = match sample.reading {
+        Reading::Value(_) => tag::VALUE,
+        Reading::Missed(_) => tag::MISSED,
+        Reading::Error(_) => tag::ERROR,
+    };
+    record.push(tag);
+    record.extend_from_slice(&id.to_le_bytes());
+    record.extend_from_slice(&(unix(sample.time).as_nanos() as u64).to_le_bytes());
+    match &sample.reading {
+        Reading::Value(v) => record.extend_from_slice(&v.to_le_bytes()),
+        Reading::Missed(n) => record.extend_from_slice(&n.to_le_bytes()),
+        Reading::Error(e) => text(&mut record, e),
+    }
+    record
+}
+
+///u16 length and UTF-8, cut on a character boundary
+fn text(record: &mut Vec<u8>, text: &str) {
+    let mut end = text.len().min(u16::MAX as usize);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    record.extend_from_slice(&(end as u16).to_le_bytes());
+    record.extend_from_slice(&text.as_bytes()[..end]);
+}
+
+///reads the fields of binary records, `None` at a record cut short
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    fn text(&mut self) -> Option<io::Result<String>> {
+        let len = self.u16()? as usize;
+        Some(String::from_utf8(self.take(len)?.to_vec()).map_err(invalid))
+    }
+}
+
+///the samples of a binary log, the channels by id and the length of its complete records
+fn read_binary(data: &[u8]) -> io::Result<(Vec<Sample>, Vec<String>, usize)> {
+    if data.len() < MAGIC.len() + 1 || &data[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a binary log"));
+    }
+    if data[MAGIC.len()] != VERSION {
+        return Err(invalid(format!("log version {}", data[MAGIC.len()])));
+    }
+    let (mut samples, mut channels) = (Vec::new(), Vec::new());
+    let mut cursor = Cursor(&data[MAGIC.len() + 1..]);
+    loop {
+        let complete = data.len() - cursor.0.len();
+        let record = (|| {
+            let tag = cursor.take(1)?[0];
+            let id = cursor.u16()? as usize;
+            if tag == tag::CHANNEL {
+                return Some(cursor.text()?.map(|name| {
+                    channels.push(name);
+                    None
+                }));
+            }
+            let time = UNIX_EPOCH + Duration::from_nanos(cursor.u64()?);
+            let reading = match tag {
+                tag::VALUE => Reading::Value(f64::from_bits(cursor.u64()?)),
+                tag::MISSED => Reading::Missed(cursor.u64()?),
+                tag::ERROR => match cursor.text()? {
+                    Ok(e) => Reading::Error(e),
+                    Err(e) => return Some(Err(e)),
+                },
+                tag => return Some(Err(invalid(format!("unknown record {}", tag)))),
+            };
+            let channel = match channels.get(id) {
+                Some(channel) => channel.clone(),
+                None => return Some(Err(invalid(format!("unknown channel {}", id)))),
+            };
+            Some(Ok(Some(Sample {
+                time,
+                channel,
+                reading,
+            })))
+        })();
+        match record {
+            Some(Ok(Some(sample))) => samples.push(sample),
+            Some(Ok(None)) => {}
+            Some(Err(e)) => return Err(e),
+            None => return Ok((samples, channels, complete)),
+        }
+    }
+}
+
+///every sample of a log file, `.csv` or `.bin`
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<Sample>> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    let samples = match path.extension().and_then(|e| e.to_str()) {
+        Some("csv") => read_csv(&data)?.0,
+        Some("bin") => read_binary(&data)?.0,
+        _ => {
+            return Err(format!(
+                "{}: unknown log format, expected .csv or .bin",
+                path.display()
+            )
+            .into())
+        }
+    };
+    Ok(samples)
+}
+
+///the file written to
+struct Current {
+    file: BufWriter<File>,
+    path: PathBuf,
+    bytes: u64,
+    ///time of the first sample
+    since: Option<SystemTime>,
+    ///ids of the channels in a binary log
+    channels: HashMap<String, u16>,
+}
+
+///numbered log files, `<prefix>-00000.csv`, `<prefix>-00001.csv`..., a new one started when
+///the current one is too big or too old
+pub struct Files {
+    directory: PathBuf,
+    prefix: String,
+    format: Format,
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    current: Option<Current>,
+}
+
+impl Files {
+    pub fn new<D: Into<PathBuf>, S: Into<String>>(directory: D, prefix: S, format: Format) -> Self {
+        Self {
+            directory: directory.into(),
+            prefix: prefix.into(),
+            format,
+            max_bytes: None,
+            max_age: None,
+            current: None,
+        }
+    }
+    ///start a new file once this size is reached
+    pub fn set_max_bytes(&mut self, bytes: u64) -> &mut Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+    ///start a new file for samples that long after the first one of the current file
+    pub fn set_max_age(&mut self, age: Duration) -> &mut Self {
+        self.max_age = Some(age);
+        self
+    }
+    ///the file written to
+    pub fn path(&self) -> Option<&Path> {
+        self.current.as_ref().map(|c| c.path.as_path())
+    }
+    fn file(&self, index: u32) -> PathBuf {
+        self.directory.join(format!(
+            "{}-{:05}.{}",
+            self.prefix,
+            index,
+            self.format.extension()
+        ))
+    }
+    ///the numbers of the files in the directory, in order
+    fn indices(&self) -> Result<Vec<u32>> {
+        let mut indices = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let name = entry?.file_name();
+            let index = name
+                .to_str()
+                .and_then(|n| n.strip_prefix(&self.prefix)?.strip_prefix('-'))
+                .and_then(|n| n.strip_suffix(self.format.extension())?.strip_suffix('.'))
+                .and_then(|n| n.parse::<u32>().ok());
+            indices.extend(index);
+        }
+        indices.sort_unstable();
+        Ok(indices)
+    }
+    ///append to the latest file, after its last complete sample, and give the time of the last
+    ///reading of every channel in it, a header cut short is written again
+    pub fn resume(&mut self) -> Result<BTreeMap<String, SystemTime>> {
+        fs::create_dir_all(&self.directory)?;
+        let path = match self.indices()?.last() {
+            Some(index) => self.file(*index),
+            None => return Ok(BTreeMap::new()),
+        };
+        let mut data = fs::read(&path)?;
+        let header = self.format.header();
+        if data.len() < header.len() && header.starts_with(&data) {
+            //a crash or a full disk right after the file was created
+            tracing::warn!(file = %path.display(), "writing the header cut short again");
+            fs::write(&path, header)?;
+            data = header.to_vec();
+        }
+        let (samples, channels, complete) = match self.format {
+            Format::Csv => {
+                let (samples, complete) = read_csv(&data)?;
+                (samples, Vec::new(), complete)
+            }
+            Format::Binary => read_binary(&data)?,
+        };
+        let file = OpenOptions::new().write(true).open(&path)?;
+        file.set_len(complete as u64)?;
+        let mut file = BufWriter::new(file);
+        io::Seek::seek(&mut file, io::SeekFrom::End(0))?;
+        let mut last = BTreeMap::new();
+        for sample in &samples {
+            if !matches!(sample.reading, Reading::Missed(_)) {
+                last.insert(sample.channel.clone(), sample.time);
+            }
+        }
+        if complete < data.len() {
+            tracing::warn!(file = %path.display(), "dropped a sample cut short");
+        }
+        self.current = Some(Current {
+            file,
+            path,
+            bytes: complete as u64,
+            since: samples.first().map(|s| s.time),
+            channels: channels
+                .into_iter()
+                .enumerate()
+                .map(|(id, name)| (name, id as u16))
+                .collect(),
+        });
+        Ok(last)
+    }
+    ///start the next file, never overwriting one
+    fn rotate(&mut self) -> Result<()> {
+        if let Some(mut current) = self.current.take() {
+            current.file.flush()?;
+        }
+        fs::create_dir_all(&self.directory)?;
+        let mut index = self.indices()?.last().map_or(0, |i| i + 1);
+        let (file, path) = loop {
+            let path = self.file(index);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => break (file, path),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => index += 1,
+                Err(e) => return Err(e.into()),
+            }
+        };
+        let mut file = BufWriter::new(file);
+        let header = self.format.header();
+        file.write_all(header)?;
+        tracing::debug!(file = %path.display(), "new log file");
+        self.current = Some(Current {
+            file,
+            path,
+            bytes: header.len() as u64,
+            since: None,
+            channels: HashMap::new(),
+        });
+        Ok(())
+    }
+    pub fn write(&mut self, sample: &Sample) -> Result<()> {
+        let full = self.current.as_ref().is_none_or(|c| {
+            let too_old = c.since.zip(self.max_age).is_some_and(|(since, age)| {
+                sample.time.duration_since(since).unwrap_or_default() >= age
+            });
+            too_old || self.max_bytes.is_some_and(|max| c.bytes >= max)
+        });
+        if full {
+            self.rotate()?;
+        }
+        let current = self.current.as_mut().unwrap();
+        let record = match self.format {
+            Format::Csv => encode_csv(sample).into_bytes(),
+            Format::Binary => {
+                let next = current.channels.len() as u16;
+                let mut record = Vec::new();
+                let id = *current
+                    .channels
+                    .entry(sample.channel.clone())
+                    .or_insert_with(|| {
+                        record.push(tag::CHANNEL);
+                        record.extend_from_slice(&next.to_le_bytes());
+                        text(&mut record, &sample.channel);
+                        next
+                    });
+                record.extend(encode_binary(sample, id));
+                record
+            }
+        };
+        current.file.write_all(&record)?;
+        current.bytes += record.len() as u64;
+        current.since.get_or_insert(sample.time);
+        Ok(())
+    }
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(current) = &mut self.current {
+            current.file.flush()?;
+        }
+        Ok(())
+    }
+}
+
+///whether the logger was stopped, waking the pollers when it is
+#[derive(Default)]
+struct Stop {
+    stopped: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl Stop {
+    ///wait for `deadline`, true when stopped before
+    fn wait_until(&self, deadline: Instant) -> bool {
+        let mut stopped = self.stopped.lock().unwrap_or_else(PoisonError::into_inner);
+        while !*stopped {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            stopped = self
+                .wake
+                .wait_timeout(stopped, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        true
+    }
+    fn stop(&self) {
+        *self.stopped.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        self.wake.notify_all();
+    }
+}
+
+///intervals of `interval` passed in `elapsed` without a reading, the last one being due now
+fn missed(elapsed: Duration, interval: Duration) -> u64 {
+    (elapsed.as_nanos() / interval.as_nanos()) as u64
+}
+
+///take the readings of the channels of one instrument until stopped
+fn poll(
+    mut channels: Vec<Channel>,
+    last: BTreeMap<String, SystemTime>,
+    samples: mpsc::Sender<Sample>,
+    stop: Arc<Stop>,
+) {
+    let now = SystemTime::now();
+    for channel in &channels {
+        if let Some(last) = last.get(&channel.name) {
+            //the first reading, taken now, is the late one of the next interval
+            let elapsed = now.duration_since(*last).unwrap_or_default();
+            let count = missed(elapsed.saturating_sub(channel.interval), channel.interval);
+            if count > 0 {
+                let _ = samples.send(Sample {
+                    time: *last + channel.interval,
+                    channel: channel.name.clone(),
+                    reading: Reading::Missed(count),
+                });
+            }
+        }
+    }
+    let mut next = vec![Instant::now(); channels.len()];
+    loop {
+        let (i, due) = match next.iter().enumerate().min_by_key(|(_, due)| **due) {
+            Some((i, due)) => (i, *due),
+            None => return,
+        };
+        if stop.wait_until(due) {
+            return;
+        }
+        let channel = &mut channels[i];
+        let late = Instant::now().saturating_duration_since(due);
+        let count = missed(late, channel.interval);
+        let time = SystemTime::now();
+        let mut sent = Ok(());
+        if count > 0 {
+            sent = samples.send(Sample {
+                time: time - late,
+                channel: channel.name.clone(),
+                reading: Reading::Missed(count),
+            });
+        }
+        //a panicking reader is retried like a failed one, the handle drops the poisoned connection
+        let reading = match panic::catch_unwind(AssertUnwindSafe(&mut channel.read)) {
+            Ok(Ok(value)) => Reading::Value(value),
+            Ok(Err(e)) => {
+                tracing::warn!(channel = %channel.name, instrument = %channel.instrument, error = %e, "reading failed");
+                Reading::Error(e.to_string())
+            }
+            Err(payload) => {
+                let message = panic_message(payload.as_ref());
+                tracing::error!(channel = %channel.name, instrument = %channel.instrument, panic = %message, "reading panicked");
+                Reading::Error(format!("panicked: {}", message))
+            }
+        };
+        next[i] =
+            due + Duration::from_nanos((channel.interval.as_nanos() * (count as u128 + 1)) as u64);
+        let sample = Sample {
+            time,
+            channel: channel.name.clone(),
+            reading,
+        };
+        if sent.and_then(|_| samples.send(sample)).is_err() {
+            //the writer is gone
+            return;
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+///write the samples as they come, what came meanwhile is flushed together
+fn write(mut files: Files, samples: mpsc::Receiver<Sample>) -> Result<()> {
+    let mut failed = None;
+    while let Ok(sample) = samples.recv() {
+        let written = std::iter::once(sample)
+            .chain(samples.try_iter())
+            .try_for_each(|s| files.write(&s))
+            .and_then(|_| files.flush());
+        if let Err(e) = written {
+            tracing::error!(error = %e, "can't write the log");
+            failed.get_or_insert(e);
+        }
+    }
+    failed.map_or(Ok(()), Err)
+}
+
+///the channels to poll and the files to write
+pub struct Logger {
+    files: Files,
+    channels: Vec<Channel>,
+}
+
+impl Logger {
+    pub fn new(files: Files) -> Self {
+        Self {
+            files,
+            channels: Vec::new(),
+        }
+    }
+    pub fn add(&mut self, channel: Channel) -> &mut Self {
+        self.channels.push(channel);
+        self
+    }
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+    ///resume the files and poll the channels, on a thread per instrument, until stopped
+    pub fn start(mut self) -> Result<Running> {
+        let last = self.files.resume()?;
+        let mut instruments: BTreeMap<String, Vec<Channel>> = BTreeMap::new();
+        for channel in self.channels {
+            instruments
+                .entry(channel.instrument.clone())
+                .or_default()
+                .push(channel);
+        }
+        let stop = Arc::new(Stop::default());
+        let (sender, receiver) = mpsc::channel();
+        let pollers = instruments
+            .into_values()
+            .map(|channels| {
+                let (last, sender, stop) = (last.clone(), sender.clone(), stop.clone());
+                thread::spawn(move || poll(channels, last, sender, stop))
+            })
+            .collect();
+        let files = self.files;
+        Ok(Running {
+            stop,
+            pollers,
+            writer: Some(thread::spawn(move || write(files, receiver))),
+        })
+    }
+}
+
+///a logger polling its channels, see [`Logger::start`], dropping it stops the polling without
+///waiting for the threads
+pub struct Running {
+    stop: Arc<Stop>,
+    pollers: Vec<JoinHandle<()>>,
+    writer: Option<JoinHandle<Result<()>>>,
+}
+
+impl Running {
+    ///stop polling once the readings under way are done, and write them, the first error
+    ///writing the files is returned
+    pub fn stop(mut self) -> Result<()> {
+        self.stop.stop();
+        for poller in self.pollers.drain(..) {
+            poller
+                .join()
+                .map_err(|_| "a thread polling the instruments panicked")?;
+        }
+        match self.writer.take() {
+            Some(writer) => writer
+                .join()
+                .map_err(|_| "the thread writing the log panicked")?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.stop.stop();
+    }
+}
+
+fn default_prefix() -> String {
+    "log".to_string()
+}
+
+///the `logger` table of a lab configuration
+///
+/// ```toml
+/// [logger]
+/// directory = "logs"
+/// format = "csv"
+/// max_bytes = 10_000_000
+/// max_age_s = 86400
+///
+/// [logger.channels.piezo_x]
+/// instrument = "piezo"
+/// query = "XR?"
+/// interval_ms = 1000
+///
+/// [logger.channels.status]
+/// instrument = "scope"
+/// query = "*STB?"
+/// interval_ms = 10000
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggerConfig {
+    ///relative to the working directory
+    pub directory: PathBuf,
+    ///start of the file names
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    #[serde(default)]
+    pub format: Format,
+    pub max_bytes: Option<u64>,
+    pub max_age_s: Option<u64>,
+    pub channels: BTreeMap<String, ChannelConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelConfig {
+    ///the name of an instrument of the lab
+    pub instrument: String,
+    ///answered with a number
+    pub query: String,
+    pub interval_ms: u64,
+}
+
+impl LoggerConfig {
+    pub fn files(&self) -> Files {
+        let mut files = Files::new(&self.directory, self.prefix.clone(), self.format);
+        if let Some(bytes) = self.max_bytes {
+            files.set_max_bytes(bytes);
+        }
+        if let Some(age) = self.max_age_s {
+            files.set_max_age(Duration::from_secs(age));
+        }
+        files
+    }
+    ///the logger of the channels, each instrument connected at the first reading and again
+    ///whenever the connection is lost
+    pub fn logger(&self, lab: &Lab) -> Result<Logger> {
+        let mut handles = BTreeMap::new();
+        let mut logger = Logger::new(self.files());
+        for (name, channel) in &self.channels {
+            let handle = match handles.get(&channel.instrument) {
+                Some(handle) => handle,
+                None => {
+                    let config = lab.get(&channel.instrument)?.clone();
+                    let handle =
+                        Handle::connect_later(channel.instrument.clone(), move || config.open());
+                    handles.entry(channel.instrument.clone()).or_insert(handle)
+                }
+            };
+            logger.add(Channel::driver(
+                name.clone(),
+                handle,
+                Duration::from_millis(channel.interval_ms),
+                channel.query.clone(),
+            ));
+        }
+        Ok(logger)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn directory(test: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("rustrument-logger-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    fn sample(seconds: f64, channel: &str, reading: Reading) -> Sample {
+        Sample {
+            time: UNIX_EPOCH + Duration::from_secs_f64(1_700_000_000.0 + seconds),
+            channel: channel.to_string(),
+            reading,
+        }
+    }
+
+    #[test]
+    fn files_rotate_and_resume() -> Result<()> {
+        for format in [Format::Csv, Format::Binary] {
+            let samples = vec![
+                sample(0.0, "piezo x", Reading::Value(12.5)),
+                sample(0.25, "power", Reading::Value(-3.2e-6)),
+                sample(
+                    1.0,
+                    "piezo x",
+                    Reading::Error("timed out, \"XR?\"".to_string()),
+                ),
+                sample(2.0, "piezo x", Reading::Missed(3)),
+                sample(5.0, "piezo x", Reading::Value(12.75)),
+                sample(5.5, "power", Reading::Value(1.0)),
+            ];
+            let path = directory(format.extension());
+            let mut files = Files::new(&path, "lab", format);
+            files.set_max_age(Duration::from_secs(5));
+            for s in &samples {
+                files.write(s)?;
+            }
+            files.flush()?;
+            let first = path.join(format!("lab-00000.{}", format.extension()));
+            let second = path.join(format!("lab-00001.{}", format.extension()));
+            assert_eq!(files.path(), Some(second.as_path()));
+            assert_eq!(read(&first)?, samples[..4]);
+            assert_eq!(read(&second)?, samples[4..]);
+            drop(files);
+
+            //a crash in the middle of a sample
+            let cut: &[u8] = match format {
+                Format::Csv => b"1700000006.5,pie",
+                Format::Binary => &[tag::VALUE, 0, 0, 1],
+            };
+            OpenOptions::new()
+                .append(true)
+                .open(&second)?
+                .write_all(cut)?;
+            let mut files = Files::new(&path, "lab", format);
+            let last = files.resume()?;
+            assert_eq!(last["piezo x"], samples[4].time);
+            assert_eq!(last["power"], samples[5].time);
+            let more = sample(6.0, "temperature", Reading::Value(21.0));
+            files.write(&more)?;
+            files.write(&samples[0])?;
+            files.flush()?;
+            assert_eq!(
+                read(&second)?,
+                [&samples[4..], &[more, samples[0].clone()]].concat()
+            );
+
+            //never overwritten, even without resuming
+            let mut files = Files::new(&path, "lab", format);
+            files.set_max_bytes(1);
+            files.write(&samples[0])?;
+            files.flush()?;
+            assert_eq!(read(&second)?.len(), 4);
+            assert!(files
+                .path()
+                .unwrap()
+                .ends_with(format!("lab-00002.{}", format.extension())));
+            fs::remove_dir_all(&path)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn cut_headers_are_written_again() -> Result<()> {
+        for format in [Format::Csv, Format::Binary] {
+            let header = format.header();
+            for cut in [0, 1, header.len() - 1] {
+                let path = directory(&format!("cut-{}-{}", format.extension(), cut));
+                fs::create_dir_all(&path)?;
+                let file = path.join(format!("lab-00000.{}", format.extension()));
+                fs::write(&file, &header[..cut])?;
+                let mut files = Files::new(&path, "lab", format);
+                assert!(files.resume()?.is_empty());
+                let s = sample(0.0, "x", Reading::Value(1.0));
+                files.write(&s)?;
+                drop(files);
+                assert_eq!(read(&file)?, std::slice::from_ref(&s));
+                //and resumed again afterwards
+                let mut files = Files::new(&path, "lab", format);
+                assert_eq!(files.resume()?["x"], s.time);
+                fs::remove_dir_all(&path)?;
+            }
+        }
+        //anything else isn't taken for a log
+        let path = directory("cut-other");
+        fs::create_dir_all(&path)?;
+        fs::write(path.join("lab-00000.csv"), "date,")?;
+        assert!(Files::new(&path, "lab", Format::Csv).resume().is_err());
+        fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    ///wait for `done`, for 10 s at most
+    fn wait_for<F: Fn() -> bool>(done: F) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn failures_and_gaps_are_recorded() -> Result<()> {
+        let path = directory("poll");
+        let interval = Duration::from_millis(20);
+        //off at first, then answering with the number of readings
+        let readings = Arc::new(AtomicUsize::new(0));
+        let positions = Arc::new(AtomicUsize::new(0));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let meter = Handle::connect_later("meter", move || {
+            match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => Err("no answer".into()),
+                _ => Ok(()),
+            }
+        });
+        let count = readings.clone();
+        let piezo = Handle::new("piezo", 0_u32);
+        let mut logger = Logger::new(Files::new(&path, "lab", Format::Csv));
+        logger
+            .add(Channel::new("power", &meter, interval, move |_| {
+                let n = count.fetch_add(1, Ordering::SeqCst);
+                //too slow once
+                if n == 2 {
+                    thread::sleep(interval * 3);
+                }
+                Ok(n as f64)
+            }))
+            .add(Channel::new("x", &piezo, interval, {
+                let positions = positions.clone();
+                move |n: &mut u32| {
+                    *n += 1;
+                    positions.fetch_add(1, Ordering::SeqCst);
+                    Ok(*n)
+                }
+            }));
+        let running = logger.start()?;
+        //the reading after the slow one, and 5 positions
+        wait_for(|| readings.load(Ordering::SeqCst) >= 4 && positions.load(Ordering::SeqCst) >= 5);
+        running.stop()?;
+
+        let samples = read(path.join("lab-00000.csv"))?;
+        let power: Vec<_> = samples.iter().filter(|s| s.channel == "power").collect();
+        assert_eq!(
+            power[0].reading,
+            Reading::Error("error: no answer".to_string())
+        );
+        assert_eq!(power[1].reading, Reading::Value(0.0));
+        assert!(power
+            .iter()
+            .any(|s| matches!(s.reading, Reading::Missed(n) if n >= 2)));
+        let x: Vec<_> = samples.iter().filter(|s| s.channel == "x").collect();
+        assert!(x.len() >= 5);
+        assert_eq!(x[0].reading, Reading::Value(1.0));
+        assert!(samples
+            .windows(2)
+            .all(|w| w[0].time <= w[1].time || w[0].channel != w[1].channel));
+
+        //started again after 5 intervals at least, appending to the same file
+        thread::sleep(interval * 5);
+        let last = x.last().unwrap().time;
+        let gap = |now: SystemTime| missed(now.duration_since(last).unwrap() - interval, interval);
+        let mut logger = Logger::new(Files::new(&path, "lab", Format::Csv));
+        logger.add(Channel::new("x", &piezo, interval, |n: &mut u32| Ok(*n)));
+        let before = SystemTime::now();
+        logger.start()?.stop()?;
+        let after = SystemTime::now();
+        let resumed = read(path.join("lab-00000.csv"))?;
+        assert_eq!(resumed[..samples.len()], samples[..]);
+        match resumed[samples.len()].reading {
+            Reading::Missed(n) => {
+                assert!(n >= 4);
+                assert!((gap(before)..=gap(after)).contains(&n), "{}", n);
+            }
+            ref r => panic!("{:?}", r),
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn piezo_off_and_panics_are_retried() -> Result<()> {
+        let path = directory("retry");
+        let connections = Arc::new(AtomicUsize::new(0));
+        let readings = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        //the controller off at first, then a stand-in for one
+        let piezo = Handle::connect_later("piezo", move || {
+            match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => Err("no controller".into()),
+                _ => Ok(12.5),
+            }
+        });
+        let count = readings.clone();
+        let mut logger = Logger::new(Files::new(&path, "lab", Format::Csv));
+        logger.add(Channel::new(
+            "x",
+            &piezo,
+            MIN_INTERVAL,
+            move |x: &mut f64| {
+                if count.fetch_add(1, Ordering::SeqCst) == 0 {
+                    panic!("lost the port");
+                }
+                Ok(*x)
+            },
+        ));
+        let running = logger.start()?;
+        wait_for(|| readings.load(Ordering::SeqCst) >= 3);
+        running.stop()?;
+
+        let samples = read(path.join("lab-00000.csv"))?;
+        let x: Vec<_> = samples
+            .iter()
+            .filter(|s| !matches!(s.reading, Reading::Missed(_)))
+            .map(|s| &s.reading)
+            .collect();
+        assert_eq!(x[0], &Reading::Error("error: no controller".to_string()));
+        assert_eq!(x[1], &Reading::Error("panicked: lost the port".to_string()));
+        assert_eq!(x[2], &Reading::Value(12.5));
+        //connected again after the panic
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn dropping_stops_the_polling() -> Result<()> {
+        let path = directory("drop");
+        let readings = Arc::new(AtomicUsize::new(0));
+        let count = readings.clone();
+        let meter = Handle::new("meter", ());
+        let mut logger = Logger::new(Files::new(&path, "lab", Format::Csv));
+        logger.add(Channel::new("power", &meter, MIN_INTERVAL, move |_| {
+            Ok(count.fetch_add(1, Ordering::SeqCst) as f64)
+        }));
+        let running = logger.start()?;
+        wait_for(|| readings.load(Ordering::SeqCst) >= 2);
+        drop(running);
+        //a reading under way is finished
+        let stopped = readings.load(Ordering::SeqCst) + 1;
+        thread::sleep(MIN_INTERVAL * 20);
+        assert!(readings.load(Ordering::SeqCst) <= stopped);
+        fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+}
@@ -1,222 +1,797 @@
-#![allow(dead_code)]
+//! `rustrument`, quick checks of instruments from the shell.
+//!
+//! Instruments are given by resource address, see [`rustrument::resource`], or by name when a
+//! lab configuration is given with `--lab` or `RUSTRUMENT_LAB`, see [`rustrument::config`].
+//! Everything is printed as text, or as JSON with `--json`.
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use repl::Session;
+use rustrument::{
+    config::{InstrumentConfig, Lab},
+    instruments::{
+        generic::Generic,
+        mdt693_b::{query, Command as PiezoCommand, MDT693B},
+        Driver, Messenger,
+    },
+    protocols::onc_rpc::{
+        port_mapper::{self, PortMapper},
+        vxi11::core::Core,
+        IpProtocol, RpcProgram,
+    },
+    resource::{Connection, DriverKind, Resource, Transport},
+    scpi::{identity::Vendor, Identity, Scpi},
+    waveform::{self, Preamble},
+    Result,
+};
+use serde_json::{json, Value};
 use std::{
-    error::Error,
-    io::{BufRead, Read, Write},
-    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
-    time::Duration,
+    fmt::Write as _,
+    io::{BufRead, Write as _},
+    net::{IpAddr, SocketAddr, TcpStream},
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, SystemTime},
 };
+use tracing_subscriber::{filter::LevelFilter, fmt, prelude::*, reload, Registry};
 
-use rustrument::{
-    instruments::{infiniium::Infiniium, Messenger},
-    protocols::{
-        onc_rpc::{
-            vxi11::{DeviceFlags, Vxi11Client},
-            RpcProgram,
-        },
-        Protocol, Tcp,
+mod repl;
+
+///switches the log of the traffic while running
+type LogHandle = reload::Handle<LevelFilter, Registry>;
+
+#[derive(Parser)]
+#[command(
+    name = "rustrument",
+    version,
+    about = "Talk to lab instruments from the shell"
+)]
+struct Cli {
+    ///print JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+    ///lab configuration naming the instruments, TOML or YAML
+    #[arg(long, global = true, env = "RUSTRUMENT_LAB")]
+    lab: Option<PathBuf>,
+    ///connection and I/O timeout in milliseconds
+    #[arg(long, global = true)]
+    timeout_ms: Option<u64>,
+    ///log the traffic to stderr, twice to dump every byte
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    ///find VXI-11 instruments by broadcasting to the portmappers
+    Discover {
+        ///broadcast address of the network to search
+        #[arg(long, default_value = "255.255.255.255")]
+        broadcast: IpAddr,
+        ///ask every instrument found for its identity
+        #[arg(long)]
+        identify: bool,
     },
-    scpi::Scpi,
-    DefaultConfig, PiezoController,
-};
-fn get_local_ip() -> Option<IpAddr> {
-    let socket = match UdpSocket::bind("0.0.0.0:0") {
-        Ok(s) => s,
-        Err(_) => return None,
-    };
+    ///ask the instrument who it is with `*IDN?`
+    Idn { resource: String },
+    ///send a message
+    Write { resource: String, message: String },
+    ///send a message and print the response
+    Query { resource: String, message: String },
+    ///read the status byte, without going through the message queue when the transport allows it
+    ReadStb { resource: String },
+    ///device clear
+    Clear { resource: String },
+    ///take the VXI-11 lock of the device and hold it until Enter is pressed
+    Lock {
+        resource: String,
+        ///hold the lock for that many seconds instead
+        #[arg(long, value_parser = seconds)]
+        hold: Option<Duration>,
+    },
+    ///release the VXI-11 lock of the device; a lock belongs to the link that took it, so only
+    ///the `lock` process holding it can release it and this always fails
+    Unlock { resource: String },
+    ///query the portmapper of a host
+    Portmap {
+        #[command(subcommand)]
+        command: Portmap,
+    },
+    ///oscilloscope waveforms
+    Waveform {
+        #[command(subcommand)]
+        command: Waveform,
+    },
+    ///Thorlabs MDT693B piezo controllers
+    Piezo {
+        #[command(subcommand)]
+        command: Piezo,
+    },
+    ///interactive terminal, type `:help` for its own commands
+    Repl { resource: String },
+    ///log the readings of the `logger` table of the lab configuration until Enter is pressed
+    Log {
+        ///log for that many seconds instead
+        #[arg(long, value_parser = seconds)]
+        seconds: Option<Duration>,
+    },
+}
 
-    match socket.connect("8.8.8.8:80") {
-        Ok(()) => (),
-        Err(_) => return None,
-    };
+#[derive(Subcommand)]
+enum Portmap {
+    ///every registered program
+    Dump { host: String },
+    ///the port of a program
+    Getport {
+        host: String,
+        program: u32,
+        version: u32,
+        #[arg(long, value_enum, default_value = "tcp")]
+        protocol: Protocol,
+    },
+}
 
-    match socket.local_addr() {
-        Ok(addr) => return Some(addr.ip()),
-        Err(_) => return None,
-    };
+#[derive(Clone, Copy, ValueEnum)]
+enum Protocol {
+    Tcp,
+    Udp,
 }
 
-const TIME_OUT: std::time::Duration = Duration::from_secs(1);
-fn main() -> Result<(), Box<dyn Error>> {
-    test_vxi11_connect("192.168.3.96".parse()?)?;
-    Ok(())
-}
-fn scpi_test(addr: IpAddr) -> Result<(), Box<dyn Error>> {
-    let mut client = Vxi11Client::default();
-    client.lock = false;
-    client.flags = DeviceFlags::new_zero();
-    let mut connect = client.connect(addr, TIME_OUT)?;
-    println!("set mask to 255");
-    connect.set_event_mask(255)?;
-    connect.set_service_mask(255)?;
-    println!("after connecting");
-    println!("{:?}", connect.get_status_byte()?);
-    println!("{:?}", connect.get_event_byte()?);
-    connect.scpi_send("*IDN?")?;
-    println!("after sending valid command without reading");
-    println!("{:?}", connect.get_status_byte()?);
-    println!("{:?}", connect.get_event_byte()?);
-    println!("{}", connect.scpi_query("*IDN?")?);
-    println!("after sending valid command and reading");
-    println!("{:?}", connect.get_status_byte()?);
-    println!("{:?}", connect.get_event_byte()?);
-    connect.scpi_send("s")?;
-    println!("after send invalid command");
-    println!("{:?}", connect.get_status_byte()?);
-    println!("{:?}", connect.get_event_byte()?);
-    connect.scpi_send(":SYSTem:DSP \"Hello World\"")?;
-    println!("after display a message");
-    println!("{:?}", connect.get_status_byte()?);
-    println!("{:?}", connect.get_event_byte()?);
-    Ok(())
-}
-fn test_vxi11_connect(addr: IpAddr) -> Result<(), Box<dyn Error>> {
-    let mut client_osc = Vxi11Client::default();
-    client_osc.lock = false;
-    client_osc.flags = DeviceFlags::new_zero();
-    let mut osc = client_osc.connect(addr, TIME_OUT)?;
-    if let Err(e) = osc.establish_interrupt((get_local_ip().unwrap(), 3480), 3457, 1) {
-        println!("establish interrupt channel failed: {}", e);
-    };
+#[derive(Subcommand)]
+enum Waveform {
+    ///the waveform of a channel, as displayed, using the `:WAVeform` subsystem shared by Keysight
+    ///and Rigol
+    Fetch {
+        resource: String,
+        #[arg(long, default_value_t = 1)]
+        channel: u8,
+        ///given by the extension of the output, csv or json with `--json` otherwise
+        #[arg(long, value_enum)]
+        format: Option<WaveformFormat>,
+        ///write to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
 
-    osc.device_write_str("*IDN?")?;
-    println!("{:#010b}", osc.device_read_stb()?);
-    println!("{}", osc.device_read_str()?);
-    println!("{:#010b}", osc.device_read_stb()?);
-    osc.device_enable_srq(true, "srq\n")?;
-
-    Ok(())
-}
-fn test_port_mapper<B: ToSocketAddrs + Clone>(remote_addr: B) -> Result<(), Box<dyn Error>> {
-    use rustrument::protocols::onc_rpc::port_mapper::*;
-    let remote_addr = remote_addr.to_socket_addrs()?.next().unwrap();
-    let dur = Duration::from_secs(1);
-    let local_ip = get_local_ip().ok_or("error getting local ip address")?;
-    let port = 12902;
-    let local_addr = SocketAddr::new(local_ip, port);
-    //tcp test
-    let prog = <PortMapper<TcpStream> as RpcProgram>::PROGRAM;
-    let vers = <PortMapper<TcpStream> as RpcProgram>::VERSION;
-    {
-        let mut tcp_handler = PortMapper::new_tcp(remote_addr.clone(), dur)?;
-        println!("{}", tcp_handler.tcp_port(prog, vers)?);
-        println!("{}", tcp_handler.udp_port(prog, vers)?);
-    }
-
-    {
-        let mut udp_handler = PortMapper::new_udp(local_addr, remote_addr, dur)?;
-        println!("{}", udp_handler.tcp_port(prog, vers)?);
-        println!("{}", udp_handler.udp_port(prog, vers)?);
-    }
-
-    {
-        let mut broad_caster = PortMapper::new_broadcaster(local_addr, dur)?;
-        {
-            let mut port_stream = broad_caster.collet_tcp_port(prog, vers, "224.0.0.1:111")?;
-            loop {
-                match port_stream.next() {
-                    Some(s) => match s {
-                        Ok((p, a)) => println!("got reply {:0>5} from {}", p, a.to_string()),
-                        Err(e) => {
-                            if e.is_timeout() {
-                                break;
-                            } else {
-                                return Err(e.into());
-                            }
-                        }
-                    },
-                    None => unreachable!(),
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum WaveformFormat {
+    Csv,
+    Json,
+    ///NumPy array of the values
+    Npy,
+    ///binary format keeping the metadata
+    Bin,
+}
+
+#[derive(Subcommand)]
+enum Piezo {
+    ///set the output voltages, V
+    Set {
+        resource: String,
+        #[arg(short)]
+        x: Option<f32>,
+        #[arg(short)]
+        y: Option<f32>,
+        #[arg(short)]
+        z: Option<f32>,
+    },
+    ///read the output voltages
+    Get { resource: String },
+}
+
+///what a command prints, as text or as JSON
+struct Output {
+    text: String,
+    json: Value,
+}
+
+impl Output {
+    fn new<S: Into<String>>(text: S, json: Value) -> Self {
+        Self {
+            text: text.into(),
+            json,
+        }
+    }
+    ///the line printed to stdout, none for an empty text or a null JSON value
+    fn printed(&self, json: bool) -> Option<String> {
+        match json {
+            true if !self.json.is_null() => Some(self.json.to_string()),
+            false if !self.text.is_empty() => Some(self.text.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Cli {
+    ///the instrument named `name` in the lab configuration, if any
+    fn configured(&self, name: &str) -> Result<Option<InstrumentConfig>> {
+        match &self.lab {
+            Some(lab) => Ok(Lab::load(lab)?.instruments.remove(name)),
+            None => Ok(None),
+        }
+    }
+    ///the instrument named `name` in the lab configuration, or at the resource address `name`
+    fn instrument(&self, name: &str) -> Result<InstrumentConfig> {
+        self.instrument_or(name, |_| {})
+    }
+    ///same as [`Cli::instrument`], `adjust` changes the default transport of a resource address
+    fn instrument_or<F: FnOnce(&mut Transport)>(
+        &self,
+        name: &str,
+        adjust: F,
+    ) -> Result<InstrumentConfig> {
+        let mut config = match self.configured(name)? {
+            Some(config) => config,
+            None => {
+                let mut transport = Transport::default();
+                adjust(&mut transport);
+                InstrumentConfig {
+                    resource: name.parse()?,
+                    driver: DriverKind::Generic,
+                    init: Vec::new(),
+                    transport,
                 }
             }
+        };
+        if let Some(timeout) = self.timeout_ms {
+            config.transport.timeout_ms = timeout;
         }
-        {
-            let mut port_stream = broad_caster.collet_udp_port(prog, vers, "224.0.0.1:111")?;
-            loop {
-                match port_stream.next() {
-                    Some(s) => match s {
-                        Ok((p, a)) => println!("got reply {:0>5} from {}", p, a.to_string()),
-                        Err(e) => {
-                            if e.is_timeout() {
-                                break;
-                            } else {
-                                return Err(e.into());
-                            }
-                        }
+        Ok(config)
+    }
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(
+            self.timeout_ms
+                .unwrap_or_else(|| Transport::default().timeout_ms),
+        )
+    }
+    ///the driver of the instrument, with its init messages sent
+    fn open(&self, name: &str) -> Result<Box<dyn Driver>> {
+        self.instrument(name)?.open()
+    }
+    fn connect(&self, name: &str) -> Result<(Resource, Connection)> {
+        let config = self.instrument(name)?;
+        let connection = config.resource.connect(&config.transport)?;
+        Ok((config.resource, connection))
+    }
+    fn run(&self, log: LogHandle) -> Result<Output> {
+        match &self.command {
+            Command::Discover {
+                broadcast,
+                identify,
+            } => self.discover(*broadcast, *identify),
+            Command::Idn { resource } => {
+                let response = self.open(resource)?.query(b"*IDN?")?;
+                let identity: Identity = text(&response).parse()?;
+                Ok(Output::new(
+                    format!(
+                        "manufacturer: {}\nmodel:        {}\nserial:       {}\nfirmware:     {}",
+                        identity.manufacturer, identity.model, identity.serial, identity.firmware
+                    ),
+                    json!(identity),
+                ))
+            }
+            Command::Write { resource, message } => {
+                self.open(resource)?.send(message.as_bytes())?;
+                Ok(Output::new("", json!({ "sent": message })))
+            }
+            Command::Query { resource, message } => {
+                let response = text(&self.open(resource)?.query(message.as_bytes())?);
+                Ok(Output::new(
+                    response.clone(),
+                    json!({ "response": response }),
+                ))
+            }
+            Command::ReadStb { resource } => {
+                let (_, mut connection) = self.connect(resource)?;
+                let stb = match connection.read_stb()? {
+                    Some(stb) => stb,
+                    None => Messenger::new(connection).bind(Generic).get_status_byte()?,
+                };
+                let flags = stb.flags();
+                Ok(Output::new(
+                    format!("{:#04x} {}", stb.byte(), flags.join(" ")),
+                    json!({ "stb": stb.byte(), "flags": flags }),
+                ))
+            }
+            Command::Clear { resource } => {
+                self.connect(resource)?.1.clear()?;
+                Ok(Output::new("cleared", json!({ "cleared": true })))
+            }
+            Command::Lock { resource, hold } => {
+                let mut link = self.vxi11(resource)?;
+                let lock = link.get_mut().lock()?;
+                match hold {
+                    Some(hold) => {
+                        eprintln!("locked for {} s", hold.as_secs_f64());
+                        std::thread::sleep(*hold);
+                    }
+                    None => {
+                        eprintln!("locked, press Enter to release");
+                        std::io::stdin().lock().read_line(&mut String::new())?;
+                    }
+                }
+                lock.unlock()?;
+                Ok(Output::new("unlocked", json!({ "locked": false })))
+            }
+            Command::Unlock { resource } => Err(format!(
+                "can't unlock {}: a lock can only be released by the `lock` process holding it",
+                resource
+            )
+            .into()),
+            Command::Portmap { command } => self.portmap(command),
+            Command::Waveform {
+                command:
+                    Waveform::Fetch {
+                        resource,
+                        channel,
+                        format,
+                        output,
                     },
-                    None => unreachable!(),
+            } => {
+                let waveform = fetch_waveform(self.open(resource)?.as_mut(), *channel)?;
+                let format = format.unwrap_or_else(|| {
+                    let extension = output.as_ref().and_then(|p| p.extension()?.to_str());
+                    match extension {
+                        Some("npy") => WaveformFormat::Npy,
+                        Some("json") => WaveformFormat::Json,
+                        Some("csv") => WaveformFormat::Csv,
+                        Some(_) => WaveformFormat::Bin,
+                        None if self.json => WaveformFormat::Json,
+                        None => WaveformFormat::Csv,
+                    }
+                });
+                let mut json = serde_json::to_value(&waveform).unwrap();
+                json["values"] = json!(waveform.values());
+                let mut data = Vec::new();
+                match format {
+                    WaveformFormat::Csv => waveform.write_csv(&mut data)?,
+                    WaveformFormat::Json => writeln!(data, "{}", json)?,
+                    WaveformFormat::Npy => waveform.write_npy(&mut data)?,
+                    WaveformFormat::Bin => waveform.write_binary(&mut data)?,
+                }
+                match output {
+                    Some(path) => {
+                        std::fs::write(path, data)?;
+                        Ok(Output::new(
+                            format!("{} points written to {}", waveform.len(), path.display()),
+                            json!({ "points": waveform.len(), "output": path }),
+                        ))
+                    }
+                    None if matches!(format, WaveformFormat::Npy | WaveformFormat::Bin) => {
+                        Err("binary waveforms need a file, given with --output".into())
+                    }
+                    None => Ok(Output::new(String::from_utf8(data)?.trim_end(), json)),
                 }
             }
+            Command::Piezo { command } => self.piezo(command),
+            Command::Repl { resource } => {
+                self.repl(resource, log)?;
+                Ok(Output::new("", Value::Null))
+            }
+            Command::Log { seconds } => self.log(*seconds),
         }
     }
-    Ok(())
-}
-fn test_piezo() -> Result<(), Box<dyn Error>> {
-    println!("Starting PiezoController connecting test\n");
-
-    let mut controller = PiezoController::new(5)?;
-    controller.set_x(30.)?;
-    controller.set_y(30.)?;
-    controller.set_z(30.)?;
-    controller.update()?;
-    println!("{}", controller);
-    Ok(())
-}
-
-fn test_osc() -> Result<(), Box<dyn Error>> {
-    print!("Starting Oscilloscope connecting test\n");
-    let mut osc = Infiniium::default_connect("169.254.209.174:5025".parse()?)?;
-    osc.send_raw("*IDN?")?;
-    println!("{}", String::from_utf8_lossy(osc.read_until(b'\n')?));
-    osc.send_raw(":WAVeform:SOURce CHANnel1")?;
-    osc.send_raw(":WAVeform:DATA?")?;
-    println!(
-        "{:?}",
-        std::str::from_utf8(osc.read_until(b'\n')?)?
-            .lines()
-            .next()
-            .expect("no data received")
-            .split_terminator(',')
-            .map(|x| -> f32 { x.parse().unwrap() })
-            .collect::<Vec<f32>>()
-    );
-    osc.send_raw(":ACQuire:SRATe:ANALog 250E+6")?;
-    osc.send_raw("STOP")?;
-    Ok(())
-}
-
-fn test_osc_rigol() -> Result<(), Box<dyn Error>> {
-    let m = Tcp::default();
-    let mut mess = std::io::BufReader::new(Messenger::new(
-        m.connect("169.254.120.131:5555".parse()?, TIME_OUT)?,
-    ));
-    println!("connect success");
-    mess.get_mut().write(":WAVeform:FORMat ASCii\n".as_ref())?;
-    mess.get_mut().write(":WAVeform:MODE MAXimum\n".as_ref())?;
-    for i in [1].iter() {
-        mess.get_mut()
-            .write(format!(":WAVeform:STOP {}\n", i * 1000_000).as_ref())?;
-        mess.get_mut().write(":WAVeform:DATA?\n".as_ref())?;
-        let mut buf = Vec::new();
-        mess.read_until(b'\n', &mut buf)?;
-        println!("read success");
-        println!(
-            "{}\n{}",
-            String::from_utf8_lossy(buf.as_ref()),
-            buf.len() / 14,
+    fn log(&self, duration: Option<Duration>) -> Result<Output> {
+        let path = self
+            .lab
+            .as_ref()
+            .ok_or("logging needs a lab configuration, see --lab")?;
+        let lab = Lab::load(path)?;
+        let config = lab
+            .logger
+            .as_ref()
+            .ok_or_else(|| format!("{}: no logger table", path.display()))?;
+        let running = config.logger(&lab)?.start()?;
+        let directory = config.directory.display();
+        match duration {
+            Some(duration) => {
+                eprintln!("logging to {} for {} s", directory, duration.as_secs_f64());
+                std::thread::sleep(duration);
+            }
+            None => {
+                eprintln!("logging to {}, press Enter to stop", directory);
+                std::io::stdin().lock().read_line(&mut String::new())?;
+            }
+        }
+        running.stop()?;
+        Ok(Output::new(
+            format!("logged {} channels to {}", config.channels.len(), directory),
+            json!({ "directory": config.directory, "channels": config.channels.keys().collect::<Vec<_>>() }),
+        ))
+    }
+    fn repl(&self, name: &str, log: LogHandle) -> Result<()> {
+        let config = self.instrument(name)?;
+        let connection = config.resource.connect(&config.transport)?;
+        let level = self.log_level();
+        let set_logging = move |on: bool| -> Result<()> {
+            let level = if on { level } else { LevelFilter::OFF };
+            log.modify(|filter| *filter = level)
+                .map_err(|e| e.to_string().into())
+        };
+        let mut session = Session::new(
+            connection,
+            config.transport.timeout(),
+            self.verbose > 0,
+            Box::new(set_logging),
+        );
+        for message in &config.init {
+            session.send(message)?;
+        }
+        let history =
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rustrument_history"));
+        session.run(&format!("{}> ", name), history.as_deref())
+    }
+    ///the level of the log when it's on
+    fn log_level(&self) -> LevelFilter {
+        if self.verbose > 1 {
+            LevelFilter::TRACE
+        } else {
+            LevelFilter::DEBUG
+        }
+    }
+    fn vxi11(&self, name: &str) -> Result<Box<rustrument::protocols::onc_rpc::vxi11::Vxi11Io>> {
+        match self.connect(name)? {
+            (_, Connection::Vxi11(io)) => Ok(io),
+            (resource, _) => Err(format!("{} isn't a VXI-11 resource", resource).into()),
+        }
+    }
+    fn discover(&self, broadcast: IpAddr, identify: bool) -> Result<Output> {
+        let program = <Core<TcpStream> as RpcProgram>::PROGRAM;
+        let version = <Core<TcpStream> as RpcProgram>::VERSION;
+        let mut broadcaster = PortMapper::new_broadcaster("0.0.0.0:0", self.timeout())?;
+        broadcaster.mut_io().set_broadcast(true)?;
+        let mut found: Vec<(IpAddr, u32)> = Vec::new();
+        for reply in broadcaster.collect_port(
+            program,
+            version,
+            IpProtocol::Tcp,
+            SocketAddr::new(broadcast, port_mapper::PORT),
+        )? {
+            match reply {
+                Ok((port, addr)) if !found.iter().any(|(ip, _)| *ip == addr.ip()) => {
+                    found.push((addr.ip(), port))
+                }
+                Ok(_) => {}
+                Err(e) if e.is_timeout() => break,
+                Err(e) => return Err(e),
+            }
+        }
+        let (mut lines, mut json) = (String::new(), Vec::new());
+        for (ip, port) in found {
+            let resource = Resource::Vxi11 {
+                host: ip.to_string(),
+                device: "inst0".to_string(),
+            };
+            let identity = if identify {
+                match self.open(&resource.to_string()) {
+                    Ok(mut driver) => driver.query(b"*IDN?").map(|r| text(&r)),
+                    Err(e) => Err(e),
+                }
+                .unwrap_or_else(|e| format!("<{}>", e))
+            } else {
+                String::new()
+            };
+            writeln!(lines, "{}  core port {}  {}", resource, port, identity).unwrap();
+            json.push(json!({ "resource": resource.to_string(), "core_port": port, "identity": identity }));
+        }
+        Ok(Output::new(lines.trim_end(), Value::Array(json)))
+    }
+    fn portmap(&self, command: &Portmap) -> Result<Output> {
+        let connect = |host: &str| -> Result<PortMapper<TcpStream>> {
+            let addr = std::net::ToSocketAddrs::to_socket_addrs(&(host, port_mapper::PORT))?
+                .next()
+                .ok_or_else(|| format!("no address found for '{}'", host))?;
+            PortMapper::new_tcp(addr, self.timeout())
+        };
+        match command {
+            Portmap::Dump { host } => {
+                let mappings = connect(host)?.dump()?;
+                let mut text = format!(
+                    "{:>10} {:>5} {:>5} {:>6}",
+                    "program", "vers", "proto", "port"
+                );
+                for m in &mappings {
+                    write!(
+                        text,
+                        "\n{:>10} {:>5} {:>5} {:>6}",
+                        m.prog,
+                        m.vers,
+                        protocol_name(m.prot),
+                        m.port
+                    )
+                    .unwrap();
+                }
+                let json = mappings
+                    .iter()
+                    .map(|m| {
+                        json!({
+                            "program": m.prog,
+                            "version": m.vers,
+                            "protocol": protocol_name(m.prot),
+                            "port": m.port,
+                        })
+                    })
+                    .collect();
+                Ok(Output::new(text, Value::Array(json)))
+            }
+            Portmap::Getport {
+                host,
+                program,
+                version,
+                protocol,
+            } => {
+                let protocol = match protocol {
+                    Protocol::Tcp => IpProtocol::Tcp,
+                    Protocol::Udp => IpProtocol::Udp,
+                };
+                let port = connect(host)?.get_port(*program, *version, protocol)?;
+                Ok(Output::new(port.to_string(), json!({ "port": port })))
+            }
+        }
+    }
+    fn piezo(&self, command: &Piezo) -> Result<Output> {
+        let resource = match command {
+            Piezo::Set { resource, .. } | Piezo::Get { resource } => resource,
+        };
+        //the controller talks at 115200 baud
+        let config = self.instrument_or(resource, |t| t.serial.baud_rate = 115_200)?;
+        let connection = config.resource.connect(&config.transport)?;
+        let mut piezo = Messenger::new(connection).bind(MDT693B);
+        if let Piezo::Set { x, y, z, .. } = command {
+            for (v, set) in [
+                (x, PiezoCommand::SetXVoltage as fn(f32) -> PiezoCommand),
+                (y, PiezoCommand::SetYVoltage),
+                (z, PiezoCommand::SetZVoltage),
+            ] {
+                if let Some(v) = v {
+                    piezo.command(set(*v))?;
+                }
+            }
+        }
+        let (x, y, z) = (
+            piezo.query(query::ReadXVoltage)?,
+            piezo.query(query::ReadYVoltage)?,
+            piezo.query(query::ReadZVoltage)?,
         );
+        Ok(Output::new(
+            format!("x: {} V\ny: {} V\nz: {} V", x, y, z),
+            json!({ "x": x, "y": y, "z": z }),
+        ))
     }
+}
 
-    Ok(())
+///a duration given in seconds, fractions allowed
+fn seconds(text: &str) -> std::result::Result<Duration, String> {
+    let seconds: f64 = text
+        .parse()
+        .map_err(|e: std::num::ParseFloatError| e.to_string())?;
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| "expected a number of seconds, 0 or more".into())
 }
 
-fn test_awg_rigol() -> Result<(), Box<dyn Error>> {
-    let m = Tcp;
-    let mut mess = std::io::BufReader::new(Messenger::new(
-        m.connect("192.168.3.94:111".parse()?, TIME_OUT)?,
-    ));
-    println!("connect success");
+fn text(response: &[u8]) -> String {
+    String::from_utf8_lossy(response).trim_end().to_string()
+}
+
+fn protocol_name(prot: u32) -> String {
+    match prot {
+        6 => "tcp".to_string(),
+        17 => "udp".to_string(),
+        p => p.to_string(),
+    }
+}
 
-    let mut buf = [0_u8; 4];
-    mess.read(&mut buf)?;
-    println!("{}", u32::from_be_bytes(buf));
-    Ok(())
+///the waveform of a channel in ASCII, scaled by the preamble of the scope's vendor
+fn fetch_waveform(driver: &mut dyn Driver, channel: u8) -> Result<waveform::Waveform> {
+    let identity: Identity = text(&driver.query(b"*IDN?")?).parse()?;
+    driver.send(format!(":WAVeform:SOURce CHANnel{}", channel).as_bytes())?;
+    driver.send(b":WAVeform:FORMat ASCii")?;
+    let preamble = driver.query(b":WAVeform:PREamble?")?;
+    let preamble = match identity.vendor() {
+        Vendor::Rigol => Preamble::rigol(&preamble)?,
+        _ => Preamble::keysight(&preamble)?,
+    };
+    let mut waveform =
+        waveform::Waveform::from_data(&preamble, &driver.query(b":WAVeform:DATA?")?)?;
+    waveform
+        .set_channel(format!("CHAN{}", channel))
+        .set_identity(identity);
+    //when the preamble doesn't tell
+    if waveform.acquired.is_none() {
+        waveform.set_acquired(SystemTime::now());
+    }
+    Ok(waveform)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let level = if cli.verbose > 0 {
+        cli.log_level()
+    } else {
+        LevelFilter::OFF
+    };
+    let (filter, log) = reload::Layer::new(level);
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .init();
+    match cli.run(log) {
+        Ok(output) => {
+            if let Some(line) = output.printed(cli.json) {
+                println!("{}", line);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) if cli.json => {
+            println!("{}", json!({ "error": e.to_string() }));
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("rustrument: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        let args = std::iter::once("rustrument").chain(args.iter().copied());
+        Cli::try_parse_from(args).unwrap()
+    }
+
+    fn rejects(args: &[&str]) -> bool {
+        let args = std::iter::once("rustrument").chain(args.iter().copied());
+        Cli::try_parse_from(args).is_err()
+    }
+
+    #[test]
+    fn parse_every_command() {
+        assert!(matches!(
+            parse(&["discover"]).command,
+            Command::Discover { broadcast, identify: false } if broadcast == IpAddr::from([255; 4])
+        ));
+        assert!(matches!(
+            parse(&["discover", "--broadcast", "192.168.1.255", "--identify"]).command,
+            Command::Discover { broadcast, identify: true }
+                if broadcast == IpAddr::from([192, 168, 1, 255])
+        ));
+        assert!(matches!(
+            parse(&["idn", "scope"]).command,
+            Command::Idn { resource } if resource == "scope"
+        ));
+        assert!(matches!(
+            parse(&["write", "scope", ":RUN"]).command,
+            Command::Write { resource, message } if resource == "scope" && message == ":RUN"
+        ));
+        assert!(matches!(
+            parse(&["query", "192.168.3.96", "*OPC?"]).command,
+            Command::Query { resource, message } if resource == "192.168.3.96" && message == "*OPC?"
+        ));
+        assert!(matches!(
+            parse(&["read-stb", "scope"]).command,
+            Command::ReadStb { resource } if resource == "scope"
+        ));
+        assert!(matches!(
+            parse(&["clear", "scope"]).command,
+            Command::Clear { resource } if resource == "scope"
+        ));
+        assert!(matches!(
+            parse(&["lock", "scope"]).command,
+            Command::Lock { hold: None, .. }
+        ));
+        assert!(matches!(
+            parse(&["lock", "scope", "--hold", "2.5"]).command,
+            Command::Lock { hold: Some(h), .. } if h == Duration::from_millis(2500)
+        ));
+        assert!(matches!(
+            parse(&["unlock", "scope"]).command,
+            Command::Unlock { resource } if resource == "scope"
+        ));
+        assert!(matches!(
+            parse(&["portmap", "dump", "gw"]).command,
+            Command::Portmap { command: Portmap::Dump { host } } if host == "gw"
+        ));
+        assert!(matches!(
+            parse(&["portmap", "getport", "gw", "395183", "1"]).command,
+            Command::Portmap {
+                command: Portmap::Getport {
+                    program: 395183,
+                    version: 1,
+                    protocol: Protocol::Tcp,
+                    ..
+                }
+            }
+        ));
+        assert!(matches!(
+            parse(&[
+                "portmap",
+                "getport",
+                "gw",
+                "100000",
+                "2",
+                "--protocol",
+                "udp"
+            ])
+            .command,
+            Command::Portmap {
+                command: Portmap::Getport {
+                    protocol: Protocol::Udp,
+                    ..
+                }
+            }
+        ));
+        assert!(matches!(
+            parse(&["waveform", "fetch", "scope"]).command,
+            Command::Waveform {
+                command: Waveform::Fetch {
+                    channel: 1,
+                    format: None,
+                    output: None,
+                    ..
+                }
+            }
+        ));
+        assert!(matches!(
+            parse(&["waveform", "fetch", "scope", "--channel", "3", "--format", "npy", "-o", "c3.npy"])
+                .command,
+            Command::Waveform {
+                command: Waveform::Fetch {
+                    channel: 3,
+                    format: Some(WaveformFormat::Npy),
+                    output: Some(o),
+                    ..
+                }
+            } if o == std::path::Path::new("c3.npy")
+        ));
+        assert!(matches!(
+            parse(&["piezo", "set", "COM3", "-x", "10", "-z", "2.5"]).command,
+            Command::Piezo {
+                command: Piezo::Set {
+                    x: Some(x),
+                    y: None,
+                    z: Some(z),
+                    ..
+                }
+            } if x == 10.0 && z == 2.5
+        ));
+        assert!(matches!(
+            parse(&["piezo", "get", "COM3"]).command,
+            Command::Piezo { command: Piezo::Get { resource } } if resource == "COM3"
+        ));
+        assert!(matches!(
+            parse(&["repl", "scope"]).command,
+            Command::Repl { resource } if resource == "scope"
+        ));
+        assert!(matches!(
+            parse(&["log"]).command,
+            Command::Log { seconds: None }
+        ));
+        assert!(matches!(
+            parse(&["log", "--seconds", "60"]).command,
+            Command::Log { seconds: Some(s) } if s == Duration::from_secs(60)
+        ));
+        //global options go before or after the command
+        let cli = parse(&["idn", "scope", "--json", "--timeout-ms", "250", "-vv"]);
+        assert!(cli.json);
+        assert_eq!((cli.timeout_ms, cli.verbose), (Some(250), 2));
+        let cli = parse(&["--lab", "lab.toml", "idn", "scope"]);
+        assert_eq!(cli.lab, Some(PathBuf::from("lab.toml")));
+        for args in [
+            &["idn"][..],
+            &["frobnicate"],
+            &["write", "scope"],
+            &["portmap", "getport", "gw", "1", "1", "--protocol", "sctp"],
+            &["waveform", "fetch", "scope", "--format", "png"],
+            &["log", "--seconds=-1"],
+            &["log", "--seconds", "NaN"],
+            &["log", "--seconds", "inf"],
+            &["lock", "scope", "--hold", "1e30"],
+        ] {
+            assert!(rejects(args), "{:?}", args);
+        }
+    }
 }
@@ -0,0 +1,448 @@
+//! Instruments shared between threads, e.g. a GUI, a data logger and a scan using the same piezo
+//! controller and scope.
+//!
+//! A [`Manager`] owns the connections by name and gives out [`Handle`]s, cloneable and `Send`.
+//! Every call through a handle holds the connection for its whole duration, so a group of
+//! messages sent in one [`Handle::transaction`] is never interleaved with another thread's.
+//! A connection lost during a transaction, or left in an unknown state by a panic, is dropped
+//! and opened again by the connector it was added with.
+use crate::{protocols::onc_rpc::policy::is_connection_lost, Result};
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+///a transaction running longer is reported as stuck
+pub const STUCK_AFTER: Duration = Duration::from_secs(10);
+///time between two attempts of `try_transaction` to get a busy instrument
+const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Error, Debug)]
+pub enum ManagerError {
+    #[error("no instrument named '{0}'")]
+    NotFound(String),
+    #[error("an instrument is already named '{0}'")]
+    AlreadyExists(String),
+    #[error("instrument '{0}' isn't of the requested type")]
+    WrongType(String),
+    #[error("instrument '{0}' still busy after {1:?}")]
+    Busy(String, Duration),
+    #[error("instrument '{0}' is disconnected and can't be connected again")]
+    Disconnected(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Idle,
+    ///in a transaction for that long, `waiting` other transactions queued behind it
+    Busy {
+        running: Duration,
+        waiting: usize,
+    },
+    ///in a transaction for longer than the stuck threshold
+    Stuck {
+        running: Duration,
+        waiting: usize,
+    },
+    ///the connection was lost or poisoned and will be opened again by the next transaction
+    Disconnected,
+}
+
+type Connector<T> = Box<dyn Fn() -> Result<T> + Send + Sync>;
+
+struct Shared<T> {
+    name: String,
+    connection: Mutex<Option<T>>,
+    connector: Option<Connector<T>>,
+    ///start of the running transaction
+    since: Mutex<Option<Instant>>,
+    waiting: AtomicUsize,
+    stuck_after: Mutex<Duration>,
+}
+
+fn lock<U>(m: &Mutex<U>) -> MutexGuard<'_, U> {
+    m.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+///a shared instrument, see the [module](self) documentation
+pub struct Handle<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+///clears the running transaction, even when it panics
+struct Running<'a>(&'a Mutex<Option<Instant>>);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        *lock(self.0) = None;
+    }
+}
+
+impl<T> Handle<T> {
+    ///an instrument connected once, with no way to connect it again
+    pub fn new<S: Into<String>>(name: S, connection: T) -> Self {
+        Self::with(name.into(), Some(connection), None)
+    }
+    ///connect with `connector` now and whenever the connection has been lost
+    pub fn connect<S, F>(name: S, connector: F) -> Result<Self>
+    where
+        S: Into<String>,
+        F: Fn() -> Result<T> + Send + Sync + 'static,
+    {
+        let connection = connector()?;
+        Ok(Self::with(
+            name.into(),
+            Some(connection),
+            Some(Box::new(connector)),
+        ))
+    }
+    ///connect with `connector` at the first transaction and whenever the connection has been
+    ///lost, an instrument off for now is connected once it answers
+    pub fn connect_later<S, F>(name: S, connector: F) -> Self
+    where
+        S: Into<String>,
+        F: Fn() -> Result<T> + Send + Sync + 'static,
+    {
+        Self::with(name.into(), None, Some(Box::new(connector)))
+    }
+    fn with(name: String, connection: Option<T>, connector: Option<Connector<T>>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                name,
+                connection: Mutex::new(connection),
+                connector,
+                since: Mutex::new(None),
+                waiting: AtomicUsize::new(0),
+                stuck_after: Mutex::new(STUCK_AFTER),
+            }),
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.shared.name
+    }
+    ///how long a transaction runs before the instrument is reported as stuck
+    pub fn set_stuck_after(&self, dur: Duration) -> &Self {
+        *lock(&self.shared.stuck_after) = dur;
+        self
+    }
+    pub fn status(&self) -> Status {
+        let waiting = self.shared.waiting.load(Ordering::SeqCst);
+        if let Some(since) = *lock(&self.shared.since) {
+            let running = since.elapsed();
+            return if running >= *lock(&self.shared.stuck_after) {
+                Status::Stuck { running, waiting }
+            } else {
+                Status::Busy { running, waiting }
+            };
+        }
+        match self.shared.connection.try_lock() {
+            Ok(c) if c.is_some() => Status::Idle,
+            //busy between the status checks
+            Err(std::sync::TryLockError::WouldBlock) => Status::Busy {
+                running: Duration::from_secs(0),
+                waiting,
+            },
+            _ => Status::Disconnected,
+        }
+    }
+    ///run `f` with the instrument to itself, waiting for the running transaction to end first
+    pub fn transaction<R, F>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> Result<R>,
+    {
+        self.shared.waiting.fetch_add(1, Ordering::SeqCst);
+        let guard = self.shared.connection.lock();
+        self.shared.waiting.fetch_sub(1, Ordering::SeqCst);
+        self.run(guard, f)
+    }
+    ///same as [`transaction`](Self::transaction), giving up when the instrument stays busy for
+    ///`timeout`
+    pub fn try_transaction<R, F>(&self, timeout: Duration, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> Result<R>,
+    {
+        let deadline = Instant::now() + timeout;
+        self.shared.waiting.fetch_add(1, Ordering::SeqCst);
+        let guard = loop {
+            match self.shared.connection.try_lock() {
+                Ok(guard) => break Ok(guard),
+                Err(std::sync::TryLockError::Poisoned(e)) => break Err(e),
+                Err(std::sync::TryLockError::WouldBlock) if Instant::now() < deadline => {
+                    std::thread::sleep(RETRY_INTERVAL)
+                }
+                Err(std::sync::TryLockError::WouldBlock) => {
+                    self.shared.waiting.fetch_sub(1, Ordering::SeqCst);
+                    return Err(ManagerError::Busy(self.shared.name.clone(), timeout).into());
+                }
+            }
+        };
+        self.shared.waiting.fetch_sub(1, Ordering::SeqCst);
+        self.run(guard, f)
+    }
+    fn run<R, F>(
+        &self,
+        guard: std::result::Result<
+            MutexGuard<'_, Option<T>>,
+            PoisonError<MutexGuard<'_, Option<T>>>,
+        >,
+        f: F,
+    ) -> Result<R>
+    where
+        F: FnOnce(&mut T) -> Result<R>,
+    {
+        let mut connection = match guard {
+            Ok(guard) => guard,
+            Err(poisoned) => {
+                //a transaction panicked half way, the instrument is in an unknown state
+                tracing::warn!(instrument = %self.shared.name, "dropping a poisoned connection");
+                self.shared.connection.clear_poison();
+                let mut guard = poisoned.into_inner();
+                *guard = None;
+                guard
+            }
+        };
+        if connection.is_none() {
+            let connector = self
+                .shared
+                .connector
+                .as_ref()
+                .ok_or_else(|| ManagerError::Disconnected(self.shared.name.clone()))?;
+            tracing::debug!(instrument = %self.shared.name, "connecting again");
+            *connection = Some(connector()?);
+        }
+        *lock(&self.shared.since) = Some(Instant::now());
+        let _running = Running(&self.shared.since);
+        let result = f(connection.as_mut().unwrap());
+        if let Err(e) = &result {
+            if is_connection_lost(e) {
+                tracing::warn!(instrument = %self.shared.name, error = %e, "connection lost");
+                *connection = None;
+            }
+        }
+        result
+    }
+    ///drop the connection, the next transaction connects again
+    pub fn disconnect(&self) {
+        *lock(&self.shared.connection) = None;
+    }
+}
+
+///what a manager knows of its instruments whatever their type
+trait Managed: Send + Sync {
+    fn status(&self) -> Status;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Send + 'static> Managed for Handle<T> {
+    fn status(&self) -> Status {
+        Handle::status(self)
+    }
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+///instruments by name, cloning the manager shares them
+#[derive(Clone, Default)]
+pub struct Manager {
+    instruments: Arc<Mutex<BTreeMap<String, Arc<dyn Managed>>>>,
+}
+
+impl Manager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    ///connect an instrument with `connector`, which is called again whenever the connection
+    ///has been lost
+    pub fn add<T, F>(&self, name: &str, connector: F) -> Result<Handle<T>>
+    where
+        T: Send + 'static,
+        F: Fn() -> Result<T> + Send + Sync + 'static,
+    {
+        if self.contains(name) {
+            return Err(ManagerError::AlreadyExists(name.to_string()).into());
+        }
+        self.insert(Handle::connect(name, connector)?)
+    }
+    ///add an instrument already connected, it can't be connected again once lost
+    pub fn add_connected<T: Send + 'static>(&self, name: &str, connection: T) -> Result<Handle<T>> {
+        self.insert(Handle::new(name, connection))
+    }
+    fn insert<T: Send + 'static>(&self, handle: Handle<T>) -> Result<Handle<T>> {
+        let mut instruments = lock(&self.instruments);
+        if instruments.contains_key(handle.name()) {
+            return Err(ManagerError::AlreadyExists(handle.name().to_string()).into());
+        }
+        instruments.insert(handle.name().to_string(), Arc::new(handle.clone()));
+        Ok(handle)
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        lock(&self.instruments).contains_key(name)
+    }
+    ///a handle on the instrument named `name`, connected as a `T`
+    pub fn get<T: Send + 'static>(&self, name: &str) -> Result<Handle<T>> {
+        let instruments = lock(&self.instruments);
+        let managed = instruments
+            .get(name)
+            .ok_or_else(|| ManagerError::NotFound(name.to_string()))?;
+        managed
+            .as_any()
+            .downcast_ref::<Handle<T>>()
+            .cloned()
+            .ok_or_else(|| ManagerError::WrongType(name.to_string()).into())
+    }
+    ///forget the instrument, it is disconnected once the last handle is dropped
+    pub fn remove(&self, name: &str) -> Result<()> {
+        lock(&self.instruments)
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| ManagerError::NotFound(name.to_string()).into())
+    }
+    pub fn names(&self) -> Vec<String> {
+        lock(&self.instruments).keys().cloned().collect()
+    }
+    ///the status of every instrument, by name
+    pub fn statuses(&self) -> Vec<(String, Status)> {
+        lock(&self.instruments)
+            .iter()
+            .map(|(name, m)| (name.clone(), m.status()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io,
+        sync::{atomic::AtomicBool, mpsc},
+    };
+
+    ///the messages sent to a fake instrument
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    #[test]
+    fn transactions_are_not_interleaved() -> Result<()> {
+        let manager = Manager::new();
+        let handle = manager.add_connected("piezo", Log::default())?;
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let handle = manager.get::<Log>("piezo").unwrap();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        handle
+                            .transaction(|log| {
+                                log.0.push(format!("begin {}", i));
+                                std::thread::yield_now();
+                                log.0.push(format!("end {}", i));
+                                Ok(())
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        handle.transaction(|log| {
+            assert_eq!(log.0.len(), 400);
+            for pair in log.0.chunks(2) {
+                assert_eq!(pair[0].replace("begin", "end"), pair[1]);
+            }
+            Ok(())
+        })?;
+        assert!(manager.get::<String>("piezo").is_err());
+        assert!(manager.get::<Log>("scope").is_err());
+        assert!(manager.add_connected("piezo", Log::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn busy_and_stuck_instruments() -> Result<()> {
+        let manager = Manager::new();
+        let scope = manager.add_connected("scope", 0_u32)?;
+        scope.set_stuck_after(Duration::from_millis(20));
+        assert_eq!(
+            manager.statuses(),
+            vec![("scope".to_string(), Status::Idle)]
+        );
+        let (started, release) = (mpsc::channel(), Arc::new(AtomicBool::new(false)));
+        let worker = {
+            let (scope, release, started) = (scope.clone(), release.clone(), started.0);
+            std::thread::spawn(move || {
+                scope.transaction(|_| {
+                    started.send(()).unwrap();
+                    while !release.load(Ordering::SeqCst) {
+                        std::thread::sleep(Duration::from_millis(1));
+                    }
+                    Ok(())
+                })
+            })
+        };
+        started.1.recv().unwrap();
+        assert!(matches!(scope.status(), Status::Busy { .. }));
+        let e = scope
+            .try_transaction(Duration::from_millis(5), |_| Ok(()))
+            .unwrap_err();
+        assert!(matches!(
+            e,
+            crate::error::Error::ManagerError(ManagerError::Busy(..))
+        ));
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(matches!(scope.status(), Status::Stuck { .. }));
+        release.store(true, Ordering::SeqCst);
+        worker.join().unwrap()?;
+        assert_eq!(scope.status(), Status::Idle);
+        Ok(())
+    }
+
+    #[test]
+    fn lost_and_poisoned_connections_are_opened_again() -> Result<()> {
+        let manager = Manager::new();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let counter = connections.clone();
+        let scope = manager.add("scope", move || {
+            Ok(counter.fetch_add(1, Ordering::SeqCst) + 1)
+        })?;
+        assert_eq!(scope.transaction(|c| Ok(*c))?, 1);
+
+        let lost = scope.transaction(|_| -> Result<()> {
+            Err(io::Error::from(io::ErrorKind::ConnectionReset).into())
+        });
+        assert!(lost.is_err());
+        assert_eq!(scope.status(), Status::Disconnected);
+        assert_eq!(scope.transaction(|c| Ok(*c))?, 2);
+
+        let panicking = scope.clone();
+        let panicked = std::thread::spawn(move || {
+            panicking.transaction(|_| -> Result<()> { panic!("half way through") })
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert_eq!(scope.status(), Status::Disconnected);
+        assert_eq!(scope.transaction(|c| Ok(*c))?, 3);
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+
+        //no connector, the instrument stays disconnected
+        let once = manager.add_connected("piezo", ())?;
+        once.disconnect();
+        assert!(once.transaction(|_| Ok(())).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+//! Addresses of instruments on an IEEE 488 bus, shared by the adapters reaching one.
+use crate::Result;
+use std::fmt;
+
+///highest primary or secondary address, 31 is reserved for unlisten and untalk
+pub const MAX_ADDRESS: u8 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpibAddress {
+    pub primary: u8,
+    pub secondary: Option<u8>,
+}
+
+impl GpibAddress {
+    pub fn new(primary: u8, secondary: Option<u8>) -> Result<Self> {
+        for a in std::iter::once(primary).chain(secondary) {
+            if a > MAX_ADDRESS {
+                return Err(format!("GPIB address {} out of range 0-{}", a, MAX_ADDRESS).into());
+            }
+        }
+        Ok(Self { primary, secondary })
+    }
+}
+
+///`primary` or `primary,secondary`
+impl fmt::Display for GpibAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.secondary {
+            Some(s) => write!(f, "{},{}", self.primary, s),
+            None => write!(f, "{}", self.primary),
+        }
+    }
+}
+
+#[test]
+fn addresses() {
+    assert_eq!(GpibAddress::new(8, None).unwrap().to_string(), "8");
+    assert_eq!(GpibAddress::new(8, Some(2)).unwrap().to_string(), "8,2");
+    assert!(GpibAddress::new(31, None).is_err());
+    assert!(GpibAddress::new(8, Some(31)).is_err());
+}
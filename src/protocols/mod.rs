@@ -1,9 +1,16 @@
-pub mod protocol_error;
+pub mod gpib;
 pub mod onc_rpc;
+pub mod prologix;
+pub mod protocol_error;
 pub mod serial;
+pub mod socket;
 pub mod tcp;
-pub use self::serial::Serial;
+pub mod transcript;
+pub use self::prologix::Prologix;
+pub use self::serial::{Serial, SerialDevice};
+pub use self::socket::ScpiSocket;
 pub use self::tcp::Tcp;
+pub use self::transcript::{Recorder, Replay};
 pub trait Protocol {
     type Address;
     type Error;
@@ -0,0 +1,134 @@
+use crate::Result;
+use bytes::Bytes;
+use onc_rpc::auth::{AuthFlavor, AuthUnixParams};
+
+const MAX_MACHINE_NAME_LEN: usize = 255;
+const MAX_GIDS: usize = 16;
+
+///supplies the credentials and verifier of every call made by an [`RpcProgram`](super::RpcProgram)
+pub trait AuthProvider: Send + Sync {
+    fn credentials(&self) -> AuthFlavor<Bytes>;
+    fn verifier(&self) -> AuthFlavor<Bytes> {
+        AuthFlavor::AuthNone(None)
+    }
+    ///check the verifier sent back with an accepted reply
+    fn validate(&self, _verifier: &AuthFlavor<Bytes>) -> Result<()> {
+        Ok(())
+    }
+}
+
+///`AUTH_NONE`, what every call used to send
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AuthNone;
+
+impl AuthProvider for AuthNone {
+    fn credentials(&self) -> AuthFlavor<Bytes> {
+        AuthFlavor::AuthNone(None)
+    }
+}
+
+///`AUTH_SYS`, also known as `AUTH_UNIX`, identifies the caller as a unix user of a machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthSys {
+    stamp: u32,
+    machine_name: String,
+    uid: u32,
+    gid: u32,
+    gids: Vec<u32>,
+}
+
+impl AuthSys {
+    ///fails if the machine name is longer than 255 bytes or more than 16 groups are given
+    pub fn new<S: Into<String>, G: IntoIterator<Item = u32>>(
+        machine_name: S,
+        uid: u32,
+        gid: u32,
+        gids: G,
+    ) -> Result<Self> {
+        let machine_name = machine_name.into();
+        let gids: Vec<u32> = gids.into_iter().collect();
+        if machine_name.len() > MAX_MACHINE_NAME_LEN {
+            return Err(format!(
+                "AUTH_SYS machine name is {} bytes long, at most {} allowed",
+                machine_name.len(),
+                MAX_MACHINE_NAME_LEN
+            )
+            .into());
+        }
+        if gids.len() > MAX_GIDS {
+            return Err(format!(
+                "AUTH_SYS carries at most {} groups, {} given",
+                MAX_GIDS,
+                gids.len()
+            )
+            .into());
+        }
+        Ok(Self {
+            stamp: rand::random(),
+            machine_name,
+            uid,
+            gid,
+            gids,
+        })
+    }
+    ///extract the parameters from the credentials of a received call
+    pub fn from_credentials(credentials: &AuthFlavor<Bytes>) -> Option<Self> {
+        match credentials {
+            AuthFlavor::AuthUnix(p) => Some(Self {
+                stamp: p.stamp(),
+                machine_name: String::from_utf8_lossy(p.machine_name()).to_string(),
+                uid: p.uid(),
+                gid: p.gid(),
+                gids: p.gids().map(|g| g.to_vec()).unwrap_or_default(),
+            }),
+            _ => None,
+        }
+    }
+    pub fn machine_name(&self) -> &str {
+        &self.machine_name
+    }
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+    pub fn gids(&self) -> &[u32] {
+        &self.gids
+    }
+}
+
+impl AuthProvider for AuthSys {
+    fn credentials(&self) -> AuthFlavor<Bytes> {
+        AuthFlavor::AuthUnix(AuthUnixParams::new(
+            self.stamp,
+            Bytes::copy_from_slice(self.machine_name.as_bytes()),
+            self.uid,
+            self.gid,
+            self.gids.iter().copied(),
+        ))
+    }
+}
+
+#[test]
+fn auth_sys_reaches_the_server() -> Result<()> {
+    use super::{port_mapper::PortMapper, port_mapper::Procedure, server::RpcServer, xdr::mapping};
+    use std::{net::TcpListener, sync::Arc, time::Duration};
+    assert!(AuthSys::new("a".repeat(256), 0, 0, None).is_err());
+    assert!(AuthSys::new("lab", 0, 0, 0..17).is_err());
+    let mut server = RpcServer::new();
+    server.register(100000, 2, Procedure::GetPort, |context, _: mapping| {
+        let auth = AuthSys::from_credentials(&context.credentials).ok_or_else(|| {
+            super::oncrpc_error::RejectedReply::from(super::oncrpc_error::AuthError::TooWeak)
+        })?;
+        Ok(auth.uid() + auth.gids().iter().sum::<u32>())
+    });
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let _ = Arc::new(server).serve_tcp(listener);
+    let mut client = PortMapper::new_tcp(addr, Duration::from_secs(1))?;
+    assert!(client.tcp_port(1, 1).is_err());
+    client.set_auth(Arc::new(AuthSys::new("lab", 1000, 100, vec![1, 2])?));
+    assert_eq!(client.tcp_port(1, 1)?, 1003);
+    Ok(())
+}
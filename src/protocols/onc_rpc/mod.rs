@@ -1,36 +1,31 @@
+pub mod auth;
+pub mod mux;
 pub mod oncrpc_error;
+pub mod policy;
 pub mod port_mapper;
+pub mod record;
+pub mod server;
 pub mod vxi11;
 use crate::Result;
-use bytes::{BufMut, Bytes, BytesMut};
+use auth::{AuthNone, AuthProvider};
+use bytes::{Bytes, BytesMut};
 use onc_rpc::{auth::AuthFlavor, CallBody, MessageType, ReplyBody, RpcMessage};
+use policy::CallPolicy;
 use serde::Serialize;
 use std::{
     convert::{TryFrom, TryInto},
     net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
-    time::Duration,
+    time::{Duration, Instant},
 };
 include!(concat!(env!("OUT_DIR"), r#"/xdr.rs"#));
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IpProtocol {
     Tcp,
     Udp,
 }
 
-const HEAD_LEN: usize = 4;
-fn parse_bytes(bytes: Bytes) -> Result<RpcMessage<Bytes, Bytes>> {
-    match RpcMessage::try_from(bytes) {
-        Ok(m) => Ok(m),
-        Err(onc_rpc::Error::IncompleteHeader) | Err(onc_rpc::Error::IncompleteMessage { .. }) => {
-            unreachable!()
-        }
-        Err(e) => Err(e.into()),
-    }
-}
-fn expected_message_len(data: &[u8]) -> (usize, bool) {
-    let header = u32::from_be_bytes(data.try_into().expect("header need at least 4 bytes"));
-    ((header & (!(1 << 31))) as usize, (header & (1 << 31)) != 0)
-}
+pub use record::{parse_message, serialise_message};
 pub trait RpcStream {
     fn raw_write(&mut self, buf: &[u8]) -> std::io::Result<usize>;
     fn raw_read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>;
@@ -40,43 +35,80 @@ pub trait RpcStream {
         T: AsRef<[u8]>,
         P: AsRef<[u8]>,
     {
-        raw_write_all(self, &message.serialise()?)?;
+        self.send_record(&serialise_message(&message)?)
+    }
+    ///send an already serialised message as one record
+    fn send_record(&mut self, record: &[u8]) -> Result<()> {
+        tracing::trace!(len = record.len(), data = %crate::trace::hex(record), "send record");
+        record::write_record(|b| raw_write_all(self, b), record, record::MAX_FRAGMENT_LEN)?;
+        self.flush()?;
+        Ok(())
+    }
+    ///send the message as a record split into fragments of at most `max_fragment_len` bytes
+    fn send_fragmented<T, P>(
+        &mut self,
+        message: RpcMessage<T, P>,
+        max_fragment_len: usize,
+    ) -> Result<()>
+    where
+        T: AsRef<[u8]>,
+        P: AsRef<[u8]>,
+    {
+        let buf = serialise_message(&message)?;
+        tracing::trace!(
+            len = buf.len(),
+            max_fragment_len,
+            data = %crate::trace::hex(&buf),
+            "send record"
+        );
+        record::write_record(|b| raw_write_all(self, b), &buf, max_fragment_len)?;
         self.flush()?;
         Ok(())
     }
 
     fn read(&mut self, buf: BytesMut) -> Result<RpcMessage<Bytes, Bytes>> {
-        let mut head_buf = [0_u8; 4];
-        let mut buf_cursor = MyCursor::new(buf);
-        let mut total_len = 0;
-        buf_cursor.reserve(HEAD_LEN);
-        buf_cursor.advance(HEAD_LEN);
-        raw_read_exact(self, head_buf.as_mut())?;
-        loop {
-            let (this_len, is_last) = expected_message_len(head_buf.as_ref());
-            buf_cursor.reserve(this_len);
-            raw_read_exact(self, &mut buf_cursor.as_mut()[..this_len as usize])?;
-            buf_cursor.advance(this_len);
-            total_len += this_len;
-            if is_last {
-                break;
-            } else {
-                raw_read_exact(self, head_buf.as_mut())?;
-            }
-        }
-        //for now use the non-support-for-fragment onc-rpc crate, which can't handle head of bigger than 2^31-1, about 2GB
-        //TODO: use own convert function to support message with any length
-        assert!(total_len < (1 << 31));
-        let fake_head = (total_len | (1 << 31)) as u32;
-        let mut buf = buf_cursor.into_inner();
-        buf.as_mut()[..HEAD_LEN].copy_from_slice(fake_head.to_be_bytes().as_ref());
-        Ok(parse_bytes(buf.split_to(total_len + HEAD_LEN).freeze())?)
+        parse_message(self.read_raw(buf)?)
+    }
+    ///read one record and return its content, whatever the number of fragments
+    fn read_raw(&mut self, buf: BytesMut) -> Result<Bytes> {
+        let record = record::read_record(|b| raw_read_exact(self, b), buf)?;
+        tracing::trace!(len = record.len(), data = %crate::trace::hex(&record), "read record");
+        Ok(record)
     }
     fn set_read_timeout<T: Into<Option<Duration>>>(&self, dur: T) -> Result<()>;
     fn set_write_timeout<T: Into<Option<Duration>>>(&self, dur: T) -> Result<()>;
+    fn read_timeout(&self) -> Result<Option<Duration>>;
+    ///whether every record is a datagram that may get lost
+    fn is_datagram(&self) -> bool {
+        false
+    }
+}
+
+///carries a serialised call and returns the matching reply
+pub trait RpcTransport {
+    fn exchange(
+        &mut self,
+        record: &[u8],
+        xid: u32,
+        policy: &CallPolicy,
+        buf: BytesMut,
+    ) -> Result<RpcMessage<Bytes, Bytes>>;
 }
 
-const UDP_BUFFER_LEN: usize = 256;
+impl<S: RpcStream> RpcTransport for S {
+    fn exchange(
+        &mut self,
+        record: &[u8],
+        xid: u32,
+        policy: &CallPolicy,
+        buf: BytesMut,
+    ) -> Result<RpcMessage<Bytes, Bytes>> {
+        policy::exchange(self, record, xid, policy, buf)
+    }
+}
+
+///largest UDP payload, datagrams are never truncated by the receive buffer
+const UDP_DATAGRAM_LEN: usize = 64 * 1024;
 pub trait RpcSocket {
     fn raw_send_to<A: ToSocketAddrs>(&self, buf: &[u8], addr: A) -> std::io::Result<usize>;
     fn raw_recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)>;
@@ -85,60 +117,43 @@ pub trait RpcSocket {
         message: RpcMessage<T, P>,
         addr: A,
     ) -> Result<()> {
-        let buf = message.serialise()?;
-        let buf = &buf[HEAD_LEN..];
-        if !buf.is_empty() {
-            match self.raw_send_to(&buf, addr) {
-                Ok(0) => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::WriteZero,
-                        "failed to write whole buffer",
-                    )
-                    .into());
-                }
-                Ok(n) if n == buf.len() => return Ok(()),
-                Ok(n) => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::Other,
-                        format!(
-                            "only {} byte(s) message sent, expected {} bytes",
-                            n,
-                            buf.len()
-                        ),
-                    )
-                    .into())
-                }
-                Err(e) => return Err(e.into()),
-            }
-        }
-        Ok(())
+        let buf = serialise_message(&message)?;
+        check_datagram_sent(self.raw_send_to(&buf, addr), buf.len())
     }
-    fn recv_from(&self, mut buf: BytesMut) -> Result<(RpcMessage<Bytes, Bytes>, SocketAddr)> {
-        buf.reserve(UDP_BUFFER_LEN + HEAD_LEN); //reserve uninitialized length
-        buf.put_slice(&mut [0_u8; HEAD_LEN][..]);
-        let mut buf_cursor = MyCursor::new(buf);
-        buf_cursor.advance(HEAD_LEN);
-        buf_cursor.reserve(UDP_BUFFER_LEN); //reserve unfilled but initialized length
-        let (bytes, addr) = {
-            //UDP don't fragment
-            let (num_read, addr) = self.raw_recv_from(buf_cursor.as_mut())?;
-            let mut buf = buf_cursor.into_inner();
-            let head: [u8; HEAD_LEN] = (num_read as u32).to_be_bytes();
-            assert!((num_read as u32) < (u32::MAX >> 1));
-            for i in 0..HEAD_LEN {
-                buf[i] = head[i];
-            }
-            buf[0] |= 0b10000000;
-            // Split the buffer into a single message
-            let msg_bytes = buf.split_to(num_read + HEAD_LEN as usize);
-            (msg_bytes.freeze(), addr)
-        };
-        Ok((parse_bytes(bytes)?, addr))
+    fn recv_from(&self, buf: BytesMut) -> Result<(RpcMessage<Bytes, Bytes>, SocketAddr)> {
+        let (bytes, addr) = self.recv_raw_from(buf)?;
+        Ok((parse_message(bytes)?, addr))
+    }
+    ///receive one datagram and return its content
+    fn recv_raw_from(&self, mut buf: BytesMut) -> Result<(Bytes, SocketAddr)> {
+        //UDP don't fragment
+        buf.clear();
+        buf.resize(UDP_DATAGRAM_LEN, 0);
+        let (num_read, addr) = self.raw_recv_from(&mut buf)?;
+        buf.truncate(num_read);
+        Ok((buf.freeze(), addr))
     }
     fn set_read_timeout<T: Into<Option<Duration>>>(&self, dur: T) -> Result<()>;
     fn set_write_timeout<T: Into<Option<Duration>>>(&self, dur: T) -> Result<()>;
 }
 
+fn check_datagram_sent(sent: std::io::Result<usize>, expected: usize) -> Result<()> {
+    match sent {
+        Ok(n) if n == expected => Ok(()),
+        Ok(0) => Err(std::io::Error::new(
+            std::io::ErrorKind::WriteZero,
+            "failed to write whole buffer",
+        )
+        .into()),
+        Ok(n) => Err(std::io::Error::other(format!(
+            "only {} byte(s) message sent, expected {} bytes",
+            n, expected
+        ))
+        .into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
 fn raw_write_all<S: RpcStream + ?Sized>(s: &mut S, buf: &[u8]) -> Result<()> {
     let mut buf = buf;
     while !buf.is_empty() {
@@ -181,49 +196,6 @@ fn raw_read_exact<S: RpcStream + ?Sized>(s: &mut S, mut buf: &mut [u8]) -> Resul
     }
 }
 
-struct MyCursor<T: AsRef<[u8]> + AsMut<[u8]>> {
-    inner: T,
-    filled: usize,
-}
-
-impl<T: AsRef<[u8]> + AsMut<[u8]>> MyCursor<T> {
-    fn new(inner: T) -> Self {
-        Self { inner, filled: 0 }
-    }
-    fn advance(&mut self, step: usize) -> &mut Self {
-        self.filled += step;
-        self
-    }
-    fn into_inner(self) -> T {
-        self.inner
-    }
-    fn capacity(&self) -> usize {
-        self.inner.as_ref().len()
-    }
-}
-const REDUNDANCE: usize = 5;
-impl MyCursor<BytesMut> {
-    fn reserve(&mut self, additional: usize) {
-        if self.filled + additional > self.capacity() {
-            self.inner.resize(self.filled + additional * REDUNDANCE, 0);
-        }
-    }
-}
-
-impl<T: AsRef<[u8]> + AsMut<[u8]>> AsRef<[u8]> for MyCursor<T> {
-    fn as_ref(&self) -> &[u8] {
-        assert!(self.inner.as_ref().len() >= self.filled);
-        &self.inner.as_ref()[..self.filled]
-    }
-}
-
-impl<T: AsRef<[u8]> + AsMut<[u8]>> AsMut<[u8]> for MyCursor<T> {
-    fn as_mut(&mut self) -> &mut [u8] {
-        assert!(self.inner.as_ref().len() >= self.filled);
-        &mut self.inner.as_mut()[self.filled..]
-    }
-}
-
 fn stream_receive<S, R>(s: &S, xid: u32) -> Result<(R, SocketAddr)>
 where
     S: RpcBroadcast + RpcProgram + ?Sized,
@@ -231,18 +203,24 @@ where
     R: TryFrom<Bytes>,
     crate::error::Error: From<<R as TryFrom<bytes::Bytes>>::Error>,
 {
-    {
+    loop {
         let buf = s.buffer();
         let (reply, addr) = match s.get_io().recv_from(buf)? {
             (r, addr) => (r, addr),
         };
-        if reply.xid() == xid {
+        if reply.xid() != xid && s.policy().skip_stale_xids {
+            continue;
+        }
+        return if reply.xid() == xid {
             match reply.reply_body().ok_or(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "expected reply, found call",
             ))? {
                 ReplyBody::Accepted(a) => match a.status() {
-                    onc_rpc::AcceptedStatus::Success(p) => Ok((p.clone().try_into()?, addr)),
+                    onc_rpc::AcceptedStatus::Success(p) => {
+                        s.auth().validate(a.auth_verifier())?;
+                        Ok((p.clone().try_into()?, addr))
+                    }
 
                     u => Err(oncrpc_error::UnsuccessfulAcceptStatus::from(
                         oncrpc_error::PrivateWrapper(u),
@@ -253,7 +231,7 @@ where
             }
         } else {
             Err(oncrpc_error::OncRpcError::XidUnmatched(xid, reply.xid()).into())
-        }
+        };
     }
 }
 
@@ -267,6 +245,14 @@ pub trait RpcProgram {
     fn get_io(&self) -> &Self::IO;
     fn mut_io(&mut self) -> &mut Self::IO;
     fn buffer(&self) -> BytesMut;
+    ///credentials sent by `call_authenticated` and checker of reply verifiers
+    fn auth(&self) -> &dyn AuthProvider {
+        &AuthNone
+    }
+    ///how calls wait for their reply and retry
+    fn policy(&self) -> &CallPolicy {
+        &CallPolicy::DEFAULT
+    }
 }
 
 pub trait Rpc {
@@ -297,8 +283,26 @@ pub trait Rpc {
             content,
         )
     }
+    ///call with the credentials of the program's auth provider
+    fn call_authenticated<P, C, R>(&mut self, procedure: P, content: C) -> Result<R>
+    where
+        Self: RpcProgram + Sized,
+        P: Into<u32>,
+        C: Serialize,
+        R: TryFrom<Bytes>,
+        crate::error::Error: From<<R as TryFrom<bytes::Bytes>>::Error>,
+    {
+        let credentials = self.auth().credentials();
+        let verifier = self.auth().verifier();
+        self.call(procedure, credentials, verifier, content)
+    }
 }
 
+///the replies to a broadcast, each with the address of its sender, an error once the read times
+///out
+pub type Replies<'a, R> =
+    std::iter::FromFn<Box<dyn FnMut() -> Option<Result<(R, SocketAddr)>> + 'a>>;
+
 pub trait RpcBroadcast {
     fn broadcast<'a, P, T, C, A, R>(
         &'a mut self,
@@ -307,7 +311,7 @@ pub trait RpcBroadcast {
         auth_verifier: AuthFlavor<T>,
         content: C,
         addr: A,
-    ) -> Result<std::iter::FromFn<Box<dyn FnMut() -> Option<Result<(R, SocketAddr)>> + 'a>>>
+    ) -> Result<Replies<'a, R>>
     where
         P: Into<u32>,
         T: AsRef<[u8]>,
@@ -320,7 +324,7 @@ pub trait RpcBroadcast {
         procedure: P,
         content: C,
         addr: A,
-    ) -> Result<std::iter::FromFn<Box<dyn FnMut() -> Option<Result<(R, SocketAddr)>> + 'a>>>
+    ) -> Result<Replies<'a, R>>
     where
         P: Into<u32>,
         C: Serialize,
@@ -336,12 +340,31 @@ pub trait RpcBroadcast {
             addr,
         )
     }
+    ///broadcast with the credentials of the program's auth provider
+    fn broadcast_authenticated<'a, P, C, A, R>(
+        &'a mut self,
+        procedure: P,
+        content: C,
+        addr: A,
+    ) -> Result<Replies<'a, R>>
+    where
+        Self: RpcProgram + Sized,
+        P: Into<u32>,
+        C: Serialize,
+        A: ToSocketAddrs,
+        R: TryFrom<Bytes>,
+        crate::error::Error: From<<R as TryFrom<bytes::Bytes>>::Error>,
+    {
+        let credentials = self.auth().credentials();
+        let verifier = self.auth().verifier();
+        self.broadcast(procedure, credentials, verifier, content, addr)
+    }
 }
 
 impl<S> Rpc for S
 where
     S: RpcProgram,
-    <S as RpcProgram>::IO: RpcStream,
+    <S as RpcProgram>::IO: RpcTransport,
 {
     fn call<P, T, C, R>(
         &mut self,
@@ -358,25 +381,43 @@ where
         crate::error::Error: From<<R as TryFrom<bytes::Bytes>>::Error>,
     {
         let xid = self.gen_xid();
+        let procedure = procedure.into();
+        let _span = tracing::debug_span!(
+            "rpc call",
+            program = Self::PROGRAM,
+            version = Self::VERSION,
+            procedure,
+            xid
+        )
+        .entered();
         let content = serde_xdr::to_bytes(&content)?;
         let call_body = CallBody::new(
             Self::PROGRAM,
             Self::VERSION,
-            procedure.into(),
+            procedure,
             auth_credentials,
             auth_verifier,
             &content[..],
         );
-        self.mut_io()
-            .send(RpcMessage::new(xid, MessageType::Call(call_body)))?;
+        let record = serialise_message(&RpcMessage::new(xid, MessageType::Call(call_body)))?;
+        let policy = self.policy().clone();
         let buf = self.buffer();
-        let reply = self.mut_io().read(buf)?;
-        if reply.xid() == xid {
+        let start = Instant::now();
+        let reply = self.mut_io().exchange(&record, xid, &policy, buf);
+        match &reply {
+            Ok(_) => tracing::debug!(elapsed = ?start.elapsed(), "reply"),
+            Err(e) => tracing::debug!(elapsed = ?start.elapsed(), error = %e, "no reply"),
+        }
+        let reply = reply?;
+        {
             match reply.reply_body().ok_or(oncrpc_error::OncRpcError::Other(
                 "expected reply, found call".to_string(),
             ))? {
                 ReplyBody::Accepted(a) => match a.status() {
-                    onc_rpc::AcceptedStatus::Success(p) => Ok(p.clone().try_into()?),
+                    onc_rpc::AcceptedStatus::Success(p) => {
+                        self.auth().validate(a.auth_verifier())?;
+                        Ok(p.clone().try_into()?)
+                    }
 
                     u => Err(oncrpc_error::UnsuccessfulAcceptStatus::from(
                         oncrpc_error::PrivateWrapper(u),
@@ -385,8 +426,6 @@ where
                 },
                 ReplyBody::Denied(d) => Err(oncrpc_error::RejectedReply::from(d).into()),
             }
-        } else {
-            Err(oncrpc_error::OncRpcError::XidUnmatched(xid, reply.xid()).into())
         }
     }
 }
@@ -402,7 +441,7 @@ where
         auth_verifier: AuthFlavor<T>,
         content: C,
         addr: A,
-    ) -> Result<std::iter::FromFn<Box<dyn FnMut() -> Option<Result<(R, SocketAddr)>> + 'a>>>
+    ) -> Result<Replies<'a, R>>
     where
         P: Into<u32>,
         T: AsRef<[u8]>,
@@ -416,11 +455,20 @@ where
             .to_socket_addrs()?
             .next()
             .expect("invalid socket address");
+        let procedure = procedure.into();
+        tracing::debug!(
+            program = Self::PROGRAM,
+            version = Self::VERSION,
+            procedure,
+            xid,
+            %addr,
+            "rpc broadcast"
+        );
         let content = serde_xdr::to_bytes(&content)?;
         let call_body = CallBody::new(
             Self::PROGRAM,
             Self::VERSION,
-            procedure.into(),
+            procedure,
             auth_credentials,
             auth_verifier,
             &content[..],
@@ -449,6 +497,9 @@ impl RpcStream for TcpStream {
     fn set_write_timeout<T: Into<Option<Duration>>>(&self, dur: T) -> Result<()> {
         Ok(TcpStream::set_write_timeout(self, dur.into())?)
     }
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        Ok(TcpStream::read_timeout(self)?)
+    }
 }
 impl RpcStream for UdpSocket {
     fn raw_read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
@@ -460,11 +511,19 @@ impl RpcStream for UdpSocket {
     fn flush(&mut self) -> std::io::Result<()> {
         Ok(())
     }
-    fn send<T: AsRef<[u8]>, P: AsRef<[u8]>>(&mut self, message: RpcMessage<T, P>) -> Result<()> {
-        Ok(send_without_head(self, message)?)
+    fn send_record(&mut self, record: &[u8]) -> Result<()> {
+        send_without_head(self, record)
     }
-    fn read(&mut self, buf: BytesMut) -> Result<RpcMessage<Bytes, Bytes>> {
-        Ok(recv_without_head(self, buf)?)
+    ///UDP don't fragment, the message is sent as one datagram whatever `max_fragment_len`
+    fn send_fragmented<T, P>(&mut self, message: RpcMessage<T, P>, _: usize) -> Result<()>
+    where
+        T: AsRef<[u8]>,
+        P: AsRef<[u8]>,
+    {
+        send_without_head(self, &serialise_message(&message)?)
+    }
+    fn read_raw(&mut self, buf: BytesMut) -> Result<Bytes> {
+        recv_without_head(self, buf)
     }
     fn set_read_timeout<T: Into<Option<Duration>>>(&self, dur: T) -> Result<()> {
         Ok(UdpSocket::set_read_timeout(self, dur.into())?)
@@ -472,6 +531,12 @@ impl RpcStream for UdpSocket {
     fn set_write_timeout<T: Into<Option<Duration>>>(&self, dur: T) -> Result<()> {
         Ok(UdpSocket::set_write_timeout(self, dur.into())?)
     }
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        Ok(UdpSocket::read_timeout(self)?)
+    }
+    fn is_datagram(&self) -> bool {
+        true
+    }
 }
 
 impl RpcSocket for UdpSocket {
@@ -489,61 +554,15 @@ impl RpcSocket for UdpSocket {
     }
 }
 
-fn send_without_head<S: RpcStream, T: AsRef<[u8]>, P: AsRef<[u8]>>(
-    s: &mut S,
-    message: RpcMessage<T, P>,
-) -> Result<()> {
-    let buf = message.serialise()?;
-    let buf = &buf[HEAD_LEN..];
-    if !buf.is_empty() {
-        match s.raw_write(&buf) {
-            Ok(0) => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::WriteZero,
-                    "failed to write whole buffer",
-                )
-                .into());
-            }
-            Ok(n) if n == buf.len() => return Ok(()),
-            Ok(n) => {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!(
-                        "only {} byte(s) message sent, expected {} bytes",
-                        n,
-                        buf.len()
-                    ),
-                )
-                .into())
-            }
-            Err(e) => return Err(e.into()),
-        }
-    }
-    Ok(())
+fn send_without_head<S: RpcStream>(s: &mut S, record: &[u8]) -> Result<()> {
+    check_datagram_sent(s.raw_write(record), record.len())
 }
 
-fn recv_without_head<S: RpcStream>(
-    s: &mut S,
-    mut buf: BytesMut,
-) -> Result<RpcMessage<Bytes, Bytes>> {
-    buf.reserve(UDP_BUFFER_LEN + HEAD_LEN); //reserve uninitialized length
-    buf.put_slice(&mut [0_u8; HEAD_LEN][..]);
-    let mut buf_cursor = MyCursor::new(buf);
-    buf_cursor.advance(HEAD_LEN);
-    buf_cursor.reserve(UDP_BUFFER_LEN); //reserve unfilled but initialized length
-    let bytes = {
-        //UDP don't fragment
-        let num_read = s.raw_read(buf_cursor.as_mut())?;
-        let mut buf = buf_cursor.into_inner();
-        let head: [u8; HEAD_LEN] = (num_read as u32).to_be_bytes();
-        assert!((num_read as u32) < (u32::MAX >> 1));
-        for i in 0..HEAD_LEN {
-            buf[i] = head[i];
-        }
-        buf[0] |= 0b10000000;
-        // Split the buffer into a single message
-        let msg_bytes = buf.split_to(num_read + HEAD_LEN as usize);
-        msg_bytes.freeze()
-    };
-    Ok(parse_bytes(bytes)?)
+fn recv_without_head<S: RpcStream>(s: &mut S, mut buf: BytesMut) -> Result<Bytes> {
+    //UDP don't fragment
+    buf.clear();
+    buf.resize(UDP_DATAGRAM_LEN, 0);
+    let num_read = s.raw_read(&mut buf)?;
+    buf.truncate(num_read);
+    Ok(buf.freeze())
 }
@@ -0,0 +1,225 @@
+//! Client side of a connection shared by several callers.
+//!
+//! Every caller writes its call as one record and waits for the reply carrying its xid, a
+//! background thread reads the replies and routes them to their callers, replies nobody waits
+//! for any more are dropped.
+use super::{oncrpc_error::OncRpcError, policy::CallPolicy, RpcStream, RpcTransport};
+use crate::Result;
+use bytes::{Bytes, BytesMut};
+use onc_rpc::RpcMessage;
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{mpsc, Arc, Mutex, MutexGuard, Weak},
+    time::Duration,
+};
+
+type Waiter = mpsc::Sender<Result<RpcMessage<Bytes, Bytes>>>;
+
+#[derive(Default)]
+struct Pending {
+    waiters: HashMap<u32, Waiter>,
+    ///set once the connection failed, with the kind and message of the error
+    closed: Option<(ErrorKind, String)>,
+}
+
+struct Shared {
+    stream: Mutex<TcpStream>,
+    pending: Mutex<Pending>,
+    read_timeout: Mutex<Option<Duration>>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        //wakes the reader thread up, so it notices there is nobody left
+        let _ = lock(&self.stream).shutdown(Shutdown::Both);
+    }
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+///cloneable handle to a TCP connection on which calls from several threads can be in flight
+///
+///give each thread its own program over a clone, e.g. `Core::new(client.clone())`
+#[derive(Clone)]
+pub struct MuxClient {
+    shared: Arc<Shared>,
+}
+
+impl MuxClient {
+    pub fn new(stream: TcpStream) -> Result<Self> {
+        let reader = stream.try_clone()?;
+        //the socket options are shared with the writer, replies are waited for by the callers
+        reader.set_read_timeout(None)?;
+        let shared = Arc::new(Shared {
+            stream: Mutex::new(stream),
+            pending: Mutex::new(Pending::default()),
+            read_timeout: Mutex::new(None),
+        });
+        let weak = Arc::downgrade(&shared);
+        std::thread::spawn(move || read_replies(reader, weak));
+        Ok(Self { shared })
+    }
+    pub fn connect(addr: SocketAddr) -> Result<Self> {
+        Self::new(TcpStream::connect(addr)?)
+    }
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(lock(&self.shared.stream).peer_addr()?)
+    }
+    ///how long a call waits for its reply when the call policy sets no deadline
+    pub fn set_read_timeout<T: Into<Option<Duration>>>(&self, dur: T) -> &Self {
+        *lock(&self.shared.read_timeout) = dur.into();
+        self
+    }
+    pub fn read_timeout(&self) -> Option<Duration> {
+        *lock(&self.shared.read_timeout)
+    }
+    pub fn set_write_timeout<T: Into<Option<Duration>>>(&self, dur: T) -> Result<()> {
+        Ok(lock(&self.shared.stream).set_write_timeout(dur.into())?)
+    }
+    ///number of calls waiting for their reply
+    pub fn in_flight(&self) -> usize {
+        lock(&self.shared.pending).waiters.len()
+    }
+
+    ///send the serialised call `record` and wait at most `timeout` for the reply to `xid`
+    pub fn call_raw(
+        &self,
+        record: &[u8],
+        xid: u32,
+        timeout: Option<Duration>,
+    ) -> Result<RpcMessage<Bytes, Bytes>> {
+        let (sender, receiver) = mpsc::channel();
+        {
+            let mut pending = lock(&self.shared.pending);
+            if let Some((kind, ref message)) = pending.closed {
+                return Err(std::io::Error::new(kind, message.clone()).into());
+            }
+            if pending.waiters.contains_key(&xid) {
+                return Err(OncRpcError::Other(format!("xid {} already in flight", xid)).into());
+            }
+            pending.waiters.insert(xid, sender);
+        }
+        let sent = lock(&self.shared.stream).send_record(record);
+        if let Err(e) = sent {
+            lock(&self.shared.pending).waiters.remove(&xid);
+            return Err(e);
+        }
+        let received = match timeout {
+            Some(t) => receiver.recv_timeout(t).ok(),
+            None => receiver.recv().ok(),
+        };
+        match received {
+            Some(reply) => reply,
+            None => {
+                lock(&self.shared.pending).waiters.remove(&xid);
+                Err(std::io::Error::new(ErrorKind::TimedOut, "no reply to the call").into())
+            }
+        }
+    }
+}
+
+impl RpcTransport for MuxClient {
+    fn exchange(
+        &mut self,
+        record: &[u8],
+        xid: u32,
+        policy: &CallPolicy,
+        _buf: BytesMut,
+    ) -> Result<RpcMessage<Bytes, Bytes>> {
+        match policy.deadline {
+            Some(deadline) => match self.call_raw(record, xid, Some(deadline)) {
+                Err(crate::error::Error::IOError(e)) if e.kind() == ErrorKind::TimedOut => {
+                    Err(OncRpcError::DeadlineExceeded(deadline).into())
+                }
+                r => r,
+            },
+            None => self.call_raw(record, xid, self.read_timeout()),
+        }
+    }
+}
+
+fn read_replies(mut reader: TcpStream, shared: Weak<Shared>) {
+    loop {
+        let record = reader.read_raw(BytesMut::new());
+        let shared = match shared.upgrade() {
+            Some(s) => s,
+            None => return,
+        };
+        let reply = match record {
+            Ok(r) => super::parse_message(r),
+            Err(e) => {
+                let (kind, message) = match e {
+                    crate::error::Error::IOError(e) => (e.kind(), e.to_string()),
+                    e => (ErrorKind::InvalidData, e.to_string()),
+                };
+                let mut pending = lock(&shared.pending);
+                for (_, waiter) in pending.waiters.drain() {
+                    let _ = waiter.send(Err(std::io::Error::new(kind, message.clone()).into()));
+                }
+                pending.closed = Some((kind, message));
+                return;
+            }
+        };
+        //a record that isn't a message doesn't break the framing, skip it
+        if let Ok(reply) = reply {
+            if let Some(waiter) = lock(&shared.pending).waiters.remove(&reply.xid()) {
+                let _ = waiter.send(Ok(reply));
+            }
+        }
+    }
+}
+
+#[test]
+fn replies_reach_their_callers() -> Result<()> {
+    use super::{
+        port_mapper::{PortMapper, Procedure},
+        server::RpcServer,
+        xdr::mapping,
+        RpcProgram,
+    };
+    use std::{net::TcpListener, sync::Barrier};
+    const PROGRAM: u32 = <PortMapper<MuxClient> as RpcProgram>::PROGRAM;
+    //the first call is answered only after the second, as a slow read would be
+    let barrier = Arc::new(Barrier::new(2));
+    let mut server = RpcServer::new();
+    let b = barrier.clone();
+    server.register(PROGRAM, 2, Procedure::GetPort, move |_, m: mapping| {
+        if m.prog == 1 {
+            b.wait();
+        }
+        Ok(m.prog + m.vers)
+    });
+    let server = Arc::new(server);
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let writer = Arc::new(Mutex::new(stream.try_clone().unwrap()));
+        //serve every call in its own thread, so replies go out of order
+        while let Ok(record) = stream.read_raw(BytesMut::new()) {
+            let (server, writer) = (server.clone(), writer.clone());
+            std::thread::spawn(move || {
+                let reply = server.dispatch(record, None).unwrap();
+                lock(&writer).send(reply).unwrap();
+            });
+        }
+    });
+    let client = MuxClient::connect(addr)?;
+    let slow = {
+        let mut port_mapper = PortMapper::new(client.clone());
+        std::thread::spawn(move || port_mapper.tcp_port(1, 10))
+    };
+    let mut port_mapper = PortMapper::new(client.clone());
+    while client.in_flight() == 0 {
+        std::thread::yield_now();
+    }
+    assert_eq!(port_mapper.tcp_port(2, 20)?, 22);
+    barrier.wait();
+    assert_eq!(slow.join().unwrap()?, 11);
+    assert_eq!(client.in_flight(), 0);
+    Ok(())
+}
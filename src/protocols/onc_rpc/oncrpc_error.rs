@@ -16,6 +16,8 @@ pub enum OncRpcError {
     DeserializationError(#[from] super::xdr::Error),
     #[error("reply xid unmatched, expected {0}, found {1}")]
     XidUnmatched(u32, u32),
+    #[error("no reply within the call deadline of {0:?}")]
+    DeadlineExceeded(std::time::Duration),
     #[error("rpc error: '{0}'")]
     Other(String),
 }
@@ -67,7 +69,54 @@ pub enum RejectedReply {
     #[error("The RPC version was not serviceable, supported version: {low}-{high}")]
     RpcVersionMismatch { low: u32, high: u32 },
     #[error("The authentication credentials included in the request (if any) were rejected: {0}")]
-    AuthError(String),
+    AuthError(#[from] AuthError),
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthError {
+    #[error("the credentials were rejected")]
+    BadCredentials,
+    #[error("the session has been invalidated")]
+    RejectedCredentials,
+    #[error("the verifier was not acceptable")]
+    BadVerifier,
+    #[error("the verifier was rejected or expired")]
+    RejectedVerifier,
+    #[error("the authentication scheme was rejected for security reasons")]
+    TooWeak,
+    #[error("the response verifier is invalid")]
+    InvalidResponseVerifier,
+    #[error("unknown authentication failure")]
+    Failed,
+}
+
+impl From<&onc_rpc::AuthError> for AuthError {
+    fn from(value: &onc_rpc::AuthError) -> Self {
+        match value {
+            //AUTH_OK in a denied reply makes no sense, treat it as a failure
+            onc_rpc::AuthError::Success | onc_rpc::AuthError::Failed => AuthError::Failed,
+            onc_rpc::AuthError::BadCredentials => AuthError::BadCredentials,
+            onc_rpc::AuthError::RejectedCredentials => AuthError::RejectedCredentials,
+            onc_rpc::AuthError::BadVerifier => AuthError::BadVerifier,
+            onc_rpc::AuthError::RejectedVerifier => AuthError::RejectedVerifier,
+            onc_rpc::AuthError::TooWeak => AuthError::TooWeak,
+            onc_rpc::AuthError::InvalidResponseVerifier => AuthError::InvalidResponseVerifier,
+        }
+    }
+}
+
+impl From<AuthError> for onc_rpc::AuthError {
+    fn from(value: AuthError) -> Self {
+        match value {
+            AuthError::BadCredentials => onc_rpc::AuthError::BadCredentials,
+            AuthError::RejectedCredentials => onc_rpc::AuthError::RejectedCredentials,
+            AuthError::BadVerifier => onc_rpc::AuthError::BadVerifier,
+            AuthError::RejectedVerifier => onc_rpc::AuthError::RejectedVerifier,
+            AuthError::TooWeak => onc_rpc::AuthError::TooWeak,
+            AuthError::InvalidResponseVerifier => onc_rpc::AuthError::InvalidResponseVerifier,
+            AuthError::Failed => onc_rpc::AuthError::Failed,
+        }
+    }
 }
 
 impl From<&onc_rpc::RejectedReply> for RejectedReply {
@@ -79,7 +128,38 @@ impl From<&onc_rpc::RejectedReply> for RejectedReply {
                     high: *high,
                 }
             }
-            onc_rpc::RejectedReply::AuthError(s) => RejectedReply::AuthError(format!("{:?}", s)),
+            onc_rpc::RejectedReply::AuthError(e) => RejectedReply::AuthError(e.into()),
+        }
+    }
+}
+
+impl UnsuccessfulAcceptStatus {
+    pub(crate) fn to_accepted_status<P: AsRef<[u8]>>(&self) -> AcceptedStatus<P> {
+        match self {
+            UnsuccessfulAcceptStatus::ProgramUnavailable => AcceptedStatus::ProgramUnavailable,
+            UnsuccessfulAcceptStatus::ProgramMismatch { low, high } => {
+                AcceptedStatus::ProgramMismatch {
+                    low: *low,
+                    high: *high,
+                }
+            }
+            UnsuccessfulAcceptStatus::ProcedureUnavailable => AcceptedStatus::ProcedureUnavailable,
+            UnsuccessfulAcceptStatus::GarbageArgs => AcceptedStatus::GarbageArgs,
+            UnsuccessfulAcceptStatus::SystemError => AcceptedStatus::SystemError,
+        }
+    }
+}
+
+impl RejectedReply {
+    pub(crate) fn to_rejected_reply(&self) -> onc_rpc::RejectedReply {
+        match self {
+            RejectedReply::RpcVersionMismatch { low, high } => {
+                onc_rpc::RejectedReply::RpcVersionMismatch {
+                    low: *low,
+                    high: *high,
+                }
+            }
+            RejectedReply::AuthError(e) => onc_rpc::RejectedReply::AuthError((*e).into()),
         }
     }
 }
@@ -0,0 +1,301 @@
+use super::{oncrpc_error::OncRpcError, RpcStream};
+use crate::{error::Error, Result};
+use bytes::{Bytes, BytesMut};
+use onc_rpc::RpcMessage;
+use std::time::{Duration, Instant};
+
+///how a call waits for its reply, retries and gives up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallPolicy {
+    ///drop replies carrying another xid, left behind by an earlier call that timed out,
+    ///instead of failing with `XidUnmatched`
+    pub skip_stale_xids: bool,
+    ///give up a call, retransmissions included, once it took this long
+    pub deadline: Option<Duration>,
+    ///wait this long for a reply before sending a datagram again, `None` disables retransmission,
+    ///calls over streams are never retransmitted
+    pub retransmit_timeout: Option<Duration>,
+    ///the wait is multiplied by this after every retransmission
+    pub backoff: u32,
+    ///upper bound of the wait between two retransmissions
+    pub max_retransmit_timeout: Duration,
+    pub max_retransmissions: u32,
+    ///reconnect and retry once when a VXI-11 connection is reset, the link is created again
+    pub reconnect: bool,
+}
+
+impl CallPolicy {
+    pub const DEFAULT: CallPolicy = CallPolicy {
+        skip_stale_xids: true,
+        deadline: None,
+        retransmit_timeout: Some(Duration::from_millis(500)),
+        backoff: 2,
+        max_retransmit_timeout: Duration::from_secs(8),
+        max_retransmissions: 4,
+        reconnect: true,
+    };
+    ///one attempt, and `XidUnmatched` on the first stale reply
+    pub const STRICT: CallPolicy = CallPolicy {
+        skip_stale_xids: false,
+        deadline: None,
+        retransmit_timeout: None,
+        backoff: 1,
+        max_retransmit_timeout: Duration::from_secs(0),
+        max_retransmissions: 0,
+        reconnect: false,
+    };
+    pub fn new() -> Self {
+        Self::DEFAULT
+    }
+    pub fn set_skip_stale_xids(&mut self, skip: bool) -> &mut Self {
+        self.skip_stale_xids = skip;
+        self
+    }
+    pub fn set_deadline<D: Into<Option<Duration>>>(&mut self, deadline: D) -> &mut Self {
+        self.deadline = deadline.into();
+        self
+    }
+    pub fn set_retransmit<D: Into<Option<Duration>>>(
+        &mut self,
+        timeout: D,
+        backoff: u32,
+        max_timeout: Duration,
+        max_retransmissions: u32,
+    ) -> &mut Self {
+        self.retransmit_timeout = timeout.into();
+        self.backoff = backoff;
+        self.max_retransmit_timeout = max_timeout;
+        self.max_retransmissions = max_retransmissions;
+        self
+    }
+    pub fn set_reconnect(&mut self, reconnect: bool) -> &mut Self {
+        self.reconnect = reconnect;
+        self
+    }
+    ///the wait following the `n`th transmission of a datagram, counting from 0
+    pub fn retransmit_wait(&self, n: u32) -> Option<Duration> {
+        let first = self.retransmit_timeout?;
+        let factor = self.backoff.max(1).saturating_pow(n);
+        Some(
+            first
+                .checked_mul(factor)
+                .unwrap_or(self.max_retransmit_timeout)
+                .min(self.max_retransmit_timeout.max(first)),
+        )
+    }
+}
+
+impl Default for CallPolicy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+fn is_timeout(e: &Error) -> bool {
+    matches!(e, Error::IOError(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut))
+}
+
+///whether the error means the connection is gone and has to be established again
+pub fn is_connection_lost(e: &Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(e, Error::IOError(e) if matches!(
+        e.kind(),
+        ConnectionReset | ConnectionAborted | BrokenPipe | UnexpectedEof | NotConnected
+    ))
+}
+
+///send the serialised call `record` and read until the reply to `xid` arrives, following `policy`
+pub(crate) fn exchange<S: RpcStream>(
+    io: &mut S,
+    record: &[u8],
+    xid: u32,
+    policy: &CallPolicy,
+    buf: BytesMut,
+) -> Result<RpcMessage<Bytes, Bytes>> {
+    let read_timeout = io.read_timeout()?;
+    let mut current_timeout = read_timeout;
+    let ret = exchange_with_timeouts(
+        io,
+        record,
+        xid,
+        policy,
+        buf,
+        read_timeout,
+        &mut current_timeout,
+    );
+    if current_timeout != read_timeout {
+        io.set_read_timeout(read_timeout)?;
+    }
+    ret
+}
+
+fn exchange_with_timeouts<S: RpcStream>(
+    io: &mut S,
+    record: &[u8],
+    xid: u32,
+    policy: &CallPolicy,
+    buf: BytesMut,
+    read_timeout: Option<Duration>,
+    current_timeout: &mut Option<Duration>,
+) -> Result<RpcMessage<Bytes, Bytes>> {
+    let start = Instant::now();
+    let deadline = policy.deadline.map(|d| start + d);
+    let retransmit = io.is_datagram() && policy.retransmit_timeout.is_some();
+    let mut transmissions = 0;
+    io.send_record(record)?;
+    let mut resend_at = if retransmit {
+        policy.retransmit_wait(0).map(|w| start + w)
+    } else {
+        None
+    };
+    loop {
+        let now = Instant::now();
+        if deadline.is_some_and(|d| now >= d) {
+            return Err(OncRpcError::DeadlineExceeded(policy.deadline.unwrap()).into());
+        }
+        let wait = [
+            read_timeout,
+            resend_at.map(|t| t.saturating_duration_since(now)),
+            deadline.map(|t| t.saturating_duration_since(now)),
+        ]
+        .iter()
+        .flatten()
+        .min()
+        //a zero timeout is rejected by the socket
+        .map(|w| (*w).max(Duration::from_millis(1)));
+        if wait != *current_timeout {
+            io.set_read_timeout(wait)?;
+            *current_timeout = wait;
+        }
+        match io.read(buf.clone()) {
+            Ok(reply) if reply.xid() == xid => return Ok(reply),
+            Ok(_) if policy.skip_stale_xids => {}
+            Ok(reply) => return Err(OncRpcError::XidUnmatched(xid, reply.xid()).into()),
+            Err(e) if is_timeout(&e) => {
+                let now = Instant::now();
+                if deadline.is_some_and(|d| now >= d) {
+                    return Err(OncRpcError::DeadlineExceeded(policy.deadline.unwrap()).into());
+                }
+                match resend_at {
+                    Some(t) if now >= t => {
+                        if transmissions == policy.max_retransmissions {
+                            return Err(e);
+                        }
+                        transmissions += 1;
+                        io.send_record(record)?;
+                        resend_at = policy.retransmit_wait(transmissions).map(|w| now + w);
+                    }
+                    //the read timeout of the io fired before any timer of the policy
+                    _ if wait == read_timeout => return Err(e),
+                    _ => {}
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::onc_rpc::{
+        port_mapper::{PortMapper, Procedure},
+        serialise_message,
+        server::RpcServer,
+        xdr::mapping,
+        IpProtocol, RpcProgram,
+    };
+    use std::net::{TcpListener, UdpSocket};
+
+    const PROGRAM: u32 = <PortMapper<UdpSocket> as RpcProgram>::PROGRAM;
+
+    fn server() -> RpcServer {
+        let mut server = RpcServer::new();
+        server.register(PROGRAM, 2, Procedure::GetPort, |_, m: mapping| {
+            Ok(m.prog + m.vers)
+        });
+        server
+    }
+
+    #[test]
+    fn retransmission_wait_backs_off() {
+        let policy = CallPolicy::new();
+        assert_eq!(policy.retransmit_wait(0), Some(Duration::from_millis(500)));
+        assert_eq!(policy.retransmit_wait(2), Some(Duration::from_secs(2)));
+        assert_eq!(policy.retransmit_wait(40), Some(Duration::from_secs(8)));
+        assert_eq!(CallPolicy::STRICT.retransmit_wait(0), None);
+    }
+
+    #[test]
+    fn stale_replies_are_skipped() -> Result<()> {
+        let server = server();
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            loop {
+                let record = match stream.read_raw(BytesMut::new()) {
+                    Ok(r) => r,
+                    Err(_) => return,
+                };
+                let reply = server.dispatch(record, None).unwrap();
+                let reply = serialise_message(&reply).unwrap();
+                let mut stale = reply.clone();
+                stale[3] = stale[3].wrapping_add(1);
+                stream.send_record(&stale).unwrap();
+                stream.send_record(&reply).unwrap();
+            }
+        });
+        let mut client = PortMapper::new_tcp(addr, Duration::from_secs(1))?;
+        assert_eq!(client.tcp_port(1000, 7)?, 1007);
+        assert_eq!(client.tcp_port(1000, 8)?, 1008);
+        client.set_policy(CallPolicy::STRICT);
+        match client.tcp_port(1000, 9) {
+            Err(Error::OncRpcError(OncRpcError::XidUnmatched(..))) => {}
+            other => panic!("unexpected reply {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn lost_datagrams_are_retransmitted() -> Result<()> {
+        let server = server();
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        let addr = socket.local_addr()?;
+        std::thread::spawn(move || {
+            //drop the first two transmissions
+            let mut buf = [0_u8; 1024];
+            socket.recv_from(&mut buf).unwrap();
+            socket.recv_from(&mut buf).unwrap();
+            server.serve_datagram(&socket).unwrap();
+        });
+        let mut client = PortMapper::new_udp("127.0.0.1:0", addr, Duration::from_secs(5))?;
+        let mut policy = CallPolicy::new();
+        policy.set_retransmit(Duration::from_millis(20), 2, Duration::from_millis(100), 4);
+        client.set_policy(policy);
+        assert_eq!(client.get_port(1000, 7, IpProtocol::Udp)?, 1007);
+        //the read timeout of the socket is left as it was
+        assert_eq!(
+            client.get_io().read_timeout()?,
+            Some(Duration::from_secs(5))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deadline_bounds_the_call() -> Result<()> {
+        let silent = UdpSocket::bind("127.0.0.1:0")?;
+        let mut client =
+            PortMapper::new_udp("127.0.0.1:0", silent.local_addr()?, Duration::from_secs(5))?;
+        let mut policy = CallPolicy::new();
+        policy.set_deadline(Duration::from_millis(200));
+        client.set_policy(policy);
+        let start = Instant::now();
+        match client.get_port(1000, 7, IpProtocol::Udp) {
+            Err(Error::OncRpcError(OncRpcError::DeadlineExceeded(_))) => {}
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(2));
+        Ok(())
+    }
+}
@@ -1,7 +1,13 @@
-use super::{xdr::*, IpProtocol, Result, Rpc, RpcBroadcast, RpcProgram, RpcSocket, RpcStream};
+use super::{
+    auth::{AuthNone, AuthProvider},
+    policy::CallPolicy,
+    xdr::*,
+    IpProtocol, Result, Rpc, RpcBroadcast, RpcProgram, RpcSocket, RpcTransport,
+};
 use bytes::{Buf, BytesMut};
 use std::{
     net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    sync::Arc,
     time::Duration,
 };
 
@@ -10,6 +16,8 @@ pub const PORT: u16 = 111;
 pub struct PortMapper<S> {
     io: S,
     buffer: BytesMut,
+    auth: Arc<dyn AuthProvider>,
+    policy: CallPolicy,
 }
 
 impl<S> PortMapper<S> {
@@ -17,8 +25,18 @@ impl<S> PortMapper<S> {
         Self {
             io,
             buffer: BytesMut::new(),
+            auth: Arc::new(AuthNone),
+            policy: CallPolicy::DEFAULT,
         }
     }
+    pub fn set_auth(&mut self, auth: Arc<dyn AuthProvider>) -> &mut Self {
+        self.auth = auth;
+        self
+    }
+    pub fn set_policy(&mut self, policy: CallPolicy) -> &mut Self {
+        self.policy = policy;
+        self
+    }
     pub fn get_io(&self) -> &S {
         &self.io
     }
@@ -33,6 +51,12 @@ impl<S> RpcProgram for PortMapper<S> {
     fn buffer(&self) -> BytesMut {
         self.buffer.clone()
     }
+    fn auth(&self) -> &dyn AuthProvider {
+        self.auth.as_ref()
+    }
+    fn policy(&self) -> &CallPolicy {
+        &self.policy
+    }
     fn get_io(&self) -> &Self::IO {
         &self.io
     }
@@ -53,6 +77,8 @@ impl PortMapper<TcpStream> {
         Ok(PortMapper {
             io,
             buffer: BytesMut::new(),
+            auth: Arc::new(AuthNone),
+            policy: CallPolicy::DEFAULT,
         })
     }
 }
@@ -68,6 +94,8 @@ impl PortMapper<UdpSocket> {
         Ok(PortMapper {
             io,
             buffer: BytesMut::new(),
+            auth: Arc::new(AuthNone),
+            policy: CallPolicy::DEFAULT,
         })
     }
     pub fn new_broadcaster<L: ToSocketAddrs, D: Into<Option<Duration>>>(
@@ -79,12 +107,14 @@ impl PortMapper<UdpSocket> {
         Ok(PortMapper {
             io,
             buffer: BytesMut::new(),
+            auth: Arc::new(AuthNone),
+            policy: CallPolicy::DEFAULT,
         })
     }
 }
-impl<S: RpcStream> PortMapper<S> {
+impl<S: RpcTransport> PortMapper<S> {
     pub fn get_port(&mut self, prog: u32, vers: u32, ip_pro: IpProtocol) -> Result<u32> {
-        let mut b: bytes::Bytes = self.call_anonymously(
+        let mut b: bytes::Bytes = self.call_authenticated(
             Procedure::GetPort,
             mapping {
                 port: 0,
@@ -104,6 +134,58 @@ impl<S: RpcStream> PortMapper<S> {
     pub fn udp_port(&mut self, prog: u32, vers: u32) -> Result<u32> {
         self.get_port(prog, vers, IpProtocol::Udp)
     }
+    ///register a mapping, returns false if the portmapper refused it
+    pub fn set(&mut self, prog: u32, vers: u32, ip_pro: IpProtocol, port: u32) -> Result<bool> {
+        let mut b: bytes::Bytes = self.call_authenticated(
+            Procedure::Set,
+            mapping {
+                port,
+                prog,
+                prot: match ip_pro {
+                    IpProtocol::Tcp => IPPROTO_TCP,
+                    IpProtocol::Udp => IPPROTO_UDP,
+                },
+                vers,
+            },
+        )?;
+        Ok(b.get_u32() != 0)
+    }
+    ///remove every mapping of the program version, returns false if there was none
+    pub fn unset(&mut self, prog: u32, vers: u32) -> Result<bool> {
+        let mut b: bytes::Bytes = self.call_authenticated(
+            Procedure::Unset,
+            mapping {
+                port: 0,
+                prog,
+                prot: 0,
+                vers,
+            },
+        )?;
+        Ok(b.get_u32() != 0)
+    }
+    ///every mapping registered on the portmapper
+    pub fn dump(&mut self) -> Result<Vec<mapping>> {
+        let mut b: bytes::Bytes = self.call_authenticated(Procedure::Dump, ())?;
+        let mut mappings = Vec::new();
+        //a list of optional entries, ended by an absent one
+        loop {
+            if b.remaining() < 4 {
+                return Err("truncated portmapper dump".into());
+            }
+            if b.get_u32() == 0 {
+                return Ok(mappings);
+            }
+            if b.remaining() < 16 {
+                return Err("truncated portmapper dump".into());
+            }
+            mappings.push(mapping {
+                prog: b.get_u32(),
+                vers: b.get_u32(),
+                prot: b.get_u32(),
+                port: b.get_u32(),
+            });
+        }
+    }
 }
 
 impl<S: RpcSocket> PortMapper<S> {
@@ -114,7 +196,7 @@ impl<S: RpcSocket> PortMapper<S> {
         ip_pro: IpProtocol,
         addr: A,
     ) -> Result<impl Iterator<Item = Result<(u32, SocketAddr)>> + 'a> {
-        let stream = self.broadcast_anonymously(
+        let stream = self.broadcast_authenticated(
             Procedure::GetPort,
             mapping {
                 port: 0,
@@ -162,8 +244,7 @@ pub enum Procedure {
     Set,
     Unset,
     GetPort,
-    //pamamplist unsupported yet
-    //Dump,
+    Dump,
     CallIt,
 }
 impl Into<u32> for Procedure {
@@ -173,7 +254,7 @@ impl Into<u32> for Procedure {
             Set => 1,
             Unset => 2,
             GetPort => 3,
-            //Dump=>4,
+            Dump => 4,
             CallIt => 5,
         }
     }
@@ -0,0 +1,250 @@
+//! Record marking standard (RFC 5531 section 11) used to frame ONC-RPC messages on byte streams.
+//!
+//! A record is sent as one or more fragments, each prefixed by a 4-byte big-endian header whose
+//! highest bit flags the last fragment and whose 31 low bits give the fragment length.
+use crate::Result;
+use bytes::{Buf, Bytes, BytesMut};
+use onc_rpc::{MessageType, RpcMessage};
+use std::convert::TryFrom;
+
+pub const HEAD_LEN: usize = 4;
+const LAST_FRAGMENT: u32 = 1 << 31;
+///the longest fragment the 31-bit length can describe
+pub const MAX_FRAGMENT_LEN: usize = (LAST_FRAGMENT - 1) as usize;
+///read fragments in chunks of this size, so a bogus header doesn't allocate gigabytes up front
+const READ_CHUNK_LEN: usize = 64 * 1024;
+
+pub fn fragment_header(len: usize, is_last: bool) -> [u8; HEAD_LEN] {
+    debug_assert!(len <= MAX_FRAGMENT_LEN);
+    let mut header = len as u32;
+    if is_last {
+        header |= LAST_FRAGMENT;
+    }
+    header.to_be_bytes()
+}
+
+///return (fragment_len, is_last)
+pub fn parse_fragment_header(header: [u8; HEAD_LEN]) -> (usize, bool) {
+    let header = u32::from_be_bytes(header);
+    (
+        (header & !LAST_FRAGMENT) as usize,
+        header & LAST_FRAGMENT != 0,
+    )
+}
+
+///read one whole record, `read_exact` must fill the buffer it's given or fail
+pub fn read_record<F>(mut read_exact: F, mut buf: BytesMut) -> Result<Bytes>
+where
+    F: FnMut(&mut [u8]) -> Result<()>,
+{
+    buf.clear();
+    loop {
+        let mut header = [0_u8; HEAD_LEN];
+        read_exact(&mut header)?;
+        let (mut remain, is_last) = parse_fragment_header(header);
+        while remain > 0 {
+            let start = buf.len();
+            let chunk = remain.min(READ_CHUNK_LEN);
+            buf.resize(start + chunk, 0);
+            read_exact(&mut buf[start..])?;
+            remain -= chunk;
+        }
+        if is_last {
+            return Ok(buf.freeze());
+        }
+    }
+}
+
+///write `record` split into fragments of at most `max_fragment_len` bytes,
+///an empty record is sent as a single empty last fragment
+pub fn write_record<F>(mut write_all: F, record: &[u8], max_fragment_len: usize) -> Result<()>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let max_fragment_len = max_fragment_len.clamp(1, MAX_FRAGMENT_LEN);
+    let mut fragments = record.chunks(max_fragment_len).peekable();
+    if fragments.peek().is_none() {
+        return write_all(&fragment_header(0, true));
+    }
+    while let Some(fragment) = fragments.next() {
+        let is_last = fragments.peek().is_none();
+        //one write per fragment keeps small messages in a single segment
+        let mut buf = Vec::with_capacity(HEAD_LEN + fragment.len());
+        buf.extend_from_slice(&fragment_header(fragment.len(), is_last));
+        buf.extend_from_slice(fragment);
+        write_all(&buf)?;
+    }
+    Ok(())
+}
+
+///serialise a message without any record marking, as carried by a record or a datagram
+pub fn serialise_message<T, P>(message: &RpcMessage<T, P>) -> Result<Vec<u8>>
+where
+    T: AsRef<[u8]>,
+    P: AsRef<[u8]>,
+{
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&message.xid().to_be_bytes());
+    message.message().serialise_into(&mut buf)?;
+    Ok(buf)
+}
+
+///parse a message from the content of a record or a datagram
+pub fn parse_message(mut record: Bytes) -> Result<RpcMessage<Bytes, Bytes>> {
+    if record.len() < 4 {
+        return Err(onc_rpc::Error::InvalidLength.into());
+    }
+    let xid = record.get_u32();
+    Ok(RpcMessage::new(xid, MessageType::try_from(record)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    ///build the stream by hand: `record` cut at `cuts`, every piece behind its own header
+    fn hand_built(record: &[u8], cuts: &[usize]) -> Vec<u8> {
+        let mut points: Vec<usize> = cuts.iter().map(|c| c % (record.len() + 1)).collect();
+        points.push(0);
+        points.push(record.len());
+        points.sort_unstable();
+        points.dedup();
+        let mut stream = Vec::new();
+        if points.len() == 1 {
+            stream.extend_from_slice(&(1_u32 << 31).to_be_bytes());
+            return stream;
+        }
+        for (i, w) in points.windows(2).enumerate() {
+            let len = (w[1] - w[0]) as u32;
+            let last = if i == points.len() - 2 { 1 << 31 } else { 0 };
+            stream.extend_from_slice(&(len | last).to_be_bytes());
+            stream.extend_from_slice(&record[w[0]..w[1]]);
+        }
+        stream
+    }
+
+    fn read_from(stream: &[u8]) -> Result<(Bytes, usize)> {
+        let mut pos = 0;
+        let record = read_record(
+            |buf| {
+                if stream.len() - pos < buf.len() {
+                    return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                }
+                buf.copy_from_slice(&stream[pos..pos + buf.len()]);
+                pos += buf.len();
+                Ok(())
+            },
+            BytesMut::new(),
+        )?;
+        Ok((record, pos))
+    }
+
+    fn write_to(record: &[u8], max_fragment_len: usize) -> Vec<u8> {
+        let mut stream = Vec::new();
+        write_record(
+            |buf| {
+                stream.extend_from_slice(buf);
+                Ok(())
+            },
+            record,
+            max_fragment_len,
+        )
+        .unwrap();
+        stream
+    }
+
+    proptest! {
+        #[test]
+        fn reads_hand_built_fragments(
+            record in prop::collection::vec(any::<u8>(), 0..2048),
+            cuts in prop::collection::vec(any::<usize>(), 0..16),
+            trailing in prop::collection::vec(any::<u8>(), 0..16),
+        ) {
+            let mut stream = hand_built(&record, &cuts);
+            let record_end = stream.len();
+            stream.extend_from_slice(&trailing);
+            let (read, consumed) = read_from(&stream).unwrap();
+            prop_assert_eq!(read.as_ref(), record.as_slice());
+            prop_assert_eq!(consumed, record_end);
+        }
+
+        #[test]
+        fn writes_like_hand_built_fragments(
+            record in prop::collection::vec(any::<u8>(), 0..2048),
+            max_fragment_len in 1_usize..300,
+        ) {
+            let cuts: Vec<usize> = (1..)
+                .map(|i| i * max_fragment_len)
+                .take_while(|c| *c < record.len())
+                .collect();
+            prop_assert_eq!(write_to(&record, max_fragment_len), hand_built(&record, &cuts));
+        }
+
+        #[test]
+        fn round_trips(
+            record in prop::collection::vec(any::<u8>(), 0..4096),
+            max_fragment_len in 1_usize..5000,
+        ) {
+            let (read, _) = read_from(&write_to(&record, max_fragment_len)).unwrap();
+            prop_assert_eq!(read.as_ref(), record.as_slice());
+        }
+
+        #[test]
+        fn truncated_stream_is_an_error(
+            record in prop::collection::vec(any::<u8>(), 1..512),
+            cuts in prop::collection::vec(any::<usize>(), 0..8),
+            cut in any::<usize>(),
+        ) {
+            let stream = hand_built(&record, &cuts);
+            prop_assert!(read_from(&stream[..cut % stream.len()]).is_err());
+        }
+    }
+
+    #[test]
+    fn datagrams_are_never_fragmented() -> Result<()> {
+        use super::super::RpcStream;
+        use onc_rpc::{auth::AuthFlavor, CallBody};
+        use std::net::UdpSocket;
+
+        let receiver = UdpSocket::bind("127.0.0.1:0")?;
+        let mut sender = UdpSocket::bind("127.0.0.1:0")?;
+        sender.connect(receiver.local_addr()?)?;
+        receiver.set_read_timeout(Some(std::time::Duration::from_secs(5)))?;
+        let message = || {
+            let body = CallBody::new(
+                0x0607af,
+                1,
+                11,
+                AuthFlavor::AuthNone(None),
+                AuthFlavor::AuthNone(None),
+                &[7_u8; 100][..],
+            );
+            RpcMessage::<&[u8], &[u8]>::new(42, MessageType::Call(body))
+        };
+        sender.send_fragmented(message(), 16)?;
+        let mut buf = [0; 1024];
+        let len = receiver.recv(&mut buf)?;
+        //one datagram without a record mark
+        assert_eq!(&buf[..len], &serialise_message(&message())?[..]);
+        assert_eq!(
+            parse_message(Bytes::copy_from_slice(&buf[..len]))?.xid(),
+            42
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn header_bits() {
+        assert_eq!(fragment_header(5, true), [0x80, 0, 0, 5]);
+        assert_eq!(
+            fragment_header(MAX_FRAGMENT_LEN, false),
+            [0x7f, 0xff, 0xff, 0xff]
+        );
+        assert_eq!(parse_fragment_header([0x80, 0, 1, 0]), (256, true));
+        assert_eq!(
+            parse_fragment_header([0x7f, 0xff, 0xff, 0xff]),
+            (MAX_FRAGMENT_LEN, false)
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use super::{
+    oncrpc_error::{OncRpcError, RejectedReply, UnsuccessfulAcceptStatus},
+    port_mapper::PortMapper,
+    IpProtocol, RpcSocket, RpcStream,
+};
+use crate::{error::Error, Result};
+use bytes::{Buf, Bytes, BytesMut};
+use onc_rpc::{auth::AuthFlavor, AcceptedReply, MessageType, ReplyBody, RpcMessage};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    net::{SocketAddr, TcpListener, UdpSocket},
+    sync::Arc,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+const RPC_VERSION: u32 = 2;
+///every program answers procedure 0 with an empty reply, unless a handler is registered for it
+const NULL_PROCEDURE: u32 = 0;
+
+///information about the call being served, handed to every handler
+pub struct CallContext {
+    pub xid: u32,
+    pub program: u32,
+    pub version: u32,
+    pub procedure: u32,
+    pub credentials: AuthFlavor<Bytes>,
+    ///`None` when the call came through a stream without a known peer
+    pub peer: Option<SocketAddr>,
+}
+
+enum Outcome {
+    Success(Vec<u8>),
+    Unsuccessful(UnsuccessfulAcceptStatus),
+    Rejected(RejectedReply),
+}
+
+impl From<Error> for Outcome {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::OncRpcError(OncRpcError::UnsuccessfulAcceptStatus(s)) => {
+                Outcome::Unsuccessful(s)
+            }
+            Error::OncRpcError(OncRpcError::RpcRejected(r)) => Outcome::Rejected(r),
+            _ => Outcome::Unsuccessful(UnsuccessfulAcceptStatus::SystemError),
+        }
+    }
+}
+
+type Handler = Box<dyn Fn(&CallContext, Bytes) -> Outcome + Send + Sync>;
+
+///ONC-RPC server dispatching calls to handlers registered by program, version and procedure
+///
+///A handler returning an error replies with the matching accepted status when the error is an
+///[`UnsuccessfulAcceptStatus`], denies the call when it is a [`RejectedReply`], and answers
+///`SYSTEM_ERR` otherwise.
+#[derive(Default)]
+pub struct RpcServer {
+    //program -> version -> procedure
+    programs: BTreeMap<u32, BTreeMap<u32, BTreeMap<u32, Handler>>>,
+}
+
+impl RpcServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    ///register `handler` for a procedure, its argument is decoded from XDR before the call
+    pub fn register<P, A, R, F>(
+        &mut self,
+        program: u32,
+        version: u32,
+        procedure: P,
+        handler: F,
+    ) -> &mut Self
+    where
+        P: Into<u32>,
+        A: TryFrom<Bytes>,
+        R: Serialize,
+        F: Fn(&CallContext, A) -> Result<R> + Send + Sync + 'static,
+    {
+        let handler: Handler = Box::new(move |context, args| {
+            let args = match A::try_from(args) {
+                Ok(a) => a,
+                Err(_) => return Outcome::Unsuccessful(UnsuccessfulAcceptStatus::GarbageArgs),
+            };
+            match handler(context, args).and_then(|r| Ok(serde_xdr::to_bytes(&r)?)) {
+                Ok(payload) => Outcome::Success(payload),
+                Err(e) => e.into(),
+            }
+        });
+        self.programs
+            .entry(program)
+            .or_default()
+            .entry(version)
+            .or_default()
+            .insert(procedure.into(), handler);
+        self
+    }
+    ///all registered (program, version) pairs
+    pub fn program_versions(&self) -> Vec<(u32, u32)> {
+        self.programs
+            .iter()
+            .flat_map(|(p, versions)| versions.keys().map(move |v| (*p, *v)))
+            .collect()
+    }
+
+    fn outcome(&self, context: &CallContext, args: Bytes) -> Outcome {
+        let versions = match self.programs.get(&context.program) {
+            Some(v) => v,
+            None => return Outcome::Unsuccessful(UnsuccessfulAcceptStatus::ProgramUnavailable),
+        };
+        let procedures = match versions.get(&context.version) {
+            Some(p) => p,
+            None => {
+                return Outcome::Unsuccessful(UnsuccessfulAcceptStatus::ProgramMismatch {
+                    low: *versions.keys().next().unwrap(),
+                    high: *versions.keys().next_back().unwrap(),
+                })
+            }
+        };
+        match procedures.get(&context.procedure) {
+            Some(handler) => handler(context, args),
+            None if context.procedure == NULL_PROCEDURE => Outcome::Success(Vec::new()),
+            None => Outcome::Unsuccessful(UnsuccessfulAcceptStatus::ProcedureUnavailable),
+        }
+    }
+
+    ///serve one record as returned by [`RpcStream::read_raw`] or [`RpcSocket::recv_raw_from`],
+    ///returns the reply to send back, or `None` if the record isn't a call
+    pub fn dispatch(
+        &self,
+        record: Bytes,
+        peer: Option<SocketAddr>,
+    ) -> Option<RpcMessage<Bytes, Bytes>> {
+        let message = match super::parse_message(record.clone()) {
+            Ok(m) => m,
+            Err(Error::OncRpcError(OncRpcError::InvalidRpcMessage(
+                onc_rpc::Error::InvalidRpcVersion(_),
+            ))) if record.len() >= 4 => {
+                let xid = record.slice(..4).get_u32();
+                return Some(reply(
+                    xid,
+                    Outcome::Rejected(RejectedReply::RpcVersionMismatch {
+                        low: RPC_VERSION,
+                        high: RPC_VERSION,
+                    }),
+                ));
+            }
+            Err(_) => return None,
+        };
+        let call = message.call_body()?;
+        let context = CallContext {
+            xid: message.xid(),
+            program: call.program(),
+            version: call.program_version(),
+            procedure: call.procedure(),
+            credentials: call.auth_credentials().clone(),
+            peer,
+        };
+        let outcome = self.outcome(&context, call.payload().clone());
+        Some(reply(context.xid, outcome))
+    }
+
+    ///read a single call from `io` and send the reply
+    pub fn serve_one<S: RpcStream>(&self, io: &mut S, peer: Option<SocketAddr>) -> Result<()> {
+        let record = io.read_raw(BytesMut::new())?;
+        if let Some(reply) = self.dispatch(record, peer) {
+            io.send(reply)?;
+        }
+        Ok(())
+    }
+    ///serve calls on a connection until the peer closes it
+    pub fn serve_connection<S: RpcStream>(
+        &self,
+        io: &mut S,
+        peer: Option<SocketAddr>,
+    ) -> Result<()> {
+        loop {
+            match self.serve_one(io, peer) {
+                Ok(()) => {}
+                Err(Error::IOError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    return Ok(())
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    ///receive a single datagram from `socket` and send the reply to its origin
+    pub fn serve_datagram<S: RpcSocket>(&self, socket: &S) -> Result<()> {
+        let (record, peer) = socket.recv_raw_from(BytesMut::new())?;
+        if let Some(reply) = self.dispatch(record, Some(peer)) {
+            socket.send_to(reply, peer)?;
+        }
+        Ok(())
+    }
+    ///accept connections on `listener` in a background thread, each one served in its own thread
+    pub fn serve_tcp(self: Arc<Self>, listener: TcpListener) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+                let server = self.clone();
+                std::thread::spawn(move || {
+                    let peer = stream.peer_addr().ok();
+                    let _ = server.serve_connection(&mut stream, peer);
+                });
+            }
+        })
+    }
+    ///serve datagrams received on `socket` in a background thread
+    pub fn serve_udp(self: Arc<Self>, socket: UdpSocket) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            match self.serve_datagram(&socket) {
+                Err(Error::IOError(e))
+                    if !matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock
+                            | std::io::ErrorKind::TimedOut
+                            | std::io::ErrorKind::Interrupted
+                    ) =>
+                {
+                    break
+                }
+                _ => {}
+            }
+        })
+    }
+
+    ///map every registered program version to `port` on the portmapper at `portmapper`
+    pub fn register_portmapper(
+        &self,
+        portmapper: SocketAddr,
+        protocol: IpProtocol,
+        port: u16,
+        time_out: Duration,
+    ) -> Result<()> {
+        let mut port_mapper = PortMapper::new_tcp(portmapper, time_out)?;
+        for (prog, vers) in self.program_versions() {
+            if !port_mapper.set(prog, vers, protocol, port as u32)? {
+                return Err(format!(
+                    "portmapper refused mapping program {} version {} to port {}",
+                    prog, vers, port
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+    ///remove the mappings of every registered program version from the portmapper
+    pub fn unregister_portmapper(&self, portmapper: SocketAddr, time_out: Duration) -> Result<()> {
+        let mut port_mapper = PortMapper::new_tcp(portmapper, time_out)?;
+        for (prog, vers) in self.program_versions() {
+            port_mapper.unset(prog, vers)?;
+        }
+        Ok(())
+    }
+}
+
+fn reply(xid: u32, outcome: Outcome) -> RpcMessage<Bytes, Bytes> {
+    let body = match outcome {
+        Outcome::Success(payload) => ReplyBody::Accepted(AcceptedReply::new(
+            AuthFlavor::AuthNone(None),
+            onc_rpc::AcceptedStatus::Success(Bytes::from(payload)),
+        )),
+        Outcome::Unsuccessful(s) => ReplyBody::Accepted(AcceptedReply::new(
+            AuthFlavor::AuthNone(None),
+            s.to_accepted_status(),
+        )),
+        Outcome::Rejected(r) => ReplyBody::Denied(r.to_rejected_reply()),
+    };
+    RpcMessage::new(xid, MessageType::Reply(body))
+}
+
+#[test]
+fn serve_port_mapper_over_tcp() -> Result<()> {
+    use super::{port_mapper::Procedure, xdr::mapping, RpcProgram};
+    const PROGRAM: u32 = <PortMapper<std::net::TcpStream> as RpcProgram>::PROGRAM;
+    let mut server = RpcServer::new();
+    server.register(PROGRAM, 2, Procedure::GetPort, |_, m: mapping| {
+        Ok(m.prog + m.vers)
+    });
+    server.register(PROGRAM, 5, Procedure::GetPort, |_, _: mapping| Ok(0_u32));
+    server.register(PROGRAM, 2, Procedure::Dump, |_, _: Bytes| {
+        let vxi11 = mapping {
+            prog: 0x0607AF,
+            vers: 1,
+            prot: 6,
+            port: 1024,
+        };
+        Ok((1_u32, vxi11, 0_u32))
+    });
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let _ = Arc::new(server).serve_tcp(listener);
+    let mut client = PortMapper::new_tcp(addr, Duration::from_secs(1))?;
+    assert_eq!(client.tcp_port(1000, 7)?, 1007);
+    let dump = client.dump()?;
+    assert_eq!(
+        (dump.len(), dump[0].prog, dump[0].port),
+        (1, 0x0607AF, 1024)
+    );
+    match client.set(1, 1, IpProtocol::Tcp, 1) {
+        Err(Error::OncRpcError(OncRpcError::UnsuccessfulAcceptStatus(
+            UnsuccessfulAcceptStatus::ProcedureUnavailable,
+        ))) => {}
+        other => panic!("unexpected reply {:?}", other),
+    }
+    Ok(())
+}
+
+#[test]
+fn serve_port_mapper_over_udp() -> Result<()> {
+    use super::{port_mapper::Procedure, xdr::mapping, RpcProgram};
+    const PROGRAM: u32 = <PortMapper<UdpSocket> as RpcProgram>::PROGRAM;
+    let mut server = RpcServer::new();
+    server.register(PROGRAM, 2, Procedure::GetPort, |context, m: mapping| {
+        assert!(context.peer.is_some());
+        Ok(m.port + u32::from(m.prot == 17))
+    });
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    let addr = socket.local_addr()?;
+    let _ = Arc::new(server).serve_udp(socket);
+    let mut client = PortMapper::new_udp("127.0.0.1:0", addr, Duration::from_secs(1))?;
+    assert_eq!(client.udp_port(1000, 7)?, 1);
+    assert_eq!(client.tcp_port(1000, 7)?, 0);
+    Ok(())
+}
+
+#[test]
+fn unknown_programs_and_versions() -> Result<()> {
+    use super::{port_mapper::Procedure, xdr::mapping, RpcProgram};
+    const PROGRAM: u32 = <PortMapper<UdpSocket> as RpcProgram>::PROGRAM;
+    let serve = |server: RpcServer| -> Result<PortMapper<UdpSocket>> {
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        let addr = socket.local_addr()?;
+        let _ = Arc::new(server).serve_udp(socket);
+        PortMapper::new_udp("127.0.0.1:0", addr, Duration::from_secs(1))
+    };
+    //another program only
+    let mut server = RpcServer::new();
+    server.register(PROGRAM + 1, 2, Procedure::GetPort, |_, _: mapping| {
+        Ok(0_u32)
+    });
+    match serve(server)?.tcp_port(1000, 7) {
+        Err(Error::OncRpcError(OncRpcError::UnsuccessfulAcceptStatus(
+            UnsuccessfulAcceptStatus::ProgramUnavailable,
+        ))) => {}
+        other => panic!("unexpected reply {:?}", other),
+    }
+    //the port mapper, but not the version 2 the client calls
+    let mut server = RpcServer::new();
+    server.register(PROGRAM, 3, Procedure::GetPort, |_, _: mapping| Ok(0_u32));
+    server.register(PROGRAM, 4, Procedure::GetPort, |_, _: mapping| Ok(0_u32));
+    match serve(server)?.tcp_port(1000, 7) {
+        Err(Error::OncRpcError(OncRpcError::UnsuccessfulAcceptStatus(
+            UnsuccessfulAcceptStatus::ProgramMismatch { low: 3, high: 4 },
+        ))) => {}
+        other => panic!("unexpected reply {:?}", other),
+    }
+    Ok(())
+}
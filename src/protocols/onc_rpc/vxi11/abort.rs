@@ -1,8 +1,12 @@
 use super::{xdr, ErrorCode, Result};
 use bytes::BytesMut;
-use std::net::TcpStream;
+use std::sync::Arc;
 
-use crate::protocols::onc_rpc::{Rpc, RpcProgram};
+use crate::protocols::onc_rpc::{
+    auth::{AuthNone, AuthProvider},
+    policy::CallPolicy,
+    Rpc, RpcProgram, RpcTransport,
+};
 
 pub enum Procedure {
     ///device aborts an in-progress call
@@ -21,6 +25,8 @@ impl From<Procedure> for u32 {
 pub struct Abort<S> {
     io: S,
     buffer: BytesMut,
+    auth: Arc<dyn AuthProvider>,
+    policy: CallPolicy,
 }
 
 impl<S> RpcProgram for Abort<S> {
@@ -36,6 +42,12 @@ impl<S> RpcProgram for Abort<S> {
     fn buffer(&self) -> BytesMut {
         self.buffer.clone()
     }
+    fn auth(&self) -> &dyn AuthProvider {
+        self.auth.as_ref()
+    }
+    fn policy(&self) -> &CallPolicy {
+        &self.policy
+    }
 }
 
 impl<S> Abort<S> {
@@ -43,14 +55,24 @@ impl<S> Abort<S> {
         Self {
             io,
             buffer: BytesMut::new(),
+            auth: Arc::new(AuthNone),
+            policy: CallPolicy::DEFAULT,
         }
     }
+    pub fn set_auth(&mut self, auth: Arc<dyn AuthProvider>) -> &mut Self {
+        self.auth = auth;
+        self
+    }
+    pub fn set_policy(&mut self, policy: CallPolicy) -> &mut Self {
+        self.policy = policy;
+        self
+    }
 }
 
-impl Abort<TcpStream> {
+impl<S: RpcTransport> Abort<S> {
     pub fn device_abort(&mut self, link_id: i32) -> Result<()> {
         let resp: xdr::Device_Error =
-            self.call_anonymously(Procedure::DeviceAbort, xdr::Device_Link(xdr::long(link_id)))?;
+            self.call_authenticated(Procedure::DeviceAbort, xdr::Device_Link(xdr::long(link_id)))?;
         Result::from(ErrorCode::from(resp))
     }
 }
@@ -1,11 +1,16 @@
 use super::Result;
-use crate::protocols::onc_rpc::{IpProtocol, Rpc, RpcProgram};
+use crate::protocols::onc_rpc::{
+    auth::{AuthNone, AuthProvider},
+    policy::CallPolicy,
+    IpProtocol, Rpc, RpcProgram, RpcTransport,
+};
 use bytes::{Bytes, BytesMut};
 use std::{
     net::{IpAddr, TcpStream, ToSocketAddrs},
+    sync::Arc,
 };
 
-use super::{xdr, DeviceFlags, ErrorCode};
+use super::{xdr, ByteOrder, DataSize, DeviceFlags, ErrorCode};
 pub enum Procedure {
     ///opens a link to a device
     CreateLink,
@@ -62,9 +67,12 @@ impl From<Procedure> for u32 {
     }
 }
 use Procedure::*;
+#[derive(Clone)]
 pub struct Core<S> {
     io: S,
     buffer: BytesMut,
+    auth: Arc<dyn AuthProvider>,
+    policy: CallPolicy,
 }
 
 impl<S> RpcProgram for Core<S> {
@@ -80,6 +88,12 @@ impl<S> RpcProgram for Core<S> {
     fn buffer(&self) -> BytesMut {
         self.buffer.clone()
     }
+    fn auth(&self) -> &dyn AuthProvider {
+        self.auth.as_ref()
+    }
+    fn policy(&self) -> &CallPolicy {
+        &self.policy
+    }
 }
 
 impl<S> Core<S> {
@@ -87,8 +101,18 @@ impl<S> Core<S> {
         Self {
             io,
             buffer: BytesMut::new(),
+            auth: Arc::new(AuthNone),
+            policy: CallPolicy::DEFAULT,
         }
     }
+    pub fn set_auth(&mut self, auth: Arc<dyn AuthProvider>) -> &mut Self {
+        self.auth = auth;
+        self
+    }
+    pub fn set_policy(&mut self, policy: CallPolicy) -> &mut Self {
+        self.policy = policy;
+        self
+    }
 }
 
 impl Core<TcpStream> {
@@ -97,8 +121,13 @@ impl Core<TcpStream> {
         Ok(Self {
             io,
             buffer: BytesMut::new(),
+            auth: Arc::new(AuthNone),
+            policy: CallPolicy::DEFAULT,
         })
     }
+}
+
+impl<S: RpcTransport> Core<S> {
     ///return (link_id,abort_port,max_recv_size)
     pub fn create_link(
         &mut self,
@@ -107,7 +136,7 @@ impl Core<TcpStream> {
         lock_timeout: u32,
         name: String,
     ) -> Result<(i32, u32, u32)> {
-        let resp: xdr::Create_LinkResp = self.call_anonymously(
+        let resp: xdr::Create_LinkResp = self.call_authenticated(
             CreateLink,
             xdr::Create_LinkParms {
                 clientId: xdr::long(client_id),
@@ -131,7 +160,7 @@ impl Core<TcpStream> {
 
     pub fn destroy_link(&mut self, link_id: i32) -> Result<()> {
         let resp: xdr::Device_Error =
-            self.call_anonymously(DestroyLink, xdr::Device_Link(xdr::long(link_id)))?;
+            self.call_authenticated(DestroyLink, xdr::Device_Link(xdr::long(link_id)))?;
         Result::from(ErrorCode::from(resp))
     }
     pub fn device_write<D: AsRef<[u8]>>(
@@ -142,7 +171,7 @@ impl Core<TcpStream> {
         io_timeout: u32,
         data: D,
     ) -> Result<usize> {
-        let resp: xdr::Device_WriteResp = self.call_anonymously(
+        let resp: xdr::Device_WriteResp = self.call_authenticated(
             DeviceWrite,
             xdr::Device_WriteParms {
                 lid: xdr::Device_Link(xdr::long(link_id)),
@@ -164,7 +193,7 @@ impl Core<TcpStream> {
         req_size: usize,
         term: char,
     ) -> Result<Bytes> {
-        let resp: xdr::Device_ReadResp<Bytes> = self.call_anonymously(
+        let resp: xdr::Device_ReadResp<Bytes> = self.call_authenticated(
             DeviceRead,
             xdr::Device_ReadParms {
                 lid: xdr::Device_Link(xdr::long(link_id)),
@@ -198,7 +227,7 @@ impl Core<TcpStream> {
         lock_timeout: u32,
         io_timeout: u32,
     ) -> Result<u32> {
-        let resp: xdr::Device_ReadStbResp = self.call_anonymously(
+        let resp: xdr::Device_ReadStbResp = self.call_authenticated(
             DeviceReadStb,
             xdr::Device_GenericParms {
                 lid: xdr::Device_Link(xdr::long(link_id)),
@@ -217,7 +246,7 @@ impl Core<TcpStream> {
         lock_timeout: u32,
         io_timeout: u32,
     ) -> Result<()> {
-        let resp: xdr::Device_Error = self.call_anonymously(
+        let resp: xdr::Device_Error = self.call_authenticated(
             DeviceTrigger,
             xdr::Device_GenericParms {
                 lid: xdr::Device_Link(xdr::long(link_id)),
@@ -235,7 +264,7 @@ impl Core<TcpStream> {
         lock_timeout: u32,
         io_timeout: u32,
     ) -> Result<()> {
-        let resp: xdr::Device_Error = self.call_anonymously(
+        let resp: xdr::Device_Error = self.call_authenticated(
             DeviceClear,
             xdr::Device_GenericParms {
                 lid: xdr::Device_Link(xdr::long(link_id)),
@@ -253,7 +282,7 @@ impl Core<TcpStream> {
         lock_timeout: u32,
         io_timeout: u32,
     ) -> Result<()> {
-        let resp: xdr::Device_Error = self.call_anonymously(
+        let resp: xdr::Device_Error = self.call_authenticated(
             DeviceRemote,
             xdr::Device_GenericParms {
                 lid: xdr::Device_Link(xdr::long(link_id)),
@@ -271,7 +300,7 @@ impl Core<TcpStream> {
         lock_timeout: u32,
         io_timeout: u32,
     ) -> Result<()> {
-        let resp: xdr::Device_Error = self.call_anonymously(
+        let resp: xdr::Device_Error = self.call_authenticated(
             DeviceLocal,
             xdr::Device_GenericParms {
                 lid: xdr::Device_Link(xdr::long(link_id)),
@@ -288,7 +317,7 @@ impl Core<TcpStream> {
         flags: DeviceFlags,
         lock_timeout: u32,
     ) -> Result<()> {
-        let resp: xdr::Device_Error = self.call_anonymously(
+        let resp: xdr::Device_Error = self.call_authenticated(
             DeviceLock,
             xdr::Device_LockParms {
                 lid: xdr::Device_Link(xdr::long(link_id)),
@@ -300,7 +329,7 @@ impl Core<TcpStream> {
     }
     pub fn device_unlock(&mut self, link_id: i32) -> Result<()> {
         let resp: xdr::Device_Error =
-            self.call_anonymously(DeviceUnlock, &xdr::Device_Link(xdr::long(link_id)))?;
+            self.call_authenticated(DeviceUnlock, xdr::Device_Link(xdr::long(link_id)))?;
         Result::from(ErrorCode::from(resp))
     }
 
@@ -323,7 +352,7 @@ impl Core<TcpStream> {
             IpAddr::V4(i) => i,
             IpAddr::V6(_) => panic!("ipv6 not supported by vxi11"),
         };
-        let resp: xdr::Device_Error = self.call_anonymously(
+        let resp: xdr::Device_Error = self.call_authenticated(
             CreateIntrChan,
             xdr::Device_RemoteFunc {
                 hostAddr: xdr::ulong(u32::from_be_bytes(ip.octets())), //not sure if big endian
@@ -336,7 +365,7 @@ impl Core<TcpStream> {
         Result::from(ErrorCode::from(resp))
     }
     pub fn destroy_intr_chan(&mut self) -> Result<()> {
-        let resp: xdr::Device_Error = self.call_anonymously(DestroyIntrChan, ())?;
+        let resp: xdr::Device_Error = self.call_authenticated(DestroyIntrChan, ())?;
         Result::from(ErrorCode::from(resp))
     }
     pub fn device_enable_srq<D: AsRef<[u8]>>(
@@ -346,7 +375,7 @@ impl Core<TcpStream> {
         handle: D,
     ) -> Result<()> {
         debug_assert!(handle.as_ref().len() <= 40); //handle<40>
-        let resp: xdr::Device_Error = self.call_anonymously(
+        let resp: xdr::Device_Error = self.call_authenticated(
             DeviceEnableSrq,
             xdr::Device_EnableSrqParms {
                 lid: xdr::Device_Link(xdr::long(link_id)),
@@ -363,11 +392,11 @@ impl Core<TcpStream> {
         lock_timeout: u32,
         io_timeout: u32,
         cmd: i32,
-        network_order: bool,
-        data_size: i32,
+        byte_order: ByteOrder,
+        data_size: DataSize,
         data_in: D,
     ) -> Result<Bytes> {
-        let resp: xdr::Device_DocmdResp<Bytes> = self.call_anonymously(
+        let resp: xdr::Device_DocmdResp<Bytes> = self.call_authenticated(
             DeviceDoCmd,
             xdr::Device_DocmdParms {
                 lid: xdr::Device_Link(xdr::long(link_id)),
@@ -375,11 +404,11 @@ impl Core<TcpStream> {
                 io_timeout: xdr::ulong(io_timeout),
                 lock_timeout: xdr::ulong(lock_timeout),
                 cmd: xdr::long(cmd),
-                network_order,
+                network_order: byte_order == ByteOrder::Network,
                 /*
                 indicates the size of individual data elements A value of one(1) in datasize means byte data and no swapping is performed. A value of two(2) in datasize means 16-bit word data and bytes are swapped on word boundaries. A value of four(4) in datasize means 32-bit longword data and bytes are swapped on longword boundaries. A value of eight(8) in datasize means 64-bit data and bytes are swapped on 8-byte boundaries.
                 */
-                datasize: xdr::long(data_size),
+                datasize: xdr::long(data_size.into()),
                 data_in: serde_bytes::Bytes::new(data_in.as_ref()),
             },
         )?;
@@ -0,0 +1,208 @@
+//! Commands of a TCP/IP-IEEE 488.1 interface device (VXI-11.2), such as a LAN/GPIB gateway,
+//! carried by `device_docmd` on a link to the interface itself, e.g. `gpib0`.
+use super::{ByteOrder, DataSize, Vxi11, Vxi11Client};
+use crate::{protocols::gpib::GpibAddress, Result};
+use bytes::Bytes;
+
+pub const SEND_COMMAND: i32 = 0x02_0000;
+pub const BUS_STATUS: i32 = 0x02_0001;
+pub const ATN_CONTROL: i32 = 0x02_0002;
+pub const REN_CONTROL: i32 = 0x02_0003;
+pub const PASS_CONTROL: i32 = 0x02_0004;
+pub const BUS_ADDRESS: i32 = 0x02_000A;
+pub const IFC_CONTROL: i32 = 0x02_0010;
+
+///IEEE 488.1 command bytes, sent with [`Vxi11::gpib_send_command`]
+pub mod command {
+    ///go to local
+    pub const GTL: u8 = 0x01;
+    ///selected device clear
+    pub const SDC: u8 = 0x04;
+    ///parallel poll configure
+    pub const PPC: u8 = 0x05;
+    ///group execute trigger
+    pub const GET: u8 = 0x08;
+    ///take control
+    pub const TCT: u8 = 0x09;
+    ///local lockout
+    pub const LLO: u8 = 0x11;
+    ///device clear
+    pub const DCL: u8 = 0x14;
+    ///parallel poll unconfigure
+    pub const PPU: u8 = 0x15;
+    ///serial poll enable
+    pub const SPE: u8 = 0x18;
+    ///serial poll disable
+    pub const SPD: u8 = 0x19;
+    ///unlisten
+    pub const UNL: u8 = 0x3F;
+    ///untalk
+    pub const UNT: u8 = 0x5F;
+    ///my listen address
+    pub fn listen(primary: u8) -> u8 {
+        0x20 + (primary & 0x1F)
+    }
+    ///my talk address
+    pub fn talk(primary: u8) -> u8 {
+        0x40 + (primary & 0x1F)
+    }
+    ///secondary address
+    pub fn secondary(secondary: u8) -> u8 {
+        0x60 + (secondary & 0x1F)
+    }
+}
+
+///what the bus status command reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusStatus {
+    ///REN line asserted
+    Remote = 1,
+    ///SRQ line asserted
+    Srq = 2,
+    ///NDAC line asserted
+    Ndac = 3,
+    ///the interface is system controller
+    SystemController = 4,
+    ///the interface is controller in charge
+    ControllerInCharge = 5,
+    ///the interface is addressed to talk
+    Talker = 6,
+    ///the interface is addressed to listen
+    Listener = 7,
+    ///bus address of the interface
+    BusAddress = 8,
+}
+
+///device name of the instrument at `primary` (and `secondary`) on `interface`, e.g. `gpib0,8`
+pub fn gpib_device(interface: &str, primary: u8, secondary: Option<u8>) -> Result<String> {
+    let address = GpibAddress::new(primary, secondary)?;
+    Ok(format!("{},{}", interface, address))
+}
+
+impl Vxi11Client {
+    ///link to the instrument at `primary` (and `secondary`) on `gpib0` instead of `inst0`
+    pub fn set_gpib_address(&mut self, primary: u8, secondary: Option<u8>) -> Result<&mut Self> {
+        self.device = gpib_device("gpib0", primary, secondary)?;
+        Ok(self)
+    }
+}
+
+fn decode_short(data: &[u8]) -> Result<u16> {
+    match data {
+        [h, l] => Ok(u16::from_be_bytes([*h, *l])),
+        _ => Err(format!("expected a 2 bytes result, found {} bytes", data.len()).into()),
+    }
+}
+
+impl Vxi11 {
+    ///send bytes with ATN asserted, return the bytes the interface sent
+    pub fn gpib_send_command<D: AsRef<[u8]>>(&mut self, commands: D) -> Result<Bytes> {
+        self.do_cmd(SEND_COMMAND, ByteOrder::Network, DataSize::Byte, commands)
+    }
+    ///flags are reported as 0 or 1, [`BusStatus::BusAddress`] as the address
+    pub fn gpib_bus_status(&mut self, status: BusStatus) -> Result<u16> {
+        let data = self.do_cmd(
+            BUS_STATUS,
+            ByteOrder::Network,
+            DataSize::Word,
+            (status as u16).to_be_bytes(),
+        )?;
+        decode_short(&data)
+    }
+    ///assert or release ATN
+    pub fn gpib_atn(&mut self, assert: bool) -> Result<()> {
+        self.do_cmd(
+            ATN_CONTROL,
+            ByteOrder::Network,
+            DataSize::Word,
+            (assert as u16).to_be_bytes(),
+        )
+        .map(|_| ())
+    }
+    ///assert or release REN
+    pub fn gpib_ren(&mut self, assert: bool) -> Result<()> {
+        self.do_cmd(
+            REN_CONTROL,
+            ByteOrder::Network,
+            DataSize::Word,
+            (assert as u16).to_be_bytes(),
+        )
+        .map(|_| ())
+    }
+    ///pass control to the device at `address`
+    pub fn gpib_pass_control(&mut self, address: u8) -> Result<()> {
+        GpibAddress::new(address, None)?;
+        self.do_cmd(
+            PASS_CONTROL,
+            ByteOrder::Network,
+            DataSize::Long,
+            (address as u32).to_be_bytes(),
+        )
+        .map(|_| ())
+    }
+    ///set the bus address of the interface
+    pub fn gpib_set_bus_address(&mut self, address: u8) -> Result<()> {
+        GpibAddress::new(address, None)?;
+        self.do_cmd(
+            BUS_ADDRESS,
+            ByteOrder::Network,
+            DataSize::Long,
+            (address as u32).to_be_bytes(),
+        )
+        .map(|_| ())
+    }
+    ///pulse IFC, the interface becomes controller in charge
+    pub fn gpib_ifc(&mut self) -> Result<()> {
+        self.do_cmd(IFC_CONTROL, ByteOrder::Network, DataSize::Byte, [])
+            .map(|_| ())
+    }
+}
+
+#[test]
+fn gateway_commands() -> Result<()> {
+    use super::{core::Procedure, tests::fake_device, xdr};
+    use crate::protocols::onc_rpc::server::RpcServer;
+    assert_eq!(gpib_device("gpib0", 8, None)?, "gpib0,8");
+    assert_eq!(gpib_device("gpib0", 8, Some(2))?, "gpib0,8,2");
+    assert!(gpib_device("gpib0", 31, None).is_err());
+    let mut server = RpcServer::new();
+    server.register(
+        super::tests::PROGRAM,
+        super::tests::VERSION,
+        Procedure::DeviceDoCmd,
+        |_, p: xdr::Device_DocmdParms<Bytes>| {
+            assert!(p.network_order);
+            let data_out = match (p.cmd.0, p.datasize.0, p.data_in.as_ref()) {
+                (SEND_COMMAND, 1, data) => data.to_vec(),
+                (BUS_STATUS, 2, [0, 8]) => vec![0, 21],
+                (BUS_STATUS, 2, [0, 2]) => vec![0, 1],
+                (ATN_CONTROL, 2, [0, 1]) | (REN_CONTROL, 2, [0, 0]) => vec![],
+                (PASS_CONTROL, 4, [0, 0, 0, 8]) | (BUS_ADDRESS, 4, [0, 0, 0, 21]) => vec![],
+                (IFC_CONTROL, _, []) => vec![],
+                other => panic!("unexpected docmd {:?}", other),
+            };
+            Ok(xdr::Device_DocmdResp {
+                error: xdr::Device_ErrorCode(xdr::long(0)),
+                data_out: serde_bytes::ByteBuf::from(data_out),
+            })
+        },
+    );
+    let mut client = Vxi11Client::default();
+    client.set_gpib_address(8, None)?;
+    assert!(fake_device(RpcServer::new(), &client, "gpib0,8").is_ok());
+    assert!(fake_device(RpcServer::new(), &client, "inst0").is_err());
+    //gateway commands go to a link on the interface itself
+    client.device = "gpib0".to_string();
+    let mut gateway = fake_device(server, &client, "gpib0")?;
+    let commands = [command::UNL, command::listen(8), command::SDC];
+    assert_eq!(gateway.gpib_send_command(commands)?.as_ref(), commands);
+    assert_eq!(gateway.gpib_bus_status(BusStatus::BusAddress)?, 21);
+    assert_eq!(gateway.gpib_bus_status(BusStatus::Srq)?, 1);
+    gateway.gpib_atn(true)?;
+    gateway.gpib_ren(false)?;
+    gateway.gpib_pass_control(8)?;
+    gateway.gpib_set_bus_address(21)?;
+    gateway.gpib_ifc()?;
+    assert!(gateway.gpib_pass_control(31).is_err());
+    Ok(())
+}
@@ -1,7 +1,8 @@
-use super::Result;
-use crate::protocols::onc_rpc::{RpcProgram, RpcStream};
+use super::{xdr, Result};
+use crate::protocols::onc_rpc::{server::RpcServer, RpcProgram, RpcStream};
 
 use bytes::{Bytes, BytesMut};
+use std::sync::mpsc::{channel, Receiver};
 pub enum Procedure {
     ///used by device to send a service request
     DeviceIntrSrq,
@@ -21,6 +22,8 @@ pub struct Interrupt<S> {
     prog_ver: u32,
     io: S,
     buffer: BytesMut,
+    server: RpcServer,
+    requests: Receiver<Bytes>,
 }
 
 impl<S> RpcProgram for Interrupt<S> {
@@ -40,31 +43,46 @@ impl<S> RpcProgram for Interrupt<S> {
 
 impl<S> Interrupt<S> {
     pub fn new(prog_num: u32, prog_ver: u32, io: S) -> Self {
+        let (sender, requests) = channel();
+        let mut server = RpcServer::new();
+        server.register(
+            prog_num,
+            prog_ver,
+            Procedure::DeviceIntrSrq,
+            move |_, parms: xdr::Device_SrqParms<Bytes>| {
+                //the receiver lives as long as the server, sending can't fail
+                let _ = sender.send(parms.handle);
+                Ok(())
+            },
+        );
         Self {
             prog_num,
             prog_ver,
             io,
             buffer: BytesMut::new(),
+            server,
+            requests,
         }
     }
+    pub fn prog_num(&self) -> u32 {
+        self.prog_num
+    }
+    pub fn prog_ver(&self) -> u32 {
+        self.prog_ver
+    }
 }
 
 impl<S> Interrupt<S>
 where
     S: RpcStream,
 {
-    ///receive interrupt message from device
+    ///receive interrupt message from device, returns the handle set by `device_enable_srq`
     pub fn device_intr_srq(&mut self) -> Result<Bytes> {
         loop {
-            let buf = self.buffer();
-            let mess = self.mut_io().read(buf)?;
-            let call = mess.call_body().unwrap();
-            if call.program() == self.prog_num
-                && call.program_version() == self.prog_ver
-                && call.procedure() == u32::from(Procedure::DeviceIntrSrq)
-            {
-                return Ok(call.payload().clone());
+            if let Ok(handle) = self.requests.try_recv() {
+                return Ok(handle);
             }
+            self.server.serve_one(&mut self.io, None)?;
         }
     }
 }
@@ -1,21 +1,28 @@
 use std::{
     fmt::Debug,
+    io::{self, Read, Write},
     net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    ops::{Deref, DerefMut},
+    sync::Arc,
     time::Duration,
 };
 
 use bytes::Bytes;
 
-use crate::protocols::onc_rpc::RpcProgram;
+use crate::{protocols::onc_rpc::RpcProgram, trace};
 
 use self::{abort::Abort, core::Core, interrupt::Interrupt};
 
 use super::{
+    auth::{AuthNone, AuthProvider},
+    mux::MuxClient,
+    policy::{self, CallPolicy},
     port_mapper::{self, PortMapper},
     xdr,
 };
 pub mod abort;
 pub mod core;
+pub mod gpib;
 pub mod interrupt;
 pub mod vxi11_error;
 use crate::Result;
@@ -148,6 +155,9 @@ impl DeviceFlags {
         self.0 |= 1 << 0;
         self
     }
+    pub fn is_wait_lock(self) -> bool {
+        self.0 & (1 << 0) != 0
+    }
     pub fn end(mut self) -> Self {
         self.0 |= 1 << 3;
         self
@@ -168,6 +178,29 @@ impl From<DeviceFlags> for i32 {
     }
 }
 
+///size of the elements of the data of `device_docmd`, bytes are swapped on these boundaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataSize {
+    Byte = 1,
+    Word = 2,
+    Long = 4,
+    Quad = 8,
+}
+impl From<DataSize> for i32 {
+    fn from(s: DataSize) -> Self {
+        s as i32
+    }
+}
+
+///byte order of the data of `device_docmd`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    ///big endian
+    Network,
+    ///the order of the machine the data comes from
+    Host,
+}
+
 impl From<DeviceFlags> for xdr::Device_Flags {
     fn from(f: DeviceFlags) -> Self {
         Self(xdr::long(f.into()))
@@ -187,6 +220,11 @@ pub struct Vxi11Client {
     pub req_size: usize,
     pub term: char,
     pub flags: DeviceFlags,
+    ///credentials sent with every call to the portmapper and the instrument
+    pub auth: Arc<dyn AuthProvider>,
+    pub policy: CallPolicy,
+    ///device name of the link, `inst0` or e.g. `gpib0,8` behind a LAN/GPIB gateway
+    pub device: String,
 }
 
 impl Vxi11Client {
@@ -207,6 +245,9 @@ impl Vxi11Client {
             req_size,
             term,
             flags,
+            auth: Arc::new(AuthNone),
+            policy: CallPolicy::DEFAULT,
+            device: INTERFACE_NAME.to_string(),
         }
     }
 }
@@ -220,6 +261,9 @@ impl Default for Vxi11Client {
             req_size: 100 * 1024 * 1024, //100M
             term: '\n',
             flags: DeviceFlags::new_zero().terminator_set(),
+            auth: Arc::new(AuthNone),
+            policy: CallPolicy::DEFAULT,
+            device: INTERFACE_NAME.to_string(),
         }
     }
 }
@@ -232,9 +276,69 @@ pub struct Vxi11 {
     req_size: usize,
     term: char,
     flags: DeviceFlags,
-    core: Core<TcpStream>,
+    core: Core<MuxClient>,
     abort: Option<Abort<TcpStream>>,
     interrupt: Option<Interrupt<TcpStream>>,
+    config: LinkConfig,
+}
+
+///what it takes to create the link again
+struct LinkConfig {
+    addr: SocketAddr,
+    device: String,
+    client_id: i32,
+    lock: bool,
+    auth: Arc<dyn AuthProvider>,
+    policy: CallPolicy,
+    ///address, program and version of the interrupt channel, once established
+    interrupt: Option<(SocketAddr, u32, u32)>,
+}
+
+///the core channel with a link created on it, and the abort channel it announced
+struct Link {
+    core: Core<MuxClient>,
+    abort: Option<Abort<TcpStream>>,
+    link_id: i32,
+    max_recv_size: u32,
+}
+
+///connect the core channel, create a link and connect the abort channel it announces
+#[tracing::instrument(
+    level = "debug",
+    skip_all,
+    fields(addr = %config.addr, device = %config.device)
+)]
+fn open_link(config: &LinkConfig, lock_timeout: u32) -> Result<Link> {
+    let mut core = Core::new(MuxClient::connect(config.addr)?);
+    core.set_auth(config.auth.clone())
+        .set_policy(config.policy.clone());
+    let (link_id, abort_port, max_recv_size) = core.create_link(
+        config.client_id,
+        config.lock,
+        lock_timeout,
+        config.device.clone(),
+    )?;
+    let abort_addr = SocketAddr::new(config.addr.ip(), abort_port as u16);
+    let abort = match TcpStream::connect(abort_addr) {
+        Ok(c) => {
+            let mut abort = Abort::new(c);
+            abort
+                .set_auth(config.auth.clone())
+                .set_policy(config.policy.clone());
+            Some(abort)
+        }
+        Err(e) => {
+            tracing::warn!(%abort_addr, error = %e, "failed establishing abort channel");
+            None
+        }
+    };
+    tracing::debug!(link_id, abort_port, max_recv_size, "link created");
+    Ok(Link {
+        core,
+        abort,
+        link_id,
+        max_recv_size,
+    })
 }
 
 impl Vxi11 {
@@ -244,44 +348,115 @@ impl Vxi11 {
         lock: bool,
         lock_timeout: Duration,
         io_timeout: Duration,
+    ) -> Result<Self> {
+        Self::new_authenticated(
+            addr,
+            client_id,
+            lock,
+            lock_timeout,
+            io_timeout,
+            Arc::new(AuthNone),
+        )
+    }
+    ///same as [`Vxi11::new`], with `auth` supplying the credentials of the core and abort channels
+    pub fn new_authenticated<A: ToSocketAddrs>(
+        addr: A,
+        client_id: i32,
+        lock: bool,
+        lock_timeout: Duration,
+        io_timeout: Duration,
+        auth: Arc<dyn AuthProvider>,
     ) -> Result<Self> {
         let addr = addr
             .to_socket_addrs()?
             .next()
             .expect("invalid socket address");
-        let name = INTERFACE_NAME.to_string();
-        let mut core = Core::new_tcp::<SocketAddr>(addr)?;
-        let (link_id, abort_port, max_recv_size) =
-            core.create_link(client_id, lock, lock_timeout.as_millis() as u32, name)?;
-        let abort_addr = SocketAddr::new(addr.ip(), abort_port as u16);
-        let abort = match TcpStream::connect(abort_addr) {
-            Ok(c) => Some(Abort::new(c)),
-            Err(e) => {
-                //TO-DO there should be a warning message
-                println!(
-                    "failed establishing abort channel on '{}': {}",
-                    abort_addr.to_string(),
-                    e.to_string()
-                );
-                None
-            }
-        };
+        Self::open(
+            LinkConfig {
+                addr,
+                device: INTERFACE_NAME.to_string(),
+                client_id,
+                lock,
+                auth,
+                policy: CallPolicy::DEFAULT,
+                interrupt: None,
+            },
+            lock_timeout,
+            io_timeout,
+        )
+    }
+    fn open(config: LinkConfig, lock_timeout: Duration, io_timeout: Duration) -> Result<Self> {
+        let lock_timeout = lock_timeout.as_millis() as u32;
+        let link = open_link(&config, lock_timeout)?;
         Ok(Self {
-            abort,
-            core,
+            abort: link.abort,
+            core: link.core,
             interrupt: None,
             io_timeout: io_timeout.as_millis() as u32,
-            lock_timeout: lock_timeout.as_millis() as u32,
-            link_id,
-            max_recv_size,
+            lock_timeout,
+            link_id: link.link_id,
+            max_recv_size: link.max_recv_size,
             req_size: REQ_SIZE,
             term: TERM,
             flags: DeviceFlags::new_zero().terminator_set(),
+            config,
         })
     }
-    pub fn mut_core(&mut self) -> &mut Core<TcpStream> {
+    ///connect again and create a new link, as done after a reset when the policy allows it
+    ///
+    ///locks held by the old link are lost, an established interrupt channel is created again on
+    ///the new link and an error is returned if that fails
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
+    pub fn reconnect(&mut self) -> Result<()> {
+        let link = open_link(&self.config, self.lock_timeout)?;
+        self.core = link.core;
+        self.abort = link.abort;
+        self.link_id = link.link_id;
+        self.max_recv_size = link.max_recv_size;
+        self.interrupt = None;
+        if let Some((addr, prog_num, prog_ver)) = self.config.interrupt {
+            self.establish_interrupt(addr, prog_num, prog_ver)
+                .map_err(|e| {
+                    tracing::warn!(error = %e, "failed establishing the interrupt channel again");
+                    e
+                })?;
+        }
+        Ok(())
+    }
+    ///run `f` on the core channel, reconnecting if the connection was lost
+    ///
+    ///only an `idempotent` call is run again on the new link, a write or a trigger the device may
+    ///already have received returns the error instead of being sent twice
+    fn on_core<R, F>(&mut self, idempotent: bool, mut f: F) -> Result<R>
+    where
+        F: FnMut(&mut Core<MuxClient>, i32) -> Result<R>,
+    {
+        match f(&mut self.core, self.link_id) {
+            Err(e) if self.config.policy.reconnect && policy::is_connection_lost(&e) => {
+                self.reconnect()?;
+                if idempotent {
+                    f(&mut self.core, self.link_id)
+                } else {
+                    Err(e)
+                }
+            }
+            r => r,
+        }
+    }
+    pub fn mut_core(&mut self) -> &mut Core<MuxClient> {
         &mut self.core
     }
+    ///a handle on the same link usable from another thread while calls are in flight here,
+    ///e.g. to poll the status byte during a long read, it stops working after a reconnect
+    pub fn link_handle(&self) -> LinkHandle {
+        LinkHandle {
+            core: self.core.clone(),
+            link_id: self.link_id,
+            lock_timeout: self.lock_timeout,
+            io_timeout: self.io_timeout,
+            flags: self.flags,
+        }
+    }
     pub fn mut_abort(&mut self) -> &mut Option<Abort<TcpStream>> {
         &mut self.abort
     }
@@ -308,16 +483,33 @@ impl Vxi11 {
         self.flags = flags;
         self
     }
+    ///change the credentials of the core and abort channels for the following calls
+    pub fn set_auth(&mut self, auth: Arc<dyn AuthProvider>) -> &mut Self {
+        if let Some(ref mut abort) = self.abort {
+            abort.set_auth(auth.clone());
+        }
+        self.core.set_auth(auth.clone());
+        self.config.auth = auth;
+        self
+    }
+    pub fn set_policy(&mut self, policy: CallPolicy) -> &mut Self {
+        if let Some(ref mut abort) = self.abort {
+            abort.set_policy(policy.clone());
+        }
+        self.core.set_policy(policy.clone());
+        self.config.policy = policy;
+        self
+    }
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
     pub fn device_write<M: AsRef<[u8]>>(&mut self, message: M) -> Result<usize> {
         debug_assert!(message.as_ref().len() <= self.max_recv_size as usize);
-        self.core.device_write(
-            self.link_id,
-            self.flags,
-            self.lock_timeout,
-            self.io_timeout,
-            message,
-        )
+        tracing::debug!(message = %trace::payload(message.as_ref()));
+        let (flags, lock_timeout, io_timeout) = (self.flags, self.lock_timeout, self.io_timeout);
+        self.on_core(false, |core, link_id| {
+            core.device_write(link_id, flags, lock_timeout, io_timeout, message.as_ref())
+        })
     }
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
     pub fn device_write_str<S: AsRef<str>>(&mut self, message: S) -> Result<usize> {
         let message = message.as_ref().as_bytes();
         let mut temp;
@@ -329,44 +521,113 @@ impl Vxi11 {
         } else {
             message
         };
-        self.core.device_write(
-            self.link_id,
-            self.flags,
-            self.lock_timeout,
-            self.io_timeout,
-            mess,
-        )
+        tracing::debug!(message = %trace::text(mess));
+        let (flags, lock_timeout, io_timeout) = (self.flags, self.lock_timeout, self.io_timeout);
+        self.on_core(false, |core, link_id| {
+            core.device_write(link_id, flags, lock_timeout, io_timeout, mess)
+        })
     }
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
     pub fn device_read(&mut self) -> Result<Bytes> {
-        self.core.device_read(
-            self.link_id,
-            self.flags,
-            self.lock_timeout,
-            self.io_timeout,
-            self.req_size,
-            self.term,
-        )
+        let (flags, lock_timeout, io_timeout) = (self.flags, self.lock_timeout, self.io_timeout);
+        let (req_size, term) = (self.req_size, self.term);
+        let data = self.on_core(false, |core, link_id| {
+            core.device_read(link_id, flags, lock_timeout, io_timeout, req_size, term)
+        })?;
+        tracing::debug!(len = data.len(), response = %trace::payload(&data));
+        Ok(data)
     }
     pub fn device_read_str(&mut self) -> Result<String> {
         Ok(String::from_utf8_lossy(self.device_read()?.as_ref()).to_string())
     }
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
     pub fn device_read_stb(&mut self) -> Result<u8> {
-        Ok(self.core.device_read_status(
-            self.link_id,
-            self.flags,
-            self.lock_timeout,
-            self.io_timeout,
-        )? as u8)
+        let (flags, lock_timeout, io_timeout) = (self.flags, self.lock_timeout, self.io_timeout);
+        let stb = self.on_core(true, |core, link_id| {
+            core.device_read_status(link_id, flags, lock_timeout, io_timeout)
+        })? as u8;
+        tracing::debug!(stb = format_args!("{:#04x}", stb));
+        Ok(stb)
     }
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
     pub fn device_enable_srq<D: AsRef<[u8]>>(&mut self, enable: bool, handle: D) -> Result<()> {
-        self.core.device_enable_srq(self.link_id, enable, handle)
+        self.on_core(true, |core, link_id| {
+            core.device_enable_srq(link_id, enable, handle.as_ref())
+        })
     }
+    ///lock the device until the guard is dropped, when the flags carry `wait_lock` the device
+    ///waits up to the lock timeout for another link to release it, otherwise it fails at once
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
+    pub fn lock(&mut self) -> Result<LockGuard<'_>> {
+        let (flags, lock_timeout) = (self.flags, self.lock_timeout);
+        self.on_core(true, |core, link_id| {
+            core.device_lock(link_id, flags, lock_timeout)
+        })?;
+        Ok(LockGuard {
+            session: self,
+            released: false,
+        })
+    }
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
+    pub fn unlock(&mut self) -> Result<()> {
+        self.on_core(true, |core, link_id| core.device_unlock(link_id))
+    }
+    ///device clear, empties the input and output buffers of the device
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
+    pub fn clear(&mut self) -> Result<()> {
+        let (flags, lock_timeout, io_timeout) = (self.flags, self.lock_timeout, self.io_timeout);
+        self.on_core(true, |core, link_id| {
+            core.device_clear(link_id, flags, lock_timeout, io_timeout)
+        })
+    }
+    ///disable the front panel
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
+    pub fn remote(&mut self) -> Result<()> {
+        let (flags, lock_timeout, io_timeout) = (self.flags, self.lock_timeout, self.io_timeout);
+        self.on_core(true, |core, link_id| {
+            core.device_remote(link_id, flags, lock_timeout, io_timeout)
+        })
+    }
+    ///enable the front panel
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
+    pub fn local(&mut self) -> Result<()> {
+        let (flags, lock_timeout, io_timeout) = (self.flags, self.lock_timeout, self.io_timeout);
+        self.on_core(true, |core, link_id| {
+            core.device_local(link_id, flags, lock_timeout, io_timeout)
+        })
+    }
+    ///execute a device specific command, `data_in` is made of elements of `data_size` bytes
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
+    pub fn do_cmd<D: AsRef<[u8]>>(
+        &mut self,
+        cmd: i32,
+        byte_order: ByteOrder,
+        data_size: DataSize,
+        data_in: D,
+    ) -> Result<Bytes> {
+        debug_assert_eq!(data_in.as_ref().len() % data_size as usize, 0);
+        let (flags, lock_timeout, io_timeout) = (self.flags, self.lock_timeout, self.io_timeout);
+        self.on_core(false, |core, link_id| {
+            core.device_do_cmd(
+                link_id,
+                flags,
+                lock_timeout,
+                io_timeout,
+                cmd,
+                byte_order,
+                data_size,
+                data_in.as_ref(),
+            )
+        })
+    }
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
     pub fn device_abort(&mut self) -> Result<()> {
         match self.abort {
             Some(ref mut c) => c.device_abort(self.link_id),
             None => Err(vxi11_error::Vxi11Error::NotEstablished.into()),
         }
     }
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
     pub fn establish_interrupt<A: ToSocketAddrs>(
         &mut self,
         addr: A,
@@ -378,40 +639,488 @@ impl Vxi11 {
             .next()
             .expect("invalid socket address");
         let listener = TcpListener::bind(addr)?;
+        //the port the listener got when `addr` asks for any
+        let local = listener.local_addr()?;
         self.core
-            .create_intr_chan(addr, prog_num, prog_ver, super::IpProtocol::Tcp)?;
-        let (interrupt, addr) = listener.accept()?;
-        debug_assert_eq!(addr, self.core.mut_io().peer_addr()?);
+            .create_intr_chan(local, prog_num, prog_ver, super::IpProtocol::Tcp)?;
+        let (interrupt, peer) = listener.accept()?;
+        debug_assert_eq!(peer.ip(), self.core.get_io().peer_addr()?.ip());
         self.interrupt = Some(Interrupt::new(prog_num, prog_ver, interrupt));
+        self.config.interrupt = Some((addr, prog_num, prog_ver));
+        Ok(())
+    }
+    #[tracing::instrument(level = "debug", skip_all, fields(link = self.link_id))]
+    pub fn device_trigger(&mut self) -> Result<()> {
+        let (flags, lock_timeout, io_timeout) = (self.flags, self.lock_timeout, self.io_timeout);
+        self.on_core(false, |core, link_id| {
+            core.device_trigger(link_id, flags, lock_timeout, io_timeout)
+        })
+    }
+}
+
+///device lock held by a [`Vxi11`] session, released when dropped
+pub struct LockGuard<'a> {
+    session: &'a mut Vxi11,
+    released: bool,
+}
+
+impl LockGuard<'_> {
+    ///release the lock now, reporting a failure dropping the guard would ignore
+    pub fn unlock(mut self) -> Result<()> {
+        self.released = true;
+        self.session.unlock()
+    }
+}
+
+impl Deref for LockGuard<'_> {
+    type Target = Vxi11;
+    fn deref(&self) -> &Vxi11 {
+        self.session
+    }
+}
+
+impl DerefMut for LockGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Vxi11 {
+        self.session
+    }
+}
+
+impl Drop for LockGuard<'_> {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = self.session.unlock();
+        }
+    }
+}
+
+///a [`Vxi11`] link as a byte stream, e.g. to bind a driver to it
+///
+///the bytes written are sent as one message by `flush`, every read is served from one
+///`device_read` before the next
+pub struct Vxi11Io {
+    link: Vxi11,
+    pending: Vec<u8>,
+    received: Bytes,
+}
+
+impl Vxi11Io {
+    pub fn new(link: Vxi11) -> Self {
+        Self {
+            link,
+            pending: Vec::new(),
+            received: Bytes::new(),
+        }
+    }
+    pub fn get_ref(&self) -> &Vxi11 {
+        &self.link
+    }
+    ///the link, bytes written and not flushed aren't seen there
+    pub fn get_mut(&mut self) -> &mut Vxi11 {
+        &mut self.link
+    }
+    pub fn into_inner(self) -> Vxi11 {
+        self.link
+    }
+    ///device clear, bytes not flushed yet and the rest of the last response are dropped
+    pub fn clear(&mut self) -> Result<()> {
+        self.pending.clear();
+        self.received.clear();
+        self.link.clear()
+    }
+}
+
+fn to_io_error(e: crate::error::Error) -> io::Error {
+    match e {
+        crate::error::Error::IOError(e) => e,
+        crate::error::Error::Vxi11Error(vxi11_error::Vxi11Error::IOTimeOut) => {
+            io::Error::new(io::ErrorKind::TimedOut, "VXI-11 I/O timeout")
+        }
+        e => io::Error::other(e),
+    }
+}
+
+impl Write for Vxi11Io {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            self.link.device_write(&self.pending).map_err(to_io_error)?;
+            self.pending.clear();
+        }
         Ok(())
     }
+}
+
+impl Read for Vxi11Io {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.received.is_empty() {
+            self.received = self.link.device_read().map_err(to_io_error)?;
+        }
+        let n = buf.len().min(self.received.len());
+        buf[..n].copy_from_slice(&self.received.split_to(n));
+        Ok(n)
+    }
+}
+
+///cloneable handle sharing the connection and the link of a [`Vxi11`]
+#[derive(Clone)]
+pub struct LinkHandle {
+    core: Core<MuxClient>,
+    link_id: i32,
+    lock_timeout: u32,
+    io_timeout: u32,
+    flags: DeviceFlags,
+}
+
+impl LinkHandle {
+    pub fn link_id(&self) -> i32 {
+        self.link_id
+    }
+    pub fn set_io_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.io_timeout = dur.as_millis() as u32;
+        self
+    }
+    pub fn set_lock_timeout(&mut self, dur: Duration) -> &mut Self {
+        self.lock_timeout = dur.as_millis() as u32;
+        self
+    }
+    pub fn set_flags(&mut self, flags: DeviceFlags) -> &mut Self {
+        self.flags = flags;
+        self
+    }
+    pub fn device_read_stb(&mut self) -> Result<u8> {
+        Ok(self.core.device_read_status(
+            self.link_id,
+            self.flags,
+            self.lock_timeout,
+            self.io_timeout,
+        )? as u8)
+    }
     pub fn device_trigger(&mut self) -> Result<()> {
         self.core
             .device_trigger(self.link_id, self.flags, self.lock_timeout, self.io_timeout)
     }
+    pub fn device_clear(&mut self) -> Result<()> {
+        self.core
+            .device_clear(self.link_id, self.flags, self.lock_timeout, self.io_timeout)
+    }
+    pub fn device_write<M: AsRef<[u8]>>(&mut self, message: M) -> Result<usize> {
+        self.core.device_write(
+            self.link_id,
+            self.flags,
+            self.lock_timeout,
+            self.io_timeout,
+            message,
+        )
+    }
+    pub fn device_read(&mut self, req_size: usize, term: char) -> Result<Bytes> {
+        self.core.device_read(
+            self.link_id,
+            self.flags,
+            self.lock_timeout,
+            self.io_timeout,
+            req_size,
+            term,
+        )
+    }
 }
 
 impl Vxi11Client {
-    pub fn connect(self, address: IpAddr, time_out: Duration) -> Result<Vxi11> {
+    pub fn connect(&self, address: IpAddr, time_out: Duration) -> Result<Vxi11> {
         let mut port_mapper =
             PortMapper::new_tcp(SocketAddr::new(address, port_mapper::PORT), time_out)?;
+        port_mapper
+            .set_auth(self.auth.clone())
+            .set_policy(self.policy.clone());
         let core_port = port_mapper.get_port(
             <Core<TcpStream> as RpcProgram>::PROGRAM,
             <Core<TcpStream> as RpcProgram>::VERSION,
             super::IpProtocol::Tcp,
         )?;
-        let vxi11_addr = SocketAddr::new(address, core_port as u16);
-        let mut ret = Vxi11::new(
-            vxi11_addr,
-            self.client_id,
-            self.lock,
+        self.link(SocketAddr::new(address, core_port as u16))
+    }
+    ///create the link on the core channel listening at `addr`, without asking the portmapper
+    pub fn link<A: ToSocketAddrs>(&self, addr: A) -> Result<Vxi11> {
+        let addr = addr
+            .to_socket_addrs()?
+            .next()
+            .expect("invalid socket address");
+        let mut ret = Vxi11::open(
+            LinkConfig {
+                addr,
+                device: self.device.clone(),
+                client_id: self.client_id,
+                lock: self.lock,
+                auth: self.auth.clone(),
+                policy: self.policy.clone(),
+                interrupt: None,
+            },
             self.lock_timeout,
             self.io_timeout,
         )?;
         ret.flags = self.flags;
         ret.req_size = self.req_size;
         ret.term = self.term;
-        ret.flags = self.flags;
         Ok(ret)
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{core::Procedure, *};
+    use crate::protocols::onc_rpc::server::RpcServer;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    pub(crate) const PROGRAM: u32 = <Core<MuxClient> as RpcProgram>::PROGRAM;
+    pub(crate) const VERSION: u32 = <Core<MuxClient> as RpcProgram>::VERSION;
+    const NO_ERROR: xdr::Device_Error = xdr::Device_Error {
+        error: xdr::Device_ErrorCode(xdr::long(0)),
+    };
+
+    ///serve `server` as the core channel of a device named `device`, with link 7 and no abort
+    ///channel, and link to it with `client`
+    pub(crate) fn fake_device(
+        mut server: RpcServer,
+        client: &Vxi11Client,
+        device: &'static str,
+    ) -> Result<Vxi11> {
+        register_link(&mut server, device);
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let _ = Arc::new(server).serve_tcp(listener);
+        client.link(addr)
+    }
+
+    ///same as [`fake_device`], but the connection is closed instead of replying to a call whose
+    ///handler set `kill`
+    fn killable_device(mut server: RpcServer, kill: Arc<AtomicBool>) -> Result<Vxi11> {
+        use crate::protocols::onc_rpc::RpcStream;
+        register_link(&mut server, INTERFACE_NAME);
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = Arc::new(server);
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let (server, kill) = (server.clone(), kill.clone());
+                std::thread::spawn(move || {
+                    let mut stream = stream;
+                    while let Ok(record) = stream.read_raw(bytes::BytesMut::new()) {
+                        let reply = server.dispatch(record, None);
+                        if kill.swap(false, Ordering::SeqCst) {
+                            return;
+                        }
+                        if let Some(reply) = reply {
+                            if stream.send(reply).is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        Vxi11Client::default().link(addr)
+    }
+
+    fn register_link(server: &mut RpcServer, device: &'static str) {
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::CreateLink,
+            move |_, p: xdr::Create_LinkParms| {
+                //invalid address otherwise
+                let error = if p.device == device { 0 } else { 21 };
+                Ok(xdr::Create_LinkResp {
+                    error: xdr::Device_ErrorCode(xdr::long(error)),
+                    lid: xdr::Device_Link(xdr::long(7)),
+                    abortPort: xdr::ushort(0),
+                    maxRecvSize: xdr::ulong(1024),
+                })
+            },
+        );
+    }
+
+    #[test]
+    fn lock_guard_unlocks_on_drop() -> Result<()> {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut server = RpcServer::new();
+        let l = log.clone();
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceLock,
+            move |_, p: xdr::Device_LockParms| {
+                l.lock().unwrap().push(format!(
+                    "lock {} {}",
+                    (p.lid.0).0,
+                    DeviceFlags::from((p.flags.0).0).is_wait_lock()
+                ));
+                Ok(NO_ERROR)
+            },
+        );
+        let l = log.clone();
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceUnlock,
+            move |_, p: xdr::Device_Link| {
+                l.lock().unwrap().push(format!("unlock {}", (p.0).0));
+                Ok(NO_ERROR)
+            },
+        );
+        let l = log.clone();
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceClear,
+            move |_, _: xdr::Device_GenericParms| {
+                l.lock().unwrap().push("clear".to_string());
+                Ok(NO_ERROR)
+            },
+        );
+        let mut vxi11 = fake_device(server, &Vxi11Client::default(), INTERFACE_NAME)?;
+        vxi11.set_flags(DeviceFlags::new_zero().wait_lock());
+        {
+            let mut guard = vxi11.lock()?;
+            guard.clear()?;
+        }
+        vxi11.set_flags(DeviceFlags::new_zero());
+        vxi11.lock()?.unlock()?;
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "lock 7 true",
+                "clear",
+                "unlock 7",
+                "lock 7 false",
+                "unlock 7"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn do_cmd_sends_typed_arguments() -> Result<()> {
+        let mut server = RpcServer::new();
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceDoCmd,
+            |_, p: xdr::Device_DocmdParms<Bytes>| {
+                let mut data_out =
+                    vec![(p.cmd.0) as u8, p.network_order as u8, (p.datasize.0) as u8];
+                data_out.extend_from_slice(&p.data_in);
+                Ok(xdr::Device_DocmdResp {
+                    error: xdr::Device_ErrorCode(xdr::long(0)),
+                    data_out: serde_bytes::ByteBuf::from(data_out),
+                })
+            },
+        );
+        let mut vxi11 = fake_device(server, &Vxi11Client::default(), INTERFACE_NAME)?;
+        let out = vxi11.do_cmd(3, ByteOrder::Network, DataSize::Word, [1, 2])?;
+        assert_eq!(out.as_ref(), [3, 1, 2, 1, 2]);
+        Ok(())
+    }
+
+    #[test]
+    fn io_sends_one_message_per_flush() -> Result<()> {
+        use crate::instruments::{generic::Generic, Driver, Messenger};
+        let last = Arc::new(Mutex::new(Vec::new()));
+        let mut server = RpcServer::new();
+        let l = last.clone();
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceWrite,
+            move |_, p: xdr::Device_WriteParms<Bytes>| {
+                *l.lock().unwrap() = p.data.to_ascii_uppercase();
+                Ok(xdr::Device_WriteResp {
+                    error: xdr::Device_ErrorCode(xdr::long(0)),
+                    size: xdr::ulong(p.data.len() as u32),
+                })
+            },
+        );
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceRead,
+            move |_, _: xdr::Device_ReadParms| {
+                Ok(xdr::Device_ReadResp {
+                    error: xdr::Device_ErrorCode(xdr::long(0)),
+                    reason: xdr::long(1 << 2),
+                    data: serde_bytes::ByteBuf::from(last.lock().unwrap().clone()),
+                })
+            },
+        );
+        let vxi11 = fake_device(server, &Vxi11Client::default(), INTERFACE_NAME)?;
+        let mut instrument = Messenger::new(Vxi11Io::new(vxi11)).bind(Generic);
+        assert_eq!(Driver::query(&mut instrument, b"*idn?")?, b"*IDN?\n");
+        Ok(())
+    }
+
+    #[test]
+    fn reconnect_retries_idempotent_calls_only() -> Result<()> {
+        let kill = Arc::new(AtomicBool::new(false));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let channels = Arc::new(Mutex::new(Vec::new()));
+        let mut server = RpcServer::new();
+        let (l, k) = (log.clone(), kill.clone());
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceWrite,
+            move |_, p: xdr::Device_WriteParms<Bytes>| {
+                let mut log = l.lock().unwrap();
+                log.push(String::from_utf8_lossy(&p.data).to_string());
+                //the first write reaches the device but its reply is lost
+                k.store(log.len() == 1, Ordering::SeqCst);
+                Ok(xdr::Device_WriteResp {
+                    error: xdr::Device_ErrorCode(xdr::long(0)),
+                    size: xdr::ulong(p.data.len() as u32),
+                })
+            },
+        );
+        let (l, k) = (log.clone(), kill.clone());
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceReadStb,
+            move |_, _: xdr::Device_GenericParms| {
+                let mut log = l.lock().unwrap();
+                log.push("stb".to_string());
+                k.store(log.len() == 2, Ordering::SeqCst);
+                Ok(xdr::Device_ReadStbResp {
+                    error: xdr::Device_ErrorCode(xdr::long(0)),
+                    stb: xdr::xdr_uchar(0x40),
+                })
+            },
+        );
+        let c = channels.clone();
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::CreateIntrChan,
+            move |_, p: xdr::Device_RemoteFunc| {
+                let ip = std::net::Ipv4Addr::from(p.hostAddr.0);
+                c.lock()
+                    .unwrap()
+                    .push(TcpStream::connect((ip, p.hostPort.0 as u16))?);
+                Ok(NO_ERROR)
+            },
+        );
+        let mut vxi11 = killable_device(server, kill)?;
+        vxi11.establish_interrupt("127.0.0.1:0", 0x2000_0000, 1)?;
+        let e = vxi11.device_write(b"*TRG").unwrap_err();
+        assert!(policy::is_connection_lost(&e), "{}", e);
+        assert_eq!(channels.lock().unwrap().len(), 2);
+        assert!(vxi11.mut_interrupt().is_some());
+        assert_eq!(vxi11.device_read_stb()?, 0x40);
+        assert_eq!(channels.lock().unwrap().len(), 3);
+        assert_eq!(*log.lock().unwrap(), ["*TRG", "stb", "stb"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,368 @@
+//! Prologix GPIB-USB and GPIB-ETHERNET controllers.
+//!
+//! The adapter is reached through a serial port or a TCP connection on port [`PORT`], lines
+//! starting with `++` configure the adapter, every other line is sent to the addressed
+//! instrument. Data bytes that would otherwise end the line or be taken for a command (CR, LF,
+//! ESC and `+`) are escaped with ESC.
+use super::{gpib::GpibAddress, Protocol};
+use crate::{
+    scpi::{is_complete_response, Scpi, StatusByte},
+    Result,
+};
+use bytes::Bytes;
+use std::{
+    io::{self, Read, Write},
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+///TCP port of GPIB-ETHERNET controllers
+pub const PORT: u16 = 1234;
+const ESC: u8 = 0x1B;
+///the adapter accepts read timeouts of 1 to 3000 ms
+const MAX_READ_TIMEOUT: Duration = Duration::from_millis(3000);
+
+impl GpibAddress {
+    ///argument of `++addr`, secondary addresses are sent as 96-126
+    fn to_command(self) -> String {
+        match self.secondary {
+            Some(s) => format!("{} {}", self.primary, s + 96),
+            None => self.primary.to_string(),
+        }
+    }
+}
+
+///escape `data` so the adapter sends it to the instrument as is
+pub fn escape(data: &[u8], out: &mut Vec<u8>) {
+    out.reserve(data.len());
+    for &b in data {
+        if matches!(b, ESC | b'+' | b'\r' | b'\n') {
+            out.push(ESC);
+        }
+        out.push(b);
+    }
+}
+
+///adapter configuration, connect through [`Serial`](super::Serial) or [`Tcp`](super::Tcp)
+#[derive(Clone, Copy)]
+pub struct Prologix<P> {
+    pub inner: P,
+    ///instrument the returned handle talks to
+    pub address: GpibAddress,
+    ///assert EOI with the last byte sent
+    pub eoi: bool,
+    ///how long the adapter waits for a byte from the instrument, at most 3 s
+    pub read_timeout: Duration,
+}
+
+impl<P: Default> Default for Prologix<P> {
+    fn default() -> Self {
+        Self {
+            inner: P::default(),
+            address: GpibAddress {
+                primary: 1,
+                secondary: None,
+            },
+            eoi: true,
+            read_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+impl<P> Prologix<P> {
+    pub fn new(inner: P, address: GpibAddress) -> Self {
+        Self {
+            inner,
+            address,
+            eoi: true,
+            read_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+impl<P: Protocol> Protocol for Prologix<P>
+where
+    crate::error::Error: From<P::Error>,
+{
+    type Address = P::Address;
+    type Error = crate::error::Error;
+    type IO = PrologixIo<P::IO>;
+    fn connect(
+        self,
+        address: Self::Address,
+        time_out: std::time::Duration,
+    ) -> std::result::Result<Self::IO, Self::Error> {
+        let io = self.inner.connect(address, time_out)?;
+        let mut adapter = Adapter { io, address: None };
+        //controller mode, read only when asked to, nothing appended to the data sent,
+        //the line end of every message is part of the escaped data
+        adapter.command("mode 1")?;
+        adapter.command("auto 0")?;
+        adapter.command(&format!("eoi {}", self.eoi as u8))?;
+        adapter.command("eos 3")?;
+        let read_timeout = self
+            .read_timeout
+            .clamp(Duration::from_millis(1), MAX_READ_TIMEOUT);
+        adapter.command(&format!("read_tmo_ms {}", read_timeout.as_millis()))?;
+        adapter.select(self.address)?;
+        Ok(PrologixIo {
+            adapter: Arc::new(Mutex::new(adapter)),
+            address: self.address,
+            pending: Vec::new(),
+            reading: false,
+        })
+    }
+}
+
+struct Adapter<IO> {
+    io: IO,
+    ///address last sent with `++addr`
+    address: Option<GpibAddress>,
+}
+
+impl<IO: Read + Write> Adapter<IO> {
+    fn command(&mut self, command: &str) -> io::Result<()> {
+        self.io.write_all(format!("++{}\n", command).as_bytes())?;
+        self.io.flush()
+    }
+    fn select(&mut self, address: GpibAddress) -> io::Result<()> {
+        if self.address != Some(address) {
+            self.command(&format!("addr {}", address.to_command()))?;
+            self.address = Some(address);
+        }
+        Ok(())
+    }
+    ///read the line answering an adapter command
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        let mut byte = [0_u8];
+        loop {
+            self.io.read_exact(&mut byte)?;
+            match byte[0] {
+                b'\n' => break,
+                b'\r' => {}
+                b => line.push(b),
+            }
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+}
+
+///connection to one instrument behind the adapter
+///
+///[`PrologixIo::device`] gives handles to other instruments on the same bus, the adapter is
+///switched to the address of a handle before it is used
+pub struct PrologixIo<IO> {
+    adapter: Arc<Mutex<Adapter<IO>>>,
+    address: GpibAddress,
+    ///escaped data waiting for the next flush
+    pending: Vec<u8>,
+    ///`++read` was sent since the last message
+    reading: bool,
+}
+
+impl<IO: Read + Write> PrologixIo<IO> {
+    fn lock(&self) -> io::Result<MutexGuard<'_, Adapter<IO>>> {
+        let mut adapter = self.adapter.lock().unwrap_or_else(|e| e.into_inner());
+        adapter.select(self.address)?;
+        Ok(adapter)
+    }
+    ///handle to another instrument on the same adapter
+    pub fn device(&self, address: GpibAddress) -> Self {
+        Self {
+            adapter: self.adapter.clone(),
+            address,
+            pending: Vec::new(),
+            reading: false,
+        }
+    }
+    pub fn address(&self) -> GpibAddress {
+        self.address
+    }
+    ///run `f` on the connection to the adapter, e.g. to set its timeouts
+    pub fn with_io<R, F: FnOnce(&mut IO) -> R>(&self, f: F) -> R {
+        f(&mut self.adapter.lock().unwrap_or_else(|e| e.into_inner()).io)
+    }
+    ///send `++<command>` to the adapter, with this instrument addressed
+    pub fn adapter_command(&mut self, command: &str) -> Result<()> {
+        Ok(self.lock()?.command(command)?)
+    }
+    ///send `++<command>` and read the line the adapter answers
+    pub fn adapter_query(&mut self, command: &str) -> Result<String> {
+        let mut adapter = self.lock()?;
+        adapter.command(command)?;
+        Ok(adapter.read_line()?)
+    }
+    ///version string of the adapter
+    pub fn version(&mut self) -> Result<String> {
+        self.adapter_query("ver")
+    }
+    ///selected device clear
+    pub fn clear(&mut self) -> Result<()> {
+        self.pending.clear();
+        self.reading = false;
+        self.adapter_command("clr")
+    }
+    ///group execute trigger
+    pub fn trigger(&mut self) -> Result<()> {
+        self.adapter_command("trg")
+    }
+    ///go to local
+    pub fn local(&mut self) -> Result<()> {
+        self.adapter_command("loc")
+    }
+    ///pulse IFC, the adapter becomes controller in charge
+    pub fn interface_clear(&mut self) -> Result<()> {
+        self.adapter_command("ifc")
+    }
+    ///serial poll the instrument
+    pub fn serial_poll(&mut self) -> Result<StatusByte> {
+        let command = format!("spoll {}", self.address.to_command());
+        let answer = self.adapter_query(&command)?;
+        let byte = answer
+            .trim()
+            .parse::<u8>()
+            .map_err(|_| format!("unexpected serial poll answer '{}'", answer))?;
+        Ok(StatusByte::new(byte))
+    }
+}
+
+impl<IO: Read + Write> Write for PrologixIo<IO> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        escape(buf, &mut self.pending);
+        Ok(buf.len())
+    }
+    ///send the data written so far as one message
+    fn flush(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.pending.push(b'\n');
+        let mut adapter = self.lock()?;
+        let sent = adapter
+            .io
+            .write_all(&self.pending)
+            .and_then(|_| adapter.io.flush());
+        drop(adapter);
+        self.pending.clear();
+        self.reading = false;
+        sent
+    }
+}
+
+impl<IO: Read + Write> Read for PrologixIo<IO> {
+    ///address the instrument to talk on the first read after a message,
+    ///the adapter then forwards everything up to EOI
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.flush()?;
+        let ask = !self.reading;
+        let mut adapter = self.lock()?;
+        if ask {
+            adapter.command("read eoi")?;
+        }
+        let read = adapter.io.read(buf);
+        drop(adapter);
+        self.reading = true;
+        read
+    }
+}
+
+impl<IO: Read + Write> Scpi for PrologixIo<IO> {
+    fn write_bin<C: AsRef<[u8]>>(&mut self, content: C) -> Result<()> {
+        self.write_all(content.as_ref())?;
+        Ok(self.flush()?)
+    }
+    fn read_bin(&mut self) -> Result<Bytes> {
+        let mut data = Vec::new();
+        let mut chunk = [0_u8; 1024];
+        while !is_complete_response(&data, Self::TERM) {
+            match self.read(&mut chunk)? {
+                0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                n => data.extend_from_slice(&chunk[..n]),
+            }
+        }
+        Ok(Bytes::from(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    ///records what is written, answers reads from a queue
+    #[derive(Clone, Default)]
+    struct Mock {
+        sent: Rc<RefCell<Vec<u8>>>,
+        answers: Rc<RefCell<VecDeque<u8>>>,
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut answers = self.answers.borrow_mut();
+            let n = buf.len().min(answers.len());
+            for b in buf.iter_mut().take(n) {
+                *b = answers.pop_front().unwrap();
+            }
+            Ok(n)
+        }
+    }
+
+    impl Protocol for Mock {
+        type Address = ();
+        type Error = io::Error;
+        type IO = Mock;
+        fn connect(self, _: (), _: Duration) -> io::Result<Mock> {
+            Ok(self)
+        }
+    }
+
+    fn take_sent(mock: &Mock) -> String {
+        String::from_utf8_lossy(&mock.sent.borrow_mut().split_off(0)).into_owned()
+    }
+
+    #[test]
+    fn escapes_data_bytes() {
+        let mut out = Vec::new();
+        escape(b"a+b\r\n\x1B", &mut out);
+        assert_eq!(out, b"a\x1B+b\x1B\r\x1B\n\x1B\x1B");
+    }
+
+    #[test]
+    fn switches_address_and_reads() -> Result<()> {
+        let mock = Mock::default();
+        let mut dmm = Prologix::new(mock.clone(), GpibAddress::new(22, None)?)
+            .connect((), Duration::from_secs(1))?;
+        assert_eq!(
+            take_sent(&mock),
+            "++mode 1\n++auto 0\n++eoi 1\n++eos 3\n++read_tmo_ms 500\n++addr 22\n"
+        );
+        mock.answers.borrow_mut().extend(b"KEITHLEY,2000\n");
+        assert_eq!(dmm.scpi_query("*IDN?")?, "KEITHLEY,2000\n");
+        assert_eq!(take_sent(&mock), "*IDN?\x1B\n\n++read eoi\n");
+        let mut scope = dmm.device(GpibAddress::new(7, Some(2))?);
+        scope.scpi_send("CURV #13a+\n")?;
+        dmm.scpi_send("*RST")?;
+        assert_eq!(
+            take_sent(&mock),
+            "++addr 7 98\nCURV #13a\x1B+\x1B\n\n++addr 22\n*RST\x1B\n\n"
+        );
+        //a block holding the terminator is read to its end
+        mock.answers.borrow_mut().extend(b"#13a\nb\n");
+        assert_eq!(scope.read_bin()?.as_ref(), b"#13a\nb\n");
+        mock.answers.borrow_mut().extend(b"80\n");
+        assert_eq!(scope.serial_poll()?.to_string(), "80");
+        assert!(GpibAddress::new(31, None).is_err());
+        Ok(())
+    }
+}
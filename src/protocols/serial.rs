@@ -1,5 +1,5 @@
 use super::Protocol;
-use serial::SystemPort;
+use serial::{SerialPort, SystemPort};
 
 #[derive(Clone, Copy)]
 pub struct Serial {
@@ -42,3 +42,24 @@ impl Protocol for Serial {
         Ok(port)
     }
 }
+
+///same settings as [`Serial`], the port is opened by its name, e.g. `COM5` or `/dev/ttyUSB0`,
+///and reads time out after the given time
+#[derive(Clone, Copy, Default)]
+pub struct SerialDevice(pub Serial);
+
+impl Protocol for SerialDevice {
+    type Address = String;
+    type Error = serial::Error;
+    type IO = SystemPort;
+    fn connect(
+        self,
+        address: Self::Address,
+        time_out: std::time::Duration,
+    ) -> Result<Self::IO, Self::Error> {
+        let mut port = serial::open(&address)?;
+        crate::config_serial(&mut port, self.0)?;
+        port.set_timeout(time_out)?;
+        Ok(port)
+    }
+}
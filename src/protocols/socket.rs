@@ -0,0 +1,254 @@
+//! SCPI over a raw TCP socket, the "SCPI-RAW" service of LAN instruments.
+//!
+//! Messages are sent as they are and every response ends with a newline. A device clear has no
+//! equivalent on the data connection, instruments following the Keysight convention accept it
+//! on a second "control" connection whose port is answered by `SYST:COMM:LAN:CONT?`.
+use super::Protocol;
+use crate::{
+    scpi::{read_response, Scpi},
+    Result,
+};
+use bytes::Bytes;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+///port of the SCPI-RAW service of most instruments
+pub const SCPI_RAW_PORT: u16 = 5025;
+///port of the SCPI-RAW service of Rigol instruments
+pub const RIGOL_PORT: u16 = 5555;
+const CONTROL_PORT_QUERY: &str = "SYST:COMM:LAN:CONT?";
+const DEVICE_CLEAR: &[u8] = b"DCL\n";
+
+///connect a [`SocketSession`], the port is part of the address, e.g. [`SCPI_RAW_PORT`]
+#[derive(Clone, Copy, Default)]
+pub struct ScpiSocket {
+    ///port of the control connection, asked to the instrument when `None`
+    pub control_port: Option<u16>,
+}
+
+impl Protocol for ScpiSocket {
+    type Address = SocketAddr;
+    type Error = io::Error;
+    type IO = SocketSession;
+    fn connect(
+        self,
+        address: Self::Address,
+        time_out: std::time::Duration,
+    ) -> std::result::Result<Self::IO, Self::Error> {
+        let mut session = SocketSession::new(TcpStream::connect_timeout(&address, time_out)?);
+        session.control_port = self.control_port;
+        Ok(session)
+    }
+}
+
+pub struct SocketSession {
+    stream: BufReader<TcpStream>,
+    control_port: Option<u16>,
+    control: Option<BufReader<TcpStream>>,
+}
+
+impl SocketSession {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            control_port: None,
+            control: None,
+        }
+    }
+    pub fn get_ref(&self) -> &TcpStream {
+        self.stream.get_ref()
+    }
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.stream.get_ref().peer_addr()?)
+    }
+    ///read and write timeout of the data and control connections
+    pub fn set_timeout<T: Into<Option<Duration>>>(&mut self, dur: T) -> Result<&mut Self> {
+        let dur = dur.into();
+        let streams = std::iter::once(&self.stream).chain(self.control.as_ref());
+        for stream in streams {
+            stream.get_ref().set_read_timeout(dur)?;
+            stream.get_ref().set_write_timeout(dur)?;
+        }
+        Ok(self)
+    }
+    ///port of the control connection, asked with `SYST:COMM:LAN:CONT?` the first time
+    pub fn control_port(&mut self) -> Result<u16> {
+        if let Some(port) = self.control_port {
+            return Ok(port);
+        }
+        let answer = self.scpi_query(CONTROL_PORT_QUERY)?;
+        let port = answer
+            .trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|p| *p != 0)
+            .ok_or_else(|| {
+                format!(
+                    "instrument has no control connection, answered '{}'",
+                    answer.trim()
+                )
+            })?;
+        self.control_port = Some(port);
+        Ok(port)
+    }
+    fn control(&mut self) -> Result<&mut BufReader<TcpStream>> {
+        if self.control.is_none() {
+            let port = self.control_port()?;
+            let data = self.stream.get_ref();
+            let addr = SocketAddr::new(data.peer_addr()?.ip(), port);
+            let stream = TcpStream::connect(addr)?;
+            stream.set_read_timeout(data.read_timeout()?)?;
+            stream.set_write_timeout(data.write_timeout()?)?;
+            self.control = Some(BufReader::new(stream));
+        }
+        Ok(self.control.as_mut().unwrap())
+    }
+    ///clear the instrument through the control connection and drop whatever the data connection
+    ///still holds of earlier responses
+    pub fn device_clear(&mut self) -> Result<()> {
+        if self.control_port.is_none() {
+            //a stale response would be taken for the answer to the control port query
+            self.discard_input()?;
+        }
+        let control = self.control()?;
+        control.get_mut().write_all(DEVICE_CLEAR)?;
+        //the instrument echoes the command once the clear is done
+        let mut line = Vec::new();
+        while line != DEVICE_CLEAR {
+            line.clear();
+            if control.read_until(b'\n', &mut line)? == 0 {
+                self.control = None;
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+        self.discard_input()
+    }
+    ///drop the data received and not read yet
+    pub fn discard_input(&mut self) -> Result<()> {
+        let buffered = self.stream.buffer().len();
+        self.stream.consume(buffered);
+        let stream = self.stream.get_mut();
+        stream.set_nonblocking(true)?;
+        let mut buf = [0_u8; 1024];
+        let drained = loop {
+            match stream.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        stream.set_nonblocking(false)?;
+        Ok(drained?)
+    }
+}
+
+impl Write for SocketSession {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.get_mut().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.get_mut().flush()
+    }
+}
+
+impl Read for SocketSession {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Scpi for SocketSession {
+    fn write_bin<C: AsRef<[u8]>>(&mut self, content: C) -> Result<()> {
+        self.write_all(content.as_ref())?;
+        Ok(self.flush()?)
+    }
+    fn read_bin(&mut self) -> Result<Bytes> {
+        read_response(&mut self.stream, Self::TERM)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instruments::{Command, Messenger, Model},
+        test_support::line_instrument,
+    };
+    use std::net::TcpListener;
+
+    ///answers `*IDN?`, `*STB?` and a binary block, returns its address and control port
+    fn fake_instrument() -> Result<(SocketAddr, u16)> {
+        let control = TcpListener::bind("127.0.0.1:0")?;
+        let control_port = control.local_addr()?.port();
+        let (port, _) = line_instrument(move |line| match line {
+            CONTROL_PORT_QUERY => Some(format!("{}\n", control_port)),
+            "*IDN?" => Some("FAKE,SOCKET,0,1\n".to_string()),
+            "*STB?" => Some("\x10\n".to_string()),
+            "CURV?" => Some("#15a\nb\nc\n".to_string()),
+            "STUCK?" => Some("never read\n".to_string()),
+            _ => None,
+        })?;
+        std::thread::spawn(move || {
+            let (stream, _) = control.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut line = Vec::new();
+            while reader.read_until(b'\n', &mut line).unwrap() > 0 {
+                assert_eq!(line, DEVICE_CLEAR);
+                writer.write_all(DEVICE_CLEAR).unwrap();
+                line.clear();
+            }
+        });
+        Ok((SocketAddr::from(([127, 0, 0, 1], port)), control_port))
+    }
+
+    #[test]
+    fn socket_session() -> Result<()> {
+        let (addr, control_port) = fake_instrument()?;
+        let mut session = ScpiSocket::default().connect(addr, Duration::from_secs(1))?;
+        session.set_timeout(Duration::from_secs(1))?;
+        assert_eq!(session.scpi_query("*IDN?")?, "FAKE,SOCKET,0,1\n");
+        assert_eq!(session.get_status_byte()?.byte(), 0x10);
+        //a newline inside the block doesn't end the response
+        session.scpi_send("CURV?")?;
+        assert_eq!(session.read_bin()?.as_ref(), b"#15a\nb\nc\n");
+        session.scpi_send("STUCK?")?;
+        //let the response arrive before it is discarded
+        std::thread::sleep(Duration::from_millis(50));
+        session.device_clear()?;
+        assert_eq!(session.control_port()?, control_port);
+        assert_eq!(session.scpi_query("*IDN?")?, "FAKE,SOCKET,0,1\n");
+        Ok(())
+    }
+
+    struct Fake;
+    struct Message(&'static str);
+    impl Command for Message {
+        type R = &'static str;
+        fn to_bytes(self) -> Self::R {
+            self.0
+        }
+    }
+    impl Model for Fake {
+        const DESCRIPTION: &'static str = "fake";
+        type Command = Message;
+        const TERMINATOR: u8 = b'\n';
+        const END_BYTE: u8 = b'\n';
+    }
+
+    #[test]
+    fn instrument_over_tcp() -> Result<()> {
+        let (addr, _) = fake_instrument()?;
+        let stream = super::super::Tcp.connect(addr, Duration::from_secs(1))?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let mut instrument = Messenger::new(stream).bind(Fake);
+        assert_eq!(instrument.scpi_query("*IDN?")?, "FAKE,SOCKET,0,1\n");
+        instrument.scpi_send("CURV?")?;
+        assert_eq!(instrument.read_bin()?.as_ref(), b"#15a\nb\nc\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,700 @@
+//! Record the messages exchanged with an instrument and replay them later without it, e.g. in CI.
+//!
+//! A [`Recorder`] wraps a `Read + Write` transport, e.g. under a [`Messenger`](crate::instruments::Messenger),
+//! or a [`Scpi`] session such as [`Vxi11`](super::onc_rpc::vxi11::Vxi11), and writes every message
+//! sent and every response received to a transcript. A [`Replay`] plays the instrument part of a
+//! transcript back, through the same traits, and keeps the requests that differ from the recorded
+//! ones.
+//!
+//! Transcripts are JSON Lines, a header line followed by one line per message, or a compact
+//! binary format starting with [`MAGIC`]. [`Transcript::read_from`] tells them apart.
+use crate::{
+    scpi::{read_response, Scpi},
+    Result,
+};
+use bytes::Bytes;
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+///start of a binary transcript, followed by the version
+pub const MAGIC: &[u8; 4] = b"RSTR";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    ///sent to the instrument
+    Write,
+    ///received from the instrument
+    Read,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    JsonLines,
+    Binary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    ///since the start of the recording
+    pub at: Duration,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    transcript: u8,
+    start_unix_ms: u64,
+}
+
+///a line of a JSON transcript, the data is kept as text when it is UTF-8
+#[derive(Serialize, Deserialize)]
+struct Line {
+    t_us: u64,
+    dir: Direction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hex: Option<String>,
+}
+
+fn invalid<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+///fill `buf` unless the end comes first, return how much was read
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> io::Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid("odd number of hex digits"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(invalid))
+        .collect()
+}
+
+///writes the entries of a transcript as they come
+pub struct TranscriptWriter<W: Write> {
+    sink: W,
+    format: Format,
+}
+
+impl<W: Write> TranscriptWriter<W> {
+    ///write the header, stamped with the current time
+    pub fn new(mut sink: W, format: Format) -> io::Result<Self> {
+        let start_unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        match format {
+            Format::JsonLines => {
+                let header = Header {
+                    transcript: VERSION,
+                    start_unix_ms,
+                };
+                serde_json::to_writer(&mut sink, &header).map_err(invalid)?;
+                sink.write_all(b"\n")?;
+            }
+            Format::Binary => {
+                sink.write_all(MAGIC)?;
+                sink.write_all(&[VERSION])?;
+                sink.write_all(&start_unix_ms.to_le_bytes())?;
+            }
+        }
+        Ok(Self { sink, format })
+    }
+    pub fn write_entry(&mut self, entry: &Entry) -> io::Result<()> {
+        let t_us = entry.at.as_micros() as u64;
+        match self.format {
+            Format::JsonLines => {
+                let (text, hex) = match std::str::from_utf8(&entry.data) {
+                    Ok(text) => (Some(text.to_string()), None),
+                    Err(_) => (None, Some(to_hex(&entry.data))),
+                };
+                let line = Line {
+                    t_us,
+                    dir: entry.direction,
+                    text,
+                    hex,
+                };
+                serde_json::to_writer(&mut self.sink, &line).map_err(invalid)?;
+                self.sink.write_all(b"\n")?;
+            }
+            Format::Binary => {
+                self.sink.write_all(&t_us.to_le_bytes())?;
+                let direction = match entry.direction {
+                    Direction::Write => 0,
+                    Direction::Read => 1,
+                };
+                self.sink.write_all(&[direction])?;
+                self.sink
+                    .write_all(&(entry.data.len() as u32).to_le_bytes())?;
+                self.sink.write_all(&entry.data)?;
+            }
+        }
+        self.sink.flush()
+    }
+    pub fn into_inner(self) -> W {
+        self.sink
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Transcript {
+    ///when the recording started
+    pub start: Option<SystemTime>,
+    pub entries: Vec<Entry>,
+}
+
+impl Transcript {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+    ///read a transcript in either format
+    pub fn read_from<R: BufRead>(mut reader: R) -> Result<Self> {
+        let binary = reader.fill_buf()?.starts_with(MAGIC);
+        let transcript = if binary {
+            Self::read_binary(reader)
+        } else {
+            Self::read_json_lines(reader)
+        };
+        Ok(transcript?)
+    }
+    fn read_json_lines<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        let header: Header = match lines.next() {
+            Some(line) => serde_json::from_str(&line?).map_err(invalid)?,
+            None => return Err(invalid("empty transcript")),
+        };
+        let mut entries = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line: Line = serde_json::from_str(&line).map_err(invalid)?;
+            let data = match (line.text, line.hex) {
+                (Some(text), None) => text.into_bytes(),
+                (None, Some(hex)) => from_hex(&hex)?,
+                _ => return Err(invalid("an entry needs either `text` or `hex`")),
+            };
+            entries.push(Entry {
+                at: Duration::from_micros(line.t_us),
+                direction: line.dir,
+                data,
+            });
+        }
+        Ok(Self {
+            start: Some(UNIX_EPOCH + Duration::from_millis(header.start_unix_ms)),
+            entries,
+        })
+    }
+    fn read_binary<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut head = [0_u8; 13];
+        reader.read_exact(&mut head)?;
+        if head[4] != VERSION {
+            return Err(invalid(format!("unknown transcript version {}", head[4])));
+        }
+        let start_unix_ms = u64::from_le_bytes(head[5..].try_into().unwrap());
+        let mut entries = Vec::new();
+        loop {
+            let mut fixed = [0_u8; 13];
+            match read_up_to(&mut reader, &mut fixed)? {
+                0 => break,
+                n if n < fixed.len() => {
+                    return Err(invalid(format!(
+                        "truncated entry header, {} of {} bytes",
+                        n,
+                        fixed.len()
+                    )))
+                }
+                _ => {}
+            }
+            let direction = match fixed[8] {
+                0 => Direction::Write,
+                1 => Direction::Read,
+                d => return Err(invalid(format!("unknown direction {}", d))),
+            };
+            let len = u32::from_le_bytes(fixed[9..].try_into().unwrap()) as usize;
+            //allocated as it is read so that a bad length can't exhaust the memory
+            let mut data = Vec::new();
+            (&mut reader).take(len as u64).read_to_end(&mut data)?;
+            if data.len() < len {
+                return Err(invalid(format!(
+                    "truncated entry, {} of {} bytes",
+                    data.len(),
+                    len
+                )));
+            }
+            entries.push(Entry {
+                at: Duration::from_micros(u64::from_le_bytes(fixed[..8].try_into().unwrap())),
+                direction,
+                data,
+            });
+        }
+        Ok(Self {
+            start: Some(UNIX_EPOCH + Duration::from_millis(start_unix_ms)),
+            entries,
+        })
+    }
+    pub fn write_to<W: Write>(&self, sink: W, format: Format) -> io::Result<W> {
+        let mut writer = TranscriptWriter::new(sink, format)?;
+        for entry in &self.entries {
+            writer.write_entry(entry)?;
+        }
+        Ok(writer.into_inner())
+    }
+}
+
+///records what goes through `inner`: the bytes written up to a flush make a message, the bytes
+///read until the next message make a response; through [`Scpi`] every call is a message
+pub struct Recorder<T, W: Write = BufWriter<File>> {
+    inner: T,
+    ///taken by `finish`
+    writer: Option<TranscriptWriter<W>>,
+    start: Instant,
+    pending: Option<Entry>,
+}
+
+impl<T> Recorder<T> {
+    ///record into a new file at `path`
+    pub fn create<P: AsRef<Path>>(inner: T, path: P, format: Format) -> Result<Self> {
+        Ok(Self::new(
+            inner,
+            BufWriter::new(File::create(path)?),
+            format,
+        )?)
+    }
+}
+
+impl<T, W: Write> Recorder<T, W> {
+    pub fn new(inner: T, sink: W, format: Format) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            writer: Some(TranscriptWriter::new(sink, format)?),
+            start: Instant::now(),
+            pending: None,
+        })
+    }
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+    ///write the last response and return the sink, dropping the session, the transcript is
+    ///also completed when the recorder is dropped
+    pub fn finish(mut self) -> io::Result<W> {
+        self.settle()?;
+        Ok(self.writer.take().unwrap().into_inner())
+    }
+    fn write_entry(&mut self, entry: &Entry) -> io::Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer.write_entry(entry),
+            None => Ok(()),
+        }
+    }
+    fn entry(&self, direction: Direction, data: &[u8]) -> Entry {
+        Entry {
+            at: self.start.elapsed(),
+            direction,
+            data: data.to_vec(),
+        }
+    }
+    ///add `data` to the pending entry, writing it first if it goes the other way
+    fn collect(&mut self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        match &mut self.pending {
+            Some(p) if p.direction == direction => p.data.extend_from_slice(data),
+            _ => {
+                self.settle()?;
+                self.pending = Some(self.entry(direction, data));
+            }
+        }
+        Ok(())
+    }
+    fn settle(&mut self) -> io::Result<()> {
+        match self.pending.take() {
+            Some(entry) => self.write_entry(&entry),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: Write, W: Write> Write for Recorder<T, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.collect(Direction::Write, &buf[..n])?;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        if matches!(&self.pending, Some(p) if p.direction == Direction::Write) {
+            self.settle()?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read, W: Write> Read for Recorder<T, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.collect(Direction::Read, &buf[..n])?;
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Scpi, W: Write> Scpi for Recorder<T, W> {
+    const TERM: u8 = T::TERM;
+    fn write_bin<C: AsRef<[u8]>>(&mut self, content: C) -> Result<()> {
+        self.inner.write_bin(content.as_ref())?;
+        self.settle()?;
+        let entry = self.entry(Direction::Write, content.as_ref());
+        Ok(self.write_entry(&entry)?)
+    }
+    fn read_bin(&mut self) -> Result<Bytes> {
+        let response = self.inner.read_bin()?;
+        self.settle()?;
+        let entry = self.entry(Direction::Read, &response);
+        self.write_entry(&entry)?;
+        Ok(response)
+    }
+}
+
+impl<T, W: Write> Drop for Recorder<T, W> {
+    fn drop(&mut self) {
+        if let Err(e) = self.settle() {
+            tracing::warn!(error = %e, "failed writing the end of a transcript");
+        }
+    }
+}
+
+///a request that isn't the recorded one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    ///index of the entry in the transcript, its length when it is exhausted
+    pub index: usize,
+    ///what was recorded, `None` when a response was recorded instead or nothing is left
+    pub expected: Option<Vec<u8>>,
+    pub actual: Vec<u8>,
+}
+
+///plays the instrument part of a transcript
+pub struct Replay {
+    entries: Vec<Entry>,
+    ///next entry to play
+    next: usize,
+    ///bytes of the current response already read
+    offset: usize,
+    written: Vec<u8>,
+    differences: Vec<Difference>,
+    strict: bool,
+}
+
+impl Replay {
+    pub fn new(transcript: Transcript) -> Self {
+        Self {
+            entries: transcript.entries,
+            next: 0,
+            offset: 0,
+            written: Vec::new(),
+            differences: Vec::new(),
+            strict: false,
+        }
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(Transcript::load(path)?))
+    }
+    ///fail the request differing from the recorded one instead of only keeping the difference
+    pub fn set_strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+    pub fn differences(&self) -> &[Difference] {
+        &self.differences
+    }
+    ///whether every entry has been played
+    pub fn is_finished(&self) -> bool {
+        self.next == self.entries.len()
+    }
+    ///compare a request with the next entry, which is played whatever the outcome
+    fn request(&mut self, actual: &[u8]) -> io::Result<()> {
+        let index = self.next;
+        let expected = match self.entries.get(index) {
+            Some(e) if e.direction == Direction::Write => {
+                self.next += 1;
+                Some(&e.data[..])
+            }
+            _ => None,
+        };
+        if expected == Some(actual) {
+            return Ok(());
+        }
+        let difference = Difference {
+            index,
+            expected: expected.map(<[u8]>::to_vec),
+            actual: actual.to_vec(),
+        };
+        tracing::warn!(
+            index,
+            expected = %crate::trace::payload(expected.unwrap_or_default()),
+            actual = %crate::trace::payload(actual),
+            "request differs from the transcript"
+        );
+        self.differences.push(difference);
+        if self.strict {
+            return Err(invalid(format!(
+                "request {} differs from the transcript",
+                index
+            )));
+        }
+        Ok(())
+    }
+    fn settle(&mut self) -> io::Result<()> {
+        if self.written.is_empty() {
+            return Ok(());
+        }
+        let written = std::mem::take(&mut self.written);
+        self.request(&written)
+    }
+    ///the rest of the current response, `None` at the end of the transcript
+    fn response(&mut self) -> io::Result<Option<&[u8]>> {
+        match self.entries.get(self.next) {
+            Some(e) if e.direction == Direction::Read => Ok(Some(&e.data[self.offset..])),
+            Some(_) => Err(invalid(format!(
+                "entry {} of the transcript is a request, not a response",
+                self.next
+            ))),
+            None => Ok(None),
+        }
+    }
+    fn consume(&mut self, n: usize) {
+        self.offset += n;
+        if self.offset == self.entries[self.next].data.len() {
+            self.next += 1;
+            self.offset = 0;
+        }
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.settle()
+    }
+}
+
+impl Read for Replay {
+    ///the end of the transcript reads as the end of the stream
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.settle()?;
+        let n = match self.response()? {
+            Some(response) => {
+                let n = response.len().min(buf.len());
+                buf[..n].copy_from_slice(&response[..n]);
+                n
+            }
+            None => return Ok(0),
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl Scpi for Replay {
+    fn write_bin<C: AsRef<[u8]>>(&mut self, content: C) -> Result<()> {
+        self.settle()?;
+        Ok(self.request(content.as_ref())?)
+    }
+    fn read_bin(&mut self) -> Result<Bytes> {
+        self.settle()?;
+        if self.offset > 0 {
+            //part of the response was read as bytes, frame the rest
+            return read_response(&mut BufReader::new(self), Self::TERM);
+        }
+        let response = match self.response()? {
+            Some(response) => Bytes::copy_from_slice(response),
+            None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        };
+        self.consume(response.len());
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instruments::{infiniium, Messenger};
+    use std::io::Cursor;
+
+    ///a scope answering the queries of the test in order
+    struct Scope {
+        answers: Vec<&'static [u8]>,
+        out: Cursor<Vec<u8>>,
+    }
+
+    impl Write for Scope {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.ends_with(b"\n") {
+                let answer = self.answers.remove(0);
+                self.out.get_mut().extend_from_slice(answer);
+            }
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Scope {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            //a few bytes at a time, responses are recorded whole anyway
+            let n = buf.len().min(3);
+            self.out.read(&mut buf[..n])
+        }
+    }
+
+    #[test]
+    fn record_and_replay_an_instrument() -> Result<()> {
+        for (i, format) in [Format::JsonLines, Format::Binary].iter().enumerate() {
+            let path = std::env::temp_dir().join(format!(
+                "rustrument-transcript-{}-{}",
+                std::process::id(),
+                i
+            ));
+            let scope = Scope {
+                answers: vec![b"+2.50000E+08\n", b"\x00\xff\n"],
+                out: Cursor::new(Vec::new()),
+            };
+            let recorder = Recorder::create(scope, &path, *format)?;
+            let mut osc = Messenger::new(recorder).bind(infiniium::Infiniium);
+            assert_eq!(osc.query(infiniium::SampleRate)?, 2.5e8);
+            osc.send_raw("RAW?")?;
+            assert_eq!(osc.read_response()?, b"\x00\xff\n");
+            drop(osc);
+
+            let transcript = Transcript::load(&path)?;
+            std::fs::remove_file(&path)?;
+            let directions: Vec<_> = transcript.entries.iter().map(|e| e.direction).collect();
+            assert_eq!(
+                directions,
+                vec![
+                    Direction::Write,
+                    Direction::Read,
+                    Direction::Write,
+                    Direction::Read
+                ]
+            );
+            assert_eq!(transcript.entries[0].data, b":ACQuire:SRATe:ANALog?\n");
+            assert_eq!(transcript.entries[3].data, b"\x00\xff\n");
+
+            let mut osc = Messenger::new(Replay::new(transcript)).bind(infiniium::Infiniium);
+            assert_eq!(osc.query(infiniium::SampleRate)?, 2.5e8);
+            osc.send_raw("RAW?")?;
+            assert_eq!(osc.read_response()?, b"\x00\xff\n");
+            assert!(osc.get_ref().get_ref().is_finished());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn replay_reports_differences() -> Result<()> {
+        let mut recorder = Recorder::new(Replay::new(scripted()), Vec::new(), Format::JsonLines)?;
+        recorder.scpi_query("*IDN?")?;
+        recorder.scpi_query("MEAS?")?;
+        let transcript = recorder.finish()?;
+        let text = String::from_utf8(transcript.clone()).unwrap();
+        assert!(text.lines().nth(1).unwrap().contains(r#""text":"*IDN?\n""#));
+
+        let mut replay = Replay::new(Transcript::read_from(&transcript[..])?);
+        assert_eq!(replay.scpi_query("*IDN?")?, "FAKE,SCOPE,1,1\n");
+        assert_eq!(replay.scpi_query("MEAS:VPP?")?, "0.5\n");
+        assert!(replay.is_finished());
+        assert_eq!(
+            replay.differences(),
+            &[Difference {
+                index: 2,
+                expected: Some(b"MEAS?\n".to_vec()),
+                actual: b"MEAS:VPP?\n".to_vec(),
+            }]
+        );
+        let mut strict = Replay::new(Transcript::read_from(&transcript[..])?);
+        strict.set_strict(true);
+        assert!(strict.scpi_send("*RST").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_binary_transcripts_are_errors() -> Result<()> {
+        let mut data = scripted().write_to(Vec::new(), Format::Binary)?;
+        assert_eq!(Transcript::read_from(&data[..])?.entries.len(), 4);
+        //a cut entry header, then a cut entry
+        data.extend_from_slice(&[0; 5]);
+        assert!(Transcript::read_from(&data[..]).is_err());
+        data.truncate(data.len() - 6);
+        let e = Transcript::read_from(&data[..]).unwrap_err();
+        assert!(
+            e.to_string().contains("truncated entry, 3 of 4 bytes"),
+            "{}",
+            e
+        );
+        //a length of 4 GiB, nothing allocated up front
+        let mut huge = scripted().write_to(Vec::new(), Format::Binary)?;
+        huge.extend_from_slice(&[0; 8]);
+        huge.push(1);
+        huge.extend_from_slice(&u32::MAX.to_le_bytes());
+        huge.extend_from_slice(b"late");
+        let e = Transcript::read_from(&huge[..]).unwrap_err();
+        assert!(
+            e.to_string()
+                .contains("truncated entry, 4 of 4294967295 bytes"),
+            "{}",
+            e
+        );
+        Ok(())
+    }
+
+    fn scripted() -> Transcript {
+        let entry = |direction, data: &[u8]| Entry {
+            at: Duration::from_millis(1),
+            direction,
+            data: data.to_vec(),
+        };
+        Transcript {
+            start: None,
+            entries: vec![
+                entry(Direction::Write, b"*IDN?\n"),
+                entry(Direction::Read, b"FAKE,SCOPE,1,1\n"),
+                entry(Direction::Write, b"MEAS?\n"),
+                entry(Direction::Read, b"0.5\n"),
+            ],
+        }
+    }
+}
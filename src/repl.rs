@@ -0,0 +1,556 @@
+//! Interactive terminal, `rustrument repl <resource>`.
+//!
+//! Lines are sent as they are and the response is read when the message holds a `?`. Lines
+//! made of a lowercase word after a colon are handled here instead, see [`HELP`], so `:abort`
+//! is the terminal's while `:ABORt` goes to the instrument.
+use rustrument::{
+    instruments::{generic::Generic, Instrument, Messenger},
+    resource::Connection,
+    scpi::Scpi,
+    Result,
+};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, Context, Editor,
+};
+use std::{fmt::Write as _, time::Duration};
+
+pub const HELP: &str = "\
+:stb            read the status byte, beside the message queue when the transport allows it
+:esr            read and clear the standard event status register
+:clear          device clear
+:abort          abort the operation in progress
+:timeout [5s]   show or set the I/O timeout, in s or ms
+:log [on|off]   show or switch logging of the traffic to stderr
+:help           this help
+:quit           leave, as does Ctrl-D";
+
+///the mnemonics completed with Tab, `<n>` stands for a numeric suffix
+const MNEMONICS: &[&str] = &[
+    "*CLS",
+    "*ESE",
+    "*ESE?",
+    "*ESR?",
+    "*IDN?",
+    "*LRN?",
+    "*OPC",
+    "*OPC?",
+    "*OPT?",
+    "*RCL",
+    "*RST",
+    "*SAV",
+    "*SRE",
+    "*SRE?",
+    "*STB?",
+    "*TRG",
+    "*TST?",
+    "*WAI",
+    ":ABORt",
+    ":ACQuire:COUNt",
+    ":ACQuire:POINts",
+    ":ACQuire:SRATe?",
+    ":ACQuire:TYPE",
+    ":AUToscale",
+    ":CHANnel<n>:BWLimit",
+    ":CHANnel<n>:COUPling",
+    ":CHANnel<n>:DISPlay",
+    ":CHANnel<n>:OFFSet",
+    ":CHANnel<n>:PROBe",
+    ":CHANnel<n>:SCALe",
+    ":CONFigure?",
+    ":DIGitize",
+    ":DISPlay:TEXT",
+    ":FETCh?",
+    ":INITiate",
+    ":INITiate:CONTinuous",
+    ":MEASure:CURRent:DC?",
+    ":MEASure:FALLtime?",
+    ":MEASure:FREQuency?",
+    ":MEASure:PERiod?",
+    ":MEASure:RESistance?",
+    ":MEASure:RISetime?",
+    ":MEASure:VAMPlitude?",
+    ":MEASure:VAVerage?",
+    ":MEASure:VOLTage:AC?",
+    ":MEASure:VOLTage:DC?",
+    ":MEASure:VPP?",
+    ":MEASure:VRMS?",
+    ":OUTPut",
+    ":OUTPut:STATe",
+    ":READ?",
+    ":RUN",
+    ":SENSe:FUNCtion",
+    ":SINGle",
+    ":SOURce:FREQuency",
+    ":SOURce:FUNCtion",
+    ":SOURce:VOLTage",
+    ":STATus:OPERation:CONDition?",
+    ":STATus:OPERation:ENABle",
+    ":STATus:OPERation?",
+    ":STATus:PRESet",
+    ":STATus:QUEStionable:CONDition?",
+    ":STATus:QUEStionable:ENABle",
+    ":STATus:QUEStionable?",
+    ":STOP",
+    ":SYSTem:DATE",
+    ":SYSTem:ERRor:NEXT?",
+    ":SYSTem:ERRor?",
+    ":SYSTem:PRESet",
+    ":SYSTem:TIME",
+    ":SYSTem:VERSion?",
+    ":TIMebase:POSition",
+    ":TIMebase:RANGe",
+    ":TIMebase:SCALe",
+    ":TRIGger:LEVel",
+    ":TRIGger:MODE",
+    ":TRIGger:SLOPe",
+    ":TRIGger:SOURce",
+    ":TRIGger:SWEep",
+    ":WAVeform:DATA?",
+    ":WAVeform:FORMat",
+    ":WAVeform:POINts",
+    ":WAVeform:PREamble?",
+    ":WAVeform:SOURce",
+    ":WAVeform:XINCrement?",
+    ":WAVeform:XORigin?",
+    ":WAVeform:YINCrement?",
+    ":WAVeform:YORigin?",
+    ":WAVeform:YREFerence?",
+];
+
+const META: &[&str] = &[
+    "abort", "clear", "esr", "help", "log", "quit", "stb", "timeout",
+];
+
+///rows of a hex dump shown before eliding the rest
+const DUMP_ROWS: usize = 32;
+
+#[derive(Debug, PartialEq)]
+enum Meta {
+    Stb,
+    Esr,
+    Clear,
+    Abort,
+    Timeout(Option<Duration>),
+    Log(Option<bool>),
+    Help,
+    Quit,
+}
+
+impl Meta {
+    ///`None` when the line is a message for the instrument
+    fn parse(line: &str) -> Option<std::result::Result<Meta, String>> {
+        let rest = line.strip_prefix(':')?;
+        let (word, argument) = match rest.split_once(char::is_whitespace) {
+            Some((word, argument)) => (word, Some(argument.trim())),
+            None => (rest, None),
+        };
+        if !META.contains(&word) {
+            return None;
+        }
+        Some(match (word, argument) {
+            ("timeout", Some(argument)) => parse_duration(argument)
+                .map(|d| Meta::Timeout(Some(d)))
+                .ok_or_else(|| format!("invalid timeout '{}', e.g. 5s or 500ms", argument)),
+            ("timeout", None) => Ok(Meta::Timeout(None)),
+            ("log", Some("on")) => Ok(Meta::Log(Some(true))),
+            ("log", Some("off")) => Ok(Meta::Log(Some(false))),
+            ("log", Some(argument)) => Err(format!("expected on or off, not '{}'", argument)),
+            ("log", None) => Ok(Meta::Log(None)),
+            (_, Some(_)) => Err(format!(":{} takes no argument", word)),
+            ("stb", None) => Ok(Meta::Stb),
+            ("esr", None) => Ok(Meta::Esr),
+            ("clear", None) => Ok(Meta::Clear),
+            ("abort", None) => Ok(Meta::Abort),
+            ("help", None) => Ok(Meta::Help),
+            _ => Ok(Meta::Quit),
+        })
+    }
+}
+
+///`5s`, `2.5s` or `500ms`, seconds without a unit
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 1e-3)
+    } else {
+        (s.strip_suffix('s').unwrap_or(s), 1.0)
+    };
+    let seconds = number.trim().parse::<f64>().ok()? * scale;
+    if seconds.is_finite() && seconds > 0.0 {
+        Some(Duration::from_secs_f64(seconds))
+    } else {
+        None
+    }
+}
+
+///a response is expected when a header holds `?`, strings may hold one too
+fn is_query(message: &str) -> bool {
+    let mut quote = None;
+    for c in message.chars() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '?') => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+///text as it is, blocks as their length and their content, in hex unless it's text
+fn format_response(response: &[u8]) -> String {
+    let (title, body) = match response {
+        [b'#', b'0', body @ ..] => (Some("indefinite length block".to_string()), trim_end(body)),
+        [b'#', n @ b'1'..=b'9', rest @ ..] => {
+            let digits = (n - b'0') as usize;
+            let len = rest
+                .get(..digits)
+                .and_then(|d| std::str::from_utf8(d).ok()?.parse::<usize>().ok());
+            match len {
+                Some(len) => {
+                    let body = &rest[digits..];
+                    (
+                        Some(format!("block of {} bytes", len)),
+                        &body[..len.min(body.len())],
+                    )
+                }
+                None => (None, trim_end(response)),
+            }
+        }
+        _ => (None, trim_end(response)),
+    };
+    let is_text = body
+        .iter()
+        .all(|b| !b.is_ascii_control() || b"\t\r\n".contains(b));
+    let body = if is_text {
+        String::from_utf8_lossy(body).to_string()
+    } else {
+        hex_dump(body)
+    };
+    match title {
+        Some(title) => format!("{}\n{}", title, body),
+        None => body,
+    }
+}
+
+fn trim_end(data: &[u8]) -> &[u8] {
+    let end = data.len()
+        - data
+            .iter()
+            .rev()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count();
+    &data[..end]
+}
+
+fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (row, chunk) in data.chunks(16).enumerate().take(DUMP_ROWS) {
+        write!(dump, "{:08x} ", row * 16).unwrap();
+        for i in 0..16 {
+            match chunk.get(i) {
+                Some(b) => write!(dump, " {:02x}", b).unwrap(),
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str("  |");
+        dump.extend(chunk.iter().map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        }));
+        dump.push_str("|\n");
+    }
+    if data.len() > DUMP_ROWS * 16 {
+        write!(dump, "... {} more bytes", data.len() - DUMP_ROWS * 16).unwrap();
+    }
+    dump.trim_end().to_string()
+}
+
+///whether a node of the mnemonics matches a complete node typed in its long or short form
+fn node_matches(node: &str, typed: &str) -> bool {
+    let (node, typed) = (node.trim_end_matches('?'), typed.trim_end_matches('?'));
+    let (node, typed) = match node.strip_suffix("<n>") {
+        Some(node) => (node, typed.trim_end_matches(|c: char| c.is_ascii_digit())),
+        None => (node, typed),
+    };
+    let short = &node[..node
+        .find(|c: char| c.is_ascii_lowercase())
+        .unwrap_or(node.len())];
+    typed.eq_ignore_ascii_case(node) || typed.eq_ignore_ascii_case(short)
+}
+
+///where the completed word starts in `line` and its candidates, for the cursor at `pos`
+fn complete(line: &str, pos: usize) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let message_start = before.rfind(';').map_or(0, |i| i + 1);
+    let header = before[message_start..].trim_start();
+    if header.contains(char::is_whitespace) {
+        return (pos, Vec::new());
+    }
+    let header_start = pos - header.len();
+    let mut candidates = Vec::new();
+    if header.starts_with('*') {
+        candidates.extend(
+            MNEMONICS
+                .iter()
+                .filter(|m| {
+                    m.len() >= header.len() && m[..header.len()].eq_ignore_ascii_case(header)
+                })
+                .map(|m| m.to_string()),
+        );
+        candidates.dedup();
+        return (header_start, candidates);
+    }
+    let typed: Vec<&str> = header.trim_start_matches(':').split(':').collect();
+    let (partial, complete) = typed.split_last().unwrap();
+    let start = pos - partial.len();
+    if header_start == 0 && header.starts_with(':') && complete.is_empty() {
+        candidates.extend(
+            META.iter()
+                .filter(|m| m.starts_with(partial))
+                .map(|m| m.to_string()),
+        );
+    }
+    for mnemonic in MNEMONICS.iter().filter(|m| m.starts_with(':')) {
+        let nodes: Vec<&str> = mnemonic[1..].split(':').collect();
+        if nodes.len() <= complete.len()
+            || !nodes.iter().zip(complete).all(|(n, t)| node_matches(n, t))
+        {
+            continue;
+        }
+        let node = nodes[complete.len()];
+        let long = node.trim_end_matches('?').trim_end_matches("<n>");
+        if !long
+            .to_ascii_lowercase()
+            .starts_with(&partial.to_ascii_lowercase())
+        {
+            continue;
+        }
+        let candidate = if nodes.len() > complete.len() + 1 {
+            format!("{}:", long)
+        } else {
+            node.replace("<n>", "")
+        };
+        if !candidates.contains(&candidate) {
+            candidates.push(candidate);
+        }
+    }
+    (start, candidates)
+}
+
+struct Helper;
+
+impl Completer for Helper {
+    type Candidate = String;
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(line, pos))
+    }
+}
+impl Hinter for Helper {
+    type Hint = String;
+}
+impl Highlighter for Helper {}
+impl Validator for Helper {}
+impl rustyline::Helper for Helper {}
+
+///a connection driven from the terminal
+pub struct Session {
+    instrument: Instrument<Messenger<Connection>, Generic>,
+    timeout: Duration,
+    logging: bool,
+    set_logging: Box<dyn FnMut(bool) -> Result<()>>,
+}
+
+enum Outcome {
+    Print(String),
+    Nothing,
+    Quit,
+}
+
+impl Session {
+    ///`set_logging` switches the log of the traffic, which is on when `logging`
+    pub fn new(
+        connection: Connection,
+        timeout: Duration,
+        logging: bool,
+        set_logging: Box<dyn FnMut(bool) -> Result<()>>,
+    ) -> Self {
+        Self {
+            instrument: Messenger::new(connection).bind(Generic),
+            timeout,
+            logging,
+            set_logging,
+        }
+    }
+    fn connection(&mut self) -> &mut Connection {
+        self.instrument.get_mut().get_mut()
+    }
+    ///send a message, reading the response of a query
+    pub fn send(&mut self, message: &str) -> Result<Option<String>> {
+        self.instrument.scpi_send(message)?;
+        if is_query(message) {
+            Ok(Some(format_response(&self.instrument.read_bin()?)))
+        } else {
+            Ok(None)
+        }
+    }
+    fn execute(&mut self, line: &str) -> Result<Outcome> {
+        let meta = match Meta::parse(line) {
+            None => {
+                return Ok(match self.send(line)? {
+                    Some(response) => Outcome::Print(response),
+                    None => Outcome::Nothing,
+                })
+            }
+            Some(meta) => meta?,
+        };
+        Ok(match meta {
+            Meta::Stb => {
+                let stb = match self.connection().read_stb()? {
+                    Some(stb) => stb,
+                    None => self.instrument.get_status_byte()?,
+                };
+                Outcome::Print(format!("{:#04x} {}", stb.byte(), stb.flags().join(" ")))
+            }
+            Meta::Esr => {
+                let esr = self.instrument.get_event_byte()?;
+                Outcome::Print(format!("{:#04x} {}", esr.byte(), esr.flags().join(" ")))
+            }
+            Meta::Clear => {
+                self.connection().clear()?;
+                self.instrument.discard_buffered();
+                Outcome::Nothing
+            }
+            Meta::Abort => {
+                self.connection().abort()?;
+                self.instrument.discard_buffered();
+                Outcome::Nothing
+            }
+            Meta::Timeout(Some(timeout)) => {
+                self.connection().set_timeout(timeout)?;
+                self.timeout = timeout;
+                Outcome::Nothing
+            }
+            Meta::Timeout(None) => Outcome::Print(format!("{:?}", self.timeout)),
+            Meta::Log(Some(on)) => {
+                (self.set_logging)(on)?;
+                self.logging = on;
+                Outcome::Nothing
+            }
+            Meta::Log(None) => Outcome::Print(if self.logging { "on" } else { "off" }.to_string()),
+            Meta::Help => Outcome::Print(HELP.to_string()),
+            Meta::Quit => Outcome::Quit,
+        })
+    }
+    ///read lines until `:quit` or the end of the input, with the history kept in `history`
+    pub fn run(&mut self, prompt: &str, history: Option<&std::path::Path>) -> Result<()> {
+        let mut editor: Editor<Helper, DefaultHistory> =
+            Editor::new().map_err(|e| format!("can't open the terminal: {}", e))?;
+        editor.set_helper(Some(Helper));
+        if let Some(history) = history {
+            //there is none the first time
+            let _ = editor.load_history(history);
+        }
+        loop {
+            let line = match editor.readline(prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(ReadlineError::Io(e)) => return Err(e.into()),
+                Err(e) => return Err(e.to_string().into()),
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let _ = editor.add_history_entry(line);
+            match self.execute(line) {
+                Ok(Outcome::Print(text)) => println!("{}", text),
+                Ok(Outcome::Nothing) => {}
+                Ok(Outcome::Quit) => break,
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        if let Some(history) = history {
+            if let Err(e) = editor.save_history(history) {
+                eprintln!("history not saved to {}: {}", history.display(), e);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meta_commands() {
+        assert_eq!(Meta::parse(":stb"), Some(Ok(Meta::Stb)));
+        assert_eq!(
+            Meta::parse(":timeout 2.5s"),
+            Some(Ok(Meta::Timeout(Some(Duration::from_millis(2500)))))
+        );
+        assert_eq!(
+            Meta::parse(":timeout 500ms"),
+            Some(Ok(Meta::Timeout(Some(Duration::from_millis(500)))))
+        );
+        assert_eq!(Meta::parse(":log on"), Some(Ok(Meta::Log(Some(true)))));
+        assert!(matches!(Meta::parse(":timeout soon"), Some(Err(_))));
+        assert!(matches!(Meta::parse(":esr 1"), Some(Err(_))));
+        //SCPI, not the terminal's
+        assert_eq!(Meta::parse(":ABORt"), None);
+        assert_eq!(Meta::parse("*STB?"), None);
+    }
+
+    #[test]
+    fn queries() {
+        assert!(is_query("*IDN?"));
+        assert!(is_query(":MEAS:VPP? CHAN1"));
+        assert!(is_query(":TRIG:LEV 0.5;:TRIG:LEV?"));
+        assert!(!is_query(":DISP:TEXT \"ready?\""));
+        assert!(!is_query("*RST"));
+    }
+
+    #[test]
+    fn responses() {
+        assert_eq!(format_response(b"+1.25E-3\n"), "+1.25E-3");
+        assert_eq!(format_response(b"#15hello\n"), "block of 5 bytes\nhello");
+        assert_eq!(
+            format_response(b"#14\x00\x01AB\n"),
+            "block of 4 bytes\n00000000  00 01 41 42                                      |..AB|"
+        );
+        let dump = format_response(&[&b"#41000"[..], &[0_u8; 1000]].concat());
+        assert!(dump.ends_with("... 488 more bytes"));
+    }
+
+    #[test]
+    fn completion() {
+        let candidates = |line: &str| complete(line, line.len());
+        assert_eq!(candidates("*ID"), (0, vec!["*IDN?".to_string()]));
+        assert_eq!(candidates(":WAV"), (1, vec!["WAVeform:".to_string()]));
+        assert_eq!(
+            candidates(":WAV:X"),
+            (5, vec!["XINCrement?".to_string(), "XORigin?".to_string()])
+        );
+        assert_eq!(candidates(":chan2:sca"), (7, vec!["SCALe".to_string()]));
+        assert_eq!(
+            candidates(":SYST:ERR"),
+            (6, vec!["ERRor:".to_string(), "ERRor?".to_string()])
+        );
+        assert_eq!(
+            candidates(":ti"),
+            (1, vec!["timeout".to_string(), "TIMebase:".to_string()])
+        );
+        assert_eq!(candidates("*RST;:RU"), (6, vec!["RUN".to_string()]));
+        assert_eq!(candidates(":TRIG:LEV 0"), (11, vec![]));
+    }
+}
@@ -0,0 +1,703 @@
+//! Instrument addresses, in the VISA resource syntax or a shorter form, and the connections
+//! they open.
+//!
+//! | resource | shorthand | transport |
+//! |---|---|---|
+//! | `TCPIP0::192.168.3.96::inst0::INSTR` | `192.168.3.96` | VXI-11 |
+//! | `TCPIP0::169.254.209.174::5025::SOCKET` | `169.254.209.174:5025` | raw socket |
+//! | `ASRL5::INSTR`, `ASRL/dev/ttyUSB0::INSTR` | `COM5`, `/dev/ttyUSB0` | serial port |
+//! | `GPIB0::8::INSTR`, `GPIB0::8::2::INSTR` | | Prologix controller at [`Transport::adapter`] |
+//!
+//! The board numbers are ignored and the resource class is optional, e.g. `TCPIP::host`.
+//! IPv6 hosts are bracketed when a port or another field follows, e.g. `[fe80::1]:5025`.
+use crate::{
+    instruments::{
+        generic::Generic, infiniium::Infiniium, mdt693_b::MDT693B, rigol::Rigol, Driver, Messenger,
+        Registry,
+    },
+    protocols::{
+        gpib::GpibAddress,
+        onc_rpc::vxi11::{Vxi11Client, Vxi11Io},
+        prologix::{self, PrologixIo},
+        socket::SocketSession,
+        Prologix, Protocol, ScpiSocket, Serial, SerialDevice, Tcp,
+    },
+    scpi::StatusByte,
+    Result,
+};
+use serial::{SerialPort, SystemPort};
+use std::{
+    convert::TryFrom,
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    time::Duration,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Resource {
+    Vxi11 {
+        host: String,
+        device: String,
+    },
+    Socket {
+        host: String,
+        port: u16,
+    },
+    ///serial port by name, `ASRL5` is `COM5`
+    Serial {
+        port: String,
+    },
+    Gpib {
+        address: GpibAddress,
+    },
+}
+
+fn invalid(s: &str, why: &str) -> crate::error::Error {
+    format!("invalid resource '{}': {}", s, why).into()
+}
+
+///split on `::` outside of the brackets around IPv6 addresses
+fn fields(s: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let (mut start, mut brackets) = (0, 0_u32);
+    let mut bytes = s.bytes().enumerate().peekable();
+    while let Some((i, b)) = bytes.next() {
+        match b {
+            b'[' => brackets += 1,
+            b']' => brackets = brackets.saturating_sub(1),
+            b':' if brackets == 0 && bytes.peek().map(|(_, b)| *b) == Some(b':') => {
+                bytes.next();
+                fields.push(&s[start..i]);
+                start = i + 2;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&s[start..]);
+    fields
+}
+
+///`host` without the brackets around an IPv6 address
+fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+impl FromStr for Resource {
+    type Err = crate::error::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        //a bare IPv6 address, `::` alone isn't one that can be reached
+        if let Ok(ip) = s.trim().parse::<Ipv6Addr>() {
+            if !ip.is_unspecified() {
+                return Ok(Resource::Vxi11 {
+                    host: ip.to_string(),
+                    device: "inst0".to_string(),
+                });
+            }
+        }
+        let parts = fields(s.trim());
+        let (first, rest) = (parts[0], &parts[1..]);
+        if first.is_empty() || rest.iter().any(|p| p.is_empty()) {
+            return Err(invalid(s, "empty field"));
+        }
+        let upper = first.to_ascii_uppercase();
+        let board = |prefix: &str| {
+            upper
+                .strip_prefix(prefix)
+                .filter(|b| b.bytes().all(|c| c.is_ascii_digit()))
+        };
+        let port = |p: &str| p.parse::<u16>().map_err(|_| invalid(s, "invalid port"));
+        if board("TCPIP").is_some() {
+            return match rest {
+                [host] | [host, "INSTR"] => Ok(Resource::Vxi11 {
+                    host: unbracket(host).to_string(),
+                    device: "inst0".to_string(),
+                }),
+                [host, device] | [host, device, "INSTR"] => Ok(Resource::Vxi11 {
+                    host: unbracket(host).to_string(),
+                    device: device.to_string(),
+                }),
+                [host, p, "SOCKET"] => Ok(Resource::Socket {
+                    host: unbracket(host).to_string(),
+                    port: port(p)?,
+                }),
+                _ => Err(invalid(
+                    s,
+                    "expected TCPIP::<host>[::<device>]::INSTR or TCPIP::<host>::<port>::SOCKET",
+                )),
+            };
+        }
+        if let Some(number) = upper.strip_prefix("ASRL") {
+            return match rest {
+                [] | ["INSTR"] if number.parse::<u8>().is_ok() => Ok(Resource::Serial {
+                    port: format!("COM{}", number),
+                }),
+                [] | ["INSTR"] if !number.is_empty() => Ok(Resource::Serial {
+                    port: first[4..].to_string(),
+                }),
+                _ => Err(invalid(s, "expected ASRL<number or device>::INSTR")),
+            };
+        }
+        if board("GPIB").is_some() {
+            let address = |p: &str| {
+                p.parse::<u8>()
+                    .map_err(|_| invalid(s, "invalid GPIB address"))
+            };
+            let (primary, secondary) = match rest {
+                [p] | [p, "INSTR"] => (address(p)?, None),
+                [p, q] | [p, q, "INSTR"] => (address(p)?, Some(address(q)?)),
+                _ => return Err(invalid(s, "expected GPIB::<primary>[::<secondary>]::INSTR")),
+            };
+            return Ok(Resource::Gpib {
+                address: GpibAddress::new(primary, secondary)?,
+            });
+        }
+        if !rest.is_empty() {
+            return Err(invalid(
+                s,
+                "unknown interface, expected TCPIP, ASRL or GPIB",
+            ));
+        }
+        if upper.starts_with("COM") && upper[3..].parse::<u8>().is_ok()
+            || first.starts_with("/dev/")
+        {
+            return Ok(Resource::Serial {
+                port: first.to_string(),
+            });
+        }
+        //`host:port`, IPv6 addresses are bracketed
+        let (host, p) = match first.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, after)) => match after.strip_prefix(':') {
+                    Some(p) => (host, Some(p)),
+                    None => return Err(invalid(s, "expected :<port> after the address")),
+                },
+                None => return Err(invalid(s, "unclosed bracket")),
+            },
+            None => match first.rsplit_once(':') {
+                Some((host, p)) => (host, Some(p)),
+                None => (first, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid(s, "empty host"));
+        }
+        match p {
+            Some(p) => Ok(Resource::Socket {
+                host: host.to_string(),
+                port: port(p)?,
+            }),
+            None => Ok(Resource::Vxi11 {
+                host: host.to_string(),
+                device: "inst0".to_string(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<String> for Resource {
+    type Error = crate::error::Error;
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+///`host`, bracketed if it's an IPv6 address
+struct Host<'a>(&'a str);
+
+impl fmt::Display for Host<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.contains(':') {
+            write!(f, "[{}]", self.0)
+        } else {
+            f.write_str(self.0)
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Vxi11 { host, device } => {
+                write!(f, "TCPIP0::{}::{}::INSTR", Host(host), device)
+            }
+            Resource::Socket { host, port } => {
+                write!(f, "TCPIP0::{}::{}::SOCKET", Host(host), port)
+            }
+            Resource::Serial { port } => match port.strip_prefix("COM") {
+                Some(n) if n.parse::<u8>().is_ok() => write!(f, "ASRL{}::INSTR", n),
+                _ => write!(f, "ASRL{}::INSTR", port),
+            },
+            Resource::Gpib { address } => match address.secondary {
+                Some(s) => write!(f, "GPIB0::{}::{}::INSTR", address.primary, s),
+                None => write!(f, "GPIB0::{}::INSTR", address.primary),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialOptions {
+    pub baud_rate: u32,
+    #[serde(deserialize_with = "data_bits")]
+    pub data_bits: u8,
+    pub parity: Parity,
+    #[serde(deserialize_with = "stop_bits")]
+    pub stop_bits: u8,
+    pub flow_control: FlowControl,
+}
+
+fn data_bits<'de, D: serde::Deserializer<'de>>(d: D) -> std::result::Result<u8, D::Error> {
+    match <u8 as serde::Deserialize>::deserialize(d)? {
+        b @ 5..=8 => Ok(b),
+        b => Err(serde::de::Error::custom(format!(
+            "{} data bits, expected 5 to 8",
+            b
+        ))),
+    }
+}
+
+fn stop_bits<'de, D: serde::Deserializer<'de>>(d: D) -> std::result::Result<u8, D::Error> {
+    match <u8 as serde::Deserialize>::deserialize(d)? {
+        b @ 1..=2 => Ok(b),
+        b => Err(serde::de::Error::custom(format!(
+            "{} stop bits, expected 1 or 2",
+            b
+        ))),
+    }
+}
+
+impl Default for SerialOptions {
+    fn default() -> Self {
+        Self {
+            baud_rate: 9600,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            flow_control: FlowControl::None,
+        }
+    }
+}
+
+impl SerialOptions {
+    pub fn to_serial(&self) -> Serial {
+        Serial {
+            baud_rate: serial::BaudRate::from_speed(self.baud_rate as usize),
+            data_bits: match self.data_bits {
+                5 => serial::Bits5,
+                6 => serial::Bits6,
+                7 => serial::Bits7,
+                _ => serial::Bits8,
+            },
+            parity: match self.parity {
+                Parity::None => serial::ParityNone,
+                Parity::Odd => serial::ParityOdd,
+                Parity::Even => serial::ParityEven,
+            },
+            stop_bits: match self.stop_bits {
+                2 => serial::Stop2,
+                _ => serial::Stop1,
+            },
+            flow_control: match self.flow_control {
+                FlowControl::None => serial::FlowNone,
+                FlowControl::Software => serial::FlowSoftware,
+                FlowControl::Hardware => serial::FlowHardware,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Vxi11Options {
+    ///lock the device while the link is open
+    pub lock: bool,
+    pub lock_timeout_ms: u64,
+    ///character ending the responses
+    pub term: char,
+}
+
+impl Default for Vxi11Options {
+    fn default() -> Self {
+        let client = Vxi11Client::default();
+        Self {
+            lock: client.lock,
+            lock_timeout_ms: client.lock_timeout.as_millis() as u64,
+            term: client.term,
+        }
+    }
+}
+
+///how to reach the instrument at a resource, the settings of other transports are ignored
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Transport {
+    ///connection and read timeout
+    pub timeout_ms: u64,
+    pub serial: SerialOptions,
+    pub vxi11: Vxi11Options,
+    ///Prologix controller of a GPIB resource, a serial port or a host, on [`prologix::PORT`]
+    ///unless given
+    pub adapter: Option<Resource>,
+}
+
+impl Default for Transport {
+    fn default() -> Self {
+        Self {
+            timeout_ms: 1000,
+            serial: SerialOptions::default(),
+            vxi11: Vxi11Options::default(),
+            adapter: None,
+        }
+    }
+}
+
+impl Transport {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+fn socket_addr(host: &str, port: u16) -> Result<SocketAddr> {
+    (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("no address found for '{}'", host).into())
+}
+
+///an open connection, whatever its transport
+pub enum Connection {
+    Vxi11(Box<Vxi11Io>),
+    Socket(SocketSession),
+    Serial(SystemPort),
+    SerialGpib(PrologixIo<SystemPort>),
+    TcpGpib(PrologixIo<TcpStream>),
+}
+
+impl Resource {
+    pub fn connect(&self, transport: &Transport) -> Result<Connection> {
+        let timeout = transport.timeout();
+        let _span = tracing::debug_span!("connect", resource = %self).entered();
+        Ok(match self {
+            Resource::Vxi11 { host, device } => {
+                let client = Vxi11Client {
+                    lock: transport.vxi11.lock,
+                    lock_timeout: Duration::from_millis(transport.vxi11.lock_timeout_ms),
+                    io_timeout: timeout,
+                    term: transport.vxi11.term,
+                    device: device.clone(),
+                    ..Vxi11Client::default()
+                };
+                let ip: IpAddr = socket_addr(host, 0)?.ip();
+                Connection::Vxi11(Box::new(Vxi11Io::new(client.connect(ip, timeout)?)))
+            }
+            Resource::Socket { host, port } => {
+                let mut session =
+                    ScpiSocket::default().connect(socket_addr(host, *port)?, timeout)?;
+                session.set_timeout(timeout)?;
+                Connection::Socket(session)
+            }
+            Resource::Serial { port } => Connection::Serial(
+                SerialDevice(transport.serial.to_serial()).connect(port.clone(), timeout)?,
+            ),
+            Resource::Gpib { address } => match &transport.adapter {
+                Some(Resource::Serial { port }) => Connection::SerialGpib(
+                    Prologix::new(SerialDevice(transport.serial.to_serial()), *address)
+                        .connect(port.clone(), timeout)?,
+                ),
+                Some(Resource::Vxi11 { host, .. }) => {
+                    let io = Prologix::new(Tcp, *address)
+                        .connect(socket_addr(host, prologix::PORT)?, timeout)?;
+                    io.with_io(|s| s.set_read_timeout(Some(timeout)))?;
+                    Connection::TcpGpib(io)
+                }
+                Some(Resource::Socket { host, port }) => {
+                    let io =
+                        Prologix::new(Tcp, *address).connect(socket_addr(host, *port)?, timeout)?;
+                    io.with_io(|s| s.set_read_timeout(Some(timeout)))?;
+                    Connection::TcpGpib(io)
+                }
+                _ => {
+                    return Err(format!(
+                        "{} needs the serial port or the host of a Prologix controller",
+                        self
+                    )
+                    .into())
+                }
+            },
+        })
+    }
+}
+
+impl Connection {
+    ///the scheme of the resource, e.g. to tell which operations it supports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Connection::Vxi11(_) => "vxi11",
+            Connection::Socket(_) => "socket",
+            Connection::Serial(_) => "serial",
+            Connection::SerialGpib(_) | Connection::TcpGpib(_) => "gpib",
+        }
+    }
+    ///the status byte read beside the message queue, `None` when the transport can only query
+    ///`*STB?`
+    pub fn read_stb(&mut self) -> Result<Option<StatusByte>> {
+        Ok(match self {
+            Connection::Vxi11(io) => Some(StatusByte::new(io.get_mut().device_read_stb()?)),
+            Connection::SerialGpib(io) => Some(io.serial_poll()?),
+            Connection::TcpGpib(io) => Some(io.serial_poll()?),
+            Connection::Socket(_) | Connection::Serial(_) => None,
+        })
+    }
+    ///device clear, responses not read yet are dropped
+    pub fn clear(&mut self) -> Result<()> {
+        match self {
+            Connection::Vxi11(io) => io.clear(),
+            Connection::Socket(io) => io.device_clear(),
+            Connection::SerialGpib(io) => io.clear(),
+            Connection::TcpGpib(io) => io.clear(),
+            Connection::Serial(_) => Err("a serial port has no device clear".into()),
+        }
+    }
+    ///abort the operation in progress, through the VXI-11 abort channel or by taking
+    ///control of the GPIB bus
+    pub fn abort(&mut self) -> Result<()> {
+        match self {
+            Connection::Vxi11(io) => io.get_mut().device_abort(),
+            Connection::SerialGpib(io) => io.interface_clear(),
+            Connection::TcpGpib(io) => io.interface_clear(),
+            Connection::Socket(_) | Connection::Serial(_) => {
+                Err(format!("a {} connection can't abort", self.kind()).into())
+            }
+        }
+    }
+    ///how long a read waits for the instrument
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
+        match self {
+            Connection::Vxi11(io) => {
+                io.get_mut().set_io_timeout(timeout);
+            }
+            Connection::Socket(io) => {
+                io.set_timeout(timeout)?;
+            }
+            Connection::Serial(io) => io.set_timeout(timeout)?,
+            Connection::SerialGpib(io) => io.with_io(|s| s.set_timeout(timeout))?,
+            Connection::TcpGpib(io) => io.with_io(|s| s.set_read_timeout(Some(timeout)))?,
+        }
+        Ok(())
+    }
+}
+
+macro_rules! each {
+    ($self:expr, $io:ident => $e:expr) => {
+        match $self {
+            Connection::Vxi11($io) => $e,
+            Connection::Socket($io) => $e,
+            Connection::Serial($io) => $e,
+            Connection::SerialGpib($io) => $e,
+            Connection::TcpGpib($io) => $e,
+        }
+    };
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        each!(self, io => io.write(buf))
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        each!(self, io => io.flush())
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        each!(self, io => io.read(buf))
+    }
+}
+
+///the driver bound to a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DriverKind {
+    ///chosen from the identity of the instrument, see [`Registry`]
+    #[default]
+    Auto,
+    Generic,
+    Infiniium,
+    Mdt693b,
+    Rigol,
+}
+
+impl FromStr for DriverKind {
+    type Err = crate::error::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "auto" => DriverKind::Auto,
+            "generic" => DriverKind::Generic,
+            "infiniium" => DriverKind::Infiniium,
+            "mdt693b" => DriverKind::Mdt693b,
+            "rigol" => DriverKind::Rigol,
+            _ => {
+                return Err(format!(
+                    "unknown driver '{}', expected auto, generic, infiniium, mdt693b or rigol",
+                    s
+                )
+                .into())
+            }
+        })
+    }
+}
+
+impl DriverKind {
+    pub fn bind(self, connection: Connection) -> Result<Box<dyn Driver>> {
+        Ok(match self {
+            DriverKind::Auto => Registry::default().identify(connection)?.driver,
+            DriverKind::Generic => Box::new(Messenger::new(connection).bind(Generic)),
+            DriverKind::Infiniium => Box::new(Messenger::new(connection).bind(Infiniium)),
+            DriverKind::Mdt693b => Box::new(Messenger::new(connection).bind(MDT693B)),
+            DriverKind::Rigol => Box::new(Messenger::new(connection).bind(Rigol)),
+        })
+    }
+}
+
+#[test]
+fn parse_resources() -> Result<()> {
+    let vxi11 = |host: &str, device: &str| Resource::Vxi11 {
+        host: host.to_string(),
+        device: device.to_string(),
+    };
+    assert_eq!(
+        "TCPIP0::192.168.3.96::INSTR".parse::<Resource>()?,
+        vxi11("192.168.3.96", "inst0")
+    );
+    assert_eq!(
+        "192.168.3.96".parse::<Resource>()?,
+        vxi11("192.168.3.96", "inst0")
+    );
+    assert_eq!(
+        "tcpip::gw::gpib0,8".parse::<Resource>()?,
+        vxi11("gw", "gpib0,8")
+    );
+    let socket = Resource::Socket {
+        host: "169.254.209.174".to_string(),
+        port: 5025,
+    };
+    assert_eq!(
+        "TCPIP::169.254.209.174::5025::SOCKET".parse::<Resource>()?,
+        socket
+    );
+    assert_eq!("169.254.209.174:5025".parse::<Resource>()?, socket);
+    assert_eq!(socket.to_string(), "TCPIP0::169.254.209.174::5025::SOCKET");
+    let com5 = Resource::Serial {
+        port: "COM5".to_string(),
+    };
+    assert_eq!("ASRL5::INSTR".parse::<Resource>()?, com5);
+    assert_eq!("COM5".parse::<Resource>()?, com5);
+    assert_eq!(com5.to_string(), "ASRL5::INSTR");
+    let tty: Resource = "ASRL/dev/ttyUSB0::INSTR".parse()?;
+    assert_eq!(tty.to_string(), "ASRL/dev/ttyUSB0::INSTR");
+    assert_eq!(
+        "GPIB0::8::2::INSTR".parse::<Resource>()?,
+        Resource::Gpib {
+            address: GpibAddress::new(8, Some(2))?
+        }
+    );
+    for bad in &[
+        "GPIB::31::INSTR",
+        "TCPIP::host::x::SOCKET",
+        "USB::1::INSTR",
+        "host:",
+        "::",
+        "[fe80::1",
+        "[fe80::1]5025",
+        ":5025",
+    ] {
+        assert!(bad.parse::<Resource>().is_err(), "{}", bad);
+    }
+    Ok(())
+}
+
+#[test]
+fn parse_ipv6_resources() -> Result<()> {
+    let vxi11 = |host: &str| Resource::Vxi11 {
+        host: host.to_string(),
+        device: "inst0".to_string(),
+    };
+    let socket = Resource::Socket {
+        host: "fe80::1".to_string(),
+        port: 5025,
+    };
+    assert_eq!("::1".parse::<Resource>()?, vxi11("::1"));
+    assert_eq!("fe80::1".parse::<Resource>()?, vxi11("fe80::1"));
+    assert_eq!("[fe80::1]".parse::<Resource>()?, vxi11("fe80::1"));
+    assert_eq!(
+        "TCPIP0::[fe80::1]::INSTR".parse::<Resource>()?,
+        vxi11("fe80::1")
+    );
+    assert_eq!("[fe80::1]:5025".parse::<Resource>()?, socket);
+    assert_eq!(socket.to_string(), "TCPIP0::[fe80::1]::5025::SOCKET");
+    assert_eq!(socket.to_string().parse::<Resource>()?, socket);
+    Ok(())
+}
+
+#[test]
+fn connect_resources() -> Result<()> {
+    use crate::test_support::line_instrument;
+    let transport = Transport::default();
+    let (port, log) = line_instrument(|l| (l == "*IDN?").then(|| "FAKE,SOCKET,0,1\n".into()))?;
+    let socket = Resource::Socket {
+        host: "127.0.0.1".to_string(),
+        port,
+    };
+    let connection = socket.connect(&transport)?;
+    assert_eq!(connection.kind(), "socket");
+    let mut driver = DriverKind::Generic.bind(connection)?;
+    assert_eq!(driver.query(b"*IDN?")?, b"FAKE,SOCKET,0,1\n");
+    assert_eq!(*log.lock().unwrap(), ["*IDN?"]);
+
+    //a Prologix controller, the escaped line end of the message comes as an empty line
+    let (port, log) = line_instrument(|l| (l == "++read eoi").then(|| "FAKE,GPIB,0,1\n".into()))?;
+    let gpib = Resource::Gpib {
+        address: GpibAddress::new(8, Some(2))?,
+    };
+    assert!(gpib.connect(&transport).is_err());
+    let transport = Transport {
+        adapter: Some(Resource::Socket {
+            host: "127.0.0.1".to_string(),
+            port,
+        }),
+        ..Transport::default()
+    };
+    let connection = gpib.connect(&transport)?;
+    assert_eq!(connection.kind(), "gpib");
+    let mut driver = DriverKind::Generic.bind(connection)?;
+    assert_eq!(driver.query(b"*IDN?")?, b"FAKE,GPIB,0,1\n");
+    let log = log.lock().unwrap();
+    assert_eq!(
+        log[log.len() - 4..],
+        ["++addr 8 98", "*IDN?\x1b", "", "++read eoi"]
+    );
+    Ok(())
+}
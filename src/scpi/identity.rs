@@ -0,0 +1,150 @@
+//! The answer to `*IDN?`: `<manufacturer>,<model>,<serial>,<firmware>`.
+//!
+//! Not every vendor sticks to the four fields, the parser accepts:
+//! - quotes around the whole answer and blanks around the fields, e.g. older Tektronix
+//! - a `MODEL ` prefix in front of the model, e.g. Keithley `MODEL 2000`
+//! - missing trailing fields, left empty, and extra fields, joined into the firmware with commas,
+//!   e.g. Rohde & Schwarz options after the version
+//! - serials reported as `0` when the instrument has none, left as they are
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Identity {
+    pub manufacturer: String,
+    pub model: String,
+    pub serial: String,
+    pub firmware: String,
+}
+
+///manufacturers known under several names, e.g. Keysight after Agilent and Hewlett-Packard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Vendor {
+    Keysight,
+    Keithley,
+    Rigol,
+    RohdeSchwarz,
+    Siglent,
+    Tektronix,
+    Thorlabs,
+    Other,
+}
+
+impl Vendor {
+    ///the vendor of a manufacturer field, compared case insensitively
+    pub fn from_manufacturer(manufacturer: &str) -> Self {
+        let m = manufacturer.to_ascii_uppercase();
+        let is = |names: &[&str]| names.iter().any(|n| m.starts_with(n));
+        if is(&[
+            "KEYSIGHT",
+            "AGILENT",
+            "HEWLETT-PACKARD",
+            "HEWLETT PACKARD",
+            "HP",
+        ]) {
+            Vendor::Keysight
+        } else if is(&["KEITHLEY"]) {
+            Vendor::Keithley
+        } else if is(&["RIGOL"]) {
+            Vendor::Rigol
+        } else if is(&["ROHDE", "R&S"]) {
+            Vendor::RohdeSchwarz
+        } else if is(&["SIGLENT"]) {
+            Vendor::Siglent
+        } else if is(&["TEKTRONIX"]) {
+            Vendor::Tektronix
+        } else if is(&["THORLABS"]) {
+            Vendor::Thorlabs
+        } else {
+            Vendor::Other
+        }
+    }
+}
+
+impl Identity {
+    pub fn new<S: Into<String>>(manufacturer: S, model: S, serial: S, firmware: S) -> Self {
+        Self {
+            manufacturer: manufacturer.into(),
+            model: model.into(),
+            serial: serial.into(),
+            firmware: firmware.into(),
+        }
+    }
+    pub fn vendor(&self) -> Vendor {
+        Vendor::from_manufacturer(&self.manufacturer)
+    }
+    ///whether the model starts with one of `prefixes`, compared case insensitively
+    pub fn model_starts_with(&self, prefixes: &[&str]) -> bool {
+        let model = self.model.to_ascii_uppercase();
+        prefixes
+            .iter()
+            .any(|p| model.starts_with(&p.to_ascii_uppercase()))
+    }
+}
+
+impl FromStr for Identity {
+    type Err = crate::error::Error;
+    fn from_str(s: &str) -> crate::Result<Self> {
+        let s = s.trim().trim_matches('"').trim();
+        let mut fields = s.splitn(4, ',').map(str::trim);
+        let manufacturer = fields.next().unwrap_or_default();
+        let model = fields.next().ok_or_else(|| {
+            format!(
+                "'{}' isn't an identification, expected <manufacturer>,<model>,...",
+                s
+            )
+        })?;
+        let model = match model.get(..6) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("MODEL ") => model[6..].trim_start(),
+            _ => model,
+        };
+        if manufacturer.is_empty() || model.is_empty() {
+            return Err(format!("'{}' has no manufacturer or model", s).into());
+        }
+        let serial = fields.next().unwrap_or_default();
+        let firmware = fields
+            .next()
+            .map(|f| f.split(',').map(str::trim).collect::<Vec<_>>().join(","))
+            .unwrap_or_default();
+        Ok(Self::new(manufacturer, model, serial, firmware.as_str()))
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.manufacturer, self.model, self.serial, self.firmware
+        )
+    }
+}
+
+#[test]
+fn parse_identities() -> crate::Result<()> {
+    let id: Identity = "KEYSIGHT TECHNOLOGIES,DSOS254A,MY55160101,06.40.01101\n".parse()?;
+    assert_eq!(
+        id,
+        Identity::new(
+            "KEYSIGHT TECHNOLOGIES",
+            "DSOS254A",
+            "MY55160101",
+            "06.40.01101"
+        )
+    );
+    assert_eq!(id.vendor(), Vendor::Keysight);
+    let id: Identity = "KEITHLEY INSTRUMENTS INC.,MODEL 2000,1234567,A19  /A02  ".parse()?;
+    assert_eq!((id.model.as_str(), id.vendor()), ("2000", Vendor::Keithley));
+    let id: Identity = "\"TEKTRONIX, TDS 210, 0, CF:91.1CT FV:v1.16\"".parse()?;
+    assert_eq!(id.model, "TDS 210");
+    assert_eq!(id.firmware, "CF:91.1CT FV:v1.16");
+    let id: Identity =
+        "Rohde&Schwarz,SMB100A,1406.6000k03/123456,3.1.19.15-3.20.390.24,B1,B22".parse()?;
+    assert_eq!(id.vendor(), Vendor::RohdeSchwarz);
+    assert_eq!(id.firmware, "3.1.19.15-3.20.390.24,B1,B22");
+    let id: Identity = "RIGOL TECHNOLOGIES,DS1104Z".parse()?;
+    assert_eq!((id.serial.as_str(), id.firmware.as_str()), ("", ""));
+    assert!(id.model_starts_with(&["ds1"]));
+    assert!("no commas here".parse::<Identity>().is_err());
+    assert!(",DS1104Z,1,1".parse::<Identity>().is_err());
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! IEEE 488.2 common commands, available on every [`Scpi`] session.
+use super::{
+    com_cmd, register_value,
+    wait::{pause, Cancel},
+    EventStatusByte, Scpi, ToCommand,
+};
+use crate::Result;
+use std::time::{Duration, Instant};
+
+///time between two `*ESR?` while waiting for the operation complete event
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+///answer to `*TST?`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestResult {
+    Passed,
+    ///the instrument specific code of the failure
+    Failed(i32),
+}
+
+impl SelfTestResult {
+    pub fn passed(&self) -> bool {
+        *self == SelfTestResult::Passed
+    }
+}
+
+pub trait Ieee4882: Scpi {
+    ///`*RST`, the default setup
+    fn reset(&mut self) -> Result<()> {
+        self.scpi_send(com_cmd::RST)
+    }
+    ///`*CLS`, clear the status registers and the error queue; not a device clear, which goes
+    ///through the transport
+    fn clear_status(&mut self) -> Result<()> {
+        self.scpi_send(com_cmd::CLS)
+    }
+    ///`*TST?`, some instruments take seconds to answer
+    fn self_test(&mut self) -> Result<SelfTestResult> {
+        let answer = self.scpi_query(com_cmd::TST.to_command().query())?;
+        let code = answer.trim().trim_start_matches('+');
+        match code.parse() {
+            Ok(0) => Ok(SelfTestResult::Passed),
+            Ok(code) => Ok(SelfTestResult::Failed(code)),
+            Err(_) => Err(format!("unexpected self test result '{}'", code).into()),
+        }
+    }
+    ///`*OPT?`, the installed options, empty slots reported as `0` are left out
+    fn options(&mut self) -> Result<Vec<String>> {
+        let answer = self.scpi_query(com_cmd::OPT.to_command().query())?;
+        Ok(answer
+            .trim()
+            .trim_matches('"')
+            .split(',')
+            .map(|o| o.trim().trim_matches('"'))
+            .filter(|o| !o.is_empty() && *o != "0")
+            .map(str::to_string)
+            .collect())
+    }
+    ///`*SAV`, store the setup in a slot of the instrument
+    fn save(&mut self, slot: u8) -> Result<()> {
+        self.scpi_send(com_cmd::SAV.to_command().para(slot.to_string()))
+    }
+    ///`*RCL`, restore the setup stored in a slot
+    fn recall(&mut self, slot: u8) -> Result<()> {
+        self.scpi_send(com_cmd::RCL.to_command().para(slot.to_string()))
+    }
+    ///`*PSC`, whether the event and service request masks are cleared at power on
+    fn set_power_on_status_clear(&mut self, clear: bool) -> Result<()> {
+        self.scpi_send(
+            com_cmd::PSC
+                .to_command()
+                .para(if clear { "1" } else { "0" }),
+        )
+    }
+    fn power_on_status_clear(&mut self) -> Result<bool> {
+        let answer = self.scpi_query(com_cmd::PSC.to_command().query())?;
+        Ok(register_value(answer.as_bytes())? != 0)
+    }
+    ///`*TRG`
+    fn trigger(&mut self) -> Result<()> {
+        self.scpi_send(com_cmd::TRG)
+    }
+    ///`*WAI`, the instrument executes the following messages once the pending operations are done
+    fn wait(&mut self) -> Result<()> {
+        self.scpi_send(com_cmd::WAI)
+    }
+    ///`*OPC?`, which is answered once the pending operations are done; reads timing out before
+    ///`timeout` are retried. After an error the `*OPC?` is still pending and its late `1` would
+    ///be read as the answer to the next query: device clear the session before going on, or wait
+    ///with [`WaitComplete::wait_complete`](super::wait::WaitComplete::wait_complete) and
+    ///[`Strategy::OpcQuery`](super::wait::Strategy::OpcQuery) which does it
+    fn wait_opc_query(&mut self, timeout: Duration) -> Result<()> {
+        self.wait_opc_query_with(timeout, &Cancel::new())
+    }
+    ///same as [`Ieee4882::wait_opc_query`], `cancel` ends the wait with an `Interrupted` error
+    ///once the read in progress times out; after a timeout or a cancel the caller must device
+    ///clear the session, the `*OPC?` is still pending
+    fn wait_opc_query_with(&mut self, timeout: Duration, cancel: &Cancel) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        self.scpi_send(com_cmd::OPC.to_command().query())?;
+        loop {
+            match self.read_bin() {
+                Ok(_) => return Ok(()),
+                Err(e) if e.is_timeout() => {
+                    pause(Duration::from_secs(0), deadline, timeout, cancel)?
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    ///`*OPC`, then `*ESR?` until the operation complete event is set; reading the event status
+    ///register clears it, the other events read meanwhile are lost
+    fn wait_opc_event(&mut self, timeout: Duration) -> Result<EventStatusByte> {
+        self.wait_opc_event_with(timeout, POLL_INTERVAL, &Cancel::new())
+    }
+    ///same as [`Ieee4882::wait_opc_event`], reading `*ESR?` every `poll`, `cancel` ends the wait
+    ///with an `Interrupted` error
+    fn wait_opc_event_with(
+        &mut self,
+        timeout: Duration,
+        poll: Duration,
+        cancel: &Cancel,
+    ) -> Result<EventStatusByte> {
+        let deadline = Instant::now() + timeout;
+        self.scpi_send(com_cmd::OPC)?;
+        loop {
+            let events = self.get_event_byte()?;
+            if events.is_opera_complete() {
+                return Ok(events);
+            }
+            pause(poll, deadline, timeout, cancel)?;
+        }
+    }
+}
+
+impl<S: Scpi> Ieee4882 for S {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::{collections::VecDeque, io};
+
+    ///records the messages and reads the scripted answers, `None` times out
+    #[derive(Default)]
+    struct Session {
+        sent: Vec<String>,
+        answers: VecDeque<Option<&'static str>>,
+    }
+
+    impl Scpi for Session {
+        fn write_bin<C: AsRef<[u8]>>(&mut self, content: C) -> Result<()> {
+            self.sent
+                .push(String::from_utf8_lossy(content.as_ref()).trim_end().into());
+            Ok(())
+        }
+        fn read_bin(&mut self) -> Result<Bytes> {
+            match self.answers.pop_front() {
+                Some(Some(a)) => Ok(Bytes::from_static(a.as_bytes())),
+                Some(None) => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+                None => panic!("nothing left to read"),
+            }
+        }
+    }
+
+    #[test]
+    fn common_commands() -> Result<()> {
+        let mut s = Session::default();
+        s.answers.extend(vec![
+            Some("+0\n"),
+            Some("-12\n"),
+            Some("\"DSOX9,001,0,0\"\n"),
+            Some("0\n"),
+            Some("1\n"),
+        ]);
+        s.reset()?;
+        s.clear_status()?;
+        assert!(s.self_test()?.passed());
+        assert_eq!(s.self_test()?, SelfTestResult::Failed(-12));
+        assert_eq!(s.options()?, vec!["DSOX9", "001"]);
+        assert!(s.options()?.is_empty());
+        s.save(3)?;
+        s.recall(3)?;
+        s.set_power_on_status_clear(false)?;
+        assert!(s.power_on_status_clear()?);
+        s.trigger()?;
+        s.wait()?;
+        assert_eq!(
+            s.sent,
+            vec![
+                "*RST", "*CLS", "*TST?", "*TST?", "*OPT?", "*OPT?", "*SAV 3", "*RCL 3", "*PSC 0",
+                "*PSC?", "*TRG", "*WAI"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn operation_complete_waits() -> Result<()> {
+        let mut s = Session::default();
+        s.answers.extend(vec![None, None, Some("1\n")]);
+        s.wait_opc_query(Duration::from_secs(1))?;
+        s.answers.extend(vec![None]);
+        let e = s.wait_opc_query(Duration::from_secs(0)).unwrap_err();
+        assert!(e.is_timeout());
+        //without a device clear the late answer is taken for the one to the next query
+        s.answers.extend(vec![Some("1\n"), Some("+0\n")]);
+        assert_eq!(s.self_test()?, SelfTestResult::Failed(1));
+        s.answers.clear();
+
+        s.sent.clear();
+        s.answers
+            .extend(vec![Some("+0\n"), Some("+32\n"), Some("+1\n")]);
+        s.wait_opc_event(Duration::from_secs(1))?;
+        assert_eq!(s.sent, vec!["*OPC", "*ESR?", "*ESR?", "*ESR?"]);
+        s.answers.extend(vec![Some("+0\n"); 3]);
+        assert!(s.wait_opc_event(POLL_INTERVAL).unwrap_err().is_timeout());
+        Ok(())
+    }
+}
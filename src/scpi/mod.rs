@@ -1,10 +1,16 @@
-use std::fmt;
+use std::{fmt, io::BufRead};
 
-use bytes::{Buf, Bytes};
+use bytes::Bytes;
 
 pub mod com_cmd;
+pub mod identity;
+pub mod ieee4882;
 pub mod scpi_error;
+pub mod wait;
 use crate::Result;
+pub use identity::Identity;
+pub use ieee4882::{Ieee4882, SelfTestResult};
+pub use wait::{Cancel, Strategy, WaitComplete};
 pub trait Scpi {
     const TERM: u8 = b'\n';
     fn write_bin<C: AsRef<[u8]>>(&mut self, content: C) -> Result<()>;
@@ -29,17 +35,17 @@ pub trait Scpi {
         self.scpi_send(mess)?;
         self.scpi_read()
     }
+    ///ask `*IDN?`
+    fn identify(&mut self) -> Result<Identity> {
+        self.scpi_query(com_cmd::IDN.to_command().query())?.parse()
+    }
     fn get_event_byte(&mut self) -> Result<EventStatusByte> {
         self.scpi_send(com_cmd::ESR.to_command().query())?;
-        let mut b = self.read_bin()?;
-        let byte = EventStatusByte::new(b.get_u8());
-        Ok(byte)
+        Ok(EventStatusByte::new(register_value(&self.read_bin()?)?))
     }
     fn get_status_byte(&mut self) -> Result<StatusByte> {
         self.scpi_send(com_cmd::STB.to_command().query())?;
-        let mut b = self.read_bin()?;
-        let byte = StatusByte::new(b.get_u8());
-        Ok(byte)
+        Ok(StatusByte::new(register_value(&self.read_bin()?)?))
     }
     fn set_event_mask<B: Into<u8>>(&mut self, byte: B) -> Result<()> {
         self.scpi_send(com_cmd::ESE.to_command().para(byte.into().to_string()))
@@ -47,7 +53,74 @@ pub trait Scpi {
     fn set_service_mask<B: Into<u8>>(&mut self, byte: B) -> Result<()> {
         self.scpi_send(com_cmd::SRE.to_command().para(byte.into().to_string()))
     }
+    fn get_event_mask(&mut self) -> Result<u8> {
+        self.scpi_send(com_cmd::ESE.to_command().query())?;
+        register_value(&self.read_bin()?)
+    }
+    fn get_service_mask(&mut self) -> Result<u8> {
+        self.scpi_send(com_cmd::SRE.to_command().query())?;
+        register_value(&self.read_bin()?)
+    }
+}
+///the value of a register query, answered in decimal like `+32`, or as the byte itself by some
+///instruments
+///
+///a lone byte, optionally followed by the terminator, is taken as is unless it's a digit, a
+///single digit answer is far more common in decimal than as a raw byte
+pub fn register_value(response: &[u8]) -> Result<u8> {
+    match response {
+        [byte] | [byte, b'\n'] | [byte, b'\r', b'\n'] if !byte.is_ascii_digit() => {
+            return Ok(*byte)
+        }
+        _ => {}
+    }
+    let text = std::str::from_utf8(response)
+        .map_err(|_| format!("{:?} isn't the value of a register", response))?
+        .trim();
+    let digits = text.strip_prefix(&['+', '-'][..]).unwrap_or(text);
+    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        if let Ok(value) = text.strip_prefix('+').unwrap_or(text).parse() {
+            return Ok(value);
+        }
+    }
+    Err(format!("'{}' isn't the value of a register", text).into())
+}
+
+///length of the first whole response in `data`: a definite length block and the `term` following
+///it, or anything up to and including `term`
+pub fn response_len(data: &[u8], term: u8) -> Option<usize> {
+    let mut from = 0;
+    if let [b'#', n, rest @ ..] = data {
+        if let Some(n) = (*n as char).to_digit(10).filter(|n| *n > 0) {
+            let n = n as usize;
+            let len = std::str::from_utf8(rest.get(..n)?)
+                .ok()
+                .and_then(|l| l.parse::<usize>().ok());
+            if let Some(len) = len {
+                from = 2 + n + len;
+            }
+        }
+    }
+    let end = data.get(from..)?.iter().position(|b| *b == term)?;
+    Some(from + end + 1)
 }
+
+///whether `data` holds exactly one whole response, see [`response_len`]
+pub fn is_complete_response(data: &[u8], term: u8) -> bool {
+    response_len(data, term) == Some(data.len())
+}
+
+///read one response from `reader`, a `term` inside a definite length block doesn't end it
+pub fn read_response<R: BufRead>(reader: &mut R, term: u8) -> Result<Bytes> {
+    let mut data = Vec::new();
+    while !is_complete_response(&data, term) {
+        if reader.read_until(term, &mut data)? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+    }
+    Ok(Bytes::from(data))
+}
+
 #[derive(Debug, Clone)]
 pub struct Command(String);
 
@@ -117,36 +190,40 @@ impl StatusByte {
     }
 
     pub fn is_triggered(&self) -> bool {
-        self.0 & (1 << 0) == 0
+        self.0 & (1 << 0) != 0
     }
     pub fn triggered(&mut self) -> &mut Self {
-        self.0 &= 1 << 0;
+        self.0 |= 1 << 0;
         self
     }
     pub fn is_displaying_message(&self) -> bool {
-        self.0 & (1 << 2) == 0
+        self.0 & (1 << 2) != 0
     }
     pub fn displaying_message(&mut self) -> &mut Self {
-        self.0 &= 1 << 2;
+        self.0 |= 1 << 2;
         self
     }
     pub fn is_message_available(&self) -> bool {
-        self.0 & (1 << 4) == 0
+        self.0 & (1 << 4) != 0
     }
     pub fn message_available(&mut self) -> &mut Self {
-        self.0 &= 1 << 4;
+        self.0 |= 1 << 4;
         self
     }
 
     pub fn is_event_happened(&self) -> bool {
-        self.0 & (1 << 5) == 0
+        self.0 & (1 << 5) != 0
     }
     pub fn event_happened(&mut self) -> &mut Self {
-        self.0 &= 1 << 5;
+        self.0 |= 1 << 5;
         self
     }
     pub fn is_requesting_service(&self) -> bool {
-        self.0 & (1 << 6) == 0
+        self.0 & (1 << 6) != 0
+    }
+    ///names of the IEEE 488.2 bits set, the others are device specific
+    pub fn flags(&self) -> Vec<&'static str> {
+        flags(self.0, &[(2, "EAV"), (4, "MAV"), (5, "ESB"), (6, "RQS")])
     }
 }
 #[derive(Clone, Copy)]
@@ -172,30 +249,54 @@ impl EventStatusByte {
         self.0 & (1 << 5) != 0
     }
     pub fn command_err(&mut self) -> &mut Self {
-        self.0 &= 1 << 5;
+        self.0 |= 1 << 5;
         self
     }
     pub fn is_device_dep_err(&self) -> bool {
         self.0 & (1 << 3) != 0
     }
     pub fn device_dep_err(&mut self) -> &mut Self {
-        self.0 &= 1 << 3;
+        self.0 |= 1 << 3;
         self
     }
     pub fn is_query_err(&self) -> bool {
         self.0 & (1 << 2) != 0
     }
     pub fn query_err(&mut self) -> &mut Self {
-        self.0 &= 1 << 2;
+        self.0 |= 1 << 2;
         self
     }
     pub fn is_opera_complete(&self) -> bool {
         self.0 & (1 << 0) != 0
     }
     pub fn opera_complete(&mut self) -> &mut Self {
-        self.0 &= 1 << 0;
+        self.0 |= 1 << 0;
         self
     }
+    ///names of the bits set
+    pub fn flags(&self) -> Vec<&'static str> {
+        flags(
+            self.0,
+            &[
+                (0, "OPC"),
+                (1, "RQC"),
+                (2, "QYE"),
+                (3, "DDE"),
+                (4, "EXE"),
+                (5, "CME"),
+                (6, "URQ"),
+                (7, "PON"),
+            ],
+        )
+    }
+}
+
+fn flags(byte: u8, names: &[(u8, &'static str)]) -> Vec<&'static str> {
+    names
+        .iter()
+        .filter(|(bit, _)| byte & (1 << bit) != 0)
+        .map(|(_, name)| *name)
+        .collect()
 }
 impl ToString for StatusByte {
     fn to_string(&self) -> String {
@@ -217,3 +318,36 @@ impl Scpi for super::protocols::onc_rpc::vxi11::Vxi11 {
         Ok(())
     }
 }
+
+#[test]
+fn register_values() {
+    assert_eq!(register_value(b"+32\n").unwrap(), 32);
+    assert_eq!(register_value(b"0\n").unwrap(), 0);
+    assert_eq!(register_value(b"128").unwrap(), 128);
+    //raw bytes, including ones that aren't UTF-8 or are whitespace
+    assert_eq!(register_value(&[0x80]).unwrap(), 0x80);
+    assert_eq!(register_value(&[0x80, b'\n']).unwrap(), 0x80);
+    assert_eq!(register_value(&[0x20, b'\r', b'\n']).unwrap(), 0x20);
+    assert_eq!(register_value(&[0x0A]).unwrap(), 0x0A);
+    assert_eq!(register_value(&[0x0A, b'\n']).unwrap(), 0x0A);
+    for bad in [&b""[..], b"-1\n", b"256", b"1.5", b"ab\n", &[0x80, 0x81]] {
+        assert!(register_value(bad).is_err(), "{:?}", bad);
+    }
+}
+
+#[test]
+fn status_flags() {
+    let mut stb = StatusByte::new(0);
+    assert!(!stb.is_triggered() && !stb.is_message_available() && !stb.is_requesting_service());
+    stb.triggered().message_available().event_happened();
+    assert_eq!(stb.byte(), 0b0011_0001);
+    assert!(stb.is_triggered() && stb.is_message_available() && stb.is_event_happened());
+    assert!(!stb.is_displaying_message() && !stb.is_requesting_service());
+    assert!(StatusByte::new(1 << 6).is_requesting_service());
+    let mut esr = EventStatusByte::new(0);
+    esr.command_err().opera_complete();
+    assert_eq!(esr.byte(), 0b0010_0001);
+    assert!(esr.is_command_err() && esr.is_opera_complete() && !esr.is_query_err());
+    esr.query_err().device_dep_err();
+    assert_eq!(esr.byte(), 0b0010_1101);
+}
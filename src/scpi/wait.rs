@@ -0,0 +1,645 @@
+//! Waiting for the pending operations of an instrument to complete, e.g. after `:AUToscale` or
+//! `:SINGle`.
+//!
+//! Every [`Strategy`] sets up what it needs, waits, then puts the event and service request masks
+//! back as it found them, whether the wait succeeded, timed out or was cancelled.
+use super::{com_cmd, Ieee4882, StatusByte};
+use crate::{
+    error::Error,
+    instruments::{Instrument, Messenger, Model},
+    protocols::{
+        onc_rpc::{vxi11::Vxi11, RpcProgram},
+        prologix::PrologixIo,
+        socket::SocketSession,
+    },
+    Result,
+};
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+///operation complete bit of the event status register
+const OPC: u8 = 1 << 0;
+///event summary bit of the status byte
+const ESB: u8 = 1 << 5;
+///handle given to the instrument with `device_enable_srq`
+const SRQ_HANDLE: &[u8] = b"rustrument opc";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    ///`*OPC?`, answered once the operations are done; the session's read timeout sets how often
+    ///cancellation is checked; when the wait gives up the answer still on its way is dropped with
+    ///a device clear, if the transport has one
+    OpcQuery,
+    ///`*OPC`, then `*ESR?` every `poll` until the operation complete event is set
+    EventPoll { poll: Duration },
+    ///`*OPC` with the event enabled into the status byte, read every `poll` until its event
+    ///summary bit is set: `device_read_stb` over VXI-11, a serial poll over GPIB, `*STB?` otherwise
+    StatusPoll { poll: Duration },
+    ///`*OPC` with the event enabled to request service, then wait for the service request on the
+    ///VXI-11 interrupt channel, see [`Vxi11::establish_interrupt`]
+    ServiceRequest,
+}
+
+///cancels the waits it is given to from another thread
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+    ///allow the waits to run again
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+fn cancelled() -> Error {
+    io::Error::new(
+        io::ErrorKind::Interrupted,
+        "wait for operation complete cancelled",
+    )
+    .into()
+}
+
+pub(super) fn not_complete(timeout: Duration) -> Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("operation not complete after {:?}", timeout),
+    )
+    .into()
+}
+
+///sleep up to `dur`, ending early past `deadline`, then tell whether to go on
+pub(super) fn pause(
+    dur: Duration,
+    deadline: Instant,
+    timeout: Duration,
+    cancel: &Cancel,
+) -> Result<()> {
+    if cancel.is_cancelled() {
+        return Err(cancelled());
+    }
+    let now = Instant::now();
+    if now >= deadline {
+        return Err(not_complete(timeout));
+    }
+    std::thread::sleep(dur.min(deadline - now));
+    Ok(())
+}
+
+pub trait WaitComplete: Ieee4882 {
+    ///the status byte, without going through the message queue when the transport allows it
+    fn read_status(&mut self) -> Result<StatusByte> {
+        self.get_status_byte()
+    }
+    ///wait for the instrument to request service, only VXI-11 has a channel for it
+    fn wait_service_request(&mut self, _timeout: Duration, _cancel: &Cancel) -> Result<()> {
+        Err("service requests can only be waited for over VXI-11".into())
+    }
+    ///enable or disable service requests, the transport may need to be told too
+    fn enable_service_request(&mut self, _enable: bool) -> Result<()> {
+        Ok(())
+    }
+    ///empty the input and output buffers of the instrument, through the transport
+    fn device_clear(&mut self) -> Result<()> {
+        Err("no device clear over this transport".into())
+    }
+    fn wait_complete(&mut self, strategy: Strategy, timeout: Duration) -> Result<()> {
+        self.wait_complete_with(strategy, timeout, &Cancel::new())
+    }
+    ///see [`Strategy`], `cancel` ends the wait with an `Interrupted` error
+    fn wait_complete_with(
+        &mut self,
+        strategy: Strategy,
+        timeout: Duration,
+        cancel: &Cancel,
+    ) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        match strategy {
+            Strategy::OpcQuery => {
+                let waited = self.wait_opc_query_with(timeout, cancel);
+                if waited.is_ok() {
+                    return waited;
+                }
+                //the answer still on its way would be taken for the one to the next query
+                restore(self, waited, |s| s.device_clear())
+            }
+            Strategy::EventPoll { poll } => {
+                self.wait_opc_event_with(timeout, poll, cancel).map(|_| ())
+            }
+            Strategy::StatusPoll { poll } => {
+                let event_mask = self.get_event_mask()?;
+                let waited = (|| {
+                    //a pending event would set the summary bit right away
+                    self.get_event_byte()?;
+                    self.set_event_mask(OPC)?;
+                    self.scpi_send(com_cmd::OPC)?;
+                    while self.read_status()?.byte() & ESB == 0 {
+                        pause(poll, deadline, timeout, cancel)?;
+                    }
+                    Ok(())
+                })();
+                restore(self, waited, |s| {
+                    s.get_event_byte()?;
+                    s.set_event_mask(event_mask)
+                })
+            }
+            Strategy::ServiceRequest => {
+                let (event_mask, service_mask) = (self.get_event_mask()?, self.get_service_mask()?);
+                let waited = (|| {
+                    self.get_event_byte()?;
+                    self.set_event_mask(OPC)?;
+                    self.set_service_mask(ESB)?;
+                    self.enable_service_request(true)?;
+                    self.scpi_send(com_cmd::OPC)?;
+                    loop {
+                        let left = deadline.saturating_duration_since(Instant::now());
+                        self.wait_service_request(left, cancel)?;
+                        //another source may have requested service
+                        if self.read_status()?.byte() & ESB != 0 {
+                            return Ok(());
+                        }
+                    }
+                })();
+                restore(self, waited, |s| {
+                    s.enable_service_request(false)?;
+                    s.get_event_byte()?;
+                    s.set_service_mask(service_mask)?;
+                    s.set_event_mask(event_mask)
+                })
+            }
+        }
+    }
+}
+
+///run `cleanup` whatever the outcome of the wait, its error is only reported if the wait succeeded
+fn restore<S: ?Sized, F: FnOnce(&mut S) -> Result<()>>(
+    session: &mut S,
+    waited: Result<()>,
+    cleanup: F,
+) -> Result<()> {
+    let cleaned = cleanup(session);
+    waited.and(cleaned)
+}
+
+impl<IO: Write + Read, M: Model> WaitComplete for Instrument<Messenger<IO>, M> {}
+impl WaitComplete for SocketSession {
+    fn device_clear(&mut self) -> Result<()> {
+        SocketSession::device_clear(self)
+    }
+}
+
+impl<IO: Read + Write> WaitComplete for PrologixIo<IO> {
+    fn read_status(&mut self) -> Result<StatusByte> {
+        self.serial_poll()
+    }
+    fn device_clear(&mut self) -> Result<()> {
+        PrologixIo::clear(self)
+    }
+}
+
+impl WaitComplete for Vxi11 {
+    fn read_status(&mut self) -> Result<StatusByte> {
+        Ok(StatusByte::new(self.device_read_stb()?))
+    }
+    fn enable_service_request(&mut self, enable: bool) -> Result<()> {
+        self.device_enable_srq(enable, SRQ_HANDLE)
+    }
+    fn device_clear(&mut self) -> Result<()> {
+        Vxi11::clear(self)
+    }
+    fn wait_service_request(&mut self, timeout: Duration, cancel: &Cancel) -> Result<()> {
+        let interrupt = self
+            .mut_interrupt()
+            .ok_or(crate::protocols::onc_rpc::vxi11::vxi11_error::Vxi11Error::NotEstablished)?;
+        let deadline = Instant::now() + timeout;
+        let previous = interrupt.get_io().read_timeout()?;
+        //wake up now and then to look at `cancel`
+        interrupt
+            .get_io()
+            .set_read_timeout(Some(Duration::from_millis(100)))?;
+        let waited = loop {
+            match interrupt.device_intr_srq() {
+                Ok(_) => break Ok(()),
+                Err(e) if e.is_timeout() => {
+                    if let Err(e) = pause(Duration::from_secs(0), deadline, timeout, cancel) {
+                        break Err(e);
+                    }
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        interrupt.get_io().set_read_timeout(previous)?;
+        waited
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scpi::Scpi;
+    use bytes::Bytes;
+    use std::collections::VecDeque;
+
+    ///an instrument completing the operation after `busy` status reads, its registers are
+    ///answered from `ese`, `sre` and the operation state; `*OPC?` sent while busy is answered
+    ///along with the next message
+    #[derive(Default)]
+    struct Session {
+        sent: Vec<String>,
+        answers: VecDeque<String>,
+        late: Option<String>,
+        ese: u8,
+        sre: u8,
+        busy: usize,
+        opc_pending: bool,
+    }
+
+    impl Session {
+        fn done(&mut self) -> bool {
+            if self.busy == 0 {
+                return self.opc_pending;
+            }
+            self.busy -= 1;
+            false
+        }
+    }
+
+    impl Scpi for Session {
+        fn write_bin<C: AsRef<[u8]>>(&mut self, content: C) -> Result<()> {
+            let message = String::from_utf8_lossy(content.as_ref())
+                .trim_end()
+                .to_string();
+            self.answers.extend(self.late.take());
+            let mut words = message.split(' ');
+            let answer = match (words.next().unwrap(), words.next()) {
+                ("*ESE", Some(v)) => {
+                    self.ese = v.parse().unwrap();
+                    None
+                }
+                ("*SRE", Some(v)) => {
+                    self.sre = v.parse().unwrap();
+                    None
+                }
+                ("*ESE?", _) => Some(self.ese.to_string()),
+                ("*SRE?", _) => Some(self.sre.to_string()),
+                ("*OPC", _) => {
+                    self.opc_pending = true;
+                    None
+                }
+                ("*OPC?", _) if self.busy > 0 => {
+                    self.late = Some("1".to_string());
+                    None
+                }
+                ("*OPC?", _) => Some("1".to_string()),
+                ("*ESR?", _) => Some((self.done() as u8).to_string()),
+                ("*STB?", _) => {
+                    let esb = self.done() && self.ese & OPC != 0;
+                    Some(if esb { "32" } else { "0" }.to_string())
+                }
+                (other, _) => panic!("unexpected message {}", other),
+            };
+            self.sent.push(message);
+            self.answers.extend(answer);
+            Ok(())
+        }
+        fn read_bin(&mut self) -> Result<Bytes> {
+            match self.answers.pop_front() {
+                Some(a) => Ok(Bytes::from(a + "\n")),
+                None => Err(io::Error::from(io::ErrorKind::TimedOut).into()),
+            }
+        }
+    }
+    impl WaitComplete for Session {
+        fn device_clear(&mut self) -> Result<()> {
+            self.sent.push("DCL".to_string());
+            self.answers.clear();
+            self.late = None;
+            Ok(())
+        }
+    }
+
+    const POLL: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn strategies_complete() -> Result<()> {
+        let mut s = Session {
+            ese: 0x3C,
+            sre: 0x10,
+            ..Default::default()
+        };
+        s.wait_complete(Strategy::OpcQuery, Duration::from_secs(1))?;
+        s.busy = 3;
+        s.wait_complete(Strategy::EventPoll { poll: POLL }, Duration::from_secs(1))?;
+        s.busy = 3;
+        s.wait_complete(Strategy::StatusPoll { poll: POLL }, Duration::from_secs(1))?;
+        assert_eq!((s.ese, s.sre), (0x3C, 0x10));
+        assert!(s.sent.contains(&"*ESE 1".to_string()));
+        //no interrupt channel but the masks are restored all the same
+        assert!(s
+            .wait_complete(Strategy::ServiceRequest, Duration::from_secs(1))
+            .is_err());
+        assert_eq!((s.ese, s.sre), (0x3C, 0x10));
+        Ok(())
+    }
+
+    #[test]
+    fn waits_time_out_and_cancel() {
+        let mut s = Session {
+            ese: 0x20,
+            busy: usize::MAX,
+            ..Default::default()
+        };
+        let status = Strategy::StatusPoll { poll: POLL };
+        let e = s.wait_complete(status, POLL * 5).unwrap_err();
+        assert!(e.is_timeout());
+        assert_eq!(s.ese, 0x20);
+
+        let cancel = Cancel::new();
+        cancel.cancel();
+        let e = s
+            .wait_complete_with(status, Duration::from_secs(10), &cancel)
+            .unwrap_err();
+        assert!(matches!(e, Error::IOError(e) if e.kind() == io::ErrorKind::Interrupted));
+        assert_eq!(s.ese, 0x20);
+        let e = s
+            .wait_complete_with(
+                Strategy::EventPoll { poll: POLL },
+                Duration::from_secs(10),
+                &cancel,
+            )
+            .unwrap_err();
+        assert!(!e.is_timeout());
+    }
+
+    #[test]
+    fn given_up_opc_query_is_cleared() -> Result<()> {
+        let mut s = Session {
+            ese: 0x20,
+            busy: usize::MAX,
+            ..Default::default()
+        };
+        let e = s.wait_complete(Strategy::OpcQuery, POLL).unwrap_err();
+        assert!(e.is_timeout());
+        let cancel = Cancel::new();
+        cancel.cancel();
+        let e = s
+            .wait_complete_with(Strategy::OpcQuery, Duration::from_secs(10), &cancel)
+            .unwrap_err();
+        assert!(matches!(e, Error::IOError(e) if e.kind() == io::ErrorKind::Interrupted));
+        assert_eq!(s.sent, ["*OPC?", "DCL", "*OPC?", "DCL"]);
+        //the next query gets its own answer rather than the late `1`
+        assert_eq!(s.get_event_mask()?, 0x20);
+        Ok(())
+    }
+
+    ///registers of a VXI-11 instrument, the operation started by `*OPC` completes after `busy`
+    ///steps: the message itself and every status byte read
+    #[derive(Default)]
+    struct Device {
+        log: Vec<String>,
+        answers: VecDeque<String>,
+        ese: u8,
+        sre: u8,
+        esr: u8,
+        busy: usize,
+        pending: bool,
+        srq: bool,
+        interrupt: Option<std::net::TcpStream>,
+    }
+
+    impl Device {
+        fn stb(&self) -> u8 {
+            if self.esr & self.ese != 0 {
+                ESB
+            } else {
+                0
+            }
+        }
+        ///once the operation completes, request service on the interrupt channel if enabled
+        fn step(&mut self) {
+            if !self.pending {
+                return;
+            }
+            if self.busy > 0 {
+                self.busy -= 1;
+                return;
+            }
+            self.pending = false;
+            self.esr |= OPC;
+            if self.srq && self.stb() & self.sre != 0 {
+                use crate::protocols::onc_rpc::{vxi11::interrupt, xdr, Rpc};
+                type Channel = interrupt::Interrupt<std::net::TcpStream>;
+                let io = self.interrupt.as_ref().unwrap().try_clone().unwrap();
+                //the reply only comes once the client reads the interrupt channel
+                std::thread::spawn(move || {
+                    let mut channel = Channel::new(Channel::PROGRAM, Channel::VERSION, io);
+                    let _: Result<Bytes> = channel.call_authenticated(
+                        interrupt::Procedure::DeviceIntrSrq,
+                        xdr::Device_SrqParms {
+                            handle: serde_bytes::Bytes::new(SRQ_HANDLE),
+                        },
+                    );
+                });
+            }
+        }
+        fn message(&mut self, message: &str) {
+            let mut words = message.split(' ');
+            match (words.next().unwrap(), words.next()) {
+                ("*ESE", Some(v)) => self.ese = v.parse().unwrap(),
+                ("*SRE", Some(v)) => self.sre = v.parse().unwrap(),
+                ("*ESE?", _) => self.answers.push_back(self.ese.to_string()),
+                ("*SRE?", _) => self.answers.push_back(self.sre.to_string()),
+                ("*ESR?", _) => {
+                    self.answers.push_back(self.esr.to_string());
+                    self.esr = 0;
+                }
+                ("*OPC", _) => {
+                    self.pending = true;
+                    self.step();
+                }
+                (other, _) => panic!("unexpected message {}", other),
+            }
+            self.log.push(message.to_string());
+        }
+    }
+
+    fn vxi11_device(device: Arc<std::sync::Mutex<Device>>) -> Result<Vxi11> {
+        use crate::protocols::onc_rpc::{
+            server::RpcServer,
+            vxi11::{
+                core::Procedure,
+                tests::{fake_device, PROGRAM, VERSION},
+                Vxi11Client,
+            },
+            xdr,
+        };
+        fn no_error() -> xdr::Device_ErrorCode {
+            xdr::Device_ErrorCode(xdr::long(0))
+        }
+        let mut server = RpcServer::new();
+        let d = device.clone();
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceWrite,
+            move |_, p: xdr::Device_WriteParms<Bytes>| {
+                d.lock()
+                    .unwrap()
+                    .message(String::from_utf8_lossy(&p.data).trim_end());
+                Ok(xdr::Device_WriteResp {
+                    error: no_error(),
+                    size: xdr::ulong(p.data.len() as u32),
+                })
+            },
+        );
+        let d = device.clone();
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceRead,
+            move |_, _: xdr::Device_ReadParms| {
+                let answer = d.lock().unwrap().answers.pop_front().unwrap();
+                Ok(xdr::Device_ReadResp {
+                    error: no_error(),
+                    reason: xdr::long(1 << 2),
+                    data: serde_bytes::ByteBuf::from(answer + "\n"),
+                })
+            },
+        );
+        let d = device.clone();
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceReadStb,
+            move |_, _: xdr::Device_GenericParms| {
+                let mut d = d.lock().unwrap();
+                d.step();
+                d.log.push("stb".to_string());
+                Ok(xdr::Device_ReadStbResp {
+                    error: no_error(),
+                    stb: xdr::xdr_uchar(d.stb() as u32),
+                })
+            },
+        );
+        let d = device.clone();
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::DeviceEnableSrq,
+            move |_, p: xdr::Device_EnableSrqParms<Bytes>| {
+                let mut d = d.lock().unwrap();
+                assert_eq!(p.handle.as_ref(), SRQ_HANDLE);
+                d.srq = p.enable;
+                d.log.push(format!("srq {}", p.enable));
+                Ok(xdr::Device_Error { error: no_error() })
+            },
+        );
+        server.register(
+            PROGRAM,
+            VERSION,
+            Procedure::CreateIntrChan,
+            move |_, p: xdr::Device_RemoteFunc| {
+                let ip = std::net::Ipv4Addr::from(p.hostAddr.0);
+                let io = std::net::TcpStream::connect((ip, p.hostPort.0 as u16))?;
+                device.lock().unwrap().interrupt = Some(io);
+                Ok(xdr::Device_Error { error: no_error() })
+            },
+        );
+        fake_device(server, &Vxi11Client::default(), "inst0")
+    }
+
+    #[test]
+    fn vxi11_reads_the_status_byte() -> Result<()> {
+        let device = Arc::new(std::sync::Mutex::new(Device {
+            ese: 0x3C,
+            busy: 3,
+            ..Default::default()
+        }));
+        let mut vxi11 = vxi11_device(device.clone())?;
+        vxi11.wait_complete(Strategy::StatusPoll { poll: POLL }, Duration::from_secs(1))?;
+        let d = device.lock().unwrap();
+        assert_eq!(d.ese, 0x3C);
+        assert_eq!(
+            d.log,
+            ["*ESE?", "*ESR?", "*ESE 1", "*OPC", "stb", "stb", "stb", "*ESR?", "*ESE 60"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn vxi11_waits_for_service_requests() -> Result<()> {
+        use crate::protocols::onc_rpc::vxi11::interrupt::Interrupt;
+        let device = Arc::new(std::sync::Mutex::new(Device {
+            ese: 0x3C,
+            sre: 0x10,
+            ..Default::default()
+        }));
+        let mut vxi11 = vxi11_device(device.clone())?;
+        vxi11.establish_interrupt(
+            "127.0.0.1:0",
+            Interrupt::<std::net::TcpStream>::PROGRAM,
+            Interrupt::<std::net::TcpStream>::VERSION,
+        )?;
+        let timeout = Some(Duration::from_secs(7));
+        vxi11
+            .mut_interrupt()
+            .unwrap()
+            .get_io()
+            .set_read_timeout(timeout)?;
+        vxi11.wait_complete(Strategy::ServiceRequest, Duration::from_secs(5))?;
+        {
+            let mut d = device.lock().unwrap();
+            assert_eq!((d.ese, d.sre, d.srq), (0x3C, 0x10, false));
+            assert_eq!(
+                d.log,
+                [
+                    "*ESE?",
+                    "*SRE?",
+                    "*ESR?",
+                    "*ESE 1",
+                    "*SRE 32",
+                    "srq true",
+                    "*OPC",
+                    "stb",
+                    "srq false",
+                    "*ESR?",
+                    "*SRE 16",
+                    "*ESE 60"
+                ]
+            );
+            d.log.clear();
+            d.busy = usize::MAX;
+        }
+        assert_eq!(
+            vxi11.mut_interrupt().unwrap().get_io().read_timeout()?,
+            timeout
+        );
+        //never completes, the interrupt channel times out every 100 ms meanwhile
+        let e = vxi11
+            .wait_complete(Strategy::ServiceRequest, Duration::from_millis(250))
+            .unwrap_err();
+        assert!(e.is_timeout(), "{}", e);
+        assert_eq!(
+            vxi11.mut_interrupt().unwrap().get_io().read_timeout()?,
+            timeout
+        );
+        let d = device.lock().unwrap();
+        assert_eq!((d.ese, d.sre, d.srq), (0x3C, 0x10, false));
+        assert!(!d.log.contains(&"stb".to_string()));
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+//! Fixtures shared by the unit tests and the tests of the command-line tool, not part of the API.
+use crate::Result;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+///a line based instrument on a local port, every line received is logged and answered with
+///what `answer` returns for it
+pub fn line_instrument<F>(answer: F) -> Result<(u16, Arc<Mutex<Vec<String>>>)>
+where
+    F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let l = log.clone();
+    let answer = Arc::new(answer);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let log = l.clone();
+            let answer = answer.clone();
+            std::thread::spawn(move || {
+                let mut writer = stream.try_clone().unwrap();
+                for line in BufReader::new(stream).lines() {
+                    let line = match line {
+                        Ok(l) => l,
+                        Err(_) => return,
+                    };
+                    log.lock().unwrap().push(line.clone());
+                    if let Some(a) = answer(&line) {
+                        if writer.write_all(a.as_bytes()).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    Ok((port, log))
+}
@@ -0,0 +1,91 @@
+//! Formatting of the bytes exchanged with instruments for the `tracing` events of this crate.
+//!
+//! Events carry the messages as fields, [`payload`] shows SCPI text as text and anything else as
+//! a hex dump, both cut after [`MAX_DUMP`] bytes so a waveform doesn't flood the log. Install a
+//! subscriber, e.g. `tracing_subscriber::fmt`, with `rustrument=trace` to see every transfer.
+use std::fmt;
+
+///bytes shown by a dump, the rest is only counted
+pub const MAX_DUMP: usize = 64;
+
+///`0a 1b ...`, see [`hex`]
+#[derive(Clone, Copy)]
+pub struct Hex<'a>(&'a [u8]);
+
+///escaped text, see [`text`]
+#[derive(Clone, Copy)]
+pub struct Text<'a>(&'a [u8]);
+
+///text or a hex dump, see [`payload`]
+#[derive(Clone, Copy)]
+pub enum Payload<'a> {
+    Text(Text<'a>),
+    Hex(Hex<'a>),
+}
+
+pub fn hex(data: &[u8]) -> Hex<'_> {
+    Hex(data)
+}
+pub fn text(data: &[u8]) -> Text<'_> {
+    Text(data)
+}
+///text when `data` is printable ASCII apart from line ends and tabs, a hex dump otherwise
+pub fn payload(data: &[u8]) -> Payload<'_> {
+    let shown = &data[..data.len().min(MAX_DUMP)];
+    if shown
+        .iter()
+        .all(|b| b.is_ascii_graphic() || matches!(b, b' ' | b'\t' | b'\r' | b'\n'))
+    {
+        Payload::Text(Text(data))
+    } else {
+        Payload::Hex(Hex(data))
+    }
+}
+
+fn rest(f: &mut fmt::Formatter<'_>, len: usize) -> fmt::Result {
+    if len > MAX_DUMP {
+        write!(f, " ... ({} bytes)", len)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.0.iter().take(MAX_DUMP).enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        rest(f, self.0.len())
+    }
+}
+
+impl fmt::Display for Text<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in self.0.iter().take(MAX_DUMP) {
+            write!(f, "{}", std::ascii::escape_default(*b))?;
+        }
+        rest(f, self.0.len())
+    }
+}
+
+impl fmt::Display for Payload<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Payload::Text(t) => t.fmt(f),
+            Payload::Hex(h) => h.fmt(f),
+        }
+    }
+}
+
+#[test]
+fn dumps() {
+    assert_eq!(payload(b"*IDN?\n").to_string(), "*IDN?\\n");
+    assert_eq!(payload(b"#12\x00\xff\n").to_string(), "23 31 32 00 ff 0a");
+    let long = vec![0_u8; MAX_DUMP + 1];
+    let dump = hex(&long).to_string();
+    assert!(dump.starts_with("00 00"));
+    assert!(dump.ends_with("00 ... (65 bytes)"));
+    assert_eq!(text(b"a\"b").to_string(), "a\\\"b");
+}
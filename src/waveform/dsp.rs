@@ -0,0 +1,497 @@
+//! Spectra, filters, averages and resampling of captured data.
+//!
+//! The functions take the samples as the drivers return them, e.g. the `Vec<f32>` of
+//! [`WaveformData`](crate::instruments::infiniium::WaveformData), with the time between two
+//! samples, the increment of [`Waveform::x`]. [`Waveform`] has the same operations keeping its
+//! time base.
+use super::{Axis, Waveform};
+use crate::Result;
+use realfft::RealFftPlanner;
+use std::f64::consts::PI;
+
+///weighting of the samples before the FFT, periodic so that an integer number of cycles leaks
+///nowhere
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    Rectangular,
+    ///general purpose, 1.5 bins of noise bandwidth
+    Hann,
+    ///flat within 0.01 dB over a bin, for the amplitude of tones between bins
+    FlatTop,
+    ///4 terms, sidelobes below -92 dB, for small tones next to large ones
+    BlackmanHarris,
+}
+
+impl Window {
+    fn cosines(&self) -> &'static [f64] {
+        match self {
+            Window::Rectangular => &[1.0],
+            Window::Hann => &[0.5, 0.5],
+            Window::FlatTop => &[
+                0.215_578_95,
+                0.416_631_58,
+                0.277_263_158,
+                0.083_578_947,
+                0.006_947_368,
+            ],
+            Window::BlackmanHarris => &[0.358_75, 0.488_29, 0.141_28, 0.011_68],
+        }
+    }
+    pub fn coefficients(&self, len: usize) -> Vec<f64> {
+        (0..len)
+            .map(|i| {
+                let x = 2.0 * PI * i as f64 / len as f64;
+                self.cosines()
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| if k % 2 == 0 { 1.0 } else { -1.0 } * a * (k as f64 * x).cos())
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+///one-sided power spectral density
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spectrum {
+    ///Hz between two bins
+    pub resolution: f64,
+    ///V²/Hz from 0 Hz
+    pub density: Vec<f64>,
+}
+
+impl Spectrum {
+    pub fn frequency(&self, bin: usize) -> f64 {
+        bin as f64 * self.resolution
+    }
+    pub fn frequencies(&self) -> Vec<f64> {
+        (0..self.density.len()).map(|k| self.frequency(k)).collect()
+    }
+    ///the density in dB relative to 1 V²/Hz
+    pub fn dbv_per_hz(&self) -> Vec<f64> {
+        self.density.iter().map(|d| 10.0 * d.log10()).collect()
+    }
+    ///the bin of the largest density past DC
+    pub fn peak(&self) -> Option<usize> {
+        (1..self.density.len()).max_by(|a, b| self.density[*a].total_cmp(&self.density[*b]))
+    }
+    ///mean square of the signal between two frequencies, e.g. around a tone, V²
+    pub fn power(&self, from: f64, to: f64) -> f64 {
+        self.frequencies()
+            .iter()
+            .zip(&self.density)
+            .filter(|(f, _)| (from..=to).contains(*f))
+            .map(|(_, d)| d * self.resolution)
+            .sum()
+    }
+}
+
+fn to_f64<T: Copy + Into<f64>>(values: &[T]) -> Vec<f64> {
+    values.iter().map(|v| (*v).into()).collect()
+}
+
+///the density of the samples taken every `x_increment` seconds
+pub fn spectrum<T: Copy + Into<f64>>(values: &[T], x_increment: f64, window: Window) -> Spectrum {
+    let len = values.len();
+    let sample_rate = 1.0 / x_increment;
+    let weights = window.coefficients(len);
+    let fft = RealFftPlanner::<f64>::new().plan_fft_forward(len);
+    let mut input: Vec<f64> = values
+        .iter()
+        .zip(&weights)
+        .map(|(v, w)| (*v).into() * w)
+        .collect();
+    let mut output = fft.make_output_vec();
+    //the buffers have the length of the plan
+    fft.process(&mut input, &mut output).unwrap();
+    let scale = 1.0 / (sample_rate * weights.iter().map(|w| w * w).sum::<f64>());
+    let density = output
+        .iter()
+        .enumerate()
+        .map(|(k, x)| {
+            //the negative frequencies fold onto the positive ones, but for DC and Nyquist
+            let sides = if k == 0 || 2 * k == len { 1.0 } else { 2.0 };
+            x.norm_sqr() * scale * sides
+        })
+        .collect();
+    Spectrum {
+        resolution: sample_rate / len as f64,
+        density,
+    }
+}
+
+///windowed-sinc low-pass FIR filter, unity gain at DC
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fir {
+    pub taps: Vec<f64>,
+}
+
+impl Fir {
+    ///`len` taps, made odd, cutting at `cutoff` Hz for a sample rate of `sample_rate`
+    pub fn low_pass(cutoff: f64, sample_rate: f64, len: usize) -> Self {
+        let len = len | 1;
+        let fc = cutoff / sample_rate;
+        let middle = (len / 2) as f64;
+        let window = Window::BlackmanHarris.coefficients(len + 1);
+        let mut taps: Vec<f64> = (0..len)
+            .map(|i| {
+                let x = i as f64 - middle;
+                let sinc = if x == 0.0 {
+                    2.0 * fc
+                } else {
+                    (2.0 * PI * fc * x).sin() / (PI * x)
+                };
+                //centred on the window, whose periodic form of length `len + 1` is symmetric
+                sinc * window[i + 1]
+            })
+            .collect();
+        let gain: f64 = taps.iter().sum();
+        taps.iter_mut().for_each(|t| *t /= gain);
+        Self { taps }
+    }
+    ///filtered samples, aligned with the input, the first and last samples repeated beyond the
+    ///ends
+    pub fn filter<T: Copy + Into<f64>>(&self, values: &[T]) -> Vec<f64> {
+        let values = to_f64(values);
+        let half = self.taps.len() / 2;
+        let at = |i: isize| values[i.clamp(0, values.len() as isize - 1) as usize];
+        (0..values.len() as isize)
+            .map(|i| {
+                self.taps
+                    .iter()
+                    .enumerate()
+                    .map(|(k, t)| t * at(i + half as isize - k as isize))
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+///second order IIR section
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biquad {
+    pub b: [f64; 3],
+    ///`a[0]` is 1
+    pub a: [f64; 3],
+}
+
+impl Biquad {
+    ///low-pass of quality `q`, `FRAC_1_SQRT_2` for Butterworth
+    pub fn low_pass(cutoff: f64, sample_rate: f64, q: f64) -> Self {
+        let w = 2.0 * PI * cutoff / sample_rate;
+        let alpha = w.sin() / (2.0 * q);
+        let a0 = 1.0 + alpha;
+        let b1 = (1.0 - w.cos()) / a0;
+        Self {
+            b: [b1 / 2.0, b1, b1 / 2.0],
+            a: [1.0, -2.0 * w.cos() / a0, (1.0 - alpha) / a0],
+        }
+    }
+    ///filtered samples, starting settled on the first one
+    pub fn filter<T: Copy + Into<f64>>(&self, values: &[T]) -> Vec<f64> {
+        let first = values.first().map_or(0.0, |v| (*v).into());
+        let (mut x, mut y) = ([first; 2], [first; 2]);
+        values
+            .iter()
+            .map(|v| {
+                let v = (*v).into();
+                let out = self.b[0] * v + self.b[1] * x[0] + self.b[2] * x[1]
+                    - self.a[1] * y[0]
+                    - self.a[2] * y[1];
+                x = [v, x[0]];
+                y = [out, y[0]];
+                out
+            })
+            .collect()
+    }
+}
+
+///every `factor`th sample, after a low-pass filter at 80 % of the new Nyquist frequency
+pub fn decimate<T: Copy + Into<f64>>(values: &[T], factor: usize) -> Vec<f64> {
+    let factor = factor.max(1);
+    let filtered = if factor > 1 {
+        Fir::low_pass(0.4 / factor as f64, 1.0, 16 * factor).filter(values)
+    } else {
+        to_f64(values)
+    };
+    filtered.into_iter().step_by(factor).collect()
+}
+
+///sample by sample mean of captures of the same length
+pub fn ensemble_average<T: Copy + Into<f64>>(captures: &[&[T]]) -> Result<Vec<f64>> {
+    let len = captures.first().map_or(0, |c| c.len());
+    if captures.iter().any(|c| c.len() != len) {
+        return Err("captures of different lengths".into());
+    }
+    Ok((0..len)
+        .map(|i| captures.iter().map(|c| c[i].into()).sum::<f64>() / captures.len() as f64)
+        .collect())
+}
+
+///average of the captures so far, like the averaging acquisition mode of the scopes: the mean
+///of the first `count` captures, then an exponential average weighing new ones by `1 / count`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunningAverage {
+    count: usize,
+    captures: usize,
+    average: Vec<f64>,
+}
+
+impl RunningAverage {
+    pub fn new(count: usize) -> Self {
+        Self {
+            count: count.max(1),
+            captures: 0,
+            average: Vec::new(),
+        }
+    }
+    pub fn add<T: Copy + Into<f64>>(&mut self, capture: &[T]) -> Result<&[f64]> {
+        if self.captures == 0 {
+            self.average = vec![0.0; capture.len()];
+        } else if capture.len() != self.average.len() {
+            return Err(format!(
+                "capture of {} samples averaged with {}",
+                capture.len(),
+                self.average.len()
+            )
+            .into());
+        }
+        self.captures += 1;
+        let weight = 1.0 / self.captures.min(self.count) as f64;
+        for (a, v) in self.average.iter_mut().zip(capture) {
+            *a += ((*v).into() - *a) * weight;
+        }
+        Ok(&self.average)
+    }
+    pub fn captures(&self) -> usize {
+        self.captures
+    }
+    pub fn average(&self) -> &[f64] {
+        &self.average
+    }
+}
+
+impl Waveform {
+    ///values at `len` times from `origin` every `increment`, linearly interpolated,
+    ///NaN outside the capture
+    pub fn resample(&self, origin: f64, increment: f64, len: usize) -> Waveform {
+        let values = self.values();
+        let resampled = (0..len)
+            .map(|i| {
+                //fractional index of the time in this waveform
+                let x = (origin + i as f64 * increment - self.x.origin) / self.x.increment
+                    + self.x.reference;
+                //times on a sample stay exact despite rounding
+                let x = if (x - x.round()).abs() < 1e-9 {
+                    x.round()
+                } else {
+                    x
+                };
+                let (i, fraction) = (x.floor(), x - x.floor());
+                match (values.get(i as usize), values.get(i as usize + 1)) {
+                    _ if x < 0.0 => f64::NAN,
+                    (Some(a), _) if fraction == 0.0 => *a,
+                    (Some(a), Some(b)) => a + (b - a) * fraction,
+                    _ => f64::NAN,
+                }
+            })
+            .collect();
+        Waveform {
+            samples: resampled,
+            x: Axis {
+                increment,
+                origin,
+                reference: 0.0,
+                unit: self.x.unit.clone(),
+            },
+            y: Axis::identity(self.y.unit.clone()),
+            ..self.clone()
+        }
+    }
+    ///the waveform filtered by `filter`
+    pub fn filtered<F: Fn(&[f64]) -> Vec<f64>>(&self, filter: F) -> Waveform {
+        Waveform {
+            samples: filter(&self.values()),
+            y: Axis::identity(self.y.unit.clone()),
+            ..self.clone()
+        }
+    }
+    pub fn decimated(&self, factor: usize) -> Waveform {
+        let mut decimated = self.filtered(|v| decimate(v, factor));
+        decimated.x.increment *= factor.max(1) as f64;
+        decimated.x.reference /= factor.max(1) as f64;
+        decimated
+    }
+    pub fn spectrum(&self, window: Window) -> Spectrum {
+        spectrum(&self.values(), self.x.increment, window)
+    }
+}
+
+///the waveforms on a common time base, where they overlap, with the finest sample interval
+pub fn align(waveforms: &[Waveform]) -> Result<Vec<Waveform>> {
+    let start = waveforms
+        .iter()
+        .map(|w| w.time(0))
+        .fold(f64::NEG_INFINITY, f64::max);
+    let end = waveforms
+        .iter()
+        .map(|w| w.time(w.len().saturating_sub(1)))
+        .fold(f64::INFINITY, f64::min);
+    let increment = waveforms
+        .iter()
+        .map(|w| w.x.increment)
+        .fold(f64::INFINITY, f64::min);
+    if waveforms.iter().any(|w| w.is_empty()) || end < start {
+        return Err("the waveforms don't overlap".into());
+    }
+    //the last sample isn't lost to rounding
+    let len = ((end - start) / increment * (1.0 + 1e-12)).floor() as usize + 1;
+    Ok(waveforms
+        .iter()
+        .map(|w| w.resample(start, increment, len))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(len: usize, cycles: f64, amplitude: f64) -> Vec<f64> {
+        (0..len)
+            .map(|i| amplitude * (2.0 * PI * cycles * i as f64 / len as f64).sin())
+            .collect()
+    }
+
+    ///uniform in ±1, repeatable
+    fn noise(len: usize) -> Vec<f64> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1_442_695_040_888_963_407);
+                (state >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spectra() {
+        //1 V sine of 100 kHz sampled at 10 MSa/s, like `:ACQuire:SRATe:ANALog?` reports
+        let x_increment = 1e-7;
+        let tone = sine(4096, 41.0, 1.0);
+        for window in [
+            Window::Rectangular,
+            Window::Hann,
+            Window::FlatTop,
+            Window::BlackmanHarris,
+        ] {
+            let spectrum = spectrum(&tone, x_increment, window);
+            assert_eq!(spectrum.density.len(), 2049);
+            assert_eq!(spectrum.peak(), Some(41));
+            assert!((spectrum.frequency(41) - 100_097.656_25).abs() < 1e-6);
+            //the mean square of the sine, spread over the bins of the window
+            let power = spectrum.power(90e3, 110e3);
+            assert!((power - 0.5).abs() < 1e-9, "{:?} {}", window, power);
+        }
+        //uniform noise of mean square 1/3 is flat at 1/3 / 5 MHz
+        let floor = spectrum(&noise(1 << 16), x_increment, Window::Hann).dbv_per_hz();
+        let mean = floor[1..].iter().sum::<f64>() / (floor.len() - 1) as f64;
+        //the mean of dB values of χ² with 2 degrees is 2.5 dB below the level
+        let level = 10.0 * (1.0 / 3.0 / 5e6_f64).log10() - 2.507;
+        assert!((mean - level).abs() < 0.2, "{} {}", mean, level);
+    }
+
+    #[test]
+    fn filters() {
+        let slow = sine(1000, 5.0, 1.0);
+        let fast = sine(1000, 400.0, 1.0);
+        let mixed: Vec<f32> = slow
+            .iter()
+            .zip(&fast)
+            .map(|(a, b)| (a + b) as f32)
+            .collect();
+        let fir = Fir::low_pass(50.0, 1000.0, 101);
+        assert_eq!(fir.taps.len(), 101);
+        let filtered = fir.filter(&mixed);
+        for (f, s) in filtered.iter().zip(&slow).skip(100).take(800) {
+            assert!((f - s).abs() < 1e-3, "{} {}", f, s);
+        }
+        //a step settles on 1 without ringing past 5 %
+        let step: Vec<f64> = (0..200).map(|i| if i < 10 { 0.0 } else { 1.0 }).collect();
+        let biquad = Biquad::low_pass(10.0, 1000.0, std::f64::consts::FRAC_1_SQRT_2);
+        let response = biquad.filter(&step);
+        assert_eq!(response[5], 0.0);
+        assert!(response.iter().all(|r| *r < 1.05));
+        assert!((response[199] - 1.0).abs() < 1e-3);
+        //the tone is 10 Hz from the new Nyquist frequency
+        let decimated = decimate(&mixed, 2);
+        assert_eq!(decimated.len(), 500);
+        for (d, s) in decimated
+            .iter()
+            .zip(slow.iter().step_by(2))
+            .skip(50)
+            .take(400)
+        {
+            assert!((d - s).abs() < 1e-2, "{} {}", d, s);
+        }
+    }
+
+    #[test]
+    fn averages() {
+        let signal = sine(256, 3.0, 1.0);
+        let captures: Vec<Vec<f64>> = (0..64)
+            .map(|i| {
+                let noise = noise(256 + i);
+                signal.iter().zip(&noise[i..]).map(|(s, n)| s + n).collect()
+            })
+            .collect();
+        let slices: Vec<&[f64]> = captures.iter().map(|c| c.as_slice()).collect();
+        let averaged = ensemble_average(&slices).unwrap();
+        let error = |a: &[f64]| {
+            a.iter()
+                .zip(&signal)
+                .map(|(a, s)| (a - s).powi(2))
+                .sum::<f64>()
+                / a.len() as f64
+        };
+        //a sample of noise alone has a variance of 1/3
+        assert!(error(&averaged) < 1.0 / 3.0 / 32.0);
+        assert!(ensemble_average(&[&[1.0][..], &[1.0, 2.0]]).is_err());
+
+        let mut running = RunningAverage::new(4);
+        running.add(&[1.0f32, 2.0]).unwrap();
+        assert_eq!(running.add(&[3.0f32, 4.0]).unwrap(), &[2.0, 3.0]);
+        running.add(&[2.0f32, 3.0]).unwrap();
+        running.add(&[2.0f32, 3.0]).unwrap();
+        //the weight stays at 1/4 past 4 captures
+        assert_eq!(running.add(&[6.0f32, 7.0]).unwrap(), &[3.0, 4.0]);
+        assert_eq!(running.captures(), 5);
+        assert!(running.add(&[1.0f32]).is_err());
+    }
+
+    #[test]
+    fn resampling() {
+        let mut a = Waveform::new(vec![0.0, 1.0, 2.0, 3.0, 4.0], 1e-3);
+        a.x.origin = -2e-3;
+        let mut b = Waveform::new(vec![10.0, 20.0, 30.0], 2e-3);
+        b.y = Axis::new(0.5, 1.0, "V");
+        let resampled = b.resample(1e-3, 1e-3, 6);
+        assert_eq!(resampled.x.increment, 1e-3);
+        assert_eq!(resampled.values()[..4], [8.5, 11.0, 13.5, 16.0]);
+        assert!(resampled.values()[4..].iter().all(|v| v.is_nan()));
+        assert!(b.resample(-1e-3, 1e-3, 1).values()[0].is_nan());
+
+        let aligned = align(&[a, b]).unwrap();
+        assert_eq!(aligned[0].len(), 3);
+        assert_eq!(aligned[0].time(0), 0.0);
+        assert_eq!(aligned[1].x, aligned[0].x);
+        assert_eq!(aligned[0].values(), vec![2.0, 3.0, 4.0]);
+        assert_eq!(aligned[1].values(), vec![6.0, 8.5, 11.0]);
+
+        let decimated = Waveform::new(vec![1.0; 100], 1e-3).decimated(4);
+        assert_eq!(decimated.len(), 25);
+        assert_eq!(decimated.x.increment, 4e-3);
+        assert!(decimated.values().iter().all(|v| (v - 1.0).abs() < 1e-12));
+    }
+}
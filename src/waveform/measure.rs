@@ -0,0 +1,527 @@
+//! Measurements of captured waveforms, computed locally instead of asking the scope for each.
+//!
+//! The definitions follow the automatic measurements of the scopes:
+//! - top and base are the most common levels in the upper and lower halves of the range, or the
+//!   maximum and minimum when the histogram has no clear peak, e.g. for a sine
+//! - edges go from 10 % to 90 % of the amplitude, with those thresholds as hysteresis, and are
+//!   timed where they cross 50 %
+//! - times are averaged over every edge or cycle of the capture
+use super::Waveform;
+use crate::Result;
+
+///share of the samples of a half of the range the histogram peak needs to be taken as its level
+const PEAK_SHARE: f64 = 0.05;
+const LEVEL_BINS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Histogram {
+    ///lower bound of the first bin
+    pub start: f64,
+    pub width: f64,
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    ///`bins` bins of equal width from the minimum to the maximum
+    pub fn new(values: &[f64], bins: usize) -> Self {
+        let bins = bins.max(1);
+        let (start, end) = (min(values), max(values));
+        let width = if end > start {
+            (end - start) / bins as f64
+        } else {
+            1.0
+        };
+        let mut counts = vec![0; bins];
+        for v in values {
+            counts[self::bin(*v, start, width, bins)] += 1;
+        }
+        Self {
+            start,
+            width,
+            counts,
+        }
+    }
+    pub fn bin(&self, value: f64) -> usize {
+        bin(value, self.start, self.width, self.counts.len())
+    }
+    ///center of a bin
+    pub fn center(&self, bin: usize) -> f64 {
+        self.start + (bin as f64 + 0.5) * self.width
+    }
+}
+
+fn bin(value: f64, start: f64, width: f64, bins: usize) -> usize {
+    (((value - start) / width) as usize).min(bins - 1)
+}
+
+pub fn min(values: &[f64]) -> f64 {
+    values.iter().copied().fold(f64::INFINITY, f64::min)
+}
+pub fn max(values: &[f64]) -> f64 {
+    values.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+}
+pub fn vpp(values: &[f64]) -> f64 {
+    max(values) - min(values)
+}
+pub fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+///DC RMS, the offset included
+pub fn rms(values: &[f64]) -> f64 {
+    (values.iter().map(|v| v * v).sum::<f64>() / values.len() as f64).sqrt()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Levels {
+    pub top: f64,
+    pub base: f64,
+}
+
+impl Levels {
+    pub fn new(values: &[f64]) -> Self {
+        let (low, high) = (min(values), max(values));
+        let middle = (low + high) / 2.0;
+        let histogram = Histogram::new(values, LEVEL_BINS);
+        let split = histogram.bin(middle);
+        //the mean of the samples in the fullest bin of a half, `None` without a clear peak
+        let level = |bins: std::ops::Range<usize>| -> Option<f64> {
+            let in_half: usize = histogram.counts[bins.clone()].iter().sum();
+            let peak = bins.max_by_key(|b| histogram.counts[*b])?;
+            if (histogram.counts[peak] as f64) < PEAK_SHARE * in_half as f64 {
+                return None;
+            }
+            let in_peak = values.iter().filter(|v| histogram.bin(**v) == peak);
+            Some(mean(&in_peak.copied().collect::<Vec<_>>()))
+        };
+        Self {
+            top: level(split..histogram.counts.len()).unwrap_or(high),
+            base: level(0..split).unwrap_or(low),
+        }
+    }
+    pub fn amplitude(&self) -> f64 {
+        self.top - self.base
+    }
+    ///the level at `percent` of the amplitude
+    pub fn at(&self, percent: f64) -> f64 {
+        self.base + self.amplitude() * percent / 100.0
+    }
+}
+
+///an edge, as fractional sample indices where it crosses 10 %, 50 % and 90 %
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub rising: bool,
+    pub start: f64,
+    pub middle: f64,
+    pub end: f64,
+}
+
+///the index between `i - 1` and `i` where the samples cross `level`
+fn crossing(values: &[f64], i: usize, level: f64) -> f64 {
+    let (a, b) = (values[i - 1], values[i]);
+    (i - 1) as f64 + (level - a) / (b - a)
+}
+
+///every complete edge, going through both the 10 % and the 90 % levels
+pub fn edges(values: &[f64], levels: Levels) -> Vec<Edge> {
+    let thresholds = [levels.at(10.0), levels.at(50.0), levels.at(90.0)];
+    let mut edges = Vec::new();
+    //whether the samples were last beyond 90 % rather than below 10 %, unknown at first
+    let mut high = match values.first() {
+        Some(v) if *v >= thresholds[2] => Some(true),
+        Some(v) if *v <= thresholds[0] => Some(false),
+        _ => None,
+    };
+    //latest crossings of the thresholds, going up and going down
+    let (mut up, mut down) = ([None; 3], [None; 3]);
+    for i in 1..values.len() {
+        let (a, b) = (values[i - 1], values[i]);
+        for (t, &level) in thresholds.iter().enumerate() {
+            if a < level && b >= level {
+                up[t] = Some(crossing(values, i, level));
+            } else if a > level && b <= level {
+                down[t] = Some(crossing(values, i, level));
+            }
+        }
+        if b >= thresholds[2] && high != Some(true) {
+            if let (Some(false), [Some(start), Some(middle), Some(end)]) = (high, up) {
+                edges.push(Edge {
+                    rising: true,
+                    start,
+                    middle,
+                    end,
+                });
+            }
+            high = Some(true);
+        } else if b <= thresholds[0] && high != Some(false) {
+            if let (Some(true), [Some(end), Some(middle), Some(start)]) = (high, down) {
+                edges.push(Edge {
+                    rising: false,
+                    start,
+                    middle,
+                    end,
+                });
+            }
+            high = Some(false);
+        }
+    }
+    edges
+}
+
+fn average<I: Iterator<Item = f64>>(values: I) -> Option<f64> {
+    let (sum, n) = values.fold((0.0, 0), |(sum, n), v| (sum + v, n + 1));
+    if n > 0 {
+        Some(sum / n as f64)
+    } else {
+        None
+    }
+}
+
+///10 % to 90 %, in units of the sample interval
+pub fn rise_time(edges: &[Edge]) -> Option<f64> {
+    average(edges.iter().filter(|e| e.rising).map(|e| e.end - e.start))
+}
+///90 % to 10 %, in units of the sample interval
+pub fn fall_time(edges: &[Edge]) -> Option<f64> {
+    average(edges.iter().filter(|e| !e.rising).map(|e| e.end - e.start))
+}
+///between the first and the last rising edge, over the cycles in between,
+///in units of the sample interval
+pub fn period(edges: &[Edge]) -> Option<f64> {
+    let mut rising = edges.iter().filter(|e| e.rising);
+    let first = rising.next()?;
+    let (cycles, last) = rising.fold((0, first), |(n, _), e| (n + 1, e));
+    if cycles > 0 {
+        Some((last.middle - first.middle) / cycles as f64)
+    } else {
+        None
+    }
+}
+///time above 50 % over the period, %
+pub fn duty_cycle(edges: &[Edge]) -> Option<f64> {
+    let width = average(
+        edges
+            .windows(2)
+            .filter(|w| w[0].rising && !w[1].rising)
+            .map(|w| w[1].middle - w[0].middle),
+    )?;
+    Some(width / period(edges)? * 100.0)
+}
+///how far the maximum goes beyond the top, % of the amplitude, 0 for a flat trace
+pub fn overshoot(values: &[f64], levels: Levels) -> f64 {
+    if levels.amplitude() > 0.0 {
+        (max(values) - levels.top) / levels.amplitude() * 100.0
+    } else {
+        0.0
+    }
+}
+
+///what `:MEASure` gives, times in the unit of the x axis
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Measurements {
+    pub min: f64,
+    pub max: f64,
+    pub vpp: f64,
+    pub mean: f64,
+    pub rms: f64,
+    pub top: f64,
+    pub base: f64,
+    pub amplitude: f64,
+    pub overshoot: f64,
+    pub period: Option<f64>,
+    pub frequency: Option<f64>,
+    pub rise_time: Option<f64>,
+    pub fall_time: Option<f64>,
+    ///%
+    pub duty_cycle: Option<f64>,
+}
+
+impl Measurements {
+    ///`None` without samples
+    pub fn new(values: &[f64], x_increment: f64) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let levels = Levels::new(values);
+        let edges = edges(values, levels);
+        let period = period(&edges).map(|p| p * x_increment);
+        Some(Self {
+            min: min(values),
+            max: max(values),
+            vpp: vpp(values),
+            mean: mean(values),
+            rms: rms(values),
+            top: levels.top,
+            base: levels.base,
+            amplitude: levels.amplitude(),
+            overshoot: overshoot(values, levels),
+            period,
+            frequency: period.map(|p| 1.0 / p),
+            rise_time: rise_time(&edges).map(|t| t * x_increment),
+            fall_time: fall_time(&edges).map(|t| t * x_increment),
+            duty_cycle: duty_cycle(&edges),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Operation {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            Operation::Add => a + b,
+            Operation::Subtract => a - b,
+            Operation::Multiply => a * b,
+            Operation::Divide => a / b,
+        }
+    }
+    fn symbol(self) -> &'static str {
+        match self {
+            Operation::Add => "+",
+            Operation::Subtract => "-",
+            Operation::Multiply => "*",
+            Operation::Divide => "/",
+        }
+    }
+}
+
+///`a <operation> b` sample by sample, the channels need the same time base
+pub fn math(a: &Waveform, operation: Operation, b: &Waveform) -> Result<Waveform> {
+    let same = |x: f64, y: f64| (x - y).abs() <= 1e-9 * x.abs().max(y.abs());
+    if a.len() != b.len() {
+        return Err(format!("{} and {} samples", a.len(), b.len()).into());
+    }
+    if !(same(a.time(0), b.time(0)) && same(a.x.increment, b.x.increment)) {
+        return Err("the waveforms have different time bases".into());
+    }
+    let unit = match operation {
+        Operation::Add | Operation::Subtract if a.y.unit != b.y.unit => {
+            return Err(format!("can't add {} and {}", a.y.unit, b.y.unit).into())
+        }
+        Operation::Add | Operation::Subtract => a.y.unit.clone(),
+        _ => format!("{}{}{}", a.y.unit, operation.symbol(), b.y.unit),
+    };
+    let channel = |w: &Waveform| w.channel.clone().unwrap_or_else(|| "?".to_string());
+    let mut result = Waveform::new(
+        a.values()
+            .into_iter()
+            .zip(b.values())
+            .map(|(x, y)| operation.apply(x, y))
+            .collect(),
+        a.x.increment,
+    );
+    result.x = a.x.clone();
+    result.y = super::Axis::identity(unit);
+    result.channel = Some(format!(
+        "{} {} {}",
+        channel(a),
+        operation.symbol(),
+        channel(b)
+    ));
+    result.acquired = a.acquired;
+    result.identity = a.identity.clone();
+    Ok(result)
+}
+
+impl Waveform {
+    pub fn measure(&self) -> Option<Measurements> {
+        Measurements::new(&self.values(), self.x.increment)
+    }
+    pub fn histogram(&self, bins: usize) -> Histogram {
+        Histogram::new(&self.values(), bins)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        protocols::transcript::{Direction, Transcript},
+        waveform::Preamble,
+    };
+
+    fn close(value: f64, expected: f64, tolerance: f64) -> bool {
+        (value - expected).abs() <= tolerance * expected.abs().max(1e-12)
+    }
+
+    ///10 cycles of 1 kHz, 1 V around 0.5 V, at 1 MSa/s
+    fn sine() -> Waveform {
+        let values = (0..10_000)
+            .map(|i| 0.5 + (2.0 * std::f64::consts::PI * i as f64 / 1000.0).sin())
+            .collect();
+        Waveform::new(values, 1e-6)
+    }
+
+    ///10 pulses of 100 kHz from 0 to 3.3 V at 1 GSa/s, rising in 100 ns, falling in 200 ns,
+    ///3 µs wide at 50 %, with a 10 % overshoot after each rising edge
+    fn pulses() -> Waveform {
+        let values = (0..100_000)
+            .map(|i| match i % 10_000 {
+                t @ 1000..=1100 => 3.3 * (t - 1000) as f64 / 100.0,
+                1101 => 3.63,
+                1102..=3950 => 3.3,
+                t @ 3951..=4150 => 3.3 * (4150 - t) as f64 / 200.0,
+                _ => 0.0,
+            })
+            .collect();
+        Waveform::new(values, 1e-9)
+    }
+
+    #[test]
+    fn sine_like_the_scope() {
+        let m = sine().measure().unwrap();
+        assert!(close(m.vpp, 2.0, 1e-6));
+        assert!(close(m.mean, 0.5, 1e-9));
+        assert!(close(m.rms, 0.75_f64.sqrt(), 1e-9));
+        assert!(close(m.frequency.unwrap(), 1e3, 1e-6));
+        assert!(close(m.period.unwrap(), 1e-3, 1e-6));
+        assert!(close(m.duty_cycle.unwrap(), 50.0, 1e-3));
+        //from -0.8 to 0.8 of the amplitude
+        let rise = 2.0 * 0.8_f64.asin() / (2.0 * std::f64::consts::PI * 1e3);
+        assert!(close(m.rise_time.unwrap(), rise, 1e-2));
+        assert!(close(m.fall_time.unwrap(), rise, 1e-2));
+    }
+
+    #[test]
+    fn pulses_like_the_scope() {
+        let pulses = pulses();
+        let m = pulses.measure().unwrap();
+        assert!(close(m.top, 3.3, 1e-3));
+        assert!(m.base.abs() < 1e-9);
+        assert!(close(m.overshoot, 10.0, 1e-2));
+        assert!(close(m.rise_time.unwrap(), 80e-9, 1e-3));
+        assert!(close(m.fall_time.unwrap(), 160e-9, 1e-3));
+        assert!(close(m.period.unwrap(), 10e-6, 1e-6));
+        assert!(close(m.duty_cycle.unwrap(), 30.0, 1e-3));
+        assert_eq!(
+            edges(&pulses.values(), Levels::new(&pulses.values())).len(),
+            20
+        );
+
+        let histogram = pulses.histogram(10);
+        assert_eq!(histogram.counts.iter().sum::<usize>(), pulses.len());
+        assert_eq!(
+            histogram.counts[0],
+            pulses.values().iter().filter(|v| **v < 0.363).count()
+        );
+    }
+
+    #[test]
+    fn flat_traces() {
+        let m = Waveform::new(vec![1.5; 100], 1e-6).measure().unwrap();
+        assert_eq!((m.vpp, m.amplitude, m.overshoot), (0.0, 0.0, 0.0));
+        assert_eq!((m.period, m.rise_time, m.duty_cycle), (None, None, None));
+        //NaN would be written as null
+        assert!(serde_json::to_string(&m)
+            .unwrap()
+            .contains(r#""overshoot":0.0"#));
+    }
+
+    ///the field of [`Measurements`] a `:MEASure` query reads, with the tolerance, relative or
+    ///absolute for the levels and percentages whose expected value can be 0; levels are off by
+    ///a 20 mV code at most, twice for the amplitude, the overshoot by the noise on the highest
+    ///sample
+    fn measured(m: &Measurements, query: &str) -> Option<(f64, f64, bool)> {
+        let name = query
+            .split('?')
+            .next()?
+            .rsplit(':')
+            .next()?
+            .to_ascii_uppercase();
+        Some(match name.as_str() {
+            "VPP" => (m.vpp, 0.02, false),
+            "VMAX" => (m.max, 0.02, false),
+            "VMIN" => (m.min, 0.02, false),
+            "VAVERAGE" => (m.mean, 1e-4, true),
+            "VRMS" => (m.rms, 1e-4, true),
+            "VTOP" => (m.top, 0.02, false),
+            "VBASE" => (m.base, 0.02, false),
+            "VAMPLITUDE" => (m.amplitude, 0.04, false),
+            "OVERSHOOT" => (m.overshoot, 1.0, false),
+            "PERIOD" => (m.period?, 1e-4, true),
+            "FREQUENCY" => (m.frequency?, 1e-4, true),
+            "RISETIME" => (m.rise_time?, 0.01, true),
+            "FALLTIME" => (m.fall_time?, 0.01, true),
+            "DUTYCYCLE" => (m.duty_cycle?, 0.01, false),
+            _ => return None,
+        })
+    }
+
+    ///`tests/synthetic/square_10khz.jsonl` is not a recording: it is a transcript in the format
+    ///of [`Recorder`](crate::protocols::Recorder) of fetching a Keysight channel in ASCII, 8-bit
+    ///codes of a 3.3 V square through a 2nd order response with noise, followed by `:MEASure`
+    ///answers computed from that model rather than by a scope
+    #[test]
+    fn synthetic_square_matches_its_model() -> Result<()> {
+        let transcript =
+            Transcript::read_from(&include_bytes!("../../tests/synthetic/square_10khz.jsonl")[..])?;
+        let mut answers = Vec::new();
+        for pair in transcript.entries.windows(2) {
+            if let [request, response] = pair {
+                if (request.direction, response.direction) == (Direction::Write, Direction::Read) {
+                    answers.push((
+                        String::from_utf8_lossy(&request.data).trim().to_string(),
+                        &response.data[..],
+                    ));
+                }
+            }
+        }
+        let answer = |query: &str| -> Result<&[u8]> {
+            answers
+                .iter()
+                .find(|(q, _)| q == query)
+                .map(|(_, a)| *a)
+                .ok_or_else(|| format!("no answer to {}", query).into())
+        };
+        let preamble = Preamble::keysight(answer(":WAVeform:PREamble?")?)?;
+        let waveform = Waveform::from_data(&preamble, answer(":WAVeform:DATA?")?)?;
+        let m = waveform.measure().unwrap();
+        let mut checked = 0;
+        for (query, response) in &answers {
+            let (value, tolerance, relative) = match measured(&m, query) {
+                Some(measured) => measured,
+                None => continue,
+            };
+            let expected: f64 = crate::instruments::parse_response(response)?;
+            let error = (value - expected).abs();
+            let allowed = if relative {
+                tolerance * expected.abs()
+            } else {
+                tolerance
+            };
+            assert!(
+                error <= allowed,
+                "{} modelled as {}, measured {}",
+                query,
+                expected,
+                value
+            );
+            checked += 1;
+        }
+        assert!(checked >= 10, "{} measurements checked", checked);
+        Ok(())
+    }
+
+    #[test]
+    fn channel_math() -> Result<()> {
+        let (mut a, mut b) = (sine(), sine());
+        a.set_channel("CHAN1");
+        b.set_channel("CHAN2");
+        let difference = math(&a, Operation::Subtract, &b)?;
+        assert_eq!(difference.channel.as_deref(), Some("CHAN1 - CHAN2"));
+        assert!(difference.values().iter().all(|v| *v == 0.0));
+        b.y.unit = "A".to_string();
+        let power = math(&a, Operation::Multiply, &b)?;
+        assert_eq!(power.y.unit, "V*A");
+        assert!(close(mean(&power.values()), 0.75, 1e-9));
+        assert!(math(&a, Operation::Add, &b).is_err());
+        assert!(math(&a, Operation::Multiply, &pulses()).is_err());
+        Ok(())
+    }
+}
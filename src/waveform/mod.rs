@@ -0,0 +1,697 @@
+//! Captured waveforms, whatever the scope they come from.
+//!
+//! A [`Waveform`] keeps the samples as the scope sent them, codes of a binary block or values
+//! of an ASCII response, with the [`Axis`] turning sample indices into times and samples into
+//! values, and what is known of the capture: channel, time of acquisition and identity of the
+//! scope. The scaling comes from the answer to `:WAVeform:PREamble?`, see [`Preamble`].
+//!
+//! Waveforms are written as
+//! - CSV, `time,value` rows after `# key: value` comments holding the metadata, e.g. for
+//!   `pandas.read_csv(path, comment='#')`
+//! - NumPy `.npy`, the values alone as a 1-D `float64` array
+//! - a binary format starting with [`MAGIC`], a JSON header with the metadata followed by the
+//!   samples, read back by [`Waveform::read_binary`]
+use crate::{scpi::Identity, Result};
+pub mod dsp;
+pub mod measure;
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+///start of the binary format, followed by the version
+pub const MAGIC: &[u8; 4] = b"RSTW";
+const VERSION: u8 = 1;
+const NPY_MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+///maps an index, or a sample, to a value: `(x - reference) * increment + origin`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Axis {
+    pub increment: f64,
+    pub origin: f64,
+    pub reference: f64,
+    pub unit: String,
+}
+
+impl Axis {
+    pub fn new<S: Into<String>>(increment: f64, origin: f64, unit: S) -> Self {
+        Self {
+            increment,
+            origin,
+            reference: 0.0,
+            unit: unit.into(),
+        }
+    }
+    ///samples that are values already
+    pub fn identity<S: Into<String>>(unit: S) -> Self {
+        Self::new(1.0, 0.0, unit)
+    }
+    pub fn set_reference(&mut self, reference: f64) -> &mut Self {
+        self.reference = reference;
+        self
+    }
+    pub fn value(&self, x: f64) -> f64 {
+        (x - self.reference) * self.increment + self.origin
+    }
+}
+
+///how `:WAVeform:DATA?` sends the samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    ///values separated by commas, possibly in a block
+    Ascii,
+    ///one byte per sample
+    Byte { signed: bool },
+    ///two bytes per sample
+    Word { signed: bool, big_endian: bool },
+}
+
+impl Encoding {
+    fn decode(&self, data: &[u8]) -> Result<Vec<f64>> {
+        match *self {
+            Encoding::Ascii => ascii_values(data),
+            Encoding::Byte { signed: true } => Ok(data.iter().map(|&b| b as i8 as f64).collect()),
+            Encoding::Byte { signed: false } => Ok(data.iter().map(|&b| b as f64).collect()),
+            Encoding::Word { signed, big_endian } => {
+                let words = data.chunks_exact(2);
+                if !words.remainder().is_empty() {
+                    return Err(format!("odd length {} of word data", data.len()).into());
+                }
+                Ok(words
+                    .map(|w| {
+                        let w = [w[0], w[1]];
+                        let word = if big_endian {
+                            u16::from_be_bytes(w)
+                        } else {
+                            u16::from_le_bytes(w)
+                        };
+                        if signed {
+                            word as i16 as f64
+                        } else {
+                            word as f64
+                        }
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+///the answer to `:WAVeform:PREamble?`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preamble {
+    pub encoding: Encoding,
+    pub points: usize,
+    ///averages of the acquisition
+    pub count: u32,
+    pub x: Axis,
+    pub y: Axis,
+    ///date and time of the acquisition, sent by Infiniium scopes only
+    pub acquired: Option<SystemTime>,
+}
+
+impl Preamble {
+    ///Keysight InfiniiVision, 10 fields, or Infiniium, 24 fields with the units,
+    ///binary data in the default byte order, most significant byte first
+    pub fn keysight(response: &[u8]) -> Result<Self> {
+        let fields = preamble_fields(response)?;
+        let number = |i: usize| number(&fields, i);
+        let (encoding, units) = match fields.len() {
+            10 => {
+                let encoding = match number(0)? as u8 {
+                    0 => Encoding::Byte { signed: false },
+                    1 => Encoding::Word {
+                        signed: false,
+                        big_endian: true,
+                    },
+                    4 => Encoding::Ascii,
+                    f => return Err(format!("unknown waveform format {}", f).into()),
+                };
+                (encoding, ("s", "V"))
+            }
+            24 => {
+                let encoding = match number(0)? as u8 {
+                    0 => Encoding::Ascii,
+                    1 => Encoding::Byte { signed: true },
+                    2 => Encoding::Word {
+                        signed: true,
+                        big_endian: true,
+                    },
+                    f => return Err(format!("unsupported waveform format {}", f).into()),
+                };
+                let unit = |i: usize| -> Result<&'static str> {
+                    Ok(match number(i)? as u8 {
+                        1 => "V",
+                        2 => "s",
+                        4 => "A",
+                        5 => "dB",
+                        6 => "Hz",
+                        7 => "W",
+                        _ => "",
+                    })
+                };
+                (encoding, (unit(20)?, unit(21)?))
+            }
+            n => return Err(format!("expected 10 or 24 preamble fields, got {}", n).into()),
+        };
+        let mut x = Axis::new(number(4)?, number(5)?, units.0);
+        x.set_reference(number(6)?);
+        let mut y = Axis::new(number(7)?, number(8)?, units.1);
+        y.set_reference(number(9)?);
+        Ok(Self {
+            encoding,
+            points: number(2)? as usize,
+            count: number(3)? as u32,
+            x,
+            y,
+            acquired: match fields.len() {
+                24 => date_time(fields[15], fields[16]),
+                _ => None,
+            },
+        })
+    }
+    ///Rigol, whose codes give `(code - y_origin - y_reference) * y_increment`,
+    ///words are sent least significant byte first
+    pub fn rigol(response: &[u8]) -> Result<Self> {
+        let fields = preamble_fields(response)?;
+        if fields.len() != 10 {
+            return Err(format!("expected 10 preamble fields, got {}", fields.len()).into());
+        }
+        let number = |i: usize| number(&fields, i);
+        let encoding = match number(0)? as u8 {
+            0 => Encoding::Word {
+                signed: false,
+                big_endian: false,
+            },
+            1 => Encoding::Byte { signed: false },
+            2 => Encoding::Ascii,
+            f => return Err(format!("unknown waveform format {}", f).into()),
+        };
+        let mut x = Axis::new(number(4)?, number(5)?, "s");
+        x.set_reference(number(6)?);
+        let mut y = Axis::new(number(7)?, 0.0, "V");
+        y.set_reference(number(8)? + number(9)?);
+        Ok(Self {
+            encoding,
+            points: number(2)? as usize,
+            count: number(3)? as u32,
+            x,
+            y,
+            acquired: None,
+        })
+    }
+}
+
+fn preamble_fields(response: &[u8]) -> Result<Vec<&str>> {
+    Ok(std::str::from_utf8(response)
+        .map_err(|_| "preamble isn't text")?
+        .trim()
+        .split(',')
+        .map(str::trim)
+        .collect())
+}
+
+fn number(fields: &[&str], i: usize) -> Result<f64> {
+    super::instruments::parse_response(fields[i].as_bytes())
+}
+
+///`"15 JUN 2011"` and `"13:10:08:40"`, hundredths last, read as UTC since the scope doesn't
+///tell its time zone
+fn date_time(date: &str, time: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+    ];
+    let mut date = date.trim_matches('"').split_whitespace();
+    let day: u32 = date.next()?.parse().ok()?;
+    let month = date.next()?.to_ascii_uppercase();
+    let month = MONTHS.iter().position(|&m| m == month)? as u32 + 1;
+    let year: i64 = date.next()?.parse().ok()?;
+    let time = time
+        .trim_matches('"')
+        .split(':')
+        .map(|x| x.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (hour, minute, second, hundredths) = match time[..] {
+        [h, m, s, c] => (h, m, s, c),
+        [h, m, s] => (h, m, s, 0),
+        _ => return None,
+    };
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 || hundredths > 99 {
+        return None;
+    }
+    //days since the epoch of a proleptic Gregorian date, with years starting in March
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m as i64 + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let seconds = days * 86_400 + (hour * 3600 + minute * 60 + second) as i64;
+    Some(UNIX_EPOCH + Duration::new(seconds.try_into().ok()?, hundredths * 10_000_000))
+}
+
+///the data of a definite length block `#<n><length><data>`, the response itself otherwise,
+///`#0` blocks run to the end of the response
+fn block(response: &[u8]) -> Result<&[u8]> {
+    match response {
+        [b'#', b'0', data @ ..] => Ok(data),
+        [b'#', n @ b'1'..=b'9', rest @ ..] => {
+            let digits = (n - b'0') as usize;
+            let len = rest
+                .get(..digits)
+                .and_then(|d| std::str::from_utf8(d).ok()?.parse::<usize>().ok())
+                .ok_or("invalid block header")?;
+            rest[digits..]
+                .get(..len)
+                .ok_or_else(|| format!("truncated block, {} bytes expected", len).into())
+        }
+        _ => Ok(response),
+    }
+}
+
+///`len` bytes, allocated as they are read so that a bad length can't exhaust the memory
+fn read_up_to<R: Read>(r: &mut R, len: usize, what: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    r.take(len as u64).read_to_end(&mut data)?;
+    if data.len() < len {
+        return Err(format!("truncated {}, {} of {} bytes", what, data.len(), len).into());
+    }
+    Ok(data)
+}
+
+fn ascii_values(data: &[u8]) -> Result<Vec<f64>> {
+    std::str::from_utf8(data)
+        .map_err(|_| "waveform data isn't text")?
+        .trim()
+        .split_terminator(',')
+        .map(|x| super::instruments::parse_response(x.as_bytes()))
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Waveform {
+    ///codes or values, see [`Waveform::y`]
+    #[serde(skip)]
+    pub samples: Vec<f64>,
+    ///time of the samples by index
+    pub x: Axis,
+    ///value of the samples
+    pub y: Axis,
+    ///source of the waveform, e.g. `CHAN1`
+    pub channel: Option<String>,
+    pub acquired: Option<SystemTime>,
+    pub identity: Option<Identity>,
+}
+
+///what precedes the samples in the binary format
+#[derive(Serialize, Deserialize)]
+struct Header {
+    points: usize,
+    waveform: Waveform,
+}
+
+impl Waveform {
+    ///values taken every `x_increment` seconds from 0 s, in V
+    pub fn new(values: Vec<f64>, x_increment: f64) -> Self {
+        Self {
+            samples: values,
+            x: Axis::new(x_increment, 0.0, "s"),
+            y: Axis::identity("V"),
+            channel: None,
+            acquired: None,
+            identity: None,
+        }
+    }
+    ///the answer to `:WAVeform:DATA?`, a block or ASCII values, scaled by the preamble
+    pub fn from_data(preamble: &Preamble, response: &[u8]) -> Result<Self> {
+        let samples = preamble.encoding.decode(block(response)?)?;
+        let y = match preamble.encoding {
+            //ASCII data are values already
+            Encoding::Ascii => Axis::identity(preamble.y.unit.clone()),
+            _ => preamble.y.clone(),
+        };
+        Ok(Self {
+            samples,
+            x: preamble.x.clone(),
+            y,
+            channel: None,
+            acquired: preamble.acquired,
+            identity: None,
+        })
+    }
+    pub fn set_channel<S: Into<String>>(&mut self, channel: S) -> &mut Self {
+        self.channel = Some(channel.into());
+        self
+    }
+    pub fn set_acquired(&mut self, acquired: SystemTime) -> &mut Self {
+        self.acquired = Some(acquired);
+        self
+    }
+    pub fn set_identity(&mut self, identity: Identity) -> &mut Self {
+        self.identity = Some(identity);
+        self
+    }
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+    pub fn time(&self, index: usize) -> f64 {
+        self.x.value(index as f64)
+    }
+    pub fn times(&self) -> Vec<f64> {
+        (0..self.len()).map(|i| self.time(i)).collect()
+    }
+    pub fn value(&self, index: usize) -> f64 {
+        self.y.value(self.samples[index])
+    }
+    pub fn values(&self) -> Vec<f64> {
+        self.samples.iter().map(|&s| self.y.value(s)).collect()
+    }
+
+    pub fn write_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        if let Some(channel) = &self.channel {
+            writeln!(w, "# channel: {}", channel)?;
+        }
+        if let Some(identity) = &self.identity {
+            writeln!(w, "# identity: {}", identity)?;
+        }
+        if let Some(acquired) = self.acquired {
+            let since = acquired.duration_since(UNIX_EPOCH).unwrap_or_default();
+            writeln!(
+                w,
+                "# acquired: {}.{:09}",
+                since.as_secs(),
+                since.subsec_nanos()
+            )?;
+        }
+        writeln!(w, "# x_increment: {}", self.x.increment)?;
+        writeln!(w, "# x_origin: {}", self.x.origin)?;
+        writeln!(w, "# x_reference: {}", self.x.reference)?;
+        writeln!(w, "# x_unit: {}", self.x.unit)?;
+        writeln!(w, "# y_unit: {}", self.y.unit)?;
+        writeln!(w, "time,value")?;
+        for i in 0..self.len() {
+            writeln!(w, "{},{}", self.time(i), self.value(i))?;
+        }
+        Ok(())
+    }
+    ///read what [`Waveform::write_csv`] writes, times come from the `x_` comments and the
+    ///values become the samples
+    pub fn read_csv<R: BufRead>(r: R) -> Result<Self> {
+        let mut waveform = Waveform::new(Vec::new(), 1.0);
+        for (n, line) in r.lines().enumerate() {
+            let line = line?;
+            let invalid = || format!("line {}: invalid '{}'", n + 1, line);
+            if let Some(comment) = line.strip_prefix('#') {
+                let (key, value) = match comment.split_once(':') {
+                    Some((key, value)) => (key.trim(), value.trim()),
+                    None => continue,
+                };
+                let number = || value.parse::<f64>().map_err(|_| invalid());
+                match key {
+                    "channel" => waveform.channel = Some(value.to_string()),
+                    "identity" => waveform.identity = Some(value.parse()?),
+                    "acquired" => {
+                        waveform.acquired = Some(crate::parse_unix_time(value).ok_or_else(invalid)?)
+                    }
+                    "x_increment" => waveform.x.increment = number()?,
+                    "x_origin" => waveform.x.origin = number()?,
+                    "x_reference" => waveform.x.reference = number()?,
+                    "x_unit" => waveform.x.unit = value.to_string(),
+                    "y_unit" => waveform.y.unit = value.to_string(),
+                    _ => {}
+                }
+            } else if line.starts_with("time") || line.trim().is_empty() {
+                continue;
+            } else {
+                let value = line.split(',').nth(1).ok_or_else(invalid)?;
+                waveform
+                    .samples
+                    .push(value.trim().parse().map_err(|_| invalid())?);
+            }
+        }
+        Ok(waveform)
+    }
+
+    ///the values as a 1-D `float64` array
+    pub fn write_npy<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let mut header = format!(
+            "{{'descr': '<f8', 'fortran_order': False, 'shape': ({},), }}",
+            self.len()
+        );
+        //the data start at a multiple of 64 bytes, after a new line
+        let unpadded = NPY_MAGIC.len() + 4 + header.len() + 1;
+        header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+        header.push('\n');
+        w.write_all(NPY_MAGIC)?;
+        w.write_all(&[1, 0])?;
+        w.write_all(&(header.len() as u16).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+        for v in self.values() {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn write_binary<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let header = serde_json::to_vec(&Header {
+            points: self.len(),
+            waveform: Waveform {
+                samples: Vec::new(),
+                ..self.clone()
+            },
+        })?;
+        w.write_all(MAGIC)?;
+        w.write_all(&[VERSION])?;
+        w.write_all(&(header.len() as u32).to_le_bytes())?;
+        w.write_all(&header)?;
+        for s in &self.samples {
+            w.write_all(&s.to_le_bytes())?;
+        }
+        Ok(())
+    }
+    pub fn read_binary<R: Read>(mut r: R) -> Result<Self> {
+        let mut head = [0_u8; 9];
+        r.read_exact(&mut head)?;
+        if &head[..4] != MAGIC {
+            return Err("not a waveform file".into());
+        }
+        if head[4] != VERSION {
+            return Err(format!("unknown waveform file version {}", head[4]).into());
+        }
+        let header_len = u32::from_le_bytes(head[5..].try_into().unwrap()) as usize;
+        let header = read_up_to(&mut r, header_len, "waveform header")?;
+        let Header {
+            points,
+            mut waveform,
+        } = serde_json::from_slice(&header)
+            .map_err(|e| format!("invalid waveform header: {}", e))?;
+        let len = points
+            .checked_mul(8)
+            .ok_or_else(|| format!("invalid waveform header: {} points", points))?;
+        let data = read_up_to(&mut r, len, "waveform samples")?;
+        waveform.samples = data
+            .chunks_exact(8)
+            .map(|s| f64::from_le_bytes(s.try_into().unwrap()))
+            .collect();
+        Ok(waveform)
+    }
+
+    ///write in the format given by the extension, `csv`, `npy` or anything else for binary
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut w = BufWriter::new(File::create(path)?);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => self.write_csv(&mut w)?,
+            Some("npy") => self.write_npy(&mut w)?,
+            _ => self.write_binary(&mut w)?,
+        }
+        Ok(w.flush()?)
+    }
+    ///read a binary or CSV file, told apart by [`MAGIC`]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut r = BufReader::new(File::open(path)?);
+        if r.fill_buf()?.starts_with(MAGIC) {
+            Self::read_binary(r)
+        } else {
+            Self::read_csv(r)
+        }
+    }
+}
+
+///the values of a 1-D little endian `float64` or `float32` array
+pub fn read_npy<R: Read>(mut r: R) -> Result<Vec<f64>> {
+    let mut head = [0_u8; 10];
+    r.read_exact(&mut head)?;
+    if &head[..6] != NPY_MAGIC {
+        return Err("not a .npy file".into());
+    }
+    let header_len = match head[6] {
+        1 => u16::from_le_bytes([head[8], head[9]]) as usize,
+        _ => {
+            let mut rest = [0_u8; 2];
+            r.read_exact(&mut rest)?;
+            u32::from_le_bytes([head[8], head[9], rest[0], rest[1]]) as usize
+        }
+    };
+    let mut header = vec![0_u8; header_len];
+    r.read_exact(&mut header)?;
+    let header = String::from_utf8(header)?;
+    let size = if header.contains("'<f8'") {
+        8
+    } else if header.contains("'<f4'") {
+        4
+    } else {
+        return Err(format!("unsupported array {}", header.trim()).into());
+    };
+    if header.contains("'fortran_order': True") {
+        return Err("fortran order arrays aren't supported".into());
+    }
+    let mut data = Vec::new();
+    r.read_to_end(&mut data)?;
+    Ok(data
+        .chunks_exact(size)
+        .map(|v| match size {
+            8 => f64::from_le_bytes(v.try_into().unwrap()),
+            _ => f32::from_le_bytes(v.try_into().unwrap()) as f64,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captured() -> Waveform {
+        let preamble = Preamble::keysight(
+            b"0,0,4,1,+1.00000000E-09,-2.00000000E-09,+0.0E+00,+4.0E-03,+1.0E-01,+128\n",
+        )
+        .unwrap();
+        let mut waveform = Waveform::from_data(&preamble, b"#14\x00\x80\x81\xff\n").unwrap();
+        waveform
+            .set_channel("CHAN1")
+            .set_acquired(UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789))
+            .set_identity(
+                "KEYSIGHT TECHNOLOGIES,DSOX3024T,MY1234,07.50"
+                    .parse()
+                    .unwrap(),
+            );
+        waveform
+    }
+
+    #[test]
+    fn preambles() -> Result<()> {
+        let waveform = captured();
+        assert_eq!(waveform.samples, vec![0.0, 128.0, 129.0, 255.0]);
+        for (t, expected) in waveform.times().into_iter().zip([-2e-9, -1e-9, 0.0, 1e-9]) {
+            assert!((t - expected).abs() < 1e-18);
+        }
+        assert!((waveform.value(0) - (0.1 - 128.0 * 4e-3)).abs() < 1e-12);
+        assert!((waveform.value(1) - 0.1).abs() < 1e-12);
+
+        let infiniium = Preamble::keysight(
+            b"2,1,2,1,1E-10,0,0,2E-5,0,0,1,5E-9,0,1,0,\"15 JUN 2011\",\"13:10:08:40\",\"DSO90804A:MY0\",1,100,2,1,8E9,0\n",
+        )?;
+        assert_eq!(infiniium.x.unit, "s");
+        assert_eq!(
+            infiniium.acquired,
+            Some(UNIX_EPOCH + Duration::new(1_308_143_408, 400_000_000))
+        );
+        let waveform = Waveform::from_data(&infiniium, b"#14\xff\xfe\x00\x02")?;
+        assert_eq!(waveform.samples, vec![-2.0, 2.0]);
+        assert_eq!(waveform.acquired, infiniium.acquired);
+        assert_eq!(
+            date_time("\"29 FEB 2024\"", "\"00:00:00:00\""),
+            Some(UNIX_EPOCH + Duration::from_secs(1_709_164_800))
+        );
+        assert_eq!(date_time("\"31 DEC 1969\"", "\"23:59:59\""), None);
+        assert_eq!(date_time("\"15 JUX 2011\"", "\"13:10:08:40\""), None);
+        assert_eq!(date_time("\"15 JUN 2011\"", "\"25:10:08:40\""), None);
+
+        let rigol = Preamble::rigol(b"1,0,3,1,1.0e-06,-6.0e-06,0,4.0e-02,-10,127\n")?;
+        let waveform = Waveform::from_data(&rigol, b"#9000000003\x75\x7f\x89\n")?;
+        assert!((waveform.value(0) - 0.0).abs() < 1e-12);
+        assert!((waveform.value(1) - 0.4).abs() < 1e-12);
+        assert!((waveform.value(2) - 0.8).abs() < 1e-12);
+        assert_eq!(waveform.time(0), -6e-6);
+        assert_eq!(rigol.acquired, None);
+
+        let ascii = Preamble::rigol(b"2,0,2,1,1.0e-06,0,0,4.0e-02,0,127\n")?;
+        let waveform = Waveform::from_data(&ascii, b"#9000000019-1.200e-02,4.0e-02,\n")?;
+        assert_eq!(waveform.values(), vec![-0.012, 0.04]);
+
+        assert!(Preamble::keysight(b"0,0,4,1").is_err());
+        assert!(Waveform::from_data(&rigol, b"#9000000004\x75").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips() -> Result<()> {
+        let waveform = captured();
+
+        let mut binary = Vec::new();
+        waveform.write_binary(&mut binary)?;
+        assert_eq!(Waveform::read_binary(&binary[..])?, waveform);
+        assert!(Waveform::read_binary(&binary[..binary.len() - 1]).is_err());
+        //a header claiming more points than the file holds
+        let mut huge = MAGIC.to_vec();
+        let header = format!(
+            "{{\"points\":{},\"waveform\":{}}}",
+            usize::MAX / 4,
+            serde_json::to_string(&waveform).unwrap()
+        );
+        huge.push(VERSION);
+        huge.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        huge.extend_from_slice(header.as_bytes());
+        let e = Waveform::read_binary(&huge[..]).unwrap_err();
+        assert!(e.to_string().contains("invalid waveform header"), "{}", e);
+        let header = header.replace(&(usize::MAX / 4).to_string(), &(1_u64 << 40).to_string());
+        let mut large = huge[..5].to_vec();
+        large.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        large.extend_from_slice(header.as_bytes());
+        let e = Waveform::read_binary(&large[..]).unwrap_err();
+        assert!(
+            e.to_string().contains("truncated waveform samples"),
+            "{}",
+            e
+        );
+        huge[5..9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Waveform::read_binary(&huge[..]).is_err());
+
+        let mut csv = Vec::new();
+        waveform.write_csv(&mut csv)?;
+        let read = Waveform::read_csv(&csv[..])?;
+        assert_eq!(read.values(), waveform.values());
+        assert_eq!(read.times(), waveform.times());
+        assert_eq!(
+            (&read.channel, &read.acquired, &read.identity),
+            (&waveform.channel, &waveform.acquired, &waveform.identity)
+        );
+        for acquired in ["1.ééééé", "1.+5", "-1", ".5"] {
+            let csv = format!("# acquired: {}\ntime,value\n0,1\n", acquired);
+            let e = Waveform::read_csv(csv.as_bytes()).unwrap_err();
+            assert!(e.to_string().contains("line 1: invalid"), "{}", e);
+        }
+        let read = Waveform::read_csv(&b"# acquired: 1.1234567891\n"[..])?;
+        assert_eq!(
+            read.acquired,
+            Some(UNIX_EPOCH + Duration::new(1, 123_456_789))
+        );
+
+        let mut npy = Vec::new();
+        waveform.write_npy(&mut npy)?;
+        //aligned header, then 4 values
+        assert_eq!(npy.len() % 64, 4 * 8);
+        assert_eq!(read_npy(&npy[..])?, waveform.values());
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use rustrument::{test_support::line_instrument, Result};
+use std::process::Command;
+
+///an instrument on a local port answering `*IDN?`, `*STB?` and `MEAS?`
+fn fake_instrument() -> Result<String> {
+    let (port, _) = line_instrument(|line| {
+        let answer = match line {
+            "*IDN?" => "FAKE,SCOPE,S1,1.2\n",
+            "*STB?" => "+80\n",
+            "MEAS?" => "1.5E-3\n",
+            _ => return None,
+        };
+        Some(answer.to_string())
+    })?;
+    Ok(format!("127.0.0.1:{}", port))
+}
+
+///what `rustrument` prints to stdout with `args`, and whether it succeeded
+fn run(args: &[&str]) -> (String, bool) {
+    let output = Command::new(env!("CARGO_BIN_EXE_rustrument"))
+        .args(args)
+        .env_remove("RUSTRUMENT_LAB")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    (stdout.trim_end().to_string(), output.status.success())
+}
+
+#[test]
+fn text_and_json_output() -> Result<()> {
+    let resource = fake_instrument()?;
+    let printed = |args: &[&str]| {
+        let (text, ok) = run(args);
+        assert!(ok, "{:?}", args);
+        text
+    };
+    assert_eq!(
+        printed(&["idn", &resource]),
+        "manufacturer: FAKE\nmodel:        SCOPE\nserial:       S1\nfirmware:     1.2"
+    );
+    assert_eq!(
+        printed(&["idn", &resource, "--json"]),
+        r#"{"firmware":"1.2","manufacturer":"FAKE","model":"SCOPE","serial":"S1"}"#
+    );
+    assert_eq!(printed(&["query", &resource, "MEAS?"]), "1.5E-3");
+    assert_eq!(
+        printed(&["query", &resource, "MEAS?", "--json"]),
+        r#"{"response":"1.5E-3"}"#
+    );
+    assert_eq!(printed(&["write", &resource, "*RST"]), "");
+    assert_eq!(
+        printed(&["write", &resource, "*RST", "--json"]),
+        r#"{"sent":"*RST"}"#
+    );
+    assert_eq!(printed(&["read-stb", &resource]), "0x50 MAV RQS");
+    assert_eq!(
+        printed(&["read-stb", &resource, "--json"]),
+        r#"{"flags":["MAV","RQS"],"stb":80}"#
+    );
+    let (error, ok) = run(&["lock", &resource, "--json"]);
+    assert!(!ok);
+    assert!(error.contains("isn't a VXI-11 resource"), "{}", error);
+    let (error, ok) = run(&["unlock", &resource, "--json"]);
+    assert!(!ok);
+    assert!(
+        error.contains("a lock can only be released by the `lock` process holding it"),
+        "{}",
+        error
+    );
+    Ok(())
+}
@@ -0,0 +1,7 @@
+#[test]
+fn derive_macros() {
+    let t = trybuild::TestCases::new();
+    t.pass("tests/ui/pass.rs");
+    t.compile_fail("tests/ui/cmd_*.rs");
+    t.compile_fail("tests/ui/query_*.rs");
+}
@@ -0,0 +1,10 @@
+use rustrument::InstrumentCommand;
+
+#[derive(InstrumentCommand)]
+enum Setting {
+    #[cmd("*RST")]
+    Reset,
+    Clear,
+}
+
+fn main() {}
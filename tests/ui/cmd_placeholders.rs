@@ -0,0 +1,9 @@
+use rustrument::InstrumentCommand;
+
+#[derive(InstrumentCommand)]
+enum Setting {
+    #[cmd(":VOLT:RANG {}")]
+    Range(f32, f32),
+}
+
+fn main() {}
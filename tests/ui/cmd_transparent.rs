@@ -0,0 +1,9 @@
+use rustrument::InstrumentCommand;
+
+#[derive(InstrumentCommand)]
+enum Setting {
+    #[cmd(transparent)]
+    Both(u8, u8),
+}
+
+fn main() {}
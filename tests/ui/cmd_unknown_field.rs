@@ -0,0 +1,9 @@
+use rustrument::InstrumentCommand;
+
+#[derive(InstrumentCommand)]
+#[cmd(":TRIG:DEL {delay}")]
+struct Trigger {
+    count: u32,
+}
+
+fn main() {}
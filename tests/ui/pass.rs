@@ -0,0 +1,59 @@
+use rustrument::{
+    instruments::{Command, Model, Query},
+    InstrumentCommand, InstrumentQuery,
+};
+
+struct Meter;
+
+impl Model for Meter {
+    const DESCRIPTION: &'static str = "meter";
+    type Command = Setting;
+    const TERMINATOR: u8 = b'\n';
+    const END_BYTE: u8 = b'\n';
+}
+
+#[derive(InstrumentCommand)]
+enum Setting {
+    ///range, V
+    #[cmd(":VOLT:RANG {}")]
+    Range(f32),
+    #[cmd(":VOLT:NPLC {nplc};:VOLT:AZER {}", _0.0, nplc = _0.1)]
+    Integration((f32, f32)),
+    #[cmd(":DISP {}", if _0 { "ON" } else { "OFF" })]
+    Display(bool),
+    #[cmd("*RST")]
+    Reset,
+    #[cmd(transparent)]
+    Trigger(Trigger),
+}
+
+#[derive(InstrumentCommand)]
+#[cmd(":TRIG:DEL {delay};:TRIG:COUN {count}")]
+struct Trigger {
+    delay: f32,
+    count: u32,
+}
+
+///reading of a channel
+#[derive(InstrumentQuery)]
+#[query(":MEAS:VOLT? (@{})", parse = f64, model = Meter)]
+struct Voltage(u8);
+
+fn main() {
+    assert_eq!(Setting::Range(10.).to_bytes(), b":VOLT:RANG 10");
+    assert_eq!(
+        Setting::Integration((1.0, 0.5)).to_bytes(),
+        b":VOLT:NPLC 0.5;:VOLT:AZER 1"
+    );
+    assert_eq!(Setting::Display(false).to_bytes(), b":DISP OFF");
+    let trigger = Setting::Trigger(Trigger {
+        delay: 0.1,
+        count: 3,
+    });
+    assert_eq!(trigger.to_bytes(), b":TRIG:DEL 0.1;:TRIG:COUN 3");
+    assert_eq!(Setting::DOCS[0].doc, "range, V");
+    assert_eq!(Setting::DOCS[4].syntax, "<Trigger>");
+    assert_eq!(Voltage(2).to_bytes(), b":MEAS:VOLT? (@2)");
+    assert_eq!(Voltage::parse(b"+1.5E+00\n").unwrap(), 1.5);
+    assert_eq!(Voltage::DOC.doc, "reading of a channel");
+}
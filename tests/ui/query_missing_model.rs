@@ -0,0 +1,7 @@
+use rustrument::InstrumentQuery;
+
+#[derive(InstrumentQuery)]
+#[query(":MEAS:VOLT?", parse = f64)]
+struct Voltage;
+
+fn main() {}
@@ -0,0 +1,8 @@
+use rustrument::InstrumentQuery;
+
+#[derive(InstrumentQuery)]
+enum Reading {
+    Voltage,
+}
+
+fn main() {}
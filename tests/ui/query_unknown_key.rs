@@ -0,0 +1,9 @@
+use rustrument::InstrumentQuery;
+
+struct Meter;
+
+#[derive(InstrumentQuery)]
+#[query(":MEAS:VOLT?", parse = f64, model = Meter, unit = V)]
+struct Voltage;
+
+fn main() {}